@@ -0,0 +1,542 @@
+use crate::config::{CommandLine, ContainerAction, Dependent, RunAs, UnitAction};
+use crate::containers;
+use crate::systemd;
+use log::{debug, error, info, warn};
+use std::collections::BTreeSet;
+use std::ffi::{CStr, CString};
+use std::io::{self, Read};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// The process groups of the commands running now, so they can be killed on shutdown
+static RUNNING: Mutex<BTreeSet<libc::pid_t>> = Mutex::new(BTreeSet::new());
+
+// Kill every command still running, along with anything it started
+pub fn kill_running() {
+    for group in RUNNING.lock().unwrap().iter() {
+        unsafe { libc::killpg(*group, libc::SIGKILL) };
+    }
+}
+
+// Run a command, killing it if it takes longer than the timeout
+pub fn run_command(command: &CommandLine, timeout: Duration) -> Result<(), String> {
+    run_command_with_env(command, &[], timeout, None)
+}
+
+// Fill in the {{name}} placeholders of a command from its NOFUS_NAME environment
+// variable, leaving any that aren't set as they are. Each argument of a list is filled
+// in on its own, so values can't spill into other arguments, and values put into a
+// shell command are quoted for where they stand, so they are only ever text to it.
+pub fn render(command: &CommandLine, env: &[(&str, String)]) -> CommandLine {
+    let text = |s: &str| render_str(s, env, false);
+    match command {
+        CommandLine::Shell(command) => CommandLine::Shell(render_str(command, env, true)),
+        CommandLine::Argv(argv) => CommandLine::Argv(argv.iter().map(|a| text(a)).collect()),
+        CommandLine::Units(action) => {
+            let units = |units: &[String]| units.iter().map(|u| text(u)).collect();
+            CommandLine::Units(UnitAction {
+                stop: units(&action.stop),
+                restart: units(&action.restart),
+                start: units(&action.start),
+            })
+        }
+        CommandLine::Containers(action) => {
+            let containers = |containers: &[String]| containers.iter().map(|c| text(c)).collect();
+            CommandLine::Containers(ContainerAction {
+                stop: containers(&action.stop),
+                pause: containers(&action.pause),
+                restart: containers(&action.restart),
+                unpause: containers(&action.unpause),
+                start: containers(&action.start),
+                socket: action.socket.as_deref().map(text),
+            })
+        }
+        CommandLine::Ready(dependent) => {
+            let render_opt = |s: &Option<String>| s.as_deref().map(text);
+            CommandLine::Ready(Box::new(Dependent {
+                unit: render_opt(&dependent.unit),
+                container: render_opt(&dependent.container),
+                container_socket: render_opt(&dependent.container_socket),
+                ready_cmd: dependent.ready_cmd.as_ref().map(|c| render(c, env)),
+                ready_timeout_seconds: dependent.ready_timeout_seconds,
+            }))
+        }
+    }
+}
+
+fn render_str(template: &str, env: &[(&str, String)], shell: bool) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut quoting = Quoting::Unquoted;
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start..].find("}}") else {
+            break;
+        };
+        let name = rest[start + 2..start + len].trim();
+        let key = format!("NOFUS_{}", name.to_uppercase());
+        rendered.push_str(&rest[..start]);
+        quoting = quoting.after(&rest[..start]);
+        match env.iter().find(|(k, _)| *k == key) {
+            Some((_, value)) if shell => rendered.push_str(&quoting.quote(value)),
+            Some((_, value)) => rendered.push_str(value),
+            None => rendered.push_str(&rest[start..start + len + 2]),
+        }
+        rest = &rest[start + len + 2..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+// What quotes a placeholder in a shell command stands in, which decides how its value
+// has to be quoted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Quoting {
+    Unquoted,
+    Single,
+    Double,
+}
+
+impl Quoting {
+    // The quotes still open once the shell has read the text after these
+    fn after(mut self, text: &str) -> Self {
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            self = match (self, c) {
+                (Quoting::Unquoted, '\\') | (Quoting::Double, '\\') => {
+                    chars.next();
+                    self
+                }
+                (Quoting::Unquoted, '\'') => Quoting::Single,
+                (Quoting::Unquoted, '"') => Quoting::Double,
+                (Quoting::Single, '\'') | (Quoting::Double, '"') => Quoting::Unquoted,
+                _ => self,
+            };
+        }
+        self
+    }
+
+    // The value as a single word of plain text to the shell. Words that need no
+    // quoting, like most paths, are left as they are.
+    fn quote(self, value: &str) -> String {
+        let plain = |c: char| c.is_ascii_alphanumeric() || "/._-+=:,@%".contains(c);
+        match self {
+            Quoting::Unquoted if !value.is_empty() && value.chars().all(plain) => value.to_string(),
+            Quoting::Unquoted => format!("'{}'", value.replace('\'', r"'\''")),
+            Quoting::Single => value.replace('\'', r"'\''"),
+            Quoting::Double => {
+                let mut quoted = String::with_capacity(value.len());
+                for c in value.chars() {
+                    if matches!(c, '$' | '`' | '"' | '\\') {
+                        quoted.push('\\');
+                    }
+                    quoted.push(c);
+                }
+                quoted
+            }
+        }
+    }
+}
+
+// Run a command with extra environment variables set, optionally as another user,
+// killing it if it takes longer than the timeout
+pub fn run_command_with_env(
+    command: &CommandLine,
+    env: &[(&str, String)],
+    timeout: Duration,
+    run_as: Option<&RunAs>,
+) -> Result<(), String> {
+    run_command_with_exit_code(command, env, timeout, run_as).0
+}
+
+// Like run_command_with_env(), also giving the exit code if the command exited
+pub fn run_command_with_exit_code(
+    command: &CommandLine,
+    env: &[(&str, String)],
+    timeout: Duration,
+    run_as: Option<&RunAs>,
+) -> (Result<(), String>, Option<i32>) {
+    let mut exit_code = None;
+    let result = execute(command, env, timeout, run_as, &mut exit_code);
+    (result, exit_code)
+}
+
+fn execute(
+    command: &CommandLine,
+    env: &[(&str, String)],
+    timeout: Duration,
+    run_as: Option<&RunAs>,
+    exit_code: &mut Option<i32>,
+) -> Result<(), String> {
+    let mut process = match command {
+        CommandLine::Shell(command_string) => {
+            let mut process = Command::new("sh");
+            process.arg("-c").arg(command_string);
+            process
+        }
+        CommandLine::Argv(argv) => {
+            let (program, args) = argv.split_first().ok_or("Command is empty")?;
+            let mut process = Command::new(program);
+            process.args(args);
+            process
+        }
+        // systemd and the container runtime do these themselves, as whoever nofus is
+        CommandLine::Units(action) => return systemd::run_units(action, timeout),
+        CommandLine::Containers(action) => return containers::run(action, timeout),
+        CommandLine::Ready(dependent) => return wait_ready(dependent, env, run_as),
+    };
+    // Switching user drops any supplementary groups along with it
+    if let Some(run_as) = run_as {
+        let credentials = credentials(run_as)?;
+        process
+            .uid(credentials.uid)
+            .gid(credentials.gid)
+            .env("USER", &run_as.user)
+            .env("LOGNAME", &run_as.user)
+            .env("HOME", &credentials.home);
+    }
+    // In a process group of its own, so anything it started can be killed with it
+    let mut child = process
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .process_group(0)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to execute command: {}", e))?;
+    let group = child.id() as libc::pid_t;
+    RUNNING.lock().unwrap().insert(group);
+    let stdout = capture(child.stdout.take());
+    let stderr = capture(child.stderr.take());
+    let status = wait(child, timeout);
+    RUNNING.lock().unwrap().remove(&group);
+    // Something it started in the background could hold the pipes open indefinitely
+    let stdout = stdout.recv_timeout(OUTPUT_GRACE).unwrap_or_default();
+    let stderr = stderr.recv_timeout(OUTPUT_GRACE).unwrap_or_default();
+
+    let result = status.and_then(|status| {
+        *exit_code = status.code();
+        match status.code() {
+            Some(code) => debug!(
+                event = "command", command:% = command, exit_code = code;
+                "Command finished with {}", status
+            ),
+            None => debug!(
+                event = "command", command:% = command;
+                "Command finished with {}", status
+            ),
+        }
+        if status.success() {
+            Ok(())
+        } else {
+            Err(format!("Command failed with status: {}", status))
+        }
+    });
+    for (stream, output) in [("stdout", &stdout), ("stderr", &stderr)] {
+        let output = output.trim_end();
+        if output.is_empty() {
+            continue;
+        }
+        if result.is_ok() {
+            debug!(
+                event = "command_output", command:% = command, stream = stream;
+                "Command {}: {}", stream, output
+            );
+        } else {
+            warn!(
+                event = "command_output", command:% = command, stream = stream;
+                "Command {}: {}", stream, output
+            );
+        }
+    }
+    // Errors carry the end of the output, preferring stderr, to show what went wrong
+    result.map_err(|e| match tail(&stderr).or_else(|| tail(&stdout)) {
+        Some(tail) => format!("{}: {}", e, tail),
+        None => e,
+    })
+}
+
+// How often to see whether a dependent is ready yet
+const READY_POLL: Duration = Duration::from_secs(1);
+
+// Wait for a dependent that was just started to be ready: for its container to be
+// running, and healthy if it has a health check, and for its ready_cmd to succeed
+fn wait_ready(
+    dependent: &Dependent,
+    env: &[(&str, String)],
+    run_as: Option<&RunAs>,
+) -> Result<(), String> {
+    let timeout = dependent.ready_timeout();
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let result = dependent
+            .container
+            .as_ref()
+            .map_or(Ok(()), |container| {
+                containers::ready(container, dependent.container_socket.as_deref(), remaining)
+            })
+            .and_then(|()| {
+                dependent.ready_cmd.as_ref().map_or(Ok(()), |command| {
+                    run_command_with_env(command, env, remaining, run_as)
+                })
+            });
+        match result {
+            Ok(()) => {
+                info!(event = "command"; "{} is ready", dependent.name());
+                return Ok(());
+            }
+            Err(e) if Instant::now() + READY_POLL >= deadline => {
+                return Err(format!(
+                    "{} wasn't ready within {}s: {}",
+                    dependent.name(),
+                    timeout.as_secs(),
+                    e
+                ))
+            }
+            Err(e) => debug!("{} isn't ready yet: {}", dependent.name(), e),
+        }
+        thread::sleep(READY_POLL);
+    }
+}
+
+// How long to wait for the rest of a command's output once it has exited
+const OUTPUT_GRACE: Duration = Duration::from_secs(1);
+
+// How much output to keep in an error
+const TAIL_LINES: usize = 5;
+const TAIL_CHARS: usize = 500;
+
+// Read everything from a pipe on a thread of its own, so a full pipe can't stall the
+// command
+fn capture(pipe: Option<impl Read + Send + 'static>) -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    if let Some(mut pipe) = pipe {
+        thread::spawn(move || {
+            let mut output = Vec::new();
+            let _ = pipe.read_to_end(&mut output);
+            let _ = sender.send(String::from_utf8_lossy(&output).into_owned());
+        });
+    }
+    receiver
+}
+
+// The last few lines of some output, if there was any
+fn tail(output: &str) -> Option<String> {
+    let lines: Vec<&str> = output.trim_end().lines().collect();
+    let tail = lines[lines.len().saturating_sub(TAIL_LINES)..].join("\n");
+    if tail.is_empty() {
+        return None;
+    }
+    let skip = tail.chars().count().saturating_sub(TAIL_CHARS);
+    Some(tail.chars().skip(skip).collect())
+}
+
+// Wait for a command to finish, killing its whole process group once the timeout is up
+fn wait(mut child: Child, timeout: Duration) -> Result<ExitStatus, String> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Ok(status),
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(50)),
+            Ok(None) => break,
+            Err(e) => return Err(format!("Failed to wait for command: {}", e)),
+        }
+    }
+
+    // The group's id is the command's pid, since it leads the group
+    let pid = child.id() as libc::pid_t;
+    if unsafe { libc::killpg(pid, libc::SIGKILL) } != 0 {
+        error!(
+            "Unable to kill command: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    // Reap it, which can't block for long now that it has been killed
+    let _ = child.wait();
+    Err(format!(
+        "Command timed out after {}s and was killed",
+        timeout.as_secs()
+    ))
+}
+
+// The ids to run a command with, and the user's home directory
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+    pub home: String,
+}
+
+// Look up the user and group to run a command as
+pub fn credentials(run_as: &RunAs) -> Result<Credentials, String> {
+    let name = CString::new(run_as.user.as_str())
+        .map_err(|_| format!("Invalid user name: {}", run_as.user))?;
+    let mut buffer = vec![0; 16384];
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    let error = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut found,
+        )
+    };
+    if error != 0 {
+        return Err(format!(
+            "Unable to look up user {}: {}",
+            run_as.user,
+            io::Error::from_raw_os_error(error)
+        ));
+    }
+    if found.is_null() {
+        return Err(format!("No such user to run commands as: {}", run_as.user));
+    }
+    let home = unsafe { CStr::from_ptr(passwd.pw_dir) }
+        .to_string_lossy()
+        .into_owned();
+    let gid = match &run_as.group {
+        Some(group) => group_id(group)?,
+        None => passwd.pw_gid,
+    };
+    Ok(Credentials {
+        uid: passwd.pw_uid,
+        gid,
+        home,
+    })
+}
+
+fn group_id(group: &str) -> Result<u32, String> {
+    let name = CString::new(group).map_err(|_| format!("Invalid group name: {}", group))?;
+    let mut buffer = vec![0; 16384];
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    let error = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut found,
+        )
+    };
+    if error != 0 {
+        return Err(format!(
+            "Unable to look up group {}: {}",
+            group,
+            io::Error::from_raw_os_error(error)
+        ));
+    }
+    if found.is_null() {
+        return Err(format!("No such group to run commands as: {}", group));
+    }
+    Ok(entry.gr_gid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(value: &str) -> Vec<(&'static str, String)> {
+        vec![
+            ("NOFUS_MOUNT", value.to_string()),
+            ("NOFUS_STATE", "stale".to_string()),
+        ]
+    }
+
+    // What the shell makes of a command
+    fn shell_output(command: &str) -> String {
+        let output = Command::new("sh").arg("-c").arg(command).output().unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn placeholders_are_filled_in() {
+        let rendered = render_str(
+            "umount {{mount}} ({{ state }}) {{nope}}",
+            &env("/mnt/a"),
+            true,
+        );
+        assert_eq!(rendered, "umount /mnt/a (stale) {{nope}}");
+        assert_eq!(render_str("{{mount", &env("/mnt/a"), true), "{{mount");
+    }
+
+    #[test]
+    fn list_arguments_are_left_unquoted() {
+        let argv = CommandLine::Argv(vec!["umount".to_string(), "{{mount}}".to_string()]);
+        let CommandLine::Argv(argv) = render(&argv, &env("/mnt/my share")) else {
+            panic!("not a list");
+        };
+        assert_eq!(argv, ["umount", "/mnt/my share"]);
+    }
+
+    #[test]
+    fn shell_values_are_only_ever_text() {
+        let values = [
+            "/mnt/my share",
+            "/mnt/a; touch /tmp/nofus-injected",
+            "$(id) `id` $HOME",
+            "it's \"quoted\"",
+            "back\\slash\nnewline",
+            "",
+        ];
+        for value in values {
+            for template in [
+                "printf %s {{mount}}",
+                "printf %s \"{{mount}}\"",
+                "printf %s '{{mount}}'",
+                "printf %s x{{mount}}y",
+            ] {
+                let command = render(&CommandLine::Shell(template.to_string()), &env(value));
+                let CommandLine::Shell(command) = command else {
+                    panic!("not a shell command");
+                };
+                let output = shell_output(&command);
+                let expected = if template.contains("x{{") {
+                    format!("x{}y", value)
+                } else {
+                    value.to_string()
+                };
+                assert_eq!(output, expected, "{:?} from {:?}", command, template);
+            }
+        }
+    }
+
+    #[test]
+    fn commands_give_their_exit_code_and_output() {
+        let shell = |command: &str| CommandLine::Shell(command.to_string());
+        let timeout = Duration::from_secs(10);
+        let env = [("NOFUS_STATE", "mounted".to_string())];
+        let (result, code) = run_command_with_exit_code(
+            &shell("test \"$NOFUS_STATE\" = mounted"),
+            &env,
+            timeout,
+            None,
+        );
+        assert_eq!((result, code), (Ok(()), Some(0)));
+        let (result, code) =
+            run_command_with_exit_code(&shell("echo it broke >&2; exit 3"), &[], timeout, None);
+        assert_eq!(code, Some(3));
+        assert!(result.unwrap_err().ends_with(": it broke"));
+    }
+
+    #[test]
+    fn timed_out_commands_are_killed_with_what_they_started() {
+        let marker = std::env::temp_dir().join(format!("nofus-test-{}-late", std::process::id()));
+        let _ = std::fs::remove_file(&marker);
+        let command = CommandLine::Shell(format!(
+            "(sleep 2; touch '{}') & sleep 30",
+            marker.display()
+        ));
+        let start = Instant::now();
+        let result = run_command(&command, Duration::from_secs(1));
+        assert!(result.unwrap_err().contains("timed out after 1s"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        // The background job went with it, so it never got as far as the file
+        thread::sleep(Duration::from_secs(2));
+        assert!(!marker.exists());
+    }
+}
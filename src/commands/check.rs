@@ -0,0 +1,185 @@
+use super::{load_expanded, NETWORK_FS_TYPES};
+use crate::config::Config;
+use crate::error::{EXIT_CONFIG, EXIT_FAILED};
+use crate::monitor::MountState;
+use crate::mounts::{probe_all, MountTable, Target};
+use log::debug;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Evaluate every mount point once, printing a summary, and return the exit code
+pub fn check(config_path: &Path) -> i32 {
+    let config = match load_expanded(config_path) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_CONFIG;
+        }
+    };
+
+    let targets: Vec<Target> = config
+        .mount_points
+        .iter()
+        .map(|m| config.target(m))
+        .collect();
+    let probes = probe_all(&targets, config.probe_timeout(), config.probe_workers);
+    let mut mounted = 0;
+    for (target, probe) in targets.iter().zip(probes) {
+        if probe.state == MountState::Mounted {
+            mounted += 1;
+            println!("{:<12} {}", probe.state, target.path);
+        } else {
+            println!("{:<12} {} ({})", probe.state, target.path, probe.cause);
+        }
+    }
+    println!(
+        "{} of {} mount points mounted",
+        mounted,
+        config.mount_points.len()
+    );
+
+    if mounted == config.mount_points.len() {
+        0
+    } else {
+        EXIT_FAILED
+    }
+}
+
+// Check the mounts once, all at the same time and within the timeout, and print a
+// single line saying how they are, for a container runtime to show. Docker keeps exit
+// code 2 for itself, so anything wrong is 1. The config is only read when it was
+// given or is where it would be by default.
+pub fn healthcheck(
+    config_path: &Path,
+    config_given: bool,
+    paths: &[String],
+    timeout: Duration,
+) -> i32 {
+    let config = if !paths.is_empty() {
+        Config::for_mounts(paths, 1, None, None)
+    } else if config_given || config_path.exists() {
+        load_expanded(config_path)
+    } else {
+        // A container given its shares as volumes may well have no config
+        let paths: Vec<String> = MountTable::open()
+            .map(|table| {
+                table
+                    .mounts()
+                    .iter()
+                    .filter(|(_, fs_type)| NETWORK_FS_TYPES.contains(&fs_type.as_str()))
+                    .map(|(path, _)| path.display().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        if paths.is_empty() {
+            println!("unhealthy: no network mounts found");
+            return 1;
+        }
+        Config::for_mounts(&paths, 1, None, None)
+    };
+    let config = match config {
+        Ok(c) => c,
+        Err(e) => {
+            println!("unhealthy: {}", e);
+            return 1;
+        }
+    };
+
+    // Leave some of the time for the server checks a mount may have before its probe
+    let probe_timeout = config.probe_timeout().min(timeout * 4 / 5);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        let targets: Vec<Target> = config
+            .mount_points
+            .iter()
+            .map(|m| config.target(m))
+            .collect();
+        let probes = probe_all(&targets, probe_timeout, targets.len());
+        let results: Vec<(String, MountState, String)> = targets
+            .iter()
+            .zip(probes)
+            .map(|(target, probe)| (target.path.to_string(), probe.state, probe.cause))
+            .collect();
+        let _ = sender.send(results);
+    });
+    let results = match receiver.recv_timeout(timeout) {
+        Ok(results) => results,
+        Err(_) => {
+            println!(
+                "unhealthy: no answer within {}",
+                humantime::format_duration(timeout)
+            );
+            return 1;
+        }
+    };
+
+    let down: Vec<String> = results
+        .iter()
+        .filter(|(_, state, _)| *state != MountState::Mounted)
+        .map(|(path, state, cause)| format!("{} {} ({})", path, state, cause))
+        .collect();
+    if down.is_empty() {
+        println!("healthy: {} of {} mounted", results.len(), results.len());
+        0
+    } else {
+        println!(
+            "unhealthy: {} of {} mounted; {}",
+            results.len() - down.len(),
+            results.len(),
+            down.join(", ")
+        );
+        1
+    }
+}
+
+// Block until the mount points are all mounted, returning the exit code
+pub fn wait(config_path: &Path, paths: &[String], timeout: Option<Duration>) -> i32 {
+    // The config is only needed for the mount points if none were given
+    let config = match load_expanded(config_path) {
+        Ok(c) => Some(c),
+        Err(e) if paths.is_empty() => {
+            eprintln!("{}", e);
+            return EXIT_CONFIG;
+        }
+        Err(_) => None,
+    };
+    // Mount points named on the command line still get their configured fs_types
+    let targets: Vec<Target> = match &config {
+        Some(c) if paths.is_empty() => c.mount_points.iter().map(|m| c.target(m)).collect(),
+        Some(c) => paths
+            .iter()
+            .map(
+                |path| match c.mount_points.iter().find(|m| &m.path == path) {
+                    Some(mount) => c.target(mount),
+                    None => Target::new(path, &c.fs_types),
+                },
+            )
+            .collect(),
+        None => paths.iter().map(|path| Target::new(path, &[])).collect(),
+    };
+    let (probe_timeout, workers) = config.as_ref().map_or((Duration::from_secs(10), 8), |c| {
+        (c.probe_timeout(), c.probe_workers)
+    });
+
+    let deadline = timeout.map(|t| Instant::now() + t);
+    loop {
+        let probes = probe_all(&targets, probe_timeout, workers);
+        let missing: Vec<(&Target, _)> = targets
+            .iter()
+            .zip(probes)
+            .filter(|(_, p)| p.state != MountState::Mounted)
+            .collect();
+        if missing.is_empty() {
+            return 0;
+        }
+        if deadline.is_some_and(|d| Instant::now() >= d) {
+            for (target, probe) in missing {
+                eprintln!("{:<12} {} ({})", probe.state, target.path, probe.cause);
+            }
+            return EXIT_FAILED;
+        }
+        debug!("Waiting for {} mount point(s)", missing.len());
+        thread::sleep(Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,95 @@
+use super::{socket_path, Options};
+use crate::config::Config;
+use crate::control::{self, Request, Response};
+use crate::error::EXIT_RUNTIME;
+use crate::top;
+use std::path::Path;
+use std::time::Duration;
+
+// Ask the running daemon for the status of each mount point and print it
+pub fn status(options: &Options, config_path: &Path, json: bool) -> i32 {
+    // The config is only needed to find the socket, so it is fine if it's missing
+    let config = Config::load(config_path).ok();
+    let mounts = match control::request(&socket_path(options, config.as_ref()), &Request::Status) {
+        Ok(Response::Status { mounts }) => mounts,
+        Ok(Response::Ok { message }) | Ok(Response::Error { message }) => {
+            eprintln!("{}", message);
+            return EXIT_RUNTIME;
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_RUNTIME;
+        }
+    };
+
+    if json {
+        match serde_json::to_string_pretty(&mounts) {
+            Ok(output) => println!("{}", output),
+            Err(e) => {
+                eprintln!("{}", e);
+                return EXIT_RUNTIME;
+            }
+        }
+        return 0;
+    }
+
+    println!(
+        "{:<12} {:<21} {:<18} PATH",
+        "STATE", "SINCE", "LAST COMMAND"
+    );
+    for mount in &mounts {
+        let mut last_command = match &mount.last_command {
+            Some(c) if c.success => "ok",
+            Some(_) => "failed",
+            None => "-",
+        }
+        .to_string();
+        if mount.queued > 0 {
+            last_command = format!("{}, {} queued", last_command, mount.queued);
+        }
+        println!(
+            "{:<12} {:<21} {:<18} {}{}",
+            mount.state,
+            mount.since.as_deref().unwrap_or("-"),
+            last_command,
+            mount.path,
+            if mount.silenced { " (silenced)" } else { "" }
+        );
+    }
+    0
+}
+
+// Send a request that changes what the running daemon does, printing its answer
+pub fn control(options: &Options, config_path: &Path, request: &Request) -> i32 {
+    let config = Config::load(config_path).ok();
+    match control::request(&socket_path(options, config.as_ref()), request) {
+        Ok(Response::Ok { message }) => {
+            println!("{}", message);
+            0
+        }
+        Ok(Response::Error { message }) => {
+            eprintln!("{}", message);
+            EXIT_RUNTIME
+        }
+        Ok(Response::Status { .. }) => {
+            eprintln!("Unexpected response from the daemon");
+            EXIT_RUNTIME
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            EXIT_RUNTIME
+        }
+    }
+}
+
+// Show the running daemon's view of the mounts until q is pressed
+pub fn top(options: &Options, config_path: &Path, interval: Duration) -> i32 {
+    let config = Config::load(config_path).ok();
+    match top::run(&socket_path(options, config.as_ref()), interval) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            EXIT_RUNTIME
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use super::load_expanded;
+use clap::builder::PossibleValuesParser;
+use clap_complete::Shell;
+use std::io;
+use std::path::Path;
+
+// The arguments of each subcommand that take the mount points in the config
+const MOUNT_ARGS: [(&str, &[&str]); 6] = [
+    ("healthcheck", &["paths"]),
+    ("wait", &["paths"]),
+    ("silence", &["mount"]),
+    ("unsilence", &["mount"]),
+    ("history", &["mount"]),
+    ("simulate", &["down", "up", "stale"]),
+];
+
+// Print a completion script for the shell. The mount points are those in the config
+// when it is written, so it has to be written again for them to follow changes.
+pub fn completions(mut command: clap::Command, config_path: &Path, shell: Shell) {
+    let mount_points: Vec<String> = load_expanded(config_path)
+        .map(|c| c.mount_points.into_iter().map(|m| m.path).collect())
+        .unwrap_or_default();
+    if !mount_points.is_empty() {
+        for (subcommand, args) in MOUNT_ARGS {
+            command = command.mut_subcommand(subcommand, |subcommand| {
+                args.iter().fold(subcommand, |subcommand, arg| {
+                    subcommand.mut_arg(arg, |arg| {
+                        arg.value_parser(PossibleValuesParser::new(mount_points.clone()))
+                    })
+                })
+            });
+        }
+    }
+    clap_complete::generate(shell, &mut command, "nofus", &mut io::stdout());
+}
@@ -0,0 +1,134 @@
+use crate::config::{self, Config};
+use crate::error::EXIT_RUNTIME;
+use crate::history::{Availability, EntryKind, History};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+// Open the history the daemon records, to read it
+fn open_history(config_path: &Path) -> Result<History, String> {
+    // Without a config, look in the default place
+    let path = match Config::load(config_path).ok().and_then(|c| c.history) {
+        Some(history) => history.path(),
+        None => config::default_history_file(),
+    };
+    if !path.exists() {
+        return Err(format!(
+            "No history in {}, add a history section to the config to record one",
+            path.display()
+        ));
+    }
+    History::open_read_only(&path)
+        .map_err(|e| format!("Unable to read the history in {}: {}", path.display(), e))
+}
+
+// Print the history the daemon has recorded
+pub fn history(config_path: &Path, mount: Option<&str>, since: Option<Duration>) -> i32 {
+    let since = since.and_then(|since| SystemTime::now().checked_sub(since));
+    let entries = match open_history(config_path).and_then(|h| h.entries(mount, since)) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_RUNTIME;
+        }
+    };
+
+    for entry in entries {
+        let what = match entry.kind {
+            EntryKind::Transition { from, to, cause } if cause.is_empty() => {
+                format!("{} -> {}", from, to)
+            }
+            EntryKind::Transition { from, to, cause } => format!("{} -> {} ({})", from, to, cause),
+            EntryKind::Probe { state, cause } => format!("looked {} ({})", state, cause),
+            EntryKind::Command {
+                command,
+                exit_code,
+                duration,
+                error,
+            } => {
+                let status = match (exit_code, error) {
+                    (Some(0), _) => "ok".to_string(),
+                    (Some(code), _) => format!("exit {}", code),
+                    (None, Some(error)) => error,
+                    (None, None) => "killed".to_string(),
+                };
+                format!(
+                    "ran {} [{} in {:.1}s]",
+                    command,
+                    status,
+                    duration.as_secs_f64()
+                )
+            }
+        };
+        println!(
+            "{:<21} {:<24} {}",
+            humantime::format_rfc3339_seconds(entry.at).to_string(),
+            entry.mount,
+            what
+        );
+    }
+    0
+}
+
+// Print each mount's availability over the period up to now
+pub fn report(config_path: &Path, period: Duration, json: bool, csv: bool) -> i32 {
+    let until = SystemTime::now();
+    let since = until.checked_sub(period).unwrap_or(SystemTime::UNIX_EPOCH);
+    let mounts = match open_history(config_path).and_then(|h| h.availability(since, until)) {
+        Ok(mounts) => mounts,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_RUNTIME;
+        }
+    };
+
+    if json {
+        match serde_json::to_string_pretty(&mounts) {
+            Ok(output) => println!("{}", output),
+            Err(e) => {
+                eprintln!("{}", e);
+                return EXIT_RUNTIME;
+            }
+        }
+        return 0;
+    }
+    // Nothing when the mount's state was never known in the period
+    let percent = |mount: &Availability| {
+        mount
+            .availability_percent
+            .map(|percent| format!("{:.3}", percent))
+    };
+    if csv {
+        println!("mount,availability_percent,outages,downtime_seconds,longest_outage_seconds,observed_seconds");
+        for mount in &mounts {
+            // Quoted in case a path has a comma in it
+            println!(
+                "\"{}\",{},{},{},{},{}",
+                mount.mount.replace('"', "\"\""),
+                percent(mount).unwrap_or_default(),
+                mount.outages,
+                mount.downtime_seconds,
+                mount.longest_outage_seconds,
+                mount.observed_seconds
+            );
+        }
+        return 0;
+    }
+
+    let duration = |seconds| humantime::format_duration(Duration::from_secs(seconds)).to_string();
+    println!(
+        "{:<10} {:<8} {:<18} {:<18} MOUNT",
+        "AVAILABLE", "OUTAGES", "DOWNTIME", "LONGEST"
+    );
+    for mount in &mounts {
+        let available = percent(mount).map_or("-".to_string(), |p| format!("{}%", p));
+        println!(
+            "{:<10} {:<8} {:<18} {:<18} {}",
+            available,
+            mount.outages,
+            duration(mount.downtime_seconds),
+            duration(mount.longest_outage_seconds),
+            mount.mount
+        );
+    }
+    0
+}
@@ -0,0 +1,135 @@
+use super::{prompt, NETWORK_FS_TYPES};
+use crate::config::{self, Config, ConfigFormat};
+use crate::mounts::{fstab_entries, MountTable};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+// Ask which mounts to monitor and what to run for them, then write a config file from
+// the answers, and return the exit code
+pub fn init(config_path: &Path) -> i32 {
+    if config_path.exists()
+        && !prompt(
+            &format!("{} already exists, replace it?", config_path.display()),
+            "n",
+        )
+        .eq_ignore_ascii_case("y")
+    {
+        println!("Leaving {} as it is", config_path.display());
+        return 1;
+    }
+
+    let candidates = network_mounts();
+    let mount_points: Vec<String> = if candidates.is_empty() {
+        println!(
+            "No NFS, CIFS or sshfs mounts found in the mount table or {}",
+            config::FSTAB
+        );
+        prompt("Mount points to monitor, separated by spaces", "")
+            .split_whitespace()
+            .map(str::to_string)
+            .collect()
+    } else {
+        println!("Found these network mounts:");
+        for (i, (path, description)) in candidates.iter().enumerate() {
+            println!("  {}) {} ({})", i + 1, path, description);
+        }
+        loop {
+            let answer = prompt("Which to monitor, by number", "all");
+            match pick(&answer, &candidates) {
+                Ok(picked) => break picked,
+                Err(e) => println!("{}", e),
+            }
+        }
+    };
+    if mount_points.is_empty() {
+        eprintln!("Nothing to monitor, not writing a config");
+        return 1;
+    }
+    let all_mounted = prompt("Command to run once all are mounted", "echo \"All clear!\"");
+    let any_unmounted = prompt("Command to run when any is unmounted", "echo \"Very bad!\"");
+    let delay_seconds = loop {
+        match prompt("Seconds between checks", "5").parse::<u64>() {
+            Ok(seconds) if seconds > 0 => break seconds,
+            _ => println!("Give a whole number of seconds, at least 1"),
+        }
+    };
+
+    // Strings are written JSON-quoted, which both YAML and TOML read as they are
+    let quote = |s: &str| serde_json::Value::from(s).to_string();
+    let paths: Vec<String> = mount_points.iter().map(|p| quote(p)).collect();
+    let format = ConfigFormat::from_path(config_path);
+    let content = match format {
+        ConfigFormat::Yaml => format!(
+            "mount_points:\n{}delay_seconds: {}\nall_mounted_cmd: {}\nany_unmounted_cmd: {}\n",
+            paths.iter().map(|p| format!("  - {}\n", p)).collect::<String>(),
+            delay_seconds,
+            quote(&all_mounted),
+            quote(&any_unmounted)
+        ),
+        ConfigFormat::Toml => format!(
+            "mount_points = [{}]\ndelay_seconds = {}\nall_mounted_cmd = {}\nany_unmounted_cmd = {}\n",
+            paths.join(", "),
+            delay_seconds,
+            quote(&all_mounted),
+            quote(&any_unmounted)
+        ),
+    };
+
+    // Check it the way the daemon will before writing it out
+    let checked = Config::parse(&content, format).and_then(|mut config| {
+        config.split_patterns()?;
+        config.validate()
+    });
+    if let Err(e) = checked {
+        eprintln!("Those answers don't make a valid config: {}", e);
+        return 1;
+    }
+    let written = config_path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(config_path, content));
+    if let Err(e) = written {
+        eprintln!("Unable to write {}: {}", config_path.display(), e);
+        return 1;
+    }
+    println!("Wrote {}", config_path.display());
+    0
+}
+
+// The NFS, CIFS and sshfs mount points in the mount table and fstab, each with its type and
+// where it was found
+fn network_mounts() -> Vec<(String, String)> {
+    let mut mounts: BTreeMap<String, String> = BTreeMap::new();
+    for (path, fs_type) in fstab_entries(Path::new(config::FSTAB)).unwrap_or_default() {
+        if NETWORK_FS_TYPES.contains(&fs_type.as_str()) {
+            mounts.insert(path, format!("{}, in {}", fs_type, config::FSTAB));
+        }
+    }
+    if let Ok(table) = MountTable::open() {
+        for (path, fs_type) in table.mounts() {
+            if NETWORK_FS_TYPES.contains(&fs_type.as_str()) {
+                mounts.insert(path.display().to_string(), format!("{}, mounted", fs_type));
+            }
+        }
+    }
+    mounts.into_iter().collect()
+}
+
+// The mount points chosen by number, separated by spaces or commas, or all of them
+fn pick(answer: &str, candidates: &[(String, String)]) -> Result<Vec<String>, String> {
+    if answer.eq_ignore_ascii_case("all") {
+        return Ok(candidates.iter().map(|(path, _)| path.clone()).collect());
+    }
+    answer
+        .split([' ', ','])
+        .filter(|n| !n.is_empty())
+        .map(|n| match n.parse::<usize>() {
+            Ok(i) if (1..=candidates.len()).contains(&i) => Ok(candidates[i - 1].0.clone()),
+            _ => Err(format!(
+                "Pick numbers from 1 to {}, or all",
+                candidates.len()
+            )),
+        })
+        .collect()
+}
@@ -0,0 +1,281 @@
+use super::prompt;
+use crate::config::Config;
+use crate::error::EXIT_CONFIG;
+use std::path::{Path, PathBuf};
+use std::{fs, process};
+
+// Where systemd looks for units installed by the administrator, or by a user
+#[cfg(not(target_os = "macos"))]
+const SYSTEM_UNIT_DIR: &str = "/etc/systemd/system";
+#[cfg(not(target_os = "macos"))]
+const USER_UNIT_DIR: &str = ".config/systemd/user";
+#[cfg(not(target_os = "macos"))]
+const UNIT_NAME: &str = "nofus.service";
+
+// And where launchd looks for daemons, or a user's agents
+#[cfg(target_os = "macos")]
+const SYSTEM_UNIT_DIR: &str = "/Library/LaunchDaemons";
+#[cfg(target_os = "macos")]
+const USER_UNIT_DIR: &str = "Library/LaunchAgents";
+#[cfg(target_os = "macos")]
+const UNIT_NAME: &str = "io.github.kariudo.nofus.plist";
+
+// Write a nofus.service for this binary and config, or a launchd job on macOS,
+// optionally enabling and starting it, and return the exit code
+pub fn install(config_path: &Path, user: bool, enable: bool, output: Option<&Path>) -> i32 {
+    let binary = match std::env::current_exe().and_then(fs::canonicalize) {
+        Ok(binary) => binary,
+        Err(e) => {
+            eprintln!("Unable to tell where the nofus binary is: {}", e);
+            return 1;
+        }
+    };
+    let config = match fs::canonicalize(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!(
+                "Unable to find the config file at {}, write one with nofus init first: {}",
+                config_path.display(),
+                e
+            );
+            return 1;
+        }
+    };
+    // The watchdog is pinged on every check, so it has to allow for the longest gap
+    let delay_seconds = Config::load(&config)
+        .map(|c| c.shortest_interval().as_secs())
+        .unwrap_or(0);
+    let unit = if cfg!(target_os = "macos") {
+        launchd_job(&binary, &config, user)
+    } else {
+        unit_file(&binary, &config, user, (delay_seconds * 2).max(30))
+    };
+
+    let dir = match (output, user) {
+        (Some(dir), _) => dir.to_path_buf(),
+        (None, false) => PathBuf::from(SYSTEM_UNIT_DIR),
+        (None, true) => match std::env::var("XDG_CONFIG_HOME") {
+            Ok(config_home) if !config_home.is_empty() && !cfg!(target_os = "macos") => {
+                PathBuf::from(config_home).join("systemd/user")
+            }
+            _ => match std::env::var("HOME") {
+                Ok(home) => PathBuf::from(home).join(USER_UNIT_DIR),
+                Err(_) => {
+                    eprintln!("HOME isn't set, so there is no user unit directory");
+                    return 1;
+                }
+            },
+        },
+    };
+    let path = dir.join(UNIT_NAME);
+    if path.exists()
+        && fs::read_to_string(&path).is_ok_and(|existing| existing != unit)
+        && !prompt(
+            &format!("{} already exists, replace it?", path.display()),
+            "n",
+        )
+        .eq_ignore_ascii_case("y")
+    {
+        println!("Leaving {} as it is", path.display());
+        return 1;
+    }
+    if let Err(e) = fs::create_dir_all(&dir).and_then(|_| fs::write(&path, unit)) {
+        eprintln!("Unable to write {}: {}", path.display(), e);
+        return 1;
+    }
+    println!("Wrote {}", path.display());
+
+    let commands = enable_commands(user, &path);
+    if !enable || output.is_some() {
+        let commands: Vec<String> = commands.iter().map(|args| args.join(" ")).collect();
+        println!("Start it with: {}", commands.join(" && "));
+        return 0;
+    }
+    for args in commands {
+        let status = process::Command::new(&args[0]).args(&args[1..]).status();
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => {
+                eprintln!("{} failed with {}", args.join(" "), status);
+                return 1;
+            }
+            Err(e) => {
+                eprintln!("Unable to run {}: {}", args[0], e);
+                return 1;
+            }
+        }
+    }
+    println!("Enabled and started {}", UNIT_NAME);
+    0
+}
+
+// A unit running the daemon under systemd's watchdog, locked down only as far as
+// nofus can still mount, run commands as other users and reach the shares
+fn unit_file(binary: &Path, config: &Path, user: bool, watchdog_seconds: u64) -> String {
+    let exec = format!("{} --config {}", unit_quote(binary), unit_quote(config));
+    let mut lines = vec![
+        "[Unit]".to_string(),
+        "Description=Nofus mount guardian daemon".to_string(),
+        "Documentation=https://github.com/kariudo/nofus".to_string(),
+    ];
+    // A user's systemd has no network targets of its own to wait for
+    if !user {
+        lines.push("After=network-online.target remote-fs.target".to_string());
+        lines.push("Wants=network-online.target".to_string());
+    }
+    lines.extend([
+        String::new(),
+        "[Service]".to_string(),
+        "Type=notify".to_string(),
+        format!("ExecStart={}", exec),
+        "ExecReload=/bin/kill -HUP $MAINPID".to_string(),
+        format!("WatchdogSec={}", watchdog_seconds),
+        "Restart=on-failure".to_string(),
+        // Restarting won't fix a broken config
+        format!("RestartPreventExitStatus={}", EXIT_CONFIG),
+        "RestartSec=5".to_string(),
+    ]);
+    // A user's systemd only applies these along with NoNewPrivileges, which would break
+    // commands that use sudo
+    if !user {
+        lines.extend(
+            [
+                "# No ProtectSystem, ProtectHome, PrivateTmp or the like: each gives the",
+                "# service a mount namespace of its own, where remounts would go unseen",
+                "LockPersonality=yes",
+                "RestrictRealtime=yes",
+                "RestrictSUIDSGID=yes",
+                "ProtectHostname=yes",
+                "SystemCallArchitectures=native",
+            ]
+            .map(str::to_string),
+        );
+    }
+    lines.extend([
+        String::new(),
+        "[Install]".to_string(),
+        format!(
+            "WantedBy={}",
+            if user {
+                "default.target"
+            } else {
+                "multi-user.target"
+            }
+        ),
+    ]);
+    lines.join("\n") + "\n"
+}
+
+// What enables and starts the service once it is written: systemctl, or launchctl
+// on macOS
+fn enable_commands(user: bool, path: &Path) -> Vec<Vec<String>> {
+    let command = |args: &[&str]| args.iter().map(|a| a.to_string()).collect();
+    if cfg!(target_os = "macos") {
+        let domain = if user {
+            format!("gui/{}", unsafe { libc::geteuid() })
+        } else {
+            "system".to_string()
+        };
+        let path = path.to_string_lossy();
+        return vec![command(&["launchctl", "bootstrap", &domain, &path])];
+    }
+    let systemctl: &[&str] = if user {
+        &["systemctl", "--user"]
+    } else {
+        &["systemctl"]
+    };
+    vec![
+        command(&[systemctl, &["daemon-reload"]].concat()),
+        command(&[systemctl, &["enable", "--now", UNIT_NAME]].concat()),
+    ]
+}
+
+// A launchd job running the daemon in the foreground, as launchd expects, from boot
+// or login, starting it again should it fail. launchd has no watchdog, and what
+// nofus logs goes to /var/log/nofus.log, or ~/Library/Logs/nofus.log for a user.
+fn launchd_job(binary: &Path, config: &Path, user: bool) -> String {
+    let log = match std::env::var_os("HOME") {
+        Some(home) if user => Path::new(&home).join("Library/Logs/nofus.log"),
+        _ => PathBuf::from("/var/log/nofus.log"),
+    };
+    let string =
+        |value: &Path| format!("<string>{}</string>", xml_escape(&value.to_string_lossy()));
+    [
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+        r#"<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">"#,
+        r#"<plist version="1.0">"#,
+        "<dict>",
+        "  <key>Label</key>",
+        &format!("  <string>{}</string>", UNIT_NAME.trim_end_matches(".plist")),
+        "  <key>ProgramArguments</key>",
+        "  <array>",
+        &format!("    {}", string(binary)),
+        "    <string>--config</string>",
+        &format!("    {}", string(config)),
+        "  </array>",
+        "  <key>RunAtLoad</key>",
+        "  <true/>",
+        "  <key>KeepAlive</key>",
+        "  <dict>",
+        "    <key>SuccessfulExit</key>",
+        "    <false/>",
+        "  </dict>",
+        "  <key>ThrottleInterval</key>",
+        "  <integer>5</integer>",
+        "  <key>StandardOutPath</key>",
+        &format!("  {}", string(&log)),
+        "  <key>StandardErrorPath</key>",
+        &format!("  {}", string(&log)),
+        "</dict>",
+        "</plist>",
+    ]
+    .join("\n")
+        + "\n"
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// A path as one word of an ExecStart line, which splits on spaces and expands %
+fn unit_quote(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('%', "%%");
+    if path.contains(char::is_whitespace) || path.contains('"') {
+        format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_stay_one_word_of_exec_start() {
+        assert_eq!(unit_quote(Path::new("/usr/bin/nofus")), "/usr/bin/nofus");
+        assert_eq!(unit_quote(Path::new("/etc/100%.yml")), "/etc/100%%.yml");
+        assert_eq!(
+            unit_quote(Path::new("/srv/my \"dir\"/c.yml")),
+            r#""/srv/my \"dir\"/c.yml""#
+        );
+    }
+
+    #[test]
+    fn user_units_go_without_lockdown_or_network_targets() {
+        let binary = Path::new("/usr/bin/nofus");
+        let config = Path::new("/etc/nofus/config.yml");
+        let system = unit_file(binary, config, false, 30);
+        assert!(system.contains("ExecStart=/usr/bin/nofus --config /etc/nofus/config.yml\n"));
+        assert!(system.contains("WatchdogSec=30\n"));
+        assert!(system.contains("After=network-online.target"));
+        assert!(system.contains("RestrictSUIDSGID=yes"));
+        assert!(system.contains("WantedBy=multi-user.target"));
+        let user = unit_file(binary, config, true, 30);
+        assert!(!user.contains("network-online") && !user.contains("RestrictSUIDSGID"));
+        assert!(user.contains("WantedBy=default.target"));
+    }
+}
@@ -0,0 +1,175 @@
+use crate::config;
+use crate::control;
+use crate::error::{EXIT_CONFIG, EXIT_FAILED, EXIT_RUNTIME, EXIT_USAGE};
+use crate::pidfile::DEFAULT_PID_FILE;
+use clap_mangen::roff::{bold, italic, roman, Roff};
+use clap_mangen::Man;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+// Print the man page, or write it to the directory along with a page for each
+// subcommand, which it refers to
+pub fn man(command: clap::Command, output: Option<&Path>) -> i32 {
+    let mut command = command.disable_help_subcommand(true);
+    command.build();
+    let Some(dir) = output else {
+        return match render_man(&command, &mut io::stdout()) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("{}", e);
+                EXIT_RUNTIME
+            }
+        };
+    };
+    let path = dir.join("nofus.1");
+    let written = fs::create_dir_all(dir)
+        .and_then(|_| fs::File::create(&path))
+        .and_then(|mut file| render_man(&command, &mut file))
+        .and_then(|_| {
+            command.get_subcommands().try_for_each(|subcommand| {
+                Man::new(subcommand.clone())
+                    .source(concat!("nofus ", env!("CARGO_PKG_VERSION")))
+                    .generate_to(dir)
+                    .map(drop)
+            })
+        });
+    if let Err(e) = written {
+        eprintln!("Unable to write the man pages to {}: {}", dir.display(), e);
+        return EXIT_RUNTIME;
+    }
+    println!("Wrote {} and a page for each subcommand", path.display());
+    0
+}
+
+// The page clap writes from the help, with the config file, signals, exit codes and
+// files after the subcommands
+fn render_man(command: &clap::Command, out: &mut dyn Write) -> io::Result<()> {
+    let mut page = Vec::new();
+    Man::new(command.clone()).render(&mut page)?;
+    let page = String::from_utf8_lossy(&page);
+
+    let mut roff = Roff::new();
+    roff.control("SH", ["CONFIGURATION"]);
+    roff.text([
+        roman(
+            "The config file is YAML, or TOML if its name ends in .toml. These are the \
+             keys it takes, which ",
+        ),
+        bold("nofus schema"),
+        roman(" prints in full as a JSON Schema."),
+    ]);
+    let schema = config::schema();
+    let required: Vec<&str> = schema["required"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|key| key.as_str())
+        .collect();
+    for (key, value) in schema["properties"].as_object().into_iter().flatten() {
+        let mut kind = schema_kind(value);
+        if required.contains(&key.as_str()) {
+            kind += ", required";
+        }
+        roff.control("TP", []);
+        roff.text([bold(key)]);
+        roff.text([roman(kind)]);
+    }
+
+    roff.control("SH", ["SIGNALS"]);
+    let signals = [
+        (
+            "SIGHUP",
+            "Reload the config file, keeping the current configuration if the new one is bad.",
+        ),
+        (
+            "SIGTERM, SIGINT",
+            "Let the queued commands finish, run on_stop and exit.",
+        ),
+        ("SIGUSR1", "Log everything the daemon is keeping track of."),
+        ("SIGUSR2", "Check every mount point now."),
+    ];
+    for (signal, effect) in signals {
+        roff.control("TP", []);
+        roff.text([bold(signal)]);
+        roff.text([roman(effect)]);
+    }
+
+    roff.control("SH", ["EXIT STATUS"]);
+    let codes = [
+        (0, "Success, or the daemon was asked to stop."),
+        (
+            EXIT_FAILED,
+            "A one-shot check found something wrong, such as a mount missing.",
+        ),
+        (EXIT_USAGE, "The command line was wrong."),
+        (
+            EXIT_RUNTIME,
+            "Something nofus relies on failed: another daemon holds the pid file, the \
+             daemon can't be reached, or a hook failed with --fail-fast.",
+        ),
+        (
+            EXIT_CONFIG,
+            "The config file is missing or wrong, which restarting won't fix.",
+        ),
+    ];
+    for (code, meaning) in codes {
+        roff.control("TP", []);
+        roff.text([bold(code.to_string())]);
+        roff.text([roman(meaning)]);
+    }
+
+    roff.control("SH", ["FILES"]);
+    let files = [
+        (
+            "/etc/nofus/config.yml",
+            "The config file, or config.toml beside it.",
+        ),
+        (
+            DEFAULT_PID_FILE,
+            "The pid file, locked while the daemon runs.",
+        ),
+        (
+            control::DEFAULT_SOCKET,
+            "The control socket the subcommands reach the daemon on.",
+        ),
+    ];
+    for (file, what) in files {
+        roff.control("TP", []);
+        roff.text([italic(file)]);
+        roff.text([roman(what)]);
+    }
+    roff.control("PP", []);
+    roff.text([roman(
+        "Run by another user than root, nofus keeps them in $XDG_CONFIG_HOME/nofus \
+         and $XDG_RUNTIME_DIR instead.",
+    )]);
+    let at = page.find(".SH VERSION").unwrap_or(page.len());
+    write!(out, "{}{}{}", &page[..at], roff.to_roff(), &page[at..])
+}
+
+// What a key of the config takes, in a few words, from its JSON Schema
+fn schema_kind(schema: &serde_json::Value) -> String {
+    if let Some(alternatives) = schema["anyOf"].as_array() {
+        let mut kinds: Vec<String> = Vec::new();
+        for kind in alternatives.iter().map(schema_kind) {
+            for kind in kind.split(" or ") {
+                if !kind.is_empty() && !kinds.iter().any(|k| k == kind) {
+                    kinds.push(kind.to_string());
+                }
+            }
+        }
+        return kinds.join(" or ");
+    }
+    if let Some(values) = schema["enum"].as_array() {
+        let values: Vec<&str> = values.iter().filter_map(|v| v.as_str()).collect();
+        return format!("one of {}", values.join(", "));
+    }
+    match schema["type"].as_str() {
+        Some("array") => "list".to_string(),
+        Some("object") if schema["properties"].is_object() => "section".to_string(),
+        Some("object") => "map".to_string(),
+        Some("null") | None => String::new(),
+        Some(kind) => kind.to_string(),
+    }
+}
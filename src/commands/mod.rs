@@ -0,0 +1,116 @@
+// The subcommands of the nofus binary, which only reads the command line and hands
+// it on to one of these. Most print what they find and return the exit code.
+mod check;
+mod client;
+mod completions;
+mod history;
+mod init;
+mod install;
+mod man;
+mod run;
+mod simulate;
+mod validate;
+
+use crate::config::Config;
+use crate::control as socket;
+use crate::error::Error;
+use crate::mounts::MountTable;
+use crate::pidfile::{self, LockError, PidFile};
+use log::warn;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+pub use check::{check, healthcheck, wait};
+pub use client::{control, status, top};
+pub use completions::completions;
+pub use history::{history, report};
+pub use init::init;
+pub use install::install;
+pub use man::man;
+pub use run::{run, sidecar, watch};
+pub use simulate::simulate;
+pub use validate::validate;
+
+// The options given on the command line for every subcommand that runs the daemon or
+// talks to it
+#[derive(Default)]
+pub struct Options {
+    pub dry_run: bool,
+    pub fail_fast: bool,
+    pub reset_state: bool,
+    pub socket: Option<String>,
+    pub pid_file: Option<String>,
+}
+
+// Filesystem types init offers to monitor, and healthcheck looks for without a config
+const NETWORK_FS_TYPES: [&str; 9] = [
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "smbfs",
+    "afpfs",
+    "fuse.sshfs",
+    "fusefs.sshfs",
+    "macfuse",
+];
+
+// Ask a question on the terminal, returning the answer or the default if there is none
+fn prompt(question: &str, default: &str) -> String {
+    print!("{} [{}]: ", question, default);
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).unwrap_or(0) == 0 {
+        // Nothing more to read, so take the default on the next line
+        println!();
+    }
+    match answer.trim() {
+        "" => default.to_string(),
+        answer => answer.to_string(),
+    }
+}
+
+// Load the config along with the mounts its patterns currently match
+fn load_expanded(config_path: &Path) -> Result<Config, String> {
+    let mut config = Config::load(config_path)?;
+    if !config.patterns.is_empty() || !config.discover_fs_types.is_empty() {
+        let table =
+            MountTable::open().map_err(|e| format!("Unable to read the mount table: {}", e))?;
+        config.expand(table.mounts());
+    }
+    Ok(config)
+}
+
+// Take the pid file, failing if another daemon holds it. Failing to write it at all
+// only means going without, as with the control socket.
+fn lock_pid_file(options: &Options, config: Option<&Config>) -> Result<Option<PidFile>, Error> {
+    let configured = config.and_then(|c| c.pid_file.as_deref());
+    let path = pidfile::pid_file_path(options.pid_file.as_deref().or(configured));
+    match PidFile::lock(&path) {
+        Ok(pid_file) => Ok(Some(pid_file)),
+        Err(LockError::Running(pid)) => {
+            let pid = pid
+                .map(|pid| format!(" as pid {}", pid))
+                .unwrap_or_default();
+            Err(Error::Runtime(format!(
+                "nofus is already running{}, holding {}",
+                pid,
+                path.display()
+            )))
+        }
+        Err(LockError::Io(e)) => {
+            warn!(
+                "Unable to write {}, not guarding against a second daemon: {}",
+                path.display(),
+                e
+            );
+            Ok(None)
+        }
+    }
+}
+
+// The control socket path from the command line, then the config file, then the default
+fn socket_path(options: &Options, config: Option<&Config>) -> PathBuf {
+    let configured = config.and_then(|c| c.control_socket.as_deref());
+    socket::socket_path(options.socket.as_deref().or(configured))
+}
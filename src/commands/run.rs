@@ -0,0 +1,114 @@
+use super::{lock_pid_file, socket_path, Options};
+use crate::config::{Config, WebConfig};
+use crate::daemon;
+use crate::error::Error;
+use log::{info, warn};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Run the monitoring daemon
+pub fn run(options: &Options, config_path: PathBuf) -> Result<(), Error> {
+    if !config_path.exists() {
+        return Err(Error::Config(format!(
+            "No config file at {}, create one with `nofus init`",
+            config_path.display()
+        )));
+    }
+    let config = Config::load(&config_path)
+        .map_err(|e| Error::Config(format!("Failed to load configuration: {}", e)))?;
+
+    let _pid_file = lock_pid_file(options, Some(&config))?;
+    if options.reset_state {
+        let state_file = config.state_file();
+        match fs::remove_file(&state_file) {
+            Ok(()) => info!("Removed the saved state in {}", state_file.display()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Unable to remove {}: {}", state_file.display(), e),
+        }
+    }
+    let socket = socket_path(options, Some(&config));
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(daemon::run(
+        config,
+        Some(config_path),
+        socket,
+        options.dry_run,
+        options.fail_fast,
+    ))?;
+    Ok(())
+}
+
+// Run the monitoring daemon for mount points and commands given on the command line
+pub fn watch(
+    options: &Options,
+    paths: &[String],
+    on_down: Option<&str>,
+    on_up: Option<&str>,
+    interval: u64,
+) -> Result<(), Error> {
+    let config = Config::for_mounts(paths, interval, on_up, on_down).map_err(Error::Config)?;
+    let _pid_file = lock_pid_file(options, None)?;
+    let socket = socket_path(options, None);
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(daemon::run(
+        config,
+        None,
+        socket,
+        options.dry_run,
+        options.fail_fast,
+    ))?;
+    Ok(())
+}
+
+// Monitor the mount points a pod was given, from the arguments, the environment and a
+// file, serving their health for the kubelet to probe
+pub fn sidecar(
+    options: &Options,
+    paths: &[String],
+    mount_points_file: Option<&Path>,
+    listen: &str,
+    exit_after: Option<Duration>,
+    interval: u64,
+) -> Result<(), Error> {
+    let split = |list: &str| -> Vec<String> {
+        list.split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|p| !p.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    let mut mount_points = paths.to_vec();
+    if let Ok(listed) = std::env::var("NOFUS_MOUNT_POINTS") {
+        mount_points.extend(split(&listed));
+    }
+    if let Some(file) = mount_points_file {
+        let listed = fs::read_to_string(file)
+            .map_err(|e| Error::Config(format!("Unable to read {}: {}", file.display(), e)))?;
+        mount_points.extend(split(&listed));
+    }
+    if mount_points.is_empty() {
+        return Err(Error::Config(
+            "No mount points given, as arguments, in NOFUS_MOUNT_POINTS or in a file".to_string(),
+        ));
+    }
+
+    let mut config =
+        Config::for_mounts(&mount_points, interval, None, None).map_err(Error::Config)?;
+    config.web = Some(WebConfig {
+        listen: listen.to_string(),
+        token: std::env::var("NOFUS_WEB_TOKEN").unwrap_or_default(),
+    });
+    config.exit_when_down_seconds = exit_after.map(|d| d.as_secs());
+    let _pid_file = lock_pid_file(options, None)?;
+    let socket = socket_path(options, None);
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(daemon::run(
+        config,
+        None,
+        socket,
+        options.dry_run,
+        options.fail_fast,
+    ))?;
+    Ok(())
+}
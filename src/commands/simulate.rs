@@ -0,0 +1,80 @@
+use super::load_expanded;
+use crate::error::{EXIT_CONFIG, EXIT_RUNTIME, EXIT_USAGE};
+use crate::monitor::{Monitor, MountState};
+use log::info;
+use std::path::Path;
+
+// Run the monitor in dry run mode against made-up mount states, logging every command
+// and notification it would send, and return the exit code
+pub fn simulate(config_path: &Path, down: &[String], up: &[String], stale: &[String]) -> i32 {
+    let mut config = match load_expanded(config_path) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_CONFIG;
+        }
+    };
+    let changes: Vec<(&String, MountState)> = down
+        .iter()
+        .map(|p| (p, MountState::Unmounted))
+        .chain(up.iter().map(|p| (p, MountState::Mounted)))
+        .chain(stale.iter().map(|p| (p, MountState::Stale)))
+        .collect();
+    if changes.is_empty() {
+        eprintln!("Nothing to simulate, give mount points with --down, --up or --stale");
+        return EXIT_USAGE;
+    }
+    for (path, _) in &changes {
+        if !config.mount_points.iter().any(|m| &m.path == *path) {
+            eprintln!("Not a configured mount point: {}", path);
+            return EXIT_USAGE;
+        }
+    }
+    // The starting point is the daemon having been running for a while
+    config.startup_grace_seconds = 0;
+    let checks = changes
+        .iter()
+        .filter_map(|(path, state)| {
+            let mount = config.mount_points.iter().find(|m| &m.path == *path)?;
+            Some(if *state == MountState::Mounted {
+                config.successes_before_up(mount)
+            } else {
+                config.failures_before_down(mount)
+            })
+        })
+        .max()
+        .unwrap_or(1);
+    let paths: Vec<String> = config.mount_points.iter().map(|m| m.path.clone()).collect();
+
+    // Notifiers are set up on the runtime, though nothing is ever sent
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_RUNTIME;
+        }
+    };
+    let _runtime = runtime.enter();
+    let mut monitor = Monitor::new(config, true);
+
+    // Settle into the starting state quietly
+    for path in &paths {
+        monitor.force(path, Some(MountState::Mounted));
+    }
+    for path in up {
+        monitor.force(path, Some(MountState::Unmounted));
+    }
+    let level = log::max_level();
+    log::set_max_level(log::LevelFilter::Off);
+    monitor.evaluate();
+    log::set_max_level(level);
+
+    for (path, state) in &changes {
+        monitor.force(path, Some(*state));
+    }
+    for check in 1..=checks {
+        info!("Simulated check {} of {}", check, checks);
+        monitor.evaluate();
+    }
+    0
+}
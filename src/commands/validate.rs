@@ -0,0 +1,156 @@
+use super::load_expanded;
+use crate::config::{self, CommandLine};
+use crate::error::EXIT_CONFIG;
+use crate::mounts::{fstab_entries, MountTable};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+// Check the config file and what it refers to, printing each problem, and return the
+// exit code
+pub fn validate(config_path: &Path) -> i32 {
+    let config = match load_expanded(config_path) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("error: {}", e);
+            return EXIT_CONFIG;
+        }
+    };
+    let mut errors = 0;
+    let mut warnings = 0;
+    let mut error = |message: String| {
+        println!("error: {}", message);
+        errors += 1;
+    };
+
+    let mounted: Vec<PathBuf> = match MountTable::open() {
+        Ok(table) => table.mounts().keys().cloned().collect(),
+        Err(e) => {
+            println!("warning: unable to read the mount table: {}", e);
+            warnings += 1;
+            Vec::new()
+        }
+    };
+    let fstab: Vec<String> = fstab_entries(Path::new(config::FSTAB))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect();
+    let mut unknown = Vec::new();
+    for mount in &config.mount_points {
+        let path = Path::new(&mount.path);
+        if !path.exists() {
+            error(format!("mount point {} does not exist", mount.path));
+        } else if !path.is_dir() {
+            error(format!("mount point {} is not a directory", mount.path));
+        }
+        if !mounted.iter().any(|m| m == path) && !fstab.contains(&mount.path) {
+            unknown.push(&mount.path);
+        }
+    }
+
+    for (setting, command) in config.commands() {
+        let Some(program) = program(command) else {
+            continue;
+        };
+        if program.is_empty() {
+            error(format!("{} is empty", setting));
+        } else if !program_exists(&program) {
+            error(format!(
+                "{} runs {}, which can't be found",
+                setting, program
+            ));
+        }
+    }
+
+    for path in unknown {
+        println!(
+            "warning: mount point {} is neither mounted nor listed in {}",
+            path,
+            config::FSTAB
+        );
+        warnings += 1;
+    }
+    if errors == 0 {
+        println!("{} is valid ({} warnings)", config_path.display(), warnings);
+        0
+    } else {
+        println!("{} errors, {} warnings", errors, warnings);
+        1
+    }
+}
+
+// Shell builtins and keywords a command may start with, which aren't on the PATH
+const SHELL_BUILTINS: [&str; 22] = [
+    ".", ":", "[", "cd", "command", "echo", "eval", "exec", "exit", "export", "false", "for", "if",
+    "printf", "read", "set", "test", "trap", "true", "until", "wait", "while",
+];
+
+// The program a command runs, or None if that can't be told without running it, as
+// when it is a shell builtin or filled in from a placeholder
+fn program(command: &CommandLine) -> Option<String> {
+    let program = match command {
+        CommandLine::Argv(argv) => argv.first().cloned().unwrap_or_default(),
+        // The first word that isn't a variable assignment
+        CommandLine::Shell(line) => line
+            .split_whitespace()
+            .find(|word| !word.contains('='))
+            .unwrap_or_default()
+            .to_string(),
+        CommandLine::Units(_) | CommandLine::Containers(_) | CommandLine::Ready(_) => return None,
+    };
+    if program.contains("{{") || SHELL_BUILTINS.contains(&program.as_str()) {
+        return None;
+    }
+    // Anything fancier than a plain word is for the shell to make sense of
+    let assignments_only = program.is_empty() && !command.to_string().trim().is_empty();
+    if matches!(command, CommandLine::Shell(_))
+        && (assignments_only || program.contains(['$', '(', '`', '{']))
+    {
+        return None;
+    }
+    Some(program)
+}
+
+// Whether a program can be run, either by its path or by looking it up on the PATH
+fn program_exists(program: &str) -> bool {
+    let executable = |path: &Path| {
+        fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+    };
+    if program.contains('/') {
+        return executable(Path::new(program));
+    }
+    std::env::var_os("PATH").is_some_and(|paths| {
+        std::env::split_paths(&paths).any(|dir| executable(&dir.join(program)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn programs_are_only_looked_for_when_they_can_be_told() {
+        let shell = |line: &str| program(&CommandLine::Shell(line.to_string()));
+        assert_eq!(shell("mount -a").as_deref(), Some("mount"));
+        assert_eq!(
+            shell("LANG=C /sbin/mount -a").as_deref(),
+            Some("/sbin/mount")
+        );
+        assert_eq!(shell("echo down"), None);
+        assert_eq!(shell("{{mount}}/bin/fix"), None);
+        assert_eq!(shell("$HOME/bin/fix"), None);
+        assert_eq!(shell("A=1"), None);
+        assert_eq!(shell("").as_deref(), Some(""));
+        let argv = CommandLine::Argv(vec!["systemctl".to_string(), "restart".to_string()]);
+        assert_eq!(program(&argv).as_deref(), Some("systemctl"));
+    }
+
+    #[test]
+    fn programs_are_found_by_path_or_on_the_path() {
+        assert!(program_exists("sh"));
+        assert!(program_exists("/bin/sh"));
+        assert!(!program_exists("/etc/passwd"));
+        assert!(!program_exists("nofus-no-such-program"));
+    }
+}
@@ -0,0 +1,2414 @@
+use crate::command::credentials;
+use crate::logging::LogFormat;
+use crate::mounts::{fstab_mount_points, fstab_options, is_read_only, Target};
+use crate::options::ExpectedOption;
+use crate::schedule::Schedule;
+use crate::schema::{names, with_schema, Schema};
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+with_schema! {
+    #[derive(Debug, Deserialize)]
+    pub struct Config {
+        // More files merged into this one, by path or glob, relative to this one
+        pub include: Option<Include>,
+        #[serde(default, deserialize_with = "deserialize_mount_points")]
+        pub mount_points: Vec<MountPoint>,
+        // Entries of mount_points given as a glob or regex, matched against the mount table
+        #[serde(skip)]
+        pub patterns: Vec<MountPattern>,
+        // Also monitor every NFS mount listed in /etc/fstab
+        #[serde(default)]
+        pub discover_from_fstab: bool,
+        // Only discover fstab mounts whose mount point matches this glob, e.g. /mnt/nfs/*
+        pub fstab_pattern: Option<String>,
+        // Monitor any mount of these filesystem types while it is mounted, e.g. nfs4, cifs
+        #[serde(default)]
+        pub discover_fs_types: Vec<String>,
+        // Only count a mount as mounted if it is one of these filesystem types
+        #[serde(default)]
+        pub fs_types: Vec<String>,
+        pub delay_seconds: u64,
+        pub all_mounted_cmd: Hook,
+        pub any_unmounted_cmd: Hook,
+        // Run for any mount that goes stale and doesn't have its own on_stale
+        pub on_stale_cmd: Option<Hook>,
+        // Run for any mount that becomes degraded and doesn't have its own on_degraded
+        pub on_degraded_cmd: Option<Hook>,
+        // Run for any mount whose server is found down and doesn't have its own
+        // on_server_down
+        pub on_server_down_cmd: Option<Hook>,
+        // Run for any mount that should be read-write but is found mounted read-only,
+        // and doesn't have its own on_readonly
+        pub on_readonly_cmd: Option<Hook>,
+        // Options every mount without its own expected_options should be mounted with,
+        // e.g. hard or vers=4.2, and what to run when they aren't
+        #[serde(default)]
+        pub expected_options: Vec<ExpectedOption>,
+        pub on_option_drift_cmd: Option<Hook>,
+        // Run once when every mount from a server with several goes down, or they are
+        // all back, instead of the commands of each of them
+        pub server_down_cmd: Option<Hook>,
+        pub server_up_cmd: Option<Hook>,
+        // Run once the daemon has checked every mount at startup, and as it shuts down
+        pub on_start: Option<Hook>,
+        #[serde(alias = "on_shutdown_cmd")]
+        pub on_stop: Option<Hook>,
+        pub heartbeat: Option<HeartbeatConfig>,
+        // Resolve the server of each mount on a timer, to tell a broken resolver from
+        // a dead server
+        pub dns_check: Option<DnsCheck>,
+        // Watch the Kerberos credentials of sec=krb5 mounts, which can expire while
+        // the mount still looks fine
+        pub kerberos_check: Option<KerberosCheck>,
+        pub reminders: Option<RemindersConfig>,
+        #[serde(default)]
+        pub maintenance: Vec<MaintenanceWindow>,
+        pub control_socket: Option<String>,
+        // Serve a status page and the control requests over HTTP
+        pub web: Option<WebConfig>,
+        // Locked while the daemon runs, so a second copy refuses to start
+        pub pid_file: Option<String>,
+        // Exit with an error once a mount has been down this long, e.g. for Kubernetes
+        // to restart the pod
+        pub exit_when_down_seconds: Option<u64>,
+        // Keep each mount's last known state across restarts, so one already down
+        // isn't acted on again
+        #[serde(default)]
+        pub persist_state: bool,
+        pub state_file: Option<String>,
+        // Record state changes, checks and commands in a database for `nofus history`
+        pub history: Option<HistoryConfig>,
+        // Send traces of checks and commands, and metrics, to an OpenTelemetry collector
+        pub otlp: Option<OtlpConfig>,
+        pub metrics: Option<MetricsConfig>,
+        pub log_format: Option<LogFormat>,
+        #[serde(default)]
+        pub notifications: NotificationsConfig,
+        // Named sets of mounts with commands of their own, in place of the global ones
+        #[serde(default)]
+        pub groups: BTreeMap<String, MountGroup>,
+        // Try to remount vanished mounts before treating them as unmounted
+        #[serde(default)]
+        pub remount: bool,
+        #[serde(default = "default_remount_attempts")]
+        pub remount_attempts: u32,
+        // Delay before the second attempt, doubled for each one after
+        #[serde(default = "default_backoff_seconds")]
+        pub remount_backoff_seconds: u64,
+        // How long a command may run before it is killed
+        #[serde(default = "default_command_timeout_seconds")]
+        pub command_timeout_seconds: u64,
+        // How long shutting down waits for commands to finish before killing them
+        #[serde(default = "default_shutdown_timeout_seconds")]
+        pub shutdown_timeout_seconds: u64,
+        // How many times to retry a failed command, and the delay before the first retry,
+        // doubled for each one after
+        #[serde(default)]
+        pub command_retries: u32,
+        #[serde(default = "default_backoff_seconds")]
+        pub command_backoff_seconds: u64,
+        // How long after a command runs before it may run again, skipping it until then
+        #[serde(default)]
+        pub command_cooldown_seconds: u64,
+        // Drop a mount's queued commands rather than running them once it changes back
+        #[serde(default)]
+        pub cancel_reverted_commands: bool,
+        // How long to wait on a mount before calling it unresponsive
+        #[serde(default = "default_probe_timeout_seconds")]
+        pub probe_timeout_seconds: u64,
+        // How many mounts to check at once
+        #[serde(default = "default_probe_workers")]
+        pub probe_workers: usize,
+        // How many checks in a row must agree before a mount is considered down or up
+        #[serde(default = "default_consecutive")]
+        pub failures_before_down: u32,
+        #[serde(default = "default_consecutive")]
+        pub successes_before_up: u32,
+        // How long a mount that came back must stay mounted before it is considered up
+        #[serde(default)]
+        pub recovery_confirmation_seconds: u64,
+        // How long after starting to only warn about missing mounts, giving them time to
+        // come up at boot
+        #[serde(default)]
+        pub startup_grace_seconds: u64,
+        // Stop acting on a mount that changes state this many times within the window
+        pub flap_threshold: Option<u32>,
+        #[serde(default = "default_flap_window_seconds")]
+        pub flap_window_seconds: u64,
+        // Call a mount degraded when checking it takes longer than this, and
+        // unresponsive past latency_critical_ms
+        pub latency_warn_ms: Option<u64>,
+        pub latency_critical_ms: Option<u64>,
+        // Alert on a percentile of the checks of a mount staying slow for a while
+        #[serde(default)]
+        pub latency_alerts: Vec<LatencyAlert>,
+        // Act on mounts filling up, or running out of inodes
+        pub disk_usage: Option<UsageThresholds>,
+        pub inode_usage: Option<UsageThresholds>,
+        // Write, read back and delete this file on each check, relative to the mount
+        // point, to catch mounts that can no longer be written to
+        pub canary_file: Option<String>,
+        // Ask the NFS server of a mount that is down whether it is still answering,
+        // giving it server_timeout_ms to
+        #[serde(default)]
+        pub ping_server: bool,
+        #[serde(default = "default_server_timeout_ms")]
+        pub server_timeout_ms: u64,
+        // Before checking an NFS mount, make sure its server accepts connections
+        // within tcp_precheck_timeout_ms, and call the server down if not
+        #[serde(default)]
+        pub tcp_precheck: bool,
+        #[serde(default = "default_tcp_precheck_timeout_ms")]
+        pub tcp_precheck_timeout_ms: u64,
+        // Before checking an NFS mount, make sure its server has NFS (and for NFSv3
+        // the mount daemon) registered with rpcbind, and call the server down if
+        // not
+        #[serde(default)]
+        pub rpcbind_check: bool,
+        // Access a mount autofs has let expire to have it mounted again, and call it
+        // unmounted if that doesn't. Without, whether it works can't be told, so its
+        // state is unknown.
+        #[serde(default = "default_true")]
+        pub trigger_automount: bool,
+    }
+}
+
+// A set of mounts handled together, considered up while at least `quorum` of them
+// are mounted
+with_schema! {
+    #[derive(Debug, Deserialize)]
+    pub struct MountGroup {
+        pub mount_points: Vec<String>,
+        // Defaults to all of them
+        pub quorum: Option<usize>,
+        // Run when the group comes up, and when it goes down
+        pub all_mounted_cmd: Option<Hook>,
+        pub any_unmounted_cmd: Option<Hook>,
+    }
+}
+
+impl MountGroup {
+    // How many of the group's mounts must be mounted for it to be up
+    pub fn quorum(&self) -> usize {
+        self.quorum.unwrap_or(self.mount_points.len())
+    }
+}
+
+// A recurring stretch of time during which mounts are still checked and logged, but
+// nothing is run or sent for them
+with_schema! {
+    #[derive(Debug, Deserialize)]
+    pub struct MaintenanceWindow {
+        // When the window opens, and for how long
+        pub schedule: Schedule,
+        pub duration_minutes: u64,
+        // The mount points it applies to, all of them if empty
+        #[serde(default)]
+        pub mount_points: Vec<String>,
+    }
+}
+
+impl MaintenanceWindow {
+    pub fn is_open(&self, now: SystemTime) -> bool {
+        self.schedule
+            .within(now, Duration::from_secs(self.duration_minutes * 60))
+    }
+
+    pub fn covers(&self, path: &str) -> bool {
+        self.mount_points.is_empty() || self.mount_points.iter().any(|p| p == path)
+    }
+}
+
+// Follow up on mounts that stay down, so an outage isn't forgotten after the first
+// alert
+with_schema! {
+    #[derive(Debug, Deserialize)]
+    pub struct RemindersConfig {
+        // How often to notify again while a mount is down
+        pub interval_seconds: u64,
+        // Whether to run the down command again with each reminder
+        #[serde(default = "default_true")]
+        pub rerun_commands: bool,
+        // Steps taken once a mount has been down for long enough, in order
+        #[serde(default)]
+        pub escalations: Vec<Escalation>,
+    }
+}
+
+impl RemindersConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_seconds)
+    }
+}
+
+// Run once a mount has been down this long, and with each reminder after, in place of
+// the down command
+with_schema! {
+    #[derive(Debug, Deserialize)]
+    pub struct Escalation {
+        pub after_seconds: u64,
+        pub command: Option<Hook>,
+    }
+}
+
+impl Escalation {
+    pub fn after(&self) -> Duration {
+        Duration::from_secs(self.after_seconds)
+    }
+}
+
+// Alert when a percentile of a mount's check latency stays over a threshold, e.g. p95
+// over 500ms for 5 minutes
+with_schema! {
+    #[derive(Debug, Deserialize)]
+    pub struct LatencyAlert {
+        pub percentile: f64,
+        pub above_ms: u64,
+        #[serde(default = "default_alert_for_seconds")]
+        pub for_seconds: u64,
+        pub command: Option<Hook>,
+    }
+}
+
+impl LatencyAlert {
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.for_seconds)
+    }
+}
+
+fn default_alert_for_seconds() -> u64 {
+    300
+}
+
+// How full a mount may get, in percent used, and what to run as it passes either
+// threshold or drops back below them. Given for a mount, each setting takes over from
+// the global one.
+with_schema! {
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct UsageThresholds {
+        pub warn_percent: Option<f64>,
+        pub critical_percent: Option<f64>,
+        pub command: Option<Hook>,
+    }
+}
+
+// The usage thresholds that apply to a mount
+pub struct UsageLimits<'a> {
+    pub warn_percent: Option<f64>,
+    pub critical_percent: Option<f64>,
+    pub command: Option<&'a Hook>,
+}
+
+impl UsageLimits<'_> {
+    fn new<'a>(
+        own: Option<&'a UsageThresholds>,
+        global: Option<&'a UsageThresholds>,
+    ) -> UsageLimits<'a> {
+        let setting = |get: fn(&UsageThresholds) -> Option<f64>| {
+            own.and_then(get).or_else(|| global.and_then(get))
+        };
+        UsageLimits {
+            warn_percent: setting(|t| t.warn_percent),
+            critical_percent: setting(|t| t.critical_percent),
+            command: own
+                .and_then(|t| t.command.as_ref())
+                .or_else(|| global.and_then(|t| t.command.as_ref())),
+        }
+    }
+
+    fn validate(&self, name: &str, path: &str) -> Result<(), String> {
+        let percents = [self.warn_percent, self.critical_percent];
+        if percents
+            .iter()
+            .flatten()
+            .any(|p| !(0.0..=100.0).contains(p))
+        {
+            return Err(format!(
+                "{} thresholds for {} must be between 0 and 100",
+                name, path
+            ));
+        }
+        if let (Some(warn), Some(critical)) = (self.warn_percent, self.critical_percent) {
+            if warn >= critical {
+                return Err(format!(
+                    "{} warn_percent for {} must be below critical_percent",
+                    name, path
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Where to send notifications about mount state changes
+with_schema! {
+    #[derive(Debug, Default, Deserialize)]
+    pub struct NotificationsConfig {
+        pub webhook: Option<WebhookConfig>,
+        pub email: Option<EmailConfig>,
+        pub ntfy: Option<NtfyConfig>,
+        #[serde(default)]
+        pub desktop: bool,
+        pub mqtt: Option<MqttConfig>,
+    }
+}
+
+// POST a JSON description of each state change to a URL
+with_schema! {
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct WebhookConfig {
+        pub url: String,
+        #[serde(default)]
+        pub headers: BTreeMap<String, String>,
+        // How many times to retry a failed delivery
+        #[serde(default = "default_retries")]
+        pub retries: u32,
+        // Delay before the first retry, doubled for each one after
+        #[serde(default = "default_backoff_seconds")]
+        pub backoff_seconds: u64,
+    }
+}
+
+// Send an email when a mount goes down and again when it recovers
+with_schema! {
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct EmailConfig {
+        pub server: String,
+        // Defaults to the standard port for the TLS mode
+        pub port: Option<u16>,
+        #[serde(default)]
+        pub tls: TlsMode,
+        pub username: Option<String>,
+        pub password: Option<String>,
+        pub from: String,
+        pub to: Vec<String>,
+    }
+}
+
+// How to secure the connection to the SMTP server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsMode {
+    // Plaintext, only suitable for a trusted local relay
+    None,
+    // Upgrade a plaintext connection with STARTTLS
+    #[default]
+    Starttls,
+    // Connect with TLS from the start
+    Tls,
+}
+
+// Publish state changes to an ntfy topic for push notifications
+with_schema! {
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct NtfyConfig {
+        #[serde(default = "default_ntfy_server")]
+        pub server: String,
+        pub topic: String,
+        pub token: Option<String>,
+        // Priority for a mount going down
+        #[serde(default = "default_ntfy_priority")]
+        pub priority: String,
+        // Priority for a mount recovering
+        #[serde(default = "default_ntfy_recovery_priority")]
+        pub recovery_priority: String,
+        // Priority for reminders about a mount that has been escalated
+        #[serde(default = "default_ntfy_escalation_priority")]
+        pub escalation_priority: String,
+    }
+}
+
+fn default_ntfy_server() -> String {
+    "https://ntfy.sh".to_string()
+}
+
+fn default_ntfy_escalation_priority() -> String {
+    "urgent".to_string()
+}
+
+fn default_ntfy_priority() -> String {
+    "high".to_string()
+}
+
+fn default_ntfy_recovery_priority() -> String {
+    "default".to_string()
+}
+
+// Publish per-mount state to an MQTT broker, optionally with Home Assistant discovery
+with_schema! {
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct MqttConfig {
+        pub host: String,
+        #[serde(default = "default_mqtt_port")]
+        pub port: u16,
+        pub username: Option<String>,
+        pub password: Option<String>,
+        // Defaults to nofus-<hostname>
+        pub client_id: Option<String>,
+        // Defaults to nofus/<hostname>
+        pub topic_prefix: Option<String>,
+        // Announce each mount to Home Assistant as a binary_sensor
+        #[serde(default = "default_true")]
+        pub discovery: bool,
+        #[serde(default = "default_discovery_prefix")]
+        pub discovery_prefix: String,
+    }
+}
+
+// Run a command or fetch a URL on a timer whatever the mounts are doing, so something
+// watching from outside notices when nofus stops
+with_schema! {
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct HeartbeatConfig {
+        pub command: Option<CommandLine>,
+        pub url: Option<String>,
+        #[serde(default = "default_heartbeat_interval_seconds")]
+        pub interval_seconds: u64,
+    }
+}
+
+impl HeartbeatConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_seconds)
+    }
+}
+
+// How often to resolve the mount servers, how long to give the resolver, and the
+// command to run when a server stops resolving, resolves again or resolves to other
+// addresses
+with_schema! {
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct DnsCheck {
+        #[serde(default = "default_dns_interval_seconds")]
+        pub interval_seconds: u64,
+        #[serde(default = "default_dns_timeout_seconds")]
+        pub timeout_seconds: u64,
+        pub command: Option<Hook>,
+    }
+}
+
+impl DnsCheck {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_seconds)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_seconds)
+    }
+}
+
+// Which credential cache sec=krb5 mounts are accessed with, how long before its
+// tickets expire to warn, and what to run when they are expiring, expired or renewed
+with_schema! {
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct KerberosCheck {
+        // A FILE credential cache, instead of rpc.gssd's newest machine credentials
+        pub ccache: Option<String>,
+        #[serde(default = "default_kerberos_warn_minutes")]
+        pub warn_minutes: u64,
+        pub command: Option<Hook>,
+    }
+}
+
+impl KerberosCheck {
+    pub fn warn(&self) -> Duration {
+        Duration::from_secs(self.warn_minutes * 60)
+    }
+}
+
+// Where OpenTelemetry data goes, over OTLP/HTTP with JSON
+with_schema! {
+    #[derive(Debug, Clone, PartialEq, Deserialize)]
+    pub struct OtlpConfig {
+        // The collector's base URL, e.g. http://localhost:4318
+        pub endpoint: String,
+        #[serde(default)]
+        pub headers: BTreeMap<String, String>,
+        // Added to the service.name, service.version and host.name sent with everything
+        #[serde(default)]
+        pub resource_attributes: BTreeMap<String, String>,
+        #[serde(default = "default_metrics_interval_seconds")]
+        pub metrics_interval_seconds: u64,
+    }
+}
+
+impl OtlpConfig {
+    pub fn metrics_interval(&self) -> Duration {
+        Duration::from_secs(self.metrics_interval_seconds.max(1))
+    }
+}
+
+fn default_metrics_interval_seconds() -> u64 {
+    60
+}
+
+// Where mount metrics are pushed, to statsd over UDP and/or InfluxDB over HTTP
+with_schema! {
+    #[derive(Debug, Clone, PartialEq, Deserialize)]
+    pub struct MetricsConfig {
+        // host:port of a statsd server
+        pub statsd: Option<String>,
+        pub influxdb: Option<InfluxDbConfig>,
+        // Put in front of every metric name
+        #[serde(default = "default_metrics_prefix")]
+        pub prefix: String,
+        #[serde(default = "default_flush_interval_seconds")]
+        pub flush_interval_seconds: u64,
+    }
+}
+
+with_schema! {
+    #[derive(Debug, Clone, PartialEq, Deserialize)]
+    pub struct InfluxDbConfig {
+        // The write endpoint with its query, e.g.
+        // http://localhost:8086/api/v2/write?org=home&bucket=nofus
+        pub url: String,
+        pub token: Option<String>,
+    }
+}
+
+impl MetricsConfig {
+    pub fn flush_interval(&self) -> Duration {
+        Duration::from_secs(self.flush_interval_seconds)
+    }
+}
+
+fn default_metrics_prefix() -> String {
+    "nofus".to_string()
+}
+
+fn default_flush_interval_seconds() -> u64 {
+    10
+}
+
+// Where the web dashboard listens, and the token its API wants
+with_schema! {
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct WebConfig {
+        #[serde(default = "default_web_listen")]
+        pub listen: String,
+        // Without one, only the health checks are served
+        #[serde(default)]
+        pub token: String,
+    }
+}
+
+fn default_web_listen() -> String {
+    "127.0.0.1:9180".to_string()
+}
+
+// Where the history is kept, and for how long
+with_schema! {
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct HistoryConfig {
+        pub path: Option<String>,
+        #[serde(default = "default_retention_days")]
+        pub retention_days: u64,
+    }
+}
+
+impl HistoryConfig {
+    pub fn path(&self) -> PathBuf {
+        self.path
+            .as_deref()
+            .map_or_else(default_history_file, PathBuf::from)
+    }
+
+    pub fn retention(&self) -> Duration {
+        Duration::from_secs(self.retention_days.saturating_mul(24 * 3600))
+    }
+}
+
+fn default_retention_days() -> u64 {
+    30
+}
+
+fn default_heartbeat_interval_seconds() -> u64 {
+    60
+}
+
+fn default_kerberos_warn_minutes() -> u64 {
+    60
+}
+
+fn default_dns_interval_seconds() -> u64 {
+    300
+}
+
+fn default_dns_timeout_seconds() -> u64 {
+    5
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_discovery_prefix() -> String {
+    "homeassistant".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_retries() -> u32 {
+    5
+}
+
+fn default_backoff_seconds() -> u64 {
+    2
+}
+
+fn default_remount_attempts() -> u32 {
+    3
+}
+
+fn default_command_timeout_seconds() -> u64 {
+    300
+}
+
+fn default_shutdown_timeout_seconds() -> u64 {
+    30
+}
+
+fn default_probe_timeout_seconds() -> u64 {
+    10
+}
+
+fn default_probe_workers() -> usize {
+    8
+}
+
+fn default_server_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_tcp_precheck_timeout_ms() -> u64 {
+    300
+}
+
+fn default_consecutive() -> u32 {
+    1
+}
+
+fn default_flap_window_seconds() -> u64 {
+    300
+}
+
+// Where persist_state keeps the state, unless told otherwise
+pub const STATE_FILE: &str = "/var/lib/nofus/state.json";
+
+// Where the history is kept, unless told otherwise
+pub const HISTORY_FILE: &str = "/var/lib/nofus/history.db";
+
+// Where discover_from_fstab looks for mounts
+pub const FSTAB: &str = "/etc/fstab";
+
+// A monitored mount point, optionally with its own commands
+with_schema! {
+    #[derive(Debug, Clone, Default, Deserialize)]
+    pub struct MountPoint {
+        // May be a glob such as /mnt/nfs/* to match every mount below it
+        #[serde(default)]
+        pub path: String,
+        // Matches mount points with a regular expression instead of a path
+        pub regex: Option<String>,
+        pub on_mounted: Option<Hook>,
+        pub on_unmounted: Option<Hook>,
+        pub on_stale: Option<Hook>,
+        pub on_degraded: Option<Hook>,
+        pub on_server_down: Option<Hook>,
+        pub on_readonly: Option<Hook>,
+        // Whether the mount is meant to be read-only, instead of going by fstab
+        pub read_only: Option<bool>,
+        pub expected_options: Option<Vec<ExpectedOption>>,
+        pub on_option_drift: Option<Hook>,
+        // Overrides the global remount setting for this mount
+        pub remount: Option<Remount>,
+        // Seconds between checks of this mount, instead of delay_seconds
+        pub interval: Option<u64>,
+        // Override the global thresholds for this mount
+        pub failures_before_down: Option<u32>,
+        pub successes_before_up: Option<u32>,
+        pub recovery_confirmation_seconds: Option<u64>,
+        pub latency_warn_ms: Option<u64>,
+        pub latency_critical_ms: Option<u64>,
+        pub disk_usage: Option<UsageThresholds>,
+        pub inode_usage: Option<UsageThresholds>,
+        // Overrides the global canary_file for this mount, and may be absolute
+        pub canary_file: Option<String>,
+        pub ping_server: Option<bool>,
+        pub tcp_precheck: Option<bool>,
+        pub rpcbind_check: Option<bool>,
+        pub trigger_automount: Option<bool>,
+        // Overrides the global fs_types for this mount
+        pub fs_types: Option<Vec<String>>,
+        // The mount namespace to check the mount in, e.g. /proc/<pid>/ns/mnt, rather
+        // than the one given with --mnt-ns or nofus's own
+        pub namespace: Option<String>,
+        // Services and containers that need the mount, in the order they are started
+        #[serde(default)]
+        pub dependents: Vec<Dependent>,
+        // Passed along with notifications, metrics and log entries about the mount,
+        // e.g. team: media
+        #[serde(default)]
+        pub labels: BTreeMap<String, String>,
+    }
+}
+
+// Label names nofus gives metrics of its own
+const RESERVED_LABELS: [&str; 5] = ["mount", "server", "state", "host", "le"];
+
+// A systemd unit or container that needs a mount, stopped when it goes down and
+// started again once it is back, waiting for it to be ready before the next one
+with_schema! {
+    #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Dependent {
+        pub unit: Option<String>,
+        pub container: Option<String>,
+        pub container_socket: Option<String>,
+        // Run until it succeeds to tell the dependent is ready, e.g. pg_isready
+        pub ready_cmd: Option<CommandLine>,
+        #[serde(default = "default_ready_timeout_seconds")]
+        pub ready_timeout_seconds: u64,
+    }
+}
+
+fn default_ready_timeout_seconds() -> u64 {
+    60
+}
+
+impl Dependent {
+    pub fn name(&self) -> &str {
+        self.unit
+            .as_deref()
+            .or(self.container.as_deref())
+            .unwrap_or_default()
+    }
+
+    // Stopping or starting it, as the command that does that
+    fn action(&self, verb: &str) -> CommandLine {
+        let names = |wanted: &str| {
+            if verb == wanted {
+                vec![self.name().to_string()]
+            } else {
+                Vec::new()
+            }
+        };
+        match &self.unit {
+            Some(_) => CommandLine::Units(UnitAction {
+                stop: names("stop"),
+                restart: Vec::new(),
+                start: names("start"),
+            }),
+            None => CommandLine::Containers(ContainerAction {
+                stop: names("stop"),
+                pause: Vec::new(),
+                restart: Vec::new(),
+                unpause: Vec::new(),
+                start: names("start"),
+                socket: self.container_socket.clone(),
+            }),
+        }
+    }
+
+    pub fn ready_timeout(&self) -> Duration {
+        Duration::from_secs(self.ready_timeout_seconds)
+    }
+}
+
+// Whether to remount a mount, or the command to remount it with
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Remount {
+    Enabled(bool),
+    Command(CommandLine),
+}
+
+// How a vanished mount should be remounted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemountAction {
+    // Run `mount <path>`, relying on fstab
+    Mount,
+    // Run a command of the user's choosing
+    Command(CommandLine),
+}
+
+// A command to run, either a string for `sh -c` or a list of arguments run directly,
+// without a shell to trip over quoting, or systemd units or containers to act on
+// instead
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum CommandLine {
+    Shell(String),
+    Argv(Vec<String>),
+    Units(UnitAction),
+    Containers(ContainerAction),
+    // Waiting for a dependent just started to be ready, which is only ever queued
+    // for dependents
+    #[serde(skip)]
+    Ready(Box<Dependent>),
+}
+
+// Units for systemd to stop, restart and start, in that order and one at a time,
+// each waited on until it has settled
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "UnitEntry")]
+pub struct UnitAction {
+    pub stop: Vec<String>,
+    pub restart: Vec<String>,
+    pub start: Vec<String>,
+}
+
+with_schema! {
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct UnitEntry {
+        #[serde(default)]
+        stop_units: Vec<String>,
+        #[serde(default)]
+        restart_units: Vec<String>,
+        #[serde(default)]
+        start_units: Vec<String>,
+    }
+}
+
+impl TryFrom<UnitEntry> for UnitAction {
+    type Error = String;
+
+    fn try_from(entry: UnitEntry) -> Result<Self, Self::Error> {
+        if entry.stop_units.is_empty()
+            && entry.restart_units.is_empty()
+            && entry.start_units.is_empty()
+        {
+            return Err("give stop_units, restart_units or start_units".to_string());
+        }
+        Ok(UnitAction {
+            stop: entry.stop_units,
+            restart: entry.restart_units,
+            start: entry.start_units,
+        })
+    }
+}
+
+impl UnitAction {
+    // Each unit with what to do with it, in the order it is done
+    pub fn steps(&self) -> impl Iterator<Item = (&'static str, &String)> {
+        self.stop
+            .iter()
+            .map(|u| ("stop", u))
+            .chain(self.restart.iter().map(|u| ("restart", u)))
+            .chain(self.start.iter().map(|u| ("start", u)))
+    }
+}
+
+// Containers for Docker or Podman to stop, pause, restart, unpause and start, in that
+// order, each given by name or id or as label=<key>=<value> for all those labelled so
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "ContainerEntry")]
+pub struct ContainerAction {
+    pub stop: Vec<String>,
+    pub pause: Vec<String>,
+    pub restart: Vec<String>,
+    pub unpause: Vec<String>,
+    pub start: Vec<String>,
+    // The API socket, found if not given
+    pub socket: Option<String>,
+}
+
+with_schema! {
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct ContainerEntry {
+        #[serde(default)]
+        stop_containers: Vec<String>,
+        #[serde(default)]
+        pause_containers: Vec<String>,
+        #[serde(default)]
+        restart_containers: Vec<String>,
+        #[serde(default)]
+        unpause_containers: Vec<String>,
+        #[serde(default)]
+        start_containers: Vec<String>,
+        container_socket: Option<String>,
+    }
+}
+
+impl TryFrom<ContainerEntry> for ContainerAction {
+    type Error = String;
+
+    fn try_from(entry: ContainerEntry) -> Result<Self, Self::Error> {
+        let action = ContainerAction {
+            stop: entry.stop_containers,
+            pause: entry.pause_containers,
+            restart: entry.restart_containers,
+            unpause: entry.unpause_containers,
+            start: entry.start_containers,
+            socket: entry.container_socket,
+        };
+        if action.steps().next().is_none() {
+            return Err(
+                "give stop_containers, pause_containers, restart_containers, \
+                        unpause_containers or start_containers"
+                    .to_string(),
+            );
+        }
+        Ok(action)
+    }
+}
+
+impl ContainerAction {
+    // Each container or label with what to do with it, in the order it is done
+    pub fn steps(&self) -> impl Iterator<Item = (&'static str, &String)> {
+        self.stop
+            .iter()
+            .map(|c| ("stop", c))
+            .chain(self.pause.iter().map(|c| ("pause", c)))
+            .chain(self.restart.iter().map(|c| ("restart", c)))
+            .chain(self.unpause.iter().map(|c| ("unpause", c)))
+            .chain(self.start.iter().map(|c| ("start", c)))
+    }
+}
+
+// One path or glob, or a list of them
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Include {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Include {
+    pub fn patterns(&self) -> &[String] {
+        match self {
+            Include::One(pattern) => std::slice::from_ref(pattern),
+            Include::Many(patterns) => patterns,
+        }
+    }
+}
+
+// The commands run for an event, in order, optionally with their own retry settings
+// and user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hook {
+    pub commands: Vec<CommandLine>,
+    pub on_error: OnError,
+    // Override the global command_retries, command_backoff_seconds and
+    // command_cooldown_seconds
+    pub retries: Option<u32>,
+    pub backoff_seconds: Option<u64>,
+    pub cooldown_seconds: Option<u64>,
+    pub run_as: Option<RunAs>,
+}
+
+// What to do with the rest of a hook's commands once one of them fails
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnError {
+    #[default]
+    Stop,
+    Continue,
+}
+
+// Who to run a command as, rather than the user nofus runs as
+with_schema! {
+    #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+    pub struct RunAs {
+        pub user: String,
+        // Defaults to the user's primary group
+        pub group: Option<String>,
+    }
+}
+
+// Hooks may be given as just the command or as a detailed entry with one command or
+// a list of them
+enum HookEntry {
+    Command(CommandLine),
+    Detailed(DetailedHook),
+}
+
+// Which entry a hook is is told from its shape by hand rather than as an untagged
+// enum, so that a mistake in it is found while the deserializer still knows where
+// it is, and YAML errors say which line
+impl<'de> Deserialize<'de> for Hook {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(HookVisitor)
+    }
+}
+
+struct HookVisitor;
+
+impl<'de> Visitor<'de> for HookVisitor {
+    type Value = Hook;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a command, a list of arguments or a hook with its commands")
+    }
+
+    fn visit_str<E: de::Error>(self, command: &str) -> Result<Hook, E> {
+        let entry = HookEntry::Command(CommandLine::Shell(command.to_string()));
+        Hook::try_from(entry).map_err(E::custom)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Hook, A::Error> {
+        let args = Vec::deserialize(SeqAccessDeserializer::new(seq))?;
+        Hook::try_from(HookEntry::Command(CommandLine::Argv(args))).map_err(de::Error::custom)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Hook, A::Error> {
+        let fields = serde_json::Map::deserialize(MapAccessDeserializer::new(map))?;
+        let has = |suffix: &str| fields.keys().any(|key| key.ends_with(suffix));
+        let units = has("_units");
+        let containers = has("_containers") || fields.contains_key("container_socket");
+        let fields = Value::Object(fields);
+        let entry = if units {
+            UnitAction::deserialize(fields).map(|a| HookEntry::Command(CommandLine::Units(a)))
+        } else if containers {
+            ContainerAction::deserialize(fields)
+                .map(|a| HookEntry::Command(CommandLine::Containers(a)))
+        } else {
+            DetailedHook::deserialize(fields).map(HookEntry::Detailed)
+        };
+        entry
+            .map_err(|e| e.to_string())
+            .and_then(Hook::try_from)
+            .map_err(de::Error::custom)
+    }
+}
+
+with_schema! {
+    #[derive(Deserialize)]
+    struct DetailedHook {
+        command: Option<CommandLine>,
+        commands: Option<Vec<CommandLine>>,
+        #[serde(default)]
+        on_error: OnError,
+        retries: Option<u32>,
+        backoff_seconds: Option<u64>,
+        cooldown_seconds: Option<u64>,
+        run_as: Option<RunAs>,
+    }
+}
+
+impl TryFrom<HookEntry> for Hook {
+    type Error = String;
+
+    fn try_from(entry: HookEntry) -> Result<Self, Self::Error> {
+        match entry {
+            HookEntry::Command(command) => Ok(Hook {
+                commands: vec![command],
+                on_error: OnError::Stop,
+                retries: None,
+                backoff_seconds: None,
+                cooldown_seconds: None,
+                run_as: None,
+            }),
+            HookEntry::Detailed(DetailedHook {
+                command,
+                commands,
+                on_error,
+                retries,
+                backoff_seconds,
+                cooldown_seconds,
+                run_as,
+            }) => {
+                let commands = match (command, commands) {
+                    (Some(command), None) => vec![command],
+                    (None, Some(commands)) if !commands.is_empty() => commands,
+                    _ => return Err("give either a command or a list of commands".to_string()),
+                };
+                Ok(Hook {
+                    commands,
+                    on_error,
+                    retries,
+                    backoff_seconds,
+                    cooldown_seconds,
+                    run_as,
+                })
+            }
+        }
+    }
+}
+
+impl Schema for Hook {
+    fn schema() -> Value {
+        json!({ "anyOf": [CommandLine::schema(), DetailedHook::schema()] })
+    }
+}
+
+impl Schema for CommandLine {
+    fn schema() -> Value {
+        json!({
+            "anyOf": [
+                { "type": "string" },
+                { "type": "array", "items": { "type": "string" }, "minItems": 1 },
+                UnitEntry::schema(),
+                ContainerEntry::schema(),
+            ]
+        })
+    }
+}
+
+impl Schema for Include {
+    fn schema() -> Value {
+        json!({
+            "anyOf": [{ "type": "string" }, { "type": "array", "items": { "type": "string" } }]
+        })
+    }
+}
+
+impl Schema for Remount {
+    fn schema() -> Value {
+        json!({ "anyOf": [{ "type": "boolean" }, CommandLine::schema()] })
+    }
+}
+
+impl Schema for OnError {
+    fn schema() -> Value {
+        names::<Self>()
+    }
+}
+
+impl Schema for TlsMode {
+    fn schema() -> Value {
+        names::<Self>()
+    }
+}
+
+impl Schema for ExpectedOption {
+    fn schema() -> Value {
+        json!({ "type": "string" })
+    }
+}
+
+impl Schema for Schedule {
+    fn schema() -> Value {
+        json!({ "type": "string" })
+    }
+}
+
+// Built from mount_points rather than read from the file
+impl Schema for MountPattern {
+    fn schema() -> Value {
+        Value::Null
+    }
+}
+
+// The JSON Schema of the config file, for editors and linters
+pub fn schema() -> Value {
+    let mut schema = Config::schema();
+    // Besides detailed entries, mount_points takes plain paths
+    schema["properties"]["mount_points"]["items"] = json!({
+        "anyOf": [{ "type": "string" }, MountPoint::schema()]
+    });
+    schema["$schema"] = json!("http://json-schema.org/draft-07/schema#");
+    schema["title"] = json!("nofus configuration");
+    schema
+}
+
+impl CommandLine {
+    // A list needs at least the program to run
+    pub fn is_empty(&self) -> bool {
+        match self {
+            CommandLine::Shell(_)
+            | CommandLine::Units(_)
+            | CommandLine::Containers(_)
+            | CommandLine::Ready(_) => false,
+            CommandLine::Argv(argv) => argv.first().is_none_or(String::is_empty),
+        }
+    }
+}
+
+impl fmt::Display for CommandLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandLine::Shell(command) => f.write_str(command),
+            CommandLine::Argv(argv) => {
+                // Quote arguments that wouldn't survive being split on spaces
+                let args: Vec<String> = argv
+                    .iter()
+                    .map(|arg| {
+                        if arg.is_empty() || arg.contains(char::is_whitespace) {
+                            format!("'{}'", arg)
+                        } else {
+                            arg.clone()
+                        }
+                    })
+                    .collect();
+                f.write_str(&args.join(" "))
+            }
+            CommandLine::Units(action) => {
+                let steps: Vec<String> = action
+                    .steps()
+                    .map(|(verb, unit)| format!("{} {}", verb, unit))
+                    .collect();
+                f.write_str(&steps.join(", "))
+            }
+            CommandLine::Containers(action) => {
+                let steps: Vec<String> = action
+                    .steps()
+                    .map(|(verb, container)| format!("{} {}", verb, container))
+                    .collect();
+                f.write_str(&steps.join(", "))
+            }
+            CommandLine::Ready(dependent) => write!(f, "wait for {} to be ready", dependent.name()),
+        }
+    }
+}
+
+// How a pattern entry picks the mount points it applies to
+#[derive(Debug)]
+pub enum Matcher {
+    Glob(glob::Pattern),
+    Regex(regex::Regex),
+}
+
+// A mount_points entry that stands for every mounted path it matches, each monitored
+// with the entry's settings
+#[derive(Debug)]
+pub struct MountPattern {
+    pub matcher: Matcher,
+    pub template: MountPoint,
+}
+
+impl MountPattern {
+    // Compile the entry's pattern, or None if it is a plain path
+    fn new(mount: &MountPoint) -> Result<Option<Self>, String> {
+        let matcher = match &mount.regex {
+            // Anchor the pattern so it has to match the whole path
+            Some(regex) => regex::Regex::new(&format!("^(?:{})$", regex))
+                .map(Matcher::Regex)
+                .map_err(|e| format!("invalid regex {}: {}", regex, e))?,
+            None if mount.path.contains(['*', '?', '[']) => glob::Pattern::new(&mount.path)
+                .map(Matcher::Glob)
+                .map_err(|e| format!("invalid pattern {}: {}", mount.path, e))?,
+            None => return Ok(None),
+        };
+        Ok(Some(MountPattern {
+            matcher,
+            template: mount.clone(),
+        }))
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        match &self.matcher {
+            Matcher::Glob(pattern) => pattern.matches(path),
+            Matcher::Regex(regex) => regex.is_match(path),
+        }
+    }
+}
+
+impl MountPoint {
+    // Whether a mount has a command of its own for coming up, or for going down
+    pub fn has_hook(&self, up: bool) -> bool {
+        if up {
+            self.on_mounted.is_some()
+        } else {
+            self.on_unmounted.is_some()
+        }
+    }
+}
+
+// Mount points may be given as a plain path or as a detailed entry. They are told
+// apart by shape rather than by trying each in turn, so that a mistake in a detailed
+// entry is reported where it is.
+struct MountPointEntry(MountPoint);
+
+impl<'de> Deserialize<'de> for MountPointEntry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(MountPointVisitor)
+    }
+}
+
+struct MountPointVisitor;
+
+impl<'de> Visitor<'de> for MountPointVisitor {
+    type Value = MountPointEntry;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a path or a mount point with its settings")
+    }
+
+    fn visit_str<E: de::Error>(self, path: &str) -> Result<MountPointEntry, E> {
+        Ok(MountPointEntry(MountPoint {
+            path: path.to_string(),
+            ..Default::default()
+        }))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<MountPointEntry, A::Error> {
+        MountPoint::deserialize(MapAccessDeserializer::new(map)).map(MountPointEntry)
+    }
+}
+
+fn deserialize_mount_points<'de, D>(deserializer: D) -> Result<Vec<MountPoint>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let entries = Vec::<MountPointEntry>::deserialize(deserializer)?;
+    Ok(entries.into_iter().map(|entry| entry.0).collect())
+}
+
+// The supported configuration file formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+}
+
+impl ConfigFormat {
+    // Detect the format from the file extension, defaulting to YAML
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => ConfigFormat::Toml,
+            _ => ConfigFormat::Yaml,
+        }
+    }
+}
+
+impl fmt::Display for ConfigFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigFormat::Yaml => write!(f, "YAML"),
+            ConfigFormat::Toml => write!(f, "TOML"),
+        }
+    }
+}
+
+impl Config {
+    // Read and parse a configuration file, in the format given by its extension
+    pub fn load(path: &Path) -> Result<Config, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let mut config = Config::parse_with_includes(path, &content)?;
+        config
+            .split_patterns()
+            .map_err(|e| format!("Invalid configuration in {}: {}", path.display(), e))?;
+        config.add_group_mounts();
+        if config.discover_from_fstab {
+            config
+                .discover(Path::new(FSTAB))
+                .map_err(|e| format!("Failed to discover mounts from {}: {}", FSTAB, e))?;
+        }
+        config
+            .validate()
+            .map_err(|e| format!("Invalid configuration in {}: {}", path.display(), e))?;
+        Ok(config)
+    }
+
+    // Move the glob and regex entries out of mount_points, which only keeps plain paths
+    pub fn split_patterns(&mut self) -> Result<(), String> {
+        let mut mount_points = Vec::new();
+        for mount in self.mount_points.drain(..) {
+            match MountPattern::new(&mount)? {
+                Some(pattern) => self.patterns.push(pattern),
+                None => mount_points.push(mount),
+            }
+        }
+        self.mount_points = mount_points;
+        Ok(())
+    }
+
+    // Monitor the members of each group even if they aren't listed in mount_points
+    pub fn add_group_mounts(&mut self) {
+        for group in self.groups.values() {
+            for path in &group.mount_points {
+                if !self.mount_points.iter().any(|m| &m.path == path) {
+                    self.mount_points.push(MountPoint {
+                        path: path.clone(),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+    }
+
+    // Whether a mount is covered by the global command for coming up, or for going
+    // down, rather than by its own or its group's. A mount or group with a command of
+    // its own for only one of them still falls back to the global one for the other.
+    pub fn uses_global_command(&self, mount: &MountPoint, up: bool) -> bool {
+        !mount.has_hook(up)
+            && !self.groups.values().any(|g| {
+                let hook = if up {
+                    &g.all_mounted_cmd
+                } else {
+                    &g.any_unmounted_cmd
+                };
+                hook.is_some() && g.mount_points.contains(&mount.path)
+            })
+    }
+
+    // Whether a mount is covered by either of the global commands
+    pub fn uses_global_commands(&self, mount: &MountPoint) -> bool {
+        self.uses_global_command(mount, true) || self.uses_global_command(mount, false)
+    }
+
+    // The mounts in the mount table that patterns or discover_fs_types say to monitor,
+    // whether or not they are already configured
+    pub fn matching_mounts(&self, mount_table: &BTreeMap<PathBuf, String>) -> Vec<MountPoint> {
+        mount_table
+            .iter()
+            .filter_map(|(path, fs_type)| {
+                let path = path.to_string_lossy();
+                let pattern = self.patterns.iter().find(|p| {
+                    let fs_types = self.fs_types(&p.template);
+                    p.matches(&path) && (fs_types.is_empty() || fs_types.contains(fs_type))
+                });
+                if let Some(pattern) = pattern {
+                    Some(MountPoint {
+                        path: path.into_owned(),
+                        regex: None,
+                        ..pattern.template.clone()
+                    })
+                } else if self.discover_fs_types.contains(fs_type) {
+                    // Whatever replaces it at the same path isn't the mount that was found
+                    Some(MountPoint {
+                        path: path.into_owned(),
+                        fs_types: Some(vec![fs_type.clone()]),
+                        ..Default::default()
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    // Add the matching mounts from the mount table that aren't already configured, for a
+    // one-off check of everything the daemon would monitor right now
+    pub fn expand(&mut self, mount_table: &BTreeMap<PathBuf, String>) {
+        for mount in self.matching_mounts(mount_table) {
+            if !self.mount_points.iter().any(|m| m.path == mount.path) {
+                self.mount_points.push(mount);
+            }
+        }
+    }
+
+    // Add the NFS mounts from an fstab that aren't already configured
+    pub fn discover(&mut self, fstab: &Path) -> Result<(), String> {
+        let pattern = self
+            .fstab_pattern
+            .as_deref()
+            .map(glob::Pattern::new)
+            .transpose()
+            .map_err(|e| format!("invalid fstab_pattern: {}", e))?;
+        let discovered = fstab_mount_points(fstab).map_err(|e| e.to_string())?;
+        for path in discovered {
+            if pattern.as_ref().is_some_and(|p| !p.matches(&path)) {
+                continue;
+            }
+            if !self.mount_points.iter().any(|m| m.path == path) {
+                self.mount_points.push(MountPoint {
+                    path,
+                    ..Default::default()
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Every hook in the config, each with the setting it comes from
+    pub fn hooks(&self) -> Vec<(String, &Hook)> {
+        let mut hooks = vec![
+            ("all_mounted_cmd".to_string(), &self.all_mounted_cmd),
+            ("any_unmounted_cmd".to_string(), &self.any_unmounted_cmd),
+        ];
+        let optional = [
+            ("on_stale_cmd", &self.on_stale_cmd),
+            ("on_degraded_cmd", &self.on_degraded_cmd),
+            ("on_server_down_cmd", &self.on_server_down_cmd),
+            ("on_readonly_cmd", &self.on_readonly_cmd),
+            ("on_option_drift_cmd", &self.on_option_drift_cmd),
+            ("server_down_cmd", &self.server_down_cmd),
+            ("server_up_cmd", &self.server_up_cmd),
+            ("on_start", &self.on_start),
+            ("on_stop", &self.on_stop),
+        ];
+        for (name, hook) in optional {
+            hooks.extend(hook.iter().map(|h| (name.to_string(), h)));
+        }
+        for mount in self
+            .mount_points
+            .iter()
+            .chain(self.patterns.iter().map(|p| &p.template))
+        {
+            let own = [
+                ("on_mounted", &mount.on_mounted),
+                ("on_unmounted", &mount.on_unmounted),
+                ("on_stale", &mount.on_stale),
+                ("on_degraded", &mount.on_degraded),
+                ("on_server_down", &mount.on_server_down),
+                ("on_readonly", &mount.on_readonly),
+                ("on_option_drift", &mount.on_option_drift),
+            ];
+            for (name, hook) in own {
+                hooks.extend(
+                    hook.iter()
+                        .map(|h| (format!("{} of {}", name, mount.path), h)),
+                );
+            }
+            for (name, thresholds) in [
+                ("disk_usage", &mount.disk_usage),
+                ("inode_usage", &mount.inode_usage),
+            ] {
+                if let Some(command) = thresholds.as_ref().and_then(|t| t.command.as_ref()) {
+                    hooks.push((format!("{} command of {}", name, mount.path), command));
+                }
+            }
+        }
+        for (group_name, group) in &self.groups {
+            let own = [
+                ("all_mounted_cmd", &group.all_mounted_cmd),
+                ("any_unmounted_cmd", &group.any_unmounted_cmd),
+            ];
+            for (name, hook) in own {
+                hooks.extend(
+                    hook.iter()
+                        .map(|h| (format!("{} of group {}", name, group_name), h)),
+                );
+            }
+        }
+        if let Some(reminders) = &self.reminders {
+            for (i, escalation) in reminders.escalations.iter().enumerate() {
+                hooks.extend(
+                    escalation
+                        .command
+                        .iter()
+                        .map(|h| (format!("escalation {}", i + 1), h)),
+                );
+            }
+        }
+        for (name, thresholds) in [
+            ("disk_usage", &self.disk_usage),
+            ("inode_usage", &self.inode_usage),
+        ] {
+            if let Some(command) = thresholds.as_ref().and_then(|t| t.command.as_ref()) {
+                hooks.push((format!("command of {}", name), command));
+            }
+        }
+        for (i, alert) in self.latency_alerts.iter().enumerate() {
+            hooks.extend(
+                alert
+                    .command
+                    .iter()
+                    .map(|h| (format!("latency alert {}", i + 1), h)),
+            );
+        }
+        if let Some(command) = self.dns_check.as_ref().and_then(|d| d.command.as_ref()) {
+            hooks.push(("dns_check command".to_string(), command));
+        }
+        if let Some(command) = self
+            .kerberos_check
+            .as_ref()
+            .and_then(|k| k.command.as_ref())
+        {
+            hooks.push(("kerberos_check command".to_string(), command));
+        }
+        hooks
+    }
+
+    // Every command in the config, each with the setting it comes from
+    pub fn commands(&self) -> Vec<(String, &CommandLine)> {
+        let mut commands: Vec<(String, &CommandLine)> = self
+            .hooks()
+            .into_iter()
+            .flat_map(|(name, hook)| hook.commands.iter().map(move |c| (name.clone(), c)))
+            .collect();
+        for mount in self
+            .mount_points
+            .iter()
+            .chain(self.patterns.iter().map(|p| &p.template))
+        {
+            if let Some(Remount::Command(command)) = &mount.remount {
+                commands.push((format!("remount of {}", mount.path), command));
+            }
+        }
+        if let Some(command) = self.heartbeat.as_ref().and_then(|h| h.command.as_ref()) {
+            commands.push(("heartbeat".to_string(), command));
+        }
+        commands
+    }
+
+    // Check the parsed configuration makes sense before it is put to use
+    pub fn validate(&self) -> Result<(), String> {
+        if self.mount_points.is_empty()
+            && self.patterns.is_empty()
+            && self.discover_fs_types.is_empty()
+        {
+            return Err("no mount points are configured".to_string());
+        }
+        let hooks = self.hooks();
+        if self.commands().iter().any(|(_, c)| c.is_empty()) {
+            return Err("commands given as a list need at least the program to run".to_string());
+        }
+        for run_as in hooks.iter().filter_map(|(_, h)| h.run_as.as_ref()) {
+            credentials(run_as)?;
+        }
+        let templates = self.patterns.iter().map(|p| &p.template);
+        for mount in self.mount_points.iter().chain(templates) {
+            // A pattern is named by its regex, if it has no glob for a path
+            let name = mount.regex.as_deref().unwrap_or(&mount.path);
+            // Prometheus is the strictest about label names
+            let bad_label = mount.labels.keys().find(|name| {
+                !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                    || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    || RESERVED_LABELS.contains(&name.as_str())
+            });
+            if let Some(label) = bad_label {
+                return Err(format!(
+                    "label {:?} of {} isn't allowed: labels are letters, digits and \
+                     underscores, not starting with a digit, and not one of {}",
+                    label,
+                    name,
+                    RESERVED_LABELS.join(", ")
+                ));
+            }
+            if let Some(dependent) = mount
+                .dependents
+                .iter()
+                .find(|d| d.unit.is_some() == d.container.is_some())
+            {
+                return Err(format!(
+                    "dependents of {} need a unit or a container, not {}",
+                    name,
+                    if dependent.unit.is_some() {
+                        "both"
+                    } else {
+                        "neither"
+                    }
+                ));
+            }
+        }
+        if self.delay_seconds == 0 || self.mount_points.iter().any(|m| m.interval == Some(0)) {
+            return Err("delay_seconds and interval must be at least 1".to_string());
+        }
+        if self.probe_timeout_seconds == 0 {
+            return Err("probe_timeout_seconds must be at least 1".to_string());
+        }
+        if self.server_timeout_ms == 0 || self.tcp_precheck_timeout_ms == 0 {
+            return Err(
+                "server_timeout_ms and tcp_precheck_timeout_ms must be at least 1".to_string(),
+            );
+        }
+        if let Some(file) = &self.canary_file {
+            if Path::new(file).is_absolute() {
+                return Err(format!(
+                    "canary_file must be relative to the mount point, not {}",
+                    file
+                ));
+            }
+        }
+        if let Some(dns) = &self.dns_check {
+            if dns.interval_seconds == 0 || dns.timeout_seconds == 0 {
+                return Err(
+                    "dns_check interval_seconds and timeout_seconds must be at least 1".to_string(),
+                );
+            }
+        }
+        if let Some(heartbeat) = &self.heartbeat {
+            if heartbeat.command.is_some() == heartbeat.url.is_some() {
+                return Err("heartbeat needs either a command or a url".to_string());
+            }
+            if heartbeat.interval_seconds == 0 {
+                return Err("heartbeat interval_seconds must be at least 1".to_string());
+            }
+        }
+        if let Some(metrics) = &self.metrics {
+            if metrics.statsd.is_none() && metrics.influxdb.is_none() {
+                return Err("metrics needs statsd or influxdb".to_string());
+            }
+            if metrics.flush_interval_seconds == 0 {
+                return Err("metrics flush_interval_seconds must be at least 1".to_string());
+            }
+        }
+        if let Some(reminders) = &self.reminders {
+            if reminders.interval_seconds == 0 {
+                return Err("reminders interval_seconds must be at least 1".to_string());
+            }
+            let after: Vec<u64> = reminders
+                .escalations
+                .iter()
+                .map(|e| e.after_seconds)
+                .collect();
+            if after.windows(2).any(|w| w[0] >= w[1]) {
+                return Err("escalations must be in order of after_seconds".to_string());
+            }
+        }
+        for alert in &self.latency_alerts {
+            if !(alert.percentile > 0.0 && alert.percentile <= 100.0) {
+                return Err("latency alert percentile must be above 0 and at most 100".to_string());
+            }
+            if alert.for_seconds == 0 {
+                return Err("latency alert for_seconds must be at least 1".to_string());
+            }
+        }
+        if self.maintenance.iter().any(|w| w.duration_minutes == 0) {
+            return Err("maintenance duration_minutes must be at least 1".to_string());
+        }
+        if self.command_timeout_seconds == 0 {
+            return Err("command_timeout_seconds must be at least 1".to_string());
+        }
+        if self.flap_threshold.is_some_and(|t| t < 2) {
+            return Err("flap_threshold must be at least 2".to_string());
+        }
+        if self.probe_workers == 0 {
+            return Err("probe_workers must be at least 1".to_string());
+        }
+        for (name, group) in &self.groups {
+            if group.mount_points.is_empty() {
+                return Err(format!("group {} has no mount points", name));
+            }
+            if group.quorum() == 0 || group.quorum() > group.mount_points.len() {
+                return Err(format!(
+                    "quorum for group {} must be between 1 and its {} mount points",
+                    name,
+                    group.mount_points.len()
+                ));
+            }
+        }
+        for (i, mount) in self.mount_points.iter().enumerate() {
+            if mount.path.is_empty() {
+                return Err(format!("mount point {} needs a path or a regex", i + 1));
+            }
+            if self.failures_before_down(mount) == 0 || self.successes_before_up(mount) == 0 {
+                return Err(format!(
+                    "failures_before_down and successes_before_up for {} must be at least 1",
+                    mount.path
+                ));
+            }
+            if let (Some(warn), Some(critical)) =
+                (self.latency_warn(mount), self.latency_critical(mount))
+            {
+                if warn >= critical {
+                    return Err(format!(
+                        "latency_warn_ms for {} must be below latency_critical_ms",
+                        mount.path
+                    ));
+                }
+            }
+            self.disk_usage(mount).validate("disk_usage", &mount.path)?;
+            self.inode_usage(mount)
+                .validate("inode_usage", &mount.path)?;
+            if self.mount_points[..i].iter().any(|m| m.path == mount.path) {
+                return Err(format!(
+                    "mount point {} is listed more than once",
+                    mount.path
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn command_timeout(&self) -> Duration {
+        Duration::from_secs(self.command_timeout_seconds)
+    }
+
+    // Where the state is kept across restarts
+    pub fn state_file(&self) -> PathBuf {
+        self.state_file
+            .as_deref()
+            .map_or_else(default_state_file, PathBuf::from)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout_seconds)
+    }
+
+    // How many times a failed command is retried
+    pub fn retries(&self, hook: &Hook) -> u32 {
+        hook.retries.unwrap_or(self.command_retries)
+    }
+
+    // The delay before a failed command's first retry
+    pub fn backoff(&self, hook: &Hook) -> Duration {
+        Duration::from_secs(hook.backoff_seconds.unwrap_or(self.command_backoff_seconds))
+    }
+
+    // How long after running before a command may run again
+    pub fn cooldown(&self, hook: &Hook) -> Duration {
+        Duration::from_secs(
+            hook.cooldown_seconds
+                .unwrap_or(self.command_cooldown_seconds),
+        )
+    }
+
+    pub fn probe_timeout(&self) -> Duration {
+        Duration::from_secs(self.probe_timeout_seconds)
+    }
+
+    pub fn flap_window(&self) -> Duration {
+        Duration::from_secs(self.flap_window_seconds)
+    }
+
+    // How often a mount is checked
+    pub fn interval(&self, mount: &MountPoint) -> Duration {
+        Duration::from_secs(mount.interval.unwrap_or(self.delay_seconds))
+    }
+
+    // The most often any mount is checked, which is how often the daemon wakes up
+    pub fn shortest_interval(&self) -> Duration {
+        self.mount_points
+            .iter()
+            .map(|m| self.interval(m))
+            .min()
+            .unwrap_or(Duration::from_secs(self.delay_seconds))
+    }
+
+    // How many failed checks in a row it takes to consider a mount down
+    pub fn failures_before_down(&self, mount: &MountPoint) -> u32 {
+        mount
+            .failures_before_down
+            .unwrap_or(self.failures_before_down)
+    }
+
+    // Stopping a mount's dependents, last first, or starting them in order and
+    // waiting for each to be ready, as a hook to queue like any other
+    pub fn dependents_hook(&self, mount: &MountPoint, up: bool) -> Option<Hook> {
+        if mount.dependents.is_empty() {
+            return None;
+        }
+        let commands = if up {
+            mount
+                .dependents
+                .iter()
+                .flat_map(|d| {
+                    // systemd has already waited for a unit without a ready_cmd
+                    let wait = (d.container.is_some() || d.ready_cmd.is_some())
+                        .then(|| CommandLine::Ready(Box::new(d.clone())));
+                    std::iter::once(d.action("start")).chain(wait)
+                })
+                .collect()
+        } else {
+            mount
+                .dependents
+                .iter()
+                .rev()
+                .map(|d| d.action("stop"))
+                .collect()
+        };
+        Some(Hook {
+            commands,
+            // Stop as many as can be, but start none whose dependencies didn't
+            on_error: if up { OnError::Stop } else { OnError::Continue },
+            retries: None,
+            backoff_seconds: None,
+            cooldown_seconds: Some(0),
+            run_as: None,
+        })
+    }
+
+    // The filesystem types the mount is expected to be, any if empty
+    pub fn fs_types<'a>(&'a self, mount: &'a MountPoint) -> &'a [String] {
+        mount.fs_types.as_deref().unwrap_or(&self.fs_types)
+    }
+
+    // The canary file to round-trip on each check of a mount, if any
+    pub fn canary_file(&self, mount: &MountPoint) -> Option<PathBuf> {
+        let file = mount.canary_file.as_ref().or(self.canary_file.as_ref())?;
+        Some(Path::new(&mount.path).join(file))
+    }
+
+    // Whether a mount is meant to be read-only, which unless said otherwise is
+    // whether fstab mounts it so
+    pub fn read_only(&self, mount: &MountPoint) -> bool {
+        mount.read_only.unwrap_or_else(|| {
+            fstab_options(&mount.path, mount.namespace.as_deref().map(Path::new))
+                .is_some_and(|options| is_read_only(&options))
+        })
+    }
+
+    // The options a mount should be mounted with
+    pub fn expected_options<'a>(&'a self, mount: &'a MountPoint) -> &'a [ExpectedOption] {
+        mount
+            .expected_options
+            .as_deref()
+            .unwrap_or(&self.expected_options)
+    }
+
+    // What probing a mount looks for
+    pub fn target<'a>(&'a self, mount: &'a MountPoint) -> Target<'a> {
+        let target = Target::new(&mount.path, self.fs_types(mount));
+        Target {
+            canary: self.canary_file(mount),
+            ping_server: mount.ping_server.unwrap_or(self.ping_server),
+            server_timeout: Duration::from_millis(self.server_timeout_ms),
+            tcp_precheck: mount
+                .tcp_precheck
+                .unwrap_or(self.tcp_precheck)
+                .then(|| Duration::from_millis(self.tcp_precheck_timeout_ms)),
+            rpcbind_check: mount.rpcbind_check.unwrap_or(self.rpcbind_check),
+            trigger_automount: mount.trigger_automount.unwrap_or(self.trigger_automount),
+            namespace: mount
+                .namespace
+                .as_deref()
+                .map(Path::new)
+                .or(target.namespace),
+            ..target
+        }
+    }
+
+    // How many successful checks in a row it takes to consider a mount back up
+    pub fn successes_before_up(&self, mount: &MountPoint) -> u32 {
+        mount
+            .successes_before_up
+            .unwrap_or(self.successes_before_up)
+    }
+
+    // How long a mount that came back must stay mounted to be considered back up
+    pub fn recovery_confirmation(&self, mount: &MountPoint) -> Duration {
+        Duration::from_secs(
+            mount
+                .recovery_confirmation_seconds
+                .unwrap_or(self.recovery_confirmation_seconds),
+        )
+    }
+
+    // How long checking a mount may take before it is degraded, and before it is as
+    // good as unresponsive
+    pub fn latency_warn(&self, mount: &MountPoint) -> Option<Duration> {
+        mount
+            .latency_warn_ms
+            .or(self.latency_warn_ms)
+            .map(Duration::from_millis)
+    }
+
+    pub fn latency_critical(&self, mount: &MountPoint) -> Option<Duration> {
+        mount
+            .latency_critical_ms
+            .or(self.latency_critical_ms)
+            .map(Duration::from_millis)
+    }
+
+    pub fn disk_usage<'a>(&'a self, mount: &'a MountPoint) -> UsageLimits<'a> {
+        UsageLimits::new(mount.disk_usage.as_ref(), self.disk_usage.as_ref())
+    }
+
+    pub fn inode_usage<'a>(&'a self, mount: &'a MountPoint) -> UsageLimits<'a> {
+        UsageLimits::new(mount.inode_usage.as_ref(), self.inode_usage.as_ref())
+    }
+
+    // How to remount a mount point, if it should be remounted at all
+    pub fn remount_action(&self, mount: &MountPoint) -> Option<RemountAction> {
+        match &mount.remount {
+            Some(Remount::Command(command)) => Some(RemountAction::Command(command.clone())),
+            Some(Remount::Enabled(true)) => Some(RemountAction::Mount),
+            Some(Remount::Enabled(false)) => None,
+            None => self.remount.then_some(RemountAction::Mount),
+        }
+    }
+
+    // A config monitoring the given mount points with the given commands, if any, and
+    // everything else at its default, for running without a config file
+    pub fn for_mounts(
+        mount_points: &[String],
+        delay_seconds: u64,
+        all_mounted_cmd: Option<&str>,
+        any_unmounted_cmd: Option<&str>,
+    ) -> Result<Config, String> {
+        let mut config: Config = serde_json::from_value(json!({
+            "mount_points": mount_points,
+            "delay_seconds": delay_seconds,
+            "all_mounted_cmd": all_mounted_cmd.unwrap_or_default(),
+            "any_unmounted_cmd": any_unmounted_cmd.unwrap_or_default(),
+        }))
+        .map_err(|e| e.to_string())?;
+        // A command not given runs nothing at all, rather than an empty one
+        if all_mounted_cmd.is_none() {
+            config.all_mounted_cmd.commands.clear();
+        }
+        if any_unmounted_cmd.is_none() {
+            config.any_unmounted_cmd.commands.clear();
+        }
+        config.split_patterns()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Parse a config file and merge the files it includes over it, in order
+    fn parse_with_includes(path: &Path, content: &str) -> Result<Config, String> {
+        let failed = |path: &Path, e: String| format!("Failed to parse {}: {}", path.display(), e);
+        let format = ConfigFormat::from_path(path);
+        let mut merged = parse_value(content, format).map_err(|e| failed(path, e))?;
+        let Some(include) = merged.get("include").cloned() else {
+            // Parse it directly, for errors that say where in the file they are
+            return Config::parse(content, format).map_err(|e| failed(path, e));
+        };
+        let include: Include = serde_json::from_value(include)
+            .map_err(|_| failed(path, "include takes a path or a list of them".to_string()))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        for pattern in include.patterns() {
+            let pattern = dir.join(pattern);
+            let files = glob::glob(&pattern.to_string_lossy()).map_err(|e| {
+                failed(
+                    path,
+                    format!("invalid include {}: {}", pattern.display(), e),
+                )
+            })?;
+            for file in files {
+                let file = file.map_err(|e| format!("Failed to read {}", e))?;
+                let content = fs::read_to_string(&file)
+                    .map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+                let fragment = parse_value(&content, ConfigFormat::from_path(&file))
+                    .map_err(|e| failed(&file, e))?;
+                if fragment.get("include").is_some() {
+                    return Err(failed(
+                        &file,
+                        "included files can't include others".to_string(),
+                    ));
+                }
+                // An empty file adds nothing
+                if !fragment.is_null() {
+                    merge(&mut merged, fragment);
+                }
+            }
+        }
+        serde_json::from_value(merged).map_err(|e| failed(path, e.to_string()))
+    }
+
+    // Parse configuration content in the given format
+    pub fn parse(content: &str, format: ConfigFormat) -> Result<Config, String> {
+        match format {
+            ConfigFormat::Yaml => serde_yml::from_str(content).map_err(|e| e.to_string()),
+            ConfigFormat::Toml => toml::from_str(content).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+// Path to the configuration file should default to $XDG_CONFIG_HOME/nofus/config.yml
+// (~/.config/nofus/config.yml) for a user instance, or /etc/nofus/config.yml for root
+// or with no user context. A config.toml in the same directory is used instead when
+// present and there is no config.yml.
+pub fn default_path() -> PathBuf {
+    let dir = user_instance()
+        .then(|| xdg_dir("XDG_CONFIG_HOME", ".config"))
+        .flatten()
+        .unwrap_or_else(|| PathBuf::from("/etc/nofus"));
+    let yaml = dir.join("config.yml");
+    let toml = dir.join("config.toml");
+    if !yaml.exists() && toml.exists() {
+        toml
+    } else {
+        yaml
+    }
+}
+
+// Whether this is a user's own nofus, e.g. a systemd user service watching the
+// user's sshfs mounts, rather than the system's, which keeps its files in the user's
+// XDG directories
+pub fn user_instance() -> bool {
+    unsafe { libc::geteuid() != 0 }
+}
+
+// The nofus directory under an XDG base directory, or under its default in HOME when
+// the variable isn't set
+fn xdg_dir(var: &str, default: &str) -> Option<PathBuf> {
+    let base = match env::var_os(var) {
+        // The spec says to ignore relative paths
+        Some(dir) if Path::new(&dir).is_absolute() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME").filter(|h| !h.is_empty())?).join(default),
+    };
+    Some(base.join("nofus"))
+}
+
+// Where a file the daemon keeps goes unless told otherwise: under /var/lib/nofus, or
+// $XDG_STATE_HOME/nofus (~/.local/state/nofus) for a user instance
+fn state_path(system: &str, name: &str) -> PathBuf {
+    user_instance()
+        .then(|| xdg_dir("XDG_STATE_HOME", ".local/state"))
+        .flatten()
+        .map_or_else(|| PathBuf::from(system), |dir| dir.join(name))
+}
+
+pub fn default_state_file() -> PathBuf {
+    state_path(STATE_FILE, "state.json")
+}
+
+pub fn default_history_file() -> PathBuf {
+    state_path(HISTORY_FILE, "history.db")
+}
+
+// Where a file that only lasts as long as the session goes for a user instance, in
+// $XDG_RUNTIME_DIR, which has no default to fall back on. macOS has no such thing,
+// but gives each user a TMPDIR of their own.
+pub fn runtime_path(name: &str) -> Option<PathBuf> {
+    if !user_instance() {
+        return None;
+    }
+    let dir = env::var_os("XDG_RUNTIME_DIR")
+        .or_else(|| env::var_os("TMPDIR").filter(|_| cfg!(target_os = "macos")))
+        .filter(|d| Path::new(d).is_absolute())?;
+    Some(PathBuf::from(dir).join(name))
+}
+
+// Read a config file without making sense of it yet, so others can be merged into it
+fn parse_value(content: &str, format: ConfigFormat) -> Result<Value, String> {
+    match format {
+        ConfigFormat::Yaml => serde_yml::from_str(content).map_err(|e| e.to_string()),
+        ConfigFormat::Toml => toml::from_str(content).map_err(|e| e.to_string()),
+    }
+}
+
+// Merge an included file over the config so far: maps key by key, with mount_points
+// lists added to and anything else replaced
+fn merge(config: &mut Value, fragment: Value) {
+    match (config, fragment) {
+        (Value::Object(config), Value::Object(fragment)) => {
+            for (key, value) in fragment {
+                match (config.get_mut(&key), value) {
+                    (Some(Value::Array(list)), Value::Array(more)) if key == "mount_points" => {
+                        list.extend(more)
+                    }
+                    (Some(existing), value) => merge(existing, value),
+                    (None, value) => {
+                        config.insert(key, value);
+                    }
+                }
+            }
+        }
+        (config, fragment) => *config = fragment,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str) -> Result<Config, String> {
+        let mut config = Config::parse(yaml, ConfigFormat::Yaml)?;
+        config.split_patterns()?;
+        config.add_group_mounts();
+        Ok(config)
+    }
+
+    const BASE: &str = "delay_seconds: 5\nall_mounted_cmd: up\nany_unmounted_cmd: down\n";
+
+    #[test]
+    fn global_commands_cover_each_event_separately() {
+        let config = parse(&format!(
+            "{}mount_points:\n  - /mnt/plain\n  - path: /mnt/down\n    on_unmounted: own\n",
+            BASE
+        ))
+        .unwrap();
+        let covered = |path: &str, up| {
+            let mount = config.mount_points.iter().find(|m| m.path == path).unwrap();
+            config.uses_global_command(mount, up)
+        };
+        assert!(covered("/mnt/plain", true) && covered("/mnt/plain", false));
+        assert!(covered("/mnt/down", true) && !covered("/mnt/down", false));
+    }
+
+    #[test]
+    fn hook_errors_say_where() {
+        let yaml = "mount_points: [/mnt/a]\ndelay_seconds: 5\nany_unmounted_cmd: down\nall_mounted_cmd:\n  on_error: continue\n";
+        let error = parse(yaml).unwrap_err();
+        assert!(
+            error.contains("give either a command or a list of commands"),
+            "{}",
+            error
+        );
+        assert!(error.contains("line 5"), "{}", error);
+
+        let yaml = format!(
+            "{}mount_points: [/mnt/a]\non_start:\n  command: x\n  retries: lots\n",
+            BASE
+        );
+        let error = parse(&yaml).unwrap_err();
+        assert!(
+            error.contains("expected u32") && error.contains("line 6"),
+            "{}",
+            error
+        );
+
+        // Including those of a mount point's own hooks
+        let yaml = format!(
+            "{}mount_points:\n  - /mnt/a\n  - path: /mnt/b\n    on_unmounted:\n      retries: 2\n",
+            BASE
+        );
+        let error = parse(&yaml).unwrap_err();
+        assert!(
+            error.contains("give either a command or a list of commands")
+                && error.contains("line 8"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn toml_reads_the_same() {
+        let config = Config::parse(
+            "mount_points = [\"/mnt/a\"]\ndelay_seconds = 5\nall_mounted_cmd = \"up\"\nany_unmounted_cmd = [\"down\", \"now\"]\n",
+            ConfigFormat::Toml,
+        )
+        .unwrap();
+        assert_eq!(config.mount_points[0].path, "/mnt/a");
+        assert_eq!(config.any_unmounted_cmd.commands.len(), 1);
+    }
+
+    #[test]
+    fn mount_points_as_paths_or_entries() {
+        let config = parse(&format!(
+            "{}mount_points:\n  - /mnt/a\n  - path: /mnt/b\n    on_unmounted: stop b\n  - /mnt/nfs/*\n",
+            BASE
+        ))
+        .unwrap();
+        let paths: Vec<&str> = config
+            .mount_points
+            .iter()
+            .map(|m| m.path.as_str())
+            .collect();
+        assert_eq!(paths, ["/mnt/a", "/mnt/b"]);
+        assert_eq!(config.patterns.len(), 1);
+        let hook = config.mount_points[1].on_unmounted.as_ref().unwrap();
+        assert_eq!(hook.commands, [CommandLine::Shell("stop b".to_string())]);
+    }
+
+    #[test]
+    fn includes_merge_over_the_main_file() {
+        let mut config = json!({
+            "delay_seconds": 5,
+            "mount_points": ["/mnt/a"],
+            "notifications": { "webhook": { "url": "http://a", "retries": 3 } },
+        });
+        merge(
+            &mut config,
+            json!({
+                "delay_seconds": 10,
+                "mount_points": ["/mnt/b"],
+                "notifications": { "webhook": { "url": "http://b" } },
+            }),
+        );
+        assert_eq!(config["delay_seconds"], 10);
+        assert_eq!(config["mount_points"], json!(["/mnt/a", "/mnt/b"]));
+        assert_eq!(config["notifications"]["webhook"]["url"], "http://b");
+        assert_eq!(config["notifications"]["webhook"]["retries"], 3);
+    }
+
+    #[test]
+    fn label_names_are_checked() {
+        let labels = |name: &str| {
+            parse(&format!(
+                "{}mount_points:\n  - path: /mnt/a\n    labels:\n      {}: x\n",
+                BASE, name
+            ))
+            .unwrap()
+            .validate()
+        };
+        assert!(labels("team").is_ok());
+        assert!(labels("mount").is_err());
+        assert!(labels("1st").is_err());
+        assert!(labels("has-dash").is_err());
+    }
+
+    #[test]
+    fn argv_commands_are_kept_apart() {
+        let config = parse(&format!(
+            "{}mount_points: [/mnt/a]\non_start: [systemctl, start, my app]\n",
+            BASE
+        ))
+        .unwrap();
+        let start = config.on_start.unwrap();
+        assert_eq!(
+            start.commands,
+            [CommandLine::Argv(vec![
+                "systemctl".to_string(),
+                "start".to_string(),
+                "my app".to_string()
+            ])]
+        );
+        let empty = parse(&format!("{}mount_points: [/mnt/a]\non_start: []\n", BASE));
+        assert!(empty.unwrap().validate().is_err());
+    }
+
+    #[test]
+    fn hooks_with_several_commands() {
+        let config = parse(&format!(
+            "{}mount_points: [/mnt/a]\non_stop:\n  commands:\n    - one\n    - [two, three]\n  on_error: continue\n",
+            BASE
+        ))
+        .unwrap();
+        let stop = config.on_stop.unwrap();
+        assert_eq!(
+            stop.commands,
+            [
+                CommandLine::Shell("one".to_string()),
+                CommandLine::Argv(vec!["two".to_string(), "three".to_string()])
+            ]
+        );
+        assert_eq!(stop.on_error, OnError::Continue);
+        assert_eq!(config.all_mounted_cmd.on_error, OnError::Stop);
+        let both = format!(
+            "{}mount_points: [/mnt/a]\non_stop:\n  command: one\n  commands: [two]\n",
+            BASE
+        );
+        assert!(parse(&both).is_err());
+    }
+
+    #[test]
+    fn dependents_need_a_unit_or_a_container() {
+        let dependents = |path: &str, dependent: &str| {
+            parse(&format!(
+                "{}mount_points:\n  - path: {}\n    dependents:\n      - {}\n",
+                BASE, path, dependent
+            ))
+            .unwrap()
+            .validate()
+        };
+        assert!(dependents("/mnt/a", "unit: app.service").is_ok());
+        assert!(dependents("/mnt/a", "container: app").is_ok());
+        let both = dependents("/mnt/a", "{unit: app.service, container: app}");
+        assert!(both.unwrap_err().contains("not both"));
+        // Patterns are checked before they match anything
+        let both = dependents("/mnt/*", "{unit: app.service, container: app}");
+        assert!(both.unwrap_err().contains("dependents of /mnt/*"));
+        let neither = dependents("/mnt/*", "ready_cmd: ready");
+        assert!(neither.unwrap_err().contains("not neither"));
+    }
+
+    #[test]
+    fn unit_actions_read_as_commands() {
+        let config = parse(&format!(
+            "{}mount_points: [/mnt/a]\non_stop:\n  commands:\n    - stop_units: [app.service]\n",
+            BASE
+        ))
+        .unwrap();
+        let stop = config.on_stop.unwrap();
+        assert!(matches!(&stop.commands[0], CommandLine::Units(u) if u.stop == ["app.service"]));
+    }
+
+    #[test]
+    fn groups_stand_in_for_the_global_commands_they_have() {
+        let config = parse(&format!(
+            "{}mount_points: [/mnt/a, /mnt/b, /mnt/c]\ngroups:\n  both:\n    mount_points: [/mnt/a]\n    all_mounted_cmd: up\n    any_unmounted_cmd: down\n  down:\n    mount_points: [/mnt/b]\n    any_unmounted_cmd: down\n",
+            BASE
+        ))
+        .unwrap();
+        let covered = |path: &str, up| {
+            let mount = config.mount_points.iter().find(|m| m.path == path).unwrap();
+            config.uses_global_command(mount, up)
+        };
+        assert!(!covered("/mnt/a", true) && !covered("/mnt/a", false));
+        assert!(covered("/mnt/b", true) && !covered("/mnt/b", false));
+        assert!(covered("/mnt/c", true) && covered("/mnt/c", false));
+    }
+
+    #[test]
+    fn commands_left_out_run_nothing() {
+        let paths = ["/mnt/a".to_string()];
+        let config = Config::for_mounts(&paths, 5, None, Some("down")).unwrap();
+        assert!(config.all_mounted_cmd.commands.is_empty());
+        assert_eq!(
+            config.any_unmounted_cmd.commands,
+            [CommandLine::Shell("down".to_string())]
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use crate::config::ContainerAction;
+use log::info;
+use serde_json::{json, Value};
+use std::env;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// Where Docker and a rootful Podman listen, tried in order when no socket is given
+const SOCKETS: [&str; 2] = ["/var/run/docker.sock", "/run/podman/podman.sock"];
+
+// The most of a response taken, which is far more than a list of containers needs
+const MAX_RESPONSE: u64 = 16 * 1024 * 1024;
+
+// Stop, pause, restart, unpause and start the containers of an action through the
+// Docker API, which Podman speaks too, one after the other. The timeout covers the
+// whole action.
+pub fn run(action: &ContainerAction, timeout: Duration) -> Result<(), String> {
+    let api = Api::new(action.socket.as_deref(), timeout)?;
+    for (verb, selector) in action.steps() {
+        let containers = match selector.strip_prefix("label=") {
+            Some(label) => {
+                let containers = api
+                    .labelled(label, verb)
+                    .map_err(|e| format!("Unable to find containers with {}: {}", selector, e))?;
+                if containers.is_empty() {
+                    let selector = selector.replace('\n', " or ");
+                    info!(event = "command"; "No containers with {} to {}", selector, verb);
+                }
+                containers
+            }
+            None => vec![selector.clone()],
+        };
+        for container in containers {
+            api.act(verb, &container)
+                .map_err(|e| format!("Unable to {} container {}: {}", verb, container, e))?;
+            info!(event = "command"; "Container {} {}", container, done(verb));
+        }
+    }
+    Ok(())
+}
+
+// Whether a container is running and, if it has a health check, healthy
+pub fn ready(container: &str, socket: Option<&str>, timeout: Duration) -> Result<(), String> {
+    let api = Api::new(socket, timeout)?;
+    let (status, body) = api.request("GET", &format!("/containers/{}/json", encode(container)))?;
+    if status != 200 {
+        return Err(message(&body));
+    }
+    let inspected: Value = serde_json::from_slice(&body).map_err(|e| e.to_string())?;
+    let state = &inspected["State"];
+    if state["Running"].as_bool() != Some(true) {
+        return Err(format!(
+            "container {} is {}",
+            container,
+            state["Status"].as_str().unwrap_or("not running")
+        ));
+    }
+    // Older Podman calls it Healthcheck, and has an empty status without one
+    let health = state["Health"]["Status"]
+        .as_str()
+        .or(state["Healthcheck"]["Status"].as_str());
+    match health {
+        None | Some("") | Some("healthy") => Ok(()),
+        Some(health) => Err(format!("container {} is {}", container, health)),
+    }
+}
+
+// DOCKER_HOST or CONTAINER_HOST if they name a Unix socket, or else the first of the
+// usual sockets that is there, including a rootless Podman's
+fn default_socket() -> Option<PathBuf> {
+    for var in ["DOCKER_HOST", "CONTAINER_HOST"] {
+        if let Some(path) = env::var(var)
+            .ok()
+            .and_then(|host| host.strip_prefix("unix://").map(PathBuf::from))
+        {
+            return Some(path);
+        }
+    }
+    let rootless = env::var_os("XDG_RUNTIME_DIR")
+        .map(|dir| Path::new(&dir).join("podman/podman.sock"))
+        .into_iter();
+    SOCKETS
+        .iter()
+        .map(PathBuf::from)
+        .chain(rootless)
+        .find(|path| path.exists())
+}
+
+fn done(verb: &str) -> &'static str {
+    match verb {
+        "stop" => "stopped",
+        "pause" => "paused",
+        "restart" => "restarted",
+        "unpause" => "unpaused",
+        _ => "started",
+    }
+}
+
+// The states a container can be acted on in, so a label doesn't pick up containers
+// already as wanted, which the API would refuse to pause or unpause
+fn actionable(verb: &str, state: &str) -> bool {
+    match verb {
+        "stop" => matches!(state, "running" | "paused" | "restarting"),
+        "pause" => state == "running",
+        "unpause" => state == "paused",
+        "start" => matches!(state, "created" | "exited"),
+        _ => matches!(state, "running" | "created" | "exited"),
+    }
+}
+
+struct Api {
+    socket: PathBuf,
+    deadline: Instant,
+}
+
+impl Api {
+    // The API on the socket given or found, for requests within the timeout
+    fn new(socket: Option<&str>, timeout: Duration) -> Result<Self, String> {
+        let socket = match socket {
+            Some(socket) => PathBuf::from(socket.strip_prefix("unix://").unwrap_or(socket)),
+            None => default_socket().ok_or("No Docker or Podman socket found")?,
+        };
+        Ok(Api {
+            socket,
+            deadline: Instant::now() + timeout,
+        })
+    }
+
+    // The containers with a label, as key=value or just the key, that can be acted on.
+    // A value of several lines, as for {{mounts}}, takes those with any of them.
+    fn labelled(&self, label: &str, verb: &str) -> Result<Vec<String>, String> {
+        let labels: Vec<String> = match label.split_once('=') {
+            Some((key, values)) => values
+                .lines()
+                .map(|value| format!("{}={}", key, value))
+                .collect(),
+            None => vec![label.to_string()],
+        };
+        let mut containers = Vec::new();
+        for label in labels {
+            let filters = json!({ "label": [label] }).to_string();
+            let path = format!("/containers/json?all=true&filters={}", encode(&filters));
+            let (status, body) = self.request("GET", &path)?;
+            if status != 200 {
+                return Err(message(&body));
+            }
+            let listed: Vec<Value> = serde_json::from_slice(&body).map_err(|e| e.to_string())?;
+            for container in listed {
+                let state = container["State"].as_str().unwrap_or_default();
+                // Named as the CLI would, without the leading slash
+                let name = container["Names"][0]
+                    .as_str()
+                    .map(|n| n.trim_start_matches('/'))
+                    .or(container["Id"].as_str())
+                    .unwrap_or_default()
+                    .to_string();
+                if actionable(verb, state) && !name.is_empty() && !containers.contains(&name) {
+                    containers.push(name);
+                }
+            }
+        }
+        Ok(containers)
+    }
+
+    fn act(&self, verb: &str, container: &str) -> Result<(), String> {
+        let path = format!("/containers/{}/{}", encode(container), verb);
+        match self.request("POST", &path)? {
+            // 304 is for a container already stopped or started
+            (204 | 304, _) => Ok(()),
+            (_, body) => Err(message(&body)),
+        }
+    }
+
+    // Make a request with no body, giving the status and body of the response
+    fn request(&self, method: &str, path: &str) -> Result<(u16, Vec<u8>), String> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err("timed out".to_string());
+        }
+        let mut stream = UnixStream::connect(&self.socket)
+            .map_err(|e| format!("unable to connect to {}: {}", self.socket.display(), e))?;
+        let timed_out = |e: io::Error| match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => "timed out".to_string(),
+            _ => e.to_string(),
+        };
+        stream
+            .set_read_timeout(Some(remaining))
+            .map_err(timed_out)?;
+        stream
+            .set_write_timeout(Some(remaining))
+            .map_err(timed_out)?;
+        // HTTP/1.0, so the response comes whole rather than chunked, and ends with the
+        // connection
+        write!(
+            stream,
+            "{} {} HTTP/1.0\r\nHost: localhost\r\nContent-Length: 0\r\n\r\n",
+            method, path
+        )
+        .map_err(timed_out)?;
+        let mut response = Vec::new();
+        stream
+            .take(MAX_RESPONSE)
+            .read_to_end(&mut response)
+            .map_err(timed_out)?;
+
+        let end = response
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .ok_or("malformed response")?;
+        let head = String::from_utf8_lossy(&response[..end]);
+        let status = head
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .ok_or("malformed response")?;
+        Ok((status, response[end + 4..].to_vec()))
+    }
+}
+
+// What the API said went wrong, which comes as {"message": ...}
+fn message(body: &[u8]) -> String {
+    serde_json::from_slice::<Value>(body)
+        .ok()
+        .and_then(|v| v["message"].as_str().map(str::to_string))
+        .unwrap_or_else(|| String::from_utf8_lossy(body).trim().to_string())
+}
+
+// Percent-encode anything but the characters left as they are in a URL
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
@@ -0,0 +1,210 @@
+use crate::config;
+use crate::monitor::MountState;
+use crate::systemd;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+// Where the daemon listens for control requests unless configured otherwise
+#[cfg(target_os = "linux")]
+pub const DEFAULT_SOCKET: &str = "/run/nofus/control.sock";
+#[cfg(not(target_os = "linux"))]
+pub const DEFAULT_SOCKET: &str = "/var/run/nofus/control.sock";
+
+// A request sent by a client, one JSON object per line
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    Status,
+    // Hold off commands and notifications for a while, for one mount or all of them
+    Silence {
+        seconds: u64,
+        mount: Option<String>,
+    },
+    // Lift silences early, those for one mount or all of them
+    Unsilence {
+        mount: Option<String>,
+    },
+    // Check every mount now, as SIGUSR2 does
+    Recheck,
+    // Take a mount to be in a state from now on, acting on it as if its checks found
+    // it so, or go back to checking it if no state is given
+    Trigger {
+        mount: String,
+        state: Option<MountState>,
+    },
+}
+
+// The daemon's reply to a request, one JSON object per line
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum Response {
+    Status { mounts: Vec<MountReport> },
+    Ok { message: String },
+    Error { message: String },
+}
+
+// The current status of a single mount point
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MountReport {
+    pub path: String,
+    pub state: MountState,
+    pub since: Option<String>,
+    pub last_command: Option<CommandReport>,
+    // How many commands for the mount are waiting to run or running
+    #[serde(default)]
+    pub queued: usize,
+    // Whether nothing is being run or sent for the mount because of a silence or a
+    // maintenance window
+    #[serde(default)]
+    pub silenced: bool,
+    // How long the last few checks took, and the last few state changes, oldest first
+    #[serde(default)]
+    pub latencies_ms: Vec<u64>,
+    #[serde(default)]
+    pub events: Vec<EventReport>,
+    // How long every check since starting took
+    #[serde(default)]
+    pub latency_histogram: HistogramReport,
+    // How much of the mount's space and inodes are used, when last checked
+    #[serde(default)]
+    pub disk_used_percent: Option<f64>,
+    #[serde(default)]
+    pub inodes_used_percent: Option<f64>,
+    // The server the mount comes from, if that can be told
+    #[serde(default)]
+    pub server: Option<String>,
+    // The labels of the mount in the config
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+// Checks counted by how long they took: counts[i] took at most buckets_ms[i], and the
+// last count, one past the buckets, is all of them
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HistogramReport {
+    pub buckets_ms: Vec<u64>,
+    pub counts: Vec<u64>,
+    pub sum_ms: f64,
+    pub count: u64,
+}
+
+// A state change of a mount point
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventReport {
+    pub at: String,
+    pub from: MountState,
+    pub to: MountState,
+    pub cause: String,
+}
+
+// The result of the last command run for a mount point
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandReport {
+    pub command: String,
+    pub at: String,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+// Listen on the socket in a background task, answering each request with the handler,
+// unless systemd passed one in. Gives whether the socket was made here.
+pub fn serve<F>(path: &Path, handler: F) -> io::Result<bool>
+where
+    F: Fn(Request) -> Response + Send + Sync + 'static,
+{
+    let inherited = systemd::take_listener(&[libc::AF_UNIX]);
+    let made = inherited.is_none();
+    let listener = match inherited {
+        Some(fd) => {
+            debug!("Using the control socket systemd passed in");
+            let listener = std::os::unix::net::UnixListener::from(fd);
+            listener.set_nonblocking(true)?;
+            tokio::net::UnixListener::from_std(listener)?
+        }
+        None => {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            // Clear out a socket left behind by a previous run
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+            tokio::net::UnixListener::bind(path)?
+        }
+    };
+    let path = path.to_path_buf();
+    let handler = Arc::new(handler);
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let handler = Arc::clone(&handler);
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_client(stream, &*handler).await {
+                            debug!("Control client error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Control socket {} error: {}", path.display(), e),
+            }
+        }
+    });
+    Ok(made)
+}
+
+async fn handle_client<F>(stream: tokio::net::UnixStream, handler: &F) -> io::Result<()>
+where
+    F: Fn(Request) -> Response,
+{
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    tokio::io::BufReader::new(reader)
+        .read_line(&mut line)
+        .await?;
+    let response = match serde_json::from_str(&line) {
+        Ok(request) => handler(request),
+        Err(e) => Response::Error {
+            message: format!("Invalid request: {}", e),
+        },
+    };
+    let mut line = serde_json::to_string(&response)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await
+}
+
+fn write_line<T: Serialize>(mut stream: UnixStream, value: &T) -> io::Result<()> {
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())
+}
+
+// Send a single request to the daemon and wait for its response
+pub fn request(path: &Path, request: &Request) -> io::Result<Response> {
+    let stream = UnixStream::connect(path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("Unable to connect to {}: {}", path.display(), e),
+        )
+    })?;
+    write_line(stream.try_clone()?, request)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+// The socket path from the config, or the default, which for a user instance is in
+// $XDG_RUNTIME_DIR
+pub fn socket_path(configured: Option<&str>) -> PathBuf {
+    configured
+        .map(PathBuf::from)
+        .or_else(|| config::runtime_path("nofus/control.sock"))
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_SOCKET))
+}
@@ -0,0 +1,505 @@
+use crate::config::Config;
+use crate::control::{self, MountReport, Request, Response};
+use crate::monitor::{Monitor, MountState};
+use crate::mounts::MountTable;
+use crate::platform::{ConfigWatcher, SOURCE_INTEREST};
+use crate::systemd;
+use crate::web;
+use log::{debug, error, info, warn};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::time;
+
+// Something the monitoring loop needs to act on, sent by the tasks watching for it
+#[derive(Debug)]
+enum Event {
+    // The periodic check is due
+    Tick,
+    // Something was mounted or unmounted somewhere, giving the new mount table
+    MountTableChanged(BTreeMap<PathBuf, String>),
+    // The config should be re-read, after SIGHUP or the file changing on disk
+    Reload,
+    // SIGTERM or SIGINT asked the daemon to stop
+    Shutdown,
+    // SIGUSR1 asked for everything being kept track of to be logged, and SIGUSR2 for
+    // every mount to be checked now
+    Dump,
+    Recheck,
+    // A client asked to hold off acting on one mount or all of them until then, or
+    // to stop doing so
+    Silence(Option<String>, SystemTime),
+    Unsilence(Option<String>),
+    // A client asked for a mount to be taken to be in a state, or checked again
+    Trigger(String, Option<MountState>),
+    // A remount tried in the background is done, one way or the other
+    Remounted(String),
+}
+
+impl Event {
+    fn name(&self) -> &'static str {
+        match self {
+            Event::Tick => "tick",
+            Event::MountTableChanged(_) => "mount table change",
+            Event::Reload => "reload",
+            Event::Shutdown => "shutdown",
+            Event::Dump => "state dump",
+            Event::Recheck => "re-check",
+            Event::Silence(..) => "silence",
+            Event::Unsilence(_) => "unsilence",
+            Event::Trigger(..) => "trigger",
+            Event::Remounted(_) => "remount",
+        }
+    }
+}
+
+// Run the daemon until it fails, with a task for each source of events feeding a
+// single monitoring loop. Without a config path there is nothing to reload. With
+// fail_fast, a hook that fails ends it.
+pub async fn run(
+    config: Config,
+    config_path: Option<PathBuf>,
+    socket: PathBuf,
+    dry_run: bool,
+    fail_fast: bool,
+) -> io::Result<()> {
+    // Serve status requests from the latest report
+    let report: Arc<Mutex<Vec<MountReport>>> = Arc::default();
+    let shared_report = Arc::clone(&report);
+    let (events, receiver) = mpsc::channel(16);
+    // Catch signals from the start, so one that comes during the first checks still
+    // shuts down cleanly once they are done
+    tokio::spawn(watch_signals(events.clone()));
+    let requests = events.clone();
+    // A socket systemd made for socket activation is left for it to clean up
+    let (serving, made_socket) = match control::serve(&socket, move |request| {
+        handle_request(request, &shared_report, &requests)
+    }) {
+        Ok(made) => {
+            debug!("Listening for control requests on {}", socket.display());
+            (true, made)
+        }
+        Err(e) => {
+            warn!("Unable to listen on {}: {}", socket.display(), e);
+            (false, false)
+        }
+    };
+
+    // The same requests can come over HTTP, from the dashboard, along with a stream of
+    // state changes
+    let mut changes = None;
+    if let Some(web) = &config.web {
+        let report = Arc::clone(&report);
+        let requests = events.clone();
+        let (stream, _) = broadcast::channel(64);
+        match web::serve(web, stream.clone(), move |request| {
+            handle_request(request, &report, &requests)
+        }) {
+            Ok(address) => {
+                info!("Serving the dashboard on http://{}", address);
+                changes = Some(stream);
+            }
+            Err(e) => warn!("Unable to listen on {}: {}", web.listen, e),
+        }
+    }
+
+    let mut monitor = Monitor::new(config, dry_run);
+    monitor.set_fail_fast(fail_fast);
+    let remounted = events.clone();
+    monitor.on_remounted(move |path| {
+        // The loop may be gone by then, shutting down
+        let _ = remounted.blocking_send(Event::Remounted(path.to_string()));
+    });
+    if let Some(stream) = changes {
+        let changes = monitor.events();
+        std::thread::spawn(move || {
+            for change in changes {
+                // Nobody may be listening, which is fine
+                let _ = stream.send(web::event_json(&change));
+            }
+        });
+    }
+    let table = match MountTable::open() {
+        Ok(table) => {
+            monitor.discover(table.mounts());
+            Some(table)
+        }
+        Err(e) => {
+            warn!(
+                "Unable to watch the mount table, relying on periodic checks: {}",
+                e
+            );
+            None
+        }
+    };
+
+    // Notify if dry run
+    if dry_run {
+        warn!("== Dry run enabled, no commands will be executed. ==");
+    }
+
+    // Execute on initial state, as far as it changed since the last run if that was
+    // saved
+    monitor.open_history();
+    monitor.open_otlp();
+    monitor.open_metrics();
+    monitor.load_state();
+    info!("Initial state: ");
+    monitor.evaluate();
+    monitor.save_state();
+    monitor.start();
+    *report.lock().unwrap() = monitor.report();
+    systemd::notify("READY=1");
+
+    // The loop pings the watchdog for every event, so checks must come often enough
+    let watchdog = systemd::watchdog_interval();
+    if let Some(interval) = watchdog {
+        if monitor.config().shortest_interval() >= interval {
+            warn!(
+                "Mounts are not checked more often than the systemd watchdog interval ({}s)",
+                interval.as_secs()
+            );
+        }
+    }
+
+    let (due_sender, due_receiver) = watch::channel(monitor.next_check());
+    tokio::spawn(tick(due_receiver, events.clone()));
+    // What is being watched, for state dumps
+    let mut watching = vec!["signals".to_string()];
+    if serving {
+        watching.push(format!("control socket {}", socket.display()));
+    }
+    if let Some(config_path) = &config_path {
+        match ConfigWatcher::new(config_path) {
+            Ok(watcher) => {
+                tokio::spawn(watch_config(watcher, events.clone()));
+                watching.push(format!("config file {}", config_path.display()));
+            }
+            Err(e) => warn!("Unable to watch {}: {}", config_path.display(), e),
+        }
+    }
+    if let Some(table) = table {
+        tokio::spawn(watch_mount_table(table, events.clone()));
+        watching.push("mount table".to_string());
+    }
+    drop(events);
+
+    // Checking mounts and running commands block, so they get a thread of their own
+    debug!(
+        "Starting observation loop ({} second delay)...",
+        monitor.config().delay_seconds
+    );
+    let result = tokio::task::spawn_blocking(move || {
+        observe(
+            monitor,
+            receiver,
+            config_path.as_deref(),
+            &report,
+            due_sender,
+            watchdog.is_some(),
+            &watching,
+        )
+    })
+    .await
+    .map_err(io::Error::other)
+    .and_then(|observed| observed);
+    if made_socket {
+        let _ = fs::remove_file(&socket);
+    }
+    result
+}
+
+// Handle events one at a time, re-checking the mounts after each
+fn observe(
+    mut monitor: Monitor,
+    mut events: mpsc::Receiver<Event>,
+    config_path: Option<&Path>,
+    report: &Mutex<Vec<MountReport>>,
+    next_check: watch::Sender<Instant>,
+    watchdog: bool,
+    watching: &[String],
+) -> io::Result<()> {
+    while let Some(event) = events.blocking_recv() {
+        // Benchmark the timing
+        let start_time = Instant::now();
+        if watchdog {
+            systemd::notify("WATCHDOG=1");
+        }
+
+        let name = event.name();
+        let tick = matches!(event, Event::Tick);
+        match event {
+            Event::Reload => match config_path {
+                Some(config_path) => {
+                    info!(event = "reload"; "Reloading configuration from {}", config_path.display());
+                    match Config::load(config_path) {
+                        Ok(new_config) => monitor.reload(new_config),
+                        Err(e) => error!("Keeping current configuration: {}", e),
+                    }
+                }
+                None => info!(event = "reload"; "No config file to reload"),
+            },
+            Event::MountTableChanged(mounts) => monitor.discover(&mounts),
+            Event::Tick => {}
+            Event::Dump => {
+                info!(event = "dump"; "Watching: {}", watching.join(", "));
+                monitor.dump();
+                // Nothing changed, so there is nothing to check
+                continue;
+            }
+            Event::Recheck => info!(event = "recheck"; "Checking every mount now"),
+            Event::Silence(mount, until) => monitor.silence(mount, until),
+            Event::Unsilence(mount) => monitor.unsilence(mount.as_deref()),
+            Event::Trigger(mount, state) => {
+                match state {
+                    Some(state) => info!(event = "trigger"; "Taking {} to be {}", mount, state),
+                    None => info!(event = "trigger"; "Checking {} again", mount),
+                }
+                monitor.force(&mount, state);
+            }
+            Event::Remounted(mount) => debug!("Done trying to remount {}", mount),
+            Event::Shutdown => {
+                info!(event = "shutdown"; "Shutting down");
+                systemd::notify("STOPPING=1");
+                monitor.stop();
+                return Ok(());
+            }
+        }
+
+        // Check mount status and act on any transitions. Ticks only check the mounts
+        // that are due, anything else might have affected any of them.
+        if tick {
+            monitor.evaluate_due();
+        } else {
+            monitor.evaluate();
+        }
+        monitor.save_state();
+        *report.lock().unwrap() = monitor.report();
+        next_check.send_replace(monitor.next_check());
+
+        if let Some((path, down_for)) = monitor.down_too_long() {
+            error!(
+                event = "shutdown", mount_point = path.as_str();
+                "{} has been down for {}s, exiting", path, down_for.as_secs()
+            );
+            systemd::notify("STOPPING=1");
+            monitor.stop();
+            return Err(io::Error::other(format!(
+                "{} has been down for {}s",
+                path,
+                down_for.as_secs()
+            )));
+        }
+
+        if let Some(failure) = monitor.failed_command() {
+            error!(event = "shutdown"; "{}, exiting", failure);
+            systemd::notify("STOPPING=1");
+            monitor.stop();
+            return Err(io::Error::other(failure));
+        }
+
+        // Job done, how long did it take?
+        let elapsed = start_time.elapsed();
+        debug!("Processed {} in {}ms", name, elapsed.as_millis());
+    }
+    Ok(())
+}
+
+// Answer a control request, passing anything that changes what the daemon does on to
+// the monitoring loop
+fn handle_request(
+    request: Request,
+    report: &Mutex<Vec<MountReport>>,
+    events: &mpsc::Sender<Event>,
+) -> Response {
+    let mount = match &request {
+        Request::Status => {
+            return Response::Status {
+                mounts: report.lock().unwrap().clone(),
+            }
+        }
+        Request::Silence { mount, .. } | Request::Unsilence { mount } => mount.clone(),
+        Request::Trigger { mount, .. } => Some(mount.clone()),
+        Request::Recheck => {
+            return match events.try_send(Event::Recheck) {
+                Ok(()) => Response::Ok {
+                    message: "Checking every mount now".to_string(),
+                },
+                Err(e) => Response::Error {
+                    message: format!("Unable to pass the request on: {}", e),
+                },
+            }
+        }
+    };
+    if let Some(path) = &mount {
+        if !report.lock().unwrap().iter().any(|m| &m.path == path) {
+            return Response::Error {
+                message: format!("Not monitoring {}", path),
+            };
+        }
+    }
+    let target = mount.as_deref().unwrap_or("all mount points").to_string();
+    let (event, message) = match request {
+        Request::Silence { seconds, .. } => {
+            let until = SystemTime::now() + Duration::from_secs(seconds);
+            (
+                Event::Silence(mount, until),
+                format!(
+                    "Silenced {} until {}",
+                    target,
+                    humantime::format_rfc3339_seconds(until)
+                ),
+            )
+        }
+        Request::Trigger { state, .. } => {
+            let message = match state {
+                Some(state) => format!("Taking {} to be {}", target, state),
+                None => format!("Checking {} again", target),
+            };
+            (Event::Trigger(target, state), message)
+        }
+        _ => (Event::Unsilence(mount), format!("Unsilenced {}", target)),
+    };
+    match events.try_send(event) {
+        Ok(()) => Response::Ok { message },
+        Err(e) => Response::Error {
+            message: format!("Unable to pass the request on: {}", e),
+        },
+    }
+}
+
+// Ask for a check whenever the next mount is due, following the schedule as the
+// monitoring loop updates it
+async fn tick(mut next_check: watch::Receiver<Instant>, events: mpsc::Sender<Event>) {
+    loop {
+        let at = *next_check.borrow_and_update();
+        tokio::select! {
+            _ = time::sleep_until(at.into()) => {
+                if events.send(Event::Tick).await.is_err() {
+                    return;
+                }
+                // Wait for the loop to reschedule after handling the tick
+                if next_check.changed().await.is_err() {
+                    return;
+                }
+            }
+            changed = next_check.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// Reload the configuration on SIGHUP, and shut down cleanly on SIGTERM or SIGINT
+async fn watch_signals(events: mpsc::Sender<Event>) {
+    let signals = [
+        SignalKind::hangup(),
+        SignalKind::terminate(),
+        SignalKind::interrupt(),
+        SignalKind::user_defined1(),
+        SignalKind::user_defined2(),
+    ]
+    .map(signal);
+    let [mut hangup, mut terminate, mut interrupt, mut dump, mut recheck] = match signals {
+        [Ok(a), Ok(b), Ok(c), Ok(d), Ok(e)] => [a, b, c, d, e],
+        signals => {
+            if let Some(Err(e)) = signals.into_iter().find(Result::is_err) {
+                error!("Unable to handle signals: {}", e);
+            }
+            return;
+        }
+    };
+    loop {
+        let event = tokio::select! {
+            _ = hangup.recv() => Event::Reload,
+            _ = terminate.recv() => Event::Shutdown,
+            _ = interrupt.recv() => Event::Shutdown,
+            _ = dump.recv() => Event::Dump,
+            _ = recheck.recv() => Event::Recheck,
+        };
+        if events.send(event).await.is_err() {
+            return;
+        }
+    }
+}
+
+// Reload the configuration whenever the file changes
+async fn watch_config(watcher: ConfigWatcher, events: mpsc::Sender<Event>) {
+    let mut watcher = match AsyncFd::with_interest(watcher, Interest::READABLE) {
+        Ok(w) => w,
+        Err(e) => {
+            warn!("Unable to watch the config file: {}", e);
+            return;
+        }
+    };
+    loop {
+        let Ok(mut guard) = watcher.readable_mut().await else {
+            return;
+        };
+        match guard.try_io(|watcher| watcher.get_mut().read_events()) {
+            Ok(Ok(true)) => {
+                if events.send(Event::Reload).await.is_err() {
+                    return;
+                }
+            }
+            Ok(Ok(false)) => {}
+            // Events may have been lost, so reload to be safe, and keep watching after a
+            // pause rather than spin on an error that stays
+            Ok(Err(e)) => {
+                warn!("Error while reading config file events: {}", e);
+                guard.clear_ready();
+                drop(guard);
+                if events.send(Event::Reload).await.is_err() {
+                    return;
+                }
+                time::sleep(Duration::from_secs(1)).await;
+            }
+            // Everything pending has been read, wait for more
+            Err(_would_block) => {}
+        }
+    }
+}
+
+// Check right away whenever something is mounted or unmounted
+async fn watch_mount_table(table: MountTable, events: mpsc::Sender<Event>) {
+    let mut table = match AsyncFd::with_interest(table, SOURCE_INTEREST) {
+        Ok(t) => t,
+        Err(e) => {
+            warn!(
+                "Unable to watch the mount table, relying on periodic checks: {}",
+                e
+            );
+            return;
+        }
+    };
+    loop {
+        let Ok(mut guard) = table.ready_mut(SOURCE_INTEREST).await else {
+            return;
+        };
+        let table = guard.get_inner_mut();
+        let changed = table.refresh();
+        let mounts = table.mounts().clone();
+        guard.clear_ready();
+        match changed {
+            Ok(changed) if changed.is_empty() => {}
+            Ok(changed) => {
+                for path in &changed {
+                    debug!("Mount table changed at {}", path.display());
+                }
+                if events.send(Event::MountTableChanged(mounts)).await.is_err() {
+                    return;
+                }
+            }
+            Err(e) => warn!("Unable to read the mount table: {}", e),
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use crate::config::DnsCheck;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// What a server name last resolved to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolved {
+    Addresses(BTreeSet<IpAddr>),
+    Failed(String),
+}
+
+impl fmt::Display for Resolved {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resolved::Addresses(addresses) => {
+                let addresses: Vec<String> = addresses.iter().map(ToString::to_string).collect();
+                f.write_str(&addresses.join(" "))
+            }
+            Resolved::Failed(error) => f.write_str(error),
+        }
+    }
+}
+
+// A server that stopped resolving, resolves again, or resolves to other addresses
+#[derive(Debug, Clone, PartialEq)]
+pub struct DnsChange {
+    pub server: String,
+    // Nothing if this is the first time it was resolved
+    pub from: Option<Resolved>,
+    pub to: Resolved,
+}
+
+impl DnsChange {
+    // Whether this is resolving failing or giving other addresses, rather than it
+    // coming back
+    pub fn is_failure(&self) -> bool {
+        !matches!(
+            (&self.from, &self.to),
+            (Some(Resolved::Failed(_)), Resolved::Addresses(_))
+        )
+    }
+}
+
+// Resolves the mount servers whenever that is due, remembering what each resolved to
+#[derive(Default)]
+pub struct Dns {
+    next: Option<Instant>,
+    servers: BTreeMap<String, Resolved>,
+}
+
+impl Dns {
+    // When the servers should next be resolved, if they are checked at all
+    pub fn next_due(&self, config: Option<&DnsCheck>) -> Option<Instant> {
+        config.map(|_| self.next.unwrap_or_else(Instant::now))
+    }
+
+    // Resolve the servers if that is due, returning what changed since last time. A
+    // server resolving fine the first time isn't a change, but failing to is.
+    pub fn check(
+        &mut self,
+        config: Option<&DnsCheck>,
+        servers: impl FnOnce() -> BTreeSet<String>,
+    ) -> Vec<DnsChange> {
+        let Some(config) = config else {
+            self.next = None;
+            self.servers.clear();
+            return Vec::new();
+        };
+        let now = Instant::now();
+        if self.next.is_some_and(|next| next > now) {
+            return Vec::new();
+        }
+        self.next = match self.next {
+            Some(due) if due + config.interval() > now => Some(due + config.interval()),
+            _ => Some(now + config.interval()),
+        };
+
+        // Addresses need no resolving
+        let names: Vec<String> = servers()
+            .into_iter()
+            .filter(|s| s.parse::<IpAddr>().is_err())
+            .collect();
+        self.servers.retain(|server, _| names.contains(server));
+        let mut changes = Vec::new();
+        for (server, to) in resolve_all(names, config.timeout()) {
+            let from = self.servers.insert(server.clone(), to.clone());
+            let changed = match &from {
+                None => matches!(to, Resolved::Failed(_)),
+                // A resolver failing the same way again is nothing new
+                Some(Resolved::Failed(_)) => !matches!(to, Resolved::Failed(_)),
+                Some(previous) => *previous != to,
+            };
+            if changed {
+                changes.push(DnsChange { server, from, to });
+            }
+        }
+        changes
+    }
+}
+
+// Resolve every name at once, each on a thread of its own since lookups can't be
+// given a timeout, and count those still going once it is up as failed
+fn resolve_all(names: Vec<String>, timeout: Duration) -> Vec<(String, Resolved)> {
+    let (sender, receiver) = mpsc::channel();
+    for name in &names {
+        let sender = sender.clone();
+        let name = name.clone();
+        thread::spawn(move || {
+            let resolved = resolve(&name);
+            let _ = sender.send((name, resolved));
+        });
+    }
+    drop(sender);
+
+    let deadline = Instant::now() + timeout;
+    let mut results = BTreeMap::new();
+    while results.len() < names.len() {
+        let wait = deadline.saturating_duration_since(Instant::now());
+        match receiver.recv_timeout(wait) {
+            Ok((name, resolved)) => {
+                results.insert(name, resolved);
+            }
+            Err(_) => break,
+        }
+    }
+    names
+        .into_iter()
+        .map(|name| {
+            let resolved = results.remove(&name).unwrap_or_else(|| {
+                Resolved::Failed(format!("no answer within {}s", timeout.as_secs()))
+            });
+            (name, resolved)
+        })
+        .collect()
+}
+
+fn resolve(name: &str) -> Resolved {
+    match (name, 0).to_socket_addrs() {
+        Ok(addresses) => {
+            let addresses: BTreeSet<IpAddr> = addresses.map(|a| a.ip()).collect();
+            if addresses.is_empty() {
+                Resolved::Failed("no addresses found".to_string())
+            } else {
+                Resolved::Addresses(addresses)
+            }
+        }
+        Err(e) => Resolved::Failed(e.to_string()),
+    }
+}
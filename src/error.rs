@@ -0,0 +1,49 @@
+use std::fmt;
+use std::io;
+
+// What nofus exits with, beyond 0 for success. Past a failed check these are from
+// sysexits(3), so a supervisor can tell a configuration it shouldn't restart on from
+// trouble that may pass.
+
+// A one-shot check found a mount missing, or the like
+pub const EXIT_FAILED: i32 = 1;
+// The command line was wrong
+pub const EXIT_USAGE: i32 = 64;
+// Something around nofus failed: the daemon it talks to, a hook with --fail-fast
+pub const EXIT_RUNTIME: i32 = 69;
+// The config file, or the mount points given instead, are missing or wrong
+pub const EXIT_CONFIG: i32 = 78;
+
+// Why the daemon couldn't start or keep running
+#[derive(Debug)]
+pub enum Error {
+    // The config file, or the mount points given instead, are missing or wrong
+    Config(String),
+    // Anything else, from a second daemon to the runtime failing to start
+    Runtime(String),
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) => EXIT_CONFIG,
+            Error::Runtime(_) => EXIT_RUNTIME,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Config(message) | Error::Runtime(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Runtime(e.to_string())
+    }
+}
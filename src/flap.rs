@@ -0,0 +1,146 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+// What recording a transition means for a mount
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flap {
+    // Changing no more than usual, so act on it
+    Normal,
+    // This transition tipped the mount into flapping
+    Started,
+    // Already flapping, so leave it be
+    Suppressed,
+}
+
+// The recent transitions of a single mount point
+#[derive(Debug, Default)]
+struct History {
+    transitions: VecDeque<Instant>,
+    flapping: bool,
+}
+
+// Notices mounts changing state too often to be worth acting on every time, such as
+// behind a flaky switch, until they have been stable for a while
+#[derive(Debug)]
+pub struct FlapDetector {
+    threshold: Option<u32>,
+    window: Duration,
+    mounts: HashMap<String, History>,
+}
+
+impl FlapDetector {
+    // Flapping is `threshold` transitions within `window`, or never without a threshold
+    pub fn new(threshold: Option<u32>, window: Duration) -> Self {
+        FlapDetector {
+            threshold,
+            window,
+            mounts: HashMap::new(),
+        }
+    }
+
+    // Change the thresholds, keeping the history of mounts that `keep` accepts
+    pub fn reconfigure(
+        &mut self,
+        threshold: Option<u32>,
+        window: Duration,
+        keep: impl Fn(&str) -> bool,
+    ) {
+        self.threshold = threshold;
+        self.window = window;
+        self.mounts.retain(|path, _| keep(path));
+    }
+
+    pub fn is_flapping(&self, path: &str) -> bool {
+        self.mounts.get(path).is_some_and(|h| h.flapping)
+    }
+
+    // Record a mount changing state
+    pub fn record(&mut self, path: &str) -> Flap {
+        let Some(threshold) = self.threshold else {
+            return Flap::Normal;
+        };
+        let now = Instant::now();
+        let history = self.mounts.entry(path.to_string()).or_default();
+        history.transitions.push_back(now);
+        while history
+            .transitions
+            .front()
+            .is_some_and(|t| now.duration_since(*t) > self.window)
+        {
+            history.transitions.pop_front();
+        }
+
+        if history.flapping {
+            Flap::Suppressed
+        } else if history.transitions.len() >= threshold as usize {
+            history.flapping = true;
+            Flap::Started
+        } else {
+            Flap::Normal
+        }
+    }
+
+    // Mounts that were flapping but haven't changed for a whole window, which are no
+    // longer considered to be flapping
+    pub fn settled(&mut self) -> Vec<String> {
+        let now = Instant::now();
+        let mut settled = Vec::new();
+        for (path, history) in &mut self.mounts {
+            let quiet = history
+                .transitions
+                .back()
+                .is_none_or(|t| now.duration_since(*t) > self.window);
+            if history.flapping && quiet {
+                history.flapping = false;
+                history.transitions.clear();
+                settled.push(path.clone());
+            }
+        }
+        settled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flapping_starts_at_the_threshold() {
+        let mut flaps = FlapDetector::new(Some(3), Duration::from_secs(60));
+        assert_eq!(flaps.record("/mnt/a"), Flap::Normal);
+        assert_eq!(flaps.record("/mnt/a"), Flap::Normal);
+        assert_eq!(flaps.record("/mnt/b"), Flap::Normal);
+        assert_eq!(flaps.record("/mnt/a"), Flap::Started);
+        assert_eq!(flaps.record("/mnt/a"), Flap::Suppressed);
+        assert!(flaps.is_flapping("/mnt/a"));
+        assert!(!flaps.is_flapping("/mnt/b"));
+        assert!(flaps.settled().is_empty());
+    }
+
+    #[test]
+    fn settles_after_a_quiet_window() {
+        let mut flaps = FlapDetector::new(Some(1), Duration::ZERO);
+        assert_eq!(flaps.record("/mnt/a"), Flap::Started);
+        std::thread::sleep(Duration::from_millis(2));
+        assert_eq!(flaps.settled(), ["/mnt/a"]);
+        assert!(!flaps.is_flapping("/mnt/a"));
+    }
+
+    #[test]
+    fn never_flaps_without_a_threshold() {
+        let mut flaps = FlapDetector::new(None, Duration::from_secs(60));
+        for _ in 0..10 {
+            assert_eq!(flaps.record("/mnt/a"), Flap::Normal);
+        }
+    }
+
+    #[test]
+    fn reconfigure_forgets_dropped_mounts() {
+        let mut flaps = FlapDetector::new(Some(1), Duration::from_secs(60));
+        flaps.record("/mnt/a");
+        flaps.record("/mnt/b");
+        flaps.reconfigure(Some(1), Duration::from_secs(60), |path| path == "/mnt/a");
+        assert!(flaps.is_flapping("/mnt/a"));
+        assert!(!flaps.is_flapping("/mnt/b"));
+    }
+}
@@ -0,0 +1,86 @@
+use crate::command::{render, run_command_with_env};
+use crate::config::HeartbeatConfig;
+use log::{debug, info, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use ureq::Agent;
+
+// How long to wait on a heartbeat URL
+const URL_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Sends the configured heartbeat whenever it is due, each on a thread of its own so a
+// slow one can't hold up monitoring. A beat is skipped rather than piled up while the
+// last one is still going.
+#[derive(Default)]
+pub struct Heartbeat {
+    next: Option<Instant>,
+    busy: Arc<AtomicBool>,
+}
+
+impl Heartbeat {
+    // When the next beat is due, if a heartbeat is configured
+    pub fn next_due(&self, config: Option<&HeartbeatConfig>) -> Option<Instant> {
+        config.map(|_| self.next.unwrap_or_else(Instant::now))
+    }
+
+    // Send a beat if one is due, with the environment given to commands
+    pub fn beat(
+        &mut self,
+        config: Option<&HeartbeatConfig>,
+        env: Vec<(&'static str, String)>,
+        timeout: Duration,
+        dry_run: bool,
+    ) {
+        let Some(config) = config else {
+            self.next = None;
+            return;
+        };
+        let now = Instant::now();
+        if self.next.is_some_and(|next| next > now) {
+            return;
+        }
+        // Keep to the schedule, like the mount checks
+        self.next = match self.next {
+            Some(due) if due + config.interval() > now => Some(due + config.interval()),
+            _ => Some(now + config.interval()),
+        };
+
+        let command = config.command.as_ref().map(|c| render(c, &env));
+        if dry_run {
+            match (&command, &config.url) {
+                (Some(command), _) => info!("Dry run enabled, would send heartbeat: {}", command),
+                (None, Some(url)) => info!("Dry run enabled, would send heartbeat: GET {}", url),
+                (None, None) => {}
+            }
+            return;
+        }
+        if self.busy.swap(true, Ordering::AcqRel) {
+            warn!(event = "heartbeat"; "Skipping heartbeat, the last one is still running");
+            return;
+        }
+        let busy = Arc::clone(&self.busy);
+        let url = config.url.clone();
+        thread::spawn(move || {
+            let result = match (command, url) {
+                (Some(command), _) => run_command_with_env(&command, &env, timeout, None),
+                (None, Some(url)) => get(&url),
+                (None, None) => Ok(()),
+            };
+            match result {
+                Ok(()) => debug!(event = "heartbeat"; "Sent heartbeat"),
+                Err(e) => warn!(event = "heartbeat"; "Heartbeat failed: {}", e),
+            }
+            busy.store(false, Ordering::Release);
+        });
+    }
+}
+
+fn get(url: &str) -> Result<(), String> {
+    let agent: Agent = Agent::config_builder()
+        .timeout_global(Some(URL_TIMEOUT))
+        .build()
+        .into();
+    agent.get(url).call().map(|_| ()).map_err(|e| e.to_string())
+}
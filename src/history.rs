@@ -0,0 +1,456 @@
+use crate::monitor::MountState;
+use crate::sqlite::{Connection, Value};
+use log::warn;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// How often old entries are cleared out while the daemon runs
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS events (
+        at INTEGER NOT NULL,
+        mount TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        from_state TEXT,
+        to_state TEXT,
+        detail TEXT,
+        command TEXT,
+        exit_code INTEGER,
+        duration_ms INTEGER
+    )",
+    "CREATE INDEX IF NOT EXISTS events_at ON events (at)",
+    "CREATE INDEX IF NOT EXISTS events_mount ON events (mount, at)",
+];
+
+// A record of what happened to the mounts and what was run for them, kept in an SQLite
+// database for looking back on with `nofus history`
+pub struct History {
+    db: Connection,
+    retention: Duration,
+    last_pruned: Mutex<Instant>,
+}
+
+// Something recorded in the history
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub at: SystemTime,
+    // The mount point, or the group or global commands a command ran for
+    pub mount: String,
+    pub kind: EntryKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryKind {
+    // A mount changed state, and why
+    Transition {
+        from: String,
+        to: String,
+        cause: String,
+    },
+    // A check found a mount in another state, not yet believed
+    Probe {
+        state: String,
+        cause: String,
+    },
+    Command {
+        command: String,
+        exit_code: Option<i64>,
+        duration: Duration,
+        error: Option<String>,
+    },
+}
+
+// How available a mount was over a period, as far as the history tells
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Availability {
+    pub mount: String,
+    // Share of the time its state was known that it was mounted, if it was known at all
+    pub availability_percent: Option<f64>,
+    pub outages: usize,
+    pub downtime_seconds: u64,
+    pub longest_outage_seconds: u64,
+    // How much of the period its state was known
+    pub observed_seconds: u64,
+}
+
+impl History {
+    // Open the history for recording, creating it if need be, and clear out anything
+    // older than the retention
+    pub fn open(path: &Path, retention: Duration) -> Result<Self, String> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        let db = Connection::open(path, false)?;
+        for statement in SCHEMA {
+            db.execute(statement, &[])?;
+        }
+        let history = History {
+            db,
+            retention,
+            last_pruned: Mutex::new(Instant::now()),
+        };
+        history.prune()?;
+        Ok(history)
+    }
+
+    // Open an existing history just to read it
+    pub fn open_read_only(path: &Path) -> Result<Self, String> {
+        Ok(History {
+            db: Connection::open(path, true)?,
+            retention: Duration::MAX,
+            last_pruned: Mutex::new(Instant::now()),
+        })
+    }
+
+    pub fn transition(&self, mount: &str, from: MountState, to: MountState, cause: &str) {
+        self.insert(
+            mount,
+            "transition",
+            [from.to_string().into(), to.to_string().into(), cause.into()],
+            [Value::Null, Value::Null, Value::Null],
+        );
+    }
+
+    pub fn probe(&self, mount: &str, state: MountState, cause: &str) {
+        self.insert(
+            mount,
+            "probe",
+            [Value::Null, state.to_string().into(), cause.into()],
+            [Value::Null, Value::Null, Value::Null],
+        );
+    }
+
+    pub fn command(
+        &self,
+        scope: &str,
+        command: &str,
+        exit_code: Option<i32>,
+        duration: Duration,
+        result: &Result<(), String>,
+    ) {
+        self.insert(
+            scope,
+            "command",
+            [Value::Null, Value::Null, result.clone().err().into()],
+            [
+                command.into(),
+                exit_code.map(i64::from).into(),
+                Some(duration.as_millis() as i64).into(),
+            ],
+        );
+    }
+
+    // Add an entry, warning rather than failing if it can't be, since the history
+    // mustn't get in the way of monitoring
+    fn insert(&self, mount: &str, kind: &str, state: [Value; 3], command: [Value; 3]) {
+        let mut params = vec![millis(SystemTime::now()).into(), mount.into(), kind.into()];
+        params.extend(state);
+        params.extend(command);
+        let result = self.db.execute(
+            "INSERT INTO events (at, mount, kind, from_state, to_state, detail, command,
+                exit_code, duration_ms) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            &params,
+        );
+        if let Err(e) = result {
+            warn!(
+                "Unable to record {} of {} in the history: {}",
+                kind, mount, e
+            );
+        }
+        let mut last_pruned = self.last_pruned.lock().unwrap();
+        if last_pruned.elapsed() >= PRUNE_INTERVAL {
+            *last_pruned = Instant::now();
+            if let Err(e) = self.prune() {
+                warn!("Unable to clear old entries from the history: {}", e);
+            }
+        }
+    }
+
+    // Forget entries older than the retention
+    fn prune(&self) -> Result<(), String> {
+        let Some(cutoff) = SystemTime::now().checked_sub(self.retention) else {
+            return Ok(());
+        };
+        self.db
+            .execute("DELETE FROM events WHERE at < ?", &[millis(cutoff).into()])
+    }
+
+    // The entries since the given time, oldest first, for one mount or all of them
+    pub fn entries(
+        &self,
+        mount: Option<&str>,
+        since: Option<SystemTime>,
+    ) -> Result<Vec<Entry>, String> {
+        let since = since.map_or(0, millis);
+        self.db.query(
+            "SELECT at, mount, kind, from_state, to_state, detail, command, exit_code,
+                duration_ms FROM events WHERE at >= ? AND (? IS NULL OR mount = ?)
+                ORDER BY at, rowid",
+            &[since.into(), mount.into(), mount.into()],
+            |row| {
+                let text = |i: usize| row[i].text().unwrap_or_default().to_string();
+                let kind = match row[2].text() {
+                    Some("transition") => EntryKind::Transition {
+                        from: text(3),
+                        to: text(4),
+                        cause: text(5),
+                    },
+                    Some("probe") => EntryKind::Probe {
+                        state: text(4),
+                        cause: text(5),
+                    },
+                    _ => EntryKind::Command {
+                        command: text(6),
+                        exit_code: row[7].integer(),
+                        duration: Duration::from_millis(row[8].integer().unwrap_or(0) as u64),
+                        error: row[5].text().map(str::to_string),
+                    },
+                };
+                Entry {
+                    at: UNIX_EPOCH + Duration::from_millis(row[0].integer().unwrap_or(0) as u64),
+                    mount: text(1),
+                    kind,
+                }
+            },
+        )
+    }
+
+    // How available each mount with a state change on record was between the two
+    // times. A mount counts as down while unmounted, stale or unresponsive, and time
+    // it wasn't known in is left out, while time nofus wasn't running counts as the
+    // state last recorded.
+    pub fn availability(
+        &self,
+        since: SystemTime,
+        until: SystemTime,
+    ) -> Result<Vec<Availability>, String> {
+        let mut transitions: BTreeMap<String, Vec<(SystemTime, String)>> = BTreeMap::new();
+        for entry in self.entries(None, None)? {
+            if let EntryKind::Transition { to, .. } = entry.kind {
+                transitions
+                    .entry(entry.mount)
+                    .or_default()
+                    .push((entry.at, to));
+            }
+        }
+        Ok(transitions
+            .into_iter()
+            .map(|(mount, changes)| availability(mount, &changes, since, until))
+            .collect())
+    }
+}
+
+// Add up a mount's time up and down from its state changes, oldest first
+fn availability(
+    mount: String,
+    changes: &[(SystemTime, String)],
+    since: SystemTime,
+    until: SystemTime,
+) -> Availability {
+    // Whether it was down as far as known, from the start of the period on
+    let down = |state: &str| match state {
+        "mounted" | "degraded" => Some(false),
+        "unknown" => None,
+        _ => Some(true),
+    };
+    let mut up = Duration::ZERO;
+    let mut downtime = Duration::ZERO;
+    let mut outages = 0;
+    let mut longest = Duration::ZERO;
+    // The outage under way, if any, and how much of it fell within the period
+    let mut outage: Option<Duration> = None;
+    let mut state = None;
+    let mut from = since;
+    let changes = changes
+        .iter()
+        .filter(|(at, _)| *at < until)
+        .map(|(at, to)| (*at, down(to)))
+        .chain([(until, None)]);
+    for (at, next) in changes {
+        if at > from {
+            let spent = at.duration_since(from).unwrap_or_default();
+            match state {
+                Some(false) => up += spent,
+                Some(true) => downtime += spent,
+                None => {}
+            }
+            if let Some(outage) = outage.as_mut() {
+                // Counted once any of it falls within the period
+                if outage.is_zero() && state == Some(true) {
+                    outages += 1;
+                }
+                if state == Some(true) {
+                    *outage += spent;
+                }
+            }
+            from = at;
+        }
+        // Not knowing for a while neither ends an outage nor starts one
+        match next {
+            Some(true) => outage = outage.or(Some(Duration::ZERO)),
+            Some(false) => {
+                longest = longest.max(outage.take().unwrap_or_default());
+            }
+            None => {}
+        }
+        state = next;
+    }
+    longest = longest.max(outage.unwrap_or_default());
+    let observed = up + downtime;
+    Availability {
+        mount,
+        availability_percent: (!observed.is_zero())
+            .then(|| up.as_secs_f64() / observed.as_secs_f64() * 100.0),
+        outages,
+        downtime_seconds: downtime.as_secs(),
+        longest_outage_seconds: longest.as_secs(),
+        observed_seconds: observed.as_secs(),
+    }
+}
+
+fn millis(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("nofus-test-{}", std::process::id()));
+        dir.join(name).join("history.db")
+    }
+
+    #[test]
+    fn recorded_entries_read_back_in_order() {
+        let path = scratch("recorded");
+        let history = History::open(&path, Duration::from_secs(3600)).unwrap();
+        history.transition(
+            "/mnt/a",
+            MountState::Mounted,
+            MountState::Stale,
+            "stale file handle",
+        );
+        history.probe("/mnt/b", MountState::Unmounted, "not in the mount table");
+        let failed = Err("Command failed with status: exit status: 1".to_string());
+        history.command(
+            "/mnt/a",
+            "remount",
+            Some(1),
+            Duration::from_millis(250),
+            &failed,
+        );
+
+        let reader = History::open_read_only(&path).unwrap();
+        let entries = reader.entries(Some("/mnt/a"), None).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[0].kind,
+            EntryKind::Transition {
+                from: "mounted".to_string(),
+                to: "stale".to_string(),
+                cause: "stale file handle".to_string(),
+            }
+        );
+        assert_eq!(
+            entries[1].kind,
+            EntryKind::Command {
+                command: "remount".to_string(),
+                exit_code: Some(1),
+                duration: Duration::from_millis(250),
+                error: failed.err(),
+            }
+        );
+        assert_eq!(reader.entries(None, None).unwrap().len(), 3);
+        let later = SystemTime::now() + Duration::from_secs(60);
+        assert!(reader.entries(None, Some(later)).unwrap().is_empty());
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    // How available a mount was from 1000s after the epoch for so many seconds, given
+    // when it changed to which state
+    fn over(changes: &[(u64, &str)], seconds: u64) -> Availability {
+        let at = |s: u64| UNIX_EPOCH + Duration::from_secs(s);
+        let changes: Vec<(SystemTime, String)> = changes
+            .iter()
+            .map(|(s, to)| (at(*s), to.to_string()))
+            .collect();
+        availability("/mnt/a".to_string(), &changes, at(1000), at(1000 + seconds))
+    }
+
+    #[test]
+    fn outages_and_downtime_add_up() {
+        let a = over(
+            &[(1000, "mounted"), (1060, "unmounted"), (1090, "mounted")],
+            120,
+        );
+        assert_eq!(a.availability_percent, Some(75.0));
+        assert_eq!(
+            (a.outages, a.downtime_seconds, a.longest_outage_seconds),
+            (1, 30, 30)
+        );
+        assert_eq!(a.observed_seconds, 120);
+
+        let a = over(
+            &[
+                (1000, "mounted"),
+                (1010, "stale"),
+                (1020, "degraded"),
+                (1050, "server_down"),
+            ],
+            100,
+        );
+        assert_eq!(
+            (a.outages, a.downtime_seconds, a.longest_outage_seconds),
+            (2, 60, 50)
+        );
+    }
+
+    #[test]
+    fn outages_begun_before_the_period_count_from_its_start() {
+        let a = over(
+            &[(500, "mounted"), (900, "unmounted"), (1020, "mounted")],
+            100,
+        );
+        assert_eq!(
+            (a.outages, a.downtime_seconds, a.longest_outage_seconds),
+            (1, 20, 20)
+        );
+        assert_eq!(a.availability_percent, Some(80.0));
+        // Changes after the period are left out
+        let a = over(&[(1000, "mounted"), (1150, "unmounted")], 100);
+        assert_eq!((a.outages, a.downtime_seconds), (0, 0));
+    }
+
+    #[test]
+    fn time_not_known_is_left_out() {
+        let a = over(
+            &[(1000, "mounted"), (1050, "unknown"), (1070, "mounted")],
+            100,
+        );
+        assert_eq!(a.availability_percent, Some(100.0));
+        assert_eq!(a.observed_seconds, 80);
+        // Nor does it split an outage in two
+        let a = over(
+            &[
+                (1000, "unmounted"),
+                (1010, "unknown"),
+                (1020, "unmounted"),
+                (1030, "mounted"),
+            ],
+            40,
+        );
+        assert_eq!(
+            (a.outages, a.downtime_seconds, a.longest_outage_seconds),
+            (1, 20, 20)
+        );
+        let a = over(&[], 100);
+        assert_eq!((a.availability_percent, a.observed_seconds), (None, 0));
+    }
+}
@@ -0,0 +1,111 @@
+use crate::logging;
+use env_filter::Filter;
+use log::kv::{self, Key, Value, VisitSource};
+use log::{Level, Log, Metadata, Record};
+use std::io;
+use std::os::unix::net::UnixDatagram;
+
+// Where journald listens for natively formatted log entries
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+// Sends log records straight to journald, keeping key/value pairs as journal fields
+pub struct JournalLogger {
+    socket: UnixDatagram,
+    filter: Filter,
+}
+
+impl JournalLogger {
+    // Connect to journald and install this as the global logger
+    pub fn init(filter: Filter) -> io::Result<()> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(JOURNAL_SOCKET)?;
+        log::set_max_level(filter.filter());
+        log::set_boxed_logger(Box::new(JournalLogger { socket, filter })).map_err(io::Error::other)
+    }
+}
+
+impl Log for JournalLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+
+        let mut entry = Vec::new();
+        add_field(&mut entry, "MESSAGE", &record.args().to_string());
+        add_field(&mut entry, "PRIORITY", priority(record.level()));
+        add_field(&mut entry, "SYSLOG_IDENTIFIER", env!("CARGO_PKG_NAME"));
+        add_field(&mut entry, "TARGET", record.target());
+        if let Some(file) = record.file() {
+            add_field(&mut entry, "CODE_FILE", file);
+        }
+        if let Some(line) = record.line() {
+            add_field(&mut entry, "CODE_LINE", &line.to_string());
+        }
+        let _ = record.key_values().visit(&mut FieldVisitor(&mut entry));
+
+        // There's nowhere left to report a failure to log
+        let _ = self.socket.send(&entry);
+    }
+
+    fn flush(&self) {}
+}
+
+// Adds each key/value pair on a record as an upper-cased journal field, and the labels
+// of a mount point as LABEL_<NAME> fields
+struct FieldVisitor<'a>(&'a mut Vec<u8>);
+
+impl<'kvs> VisitSource<'kvs> for FieldVisitor<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = value.to_string();
+        let name = field_name(key.as_str());
+        if !name.is_empty() {
+            add_field(self.0, &name, &value);
+        }
+        if key.as_str() == "mount_point" {
+            for (label, value) in logging::labels(&value).into_iter().flatten() {
+                add_field(self.0, &field_name(&format!("label_{}", label)), &value);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Field names may only contain A-Z, 0-9 and _, and can't start with _
+fn field_name(key: &str) -> String {
+    let name: String = key
+        .chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            c @ ('A'..='Z' | '0'..='9') => c,
+            _ => '_',
+        })
+        .collect();
+    name.trim_start_matches('_').to_string()
+}
+
+// Append a field using the journal's native protocol
+fn add_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        // Multi-line values are length prefixed instead of newline terminated
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+// Map log levels onto syslog priorities
+fn priority(level: Level) -> &'static str {
+    match level {
+        Level::Error => "3",
+        Level::Warn => "4",
+        Level::Info => "6",
+        Level::Debug | Level::Trace => "7",
+    }
+}
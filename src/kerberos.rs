@@ -0,0 +1,209 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Where rpc.gssd keeps the machine credentials it gets from the keytab
+const MACHINE_CCACHE_DIR: &str = "/tmp";
+const MACHINE_CCACHE_PREFIX: &str = "krb5cc_machine_";
+
+// How the credentials sec=krb5 mounts are accessed with stand, taken to be valid
+// until found otherwise
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Status {
+    #[default]
+    Valid,
+    Expiring,
+    Expired,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Status::Valid => "valid",
+            Status::Expiring => "expiring",
+            Status::Expired => "expired",
+        })
+    }
+}
+
+// The principal of a credential cache and when its ticket-granting ticket expires
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub principal: String,
+    pub expires: SystemTime,
+}
+
+// Whether the options have a mount use Kerberos, i.e. sec=krb5, krb5i or krb5p
+pub fn uses_kerberos(options: &[String]) -> bool {
+    options
+        .iter()
+        .filter_map(|o| o.strip_prefix("sec="))
+        .any(|sec| sec.split(':').any(|flavor| flavor.starts_with("krb5")))
+}
+
+// How the credentials in the cache stand, with why. Without a cache given, the
+// newest machine credential cache rpc.gssd left is used.
+pub fn check(ccache: Option<&str>, warn: Duration) -> (Status, Result<Credentials, String>) {
+    let path = match ccache {
+        Some(ccache) => PathBuf::from(ccache.strip_prefix("FILE:").unwrap_or(ccache)),
+        None => match machine_ccache() {
+            Some(path) => path,
+            None => {
+                return (
+                    Status::Expired,
+                    Err(format!(
+                        "no {}* credential cache in {}",
+                        MACHINE_CCACHE_PREFIX, MACHINE_CCACHE_DIR
+                    )),
+                )
+            }
+        },
+    };
+    match read(&path) {
+        Ok(credentials) => {
+            let now = SystemTime::now();
+            let status = if credentials.expires <= now {
+                Status::Expired
+            } else if credentials.expires <= now + warn {
+                Status::Expiring
+            } else {
+                Status::Valid
+            };
+            (status, Ok(credentials))
+        }
+        Err(e) => (
+            Status::Expired,
+            Err(format!(
+                "unable to read credential cache {}: {}",
+                path.display(),
+                e
+            )),
+        ),
+    }
+}
+
+fn machine_ccache() -> Option<PathBuf> {
+    fs::read_dir(MACHINE_CCACHE_DIR)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|e| {
+            e.file_name()
+                .to_string_lossy()
+                .starts_with(MACHINE_CCACHE_PREFIX)
+        })
+        .filter_map(|e| Some((e.metadata().ok()?.modified().ok()?, e.path())))
+        .max()
+        .map(|(_, path)| path)
+}
+
+// Read a FILE credential cache, in format version 3 or 4 as MIT and Heimdal write
+// them, going by its ticket-granting ticket or, without one, the ticket that lasts
+// longest
+fn read(path: &Path) -> Result<Credentials, String> {
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    let mut cache = Reader(&data);
+    let version = cache.u16()?;
+    if version != 0x0503 && version != 0x0504 {
+        return Err(format!("unsupported format version {:#06x}", version));
+    }
+    if version == 0x0504 {
+        let length = cache.u16()?;
+        cache.skip(length as usize)?;
+    }
+    let (principal, realm) = cache.principal()?;
+
+    let mut tgt = None;
+    let mut longest = None;
+    while !cache.0.is_empty() {
+        let _client = cache.principal()?;
+        let (server, _) = cache.principal()?;
+        let _enctype = cache.u16()?;
+        if version == 0x0503 {
+            let _enctype = cache.u16()?;
+        }
+        cache.data()?;
+        let _authtime = cache.u32()?;
+        let _starttime = cache.u32()?;
+        let endtime = cache.u32()?;
+        let _renew_till = cache.u32()?;
+        let _is_skey = cache.u8()?;
+        let _flags = cache.u32()?;
+        for _ in 0..cache.u32()? {
+            let _addrtype = cache.u16()?;
+            cache.data()?;
+        }
+        for _ in 0..cache.u32()? {
+            let _adtype = cache.u16()?;
+            cache.data()?;
+        }
+        cache.data()?;
+        cache.data()?;
+
+        // Entries naming the cache's own settings aren't tickets
+        if server.contains("X-CACHECONF:") {
+            continue;
+        }
+        if server == format!("krbtgt/{}@{}", realm, realm) {
+            tgt = Some(endtime);
+        }
+        longest = longest.max(Some(endtime));
+    }
+    let endtime = tgt.or(longest).ok_or("no tickets in the cache")?;
+    Ok(Credentials {
+        principal,
+        expires: UNIX_EPOCH + Duration::from_secs(endtime.into()),
+    })
+}
+
+// Reads the big-endian fields of a credential cache off the front of it
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take(&mut self, length: usize) -> Result<&[u8], String> {
+        if self.0.len() < length {
+            return Err("cut short".to_string());
+        }
+        let (taken, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn skip(&mut self, length: usize) -> Result<(), String> {
+        self.take(length).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn data(&mut self) -> Result<&[u8], String> {
+        let length = self.u32()? as usize;
+        self.take(length)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        Ok(String::from_utf8_lossy(self.data()?).into_owned())
+    }
+
+    // A principal as name@REALM, and its realm
+    fn principal(&mut self) -> Result<(String, String), String> {
+        let _name_type = self.u32()?;
+        let components = self.u32()?;
+        let realm = self.string()?;
+        let name: Vec<String> = (0..components)
+            .map(|_| self.string())
+            .collect::<Result<_, _>>()?;
+        Ok((format!("{}@{}", name.join("/"), realm), realm))
+    }
+}
@@ -0,0 +1,151 @@
+use crate::config::LatencyAlert;
+use crate::control::HistogramReport;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+// The upper bounds of the histogram buckets, with one more for anything slower
+pub const BUCKETS_MS: [u64; 12] = [1, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+// How long every check of a mount took since nofus started, in buckets
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    // How many checks fell in each bucket, not counting those in the ones before
+    counts: [u64; BUCKETS_MS.len() + 1],
+    sum: Duration,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, latency: Duration) {
+        let bucket = BUCKETS_MS
+            .iter()
+            .position(|&bound| latency <= Duration::from_millis(bound))
+            .unwrap_or(BUCKETS_MS.len());
+        self.counts[bucket] += 1;
+        self.sum += latency;
+    }
+}
+
+impl From<&Histogram> for HistogramReport {
+    fn from(histogram: &Histogram) -> Self {
+        let counts: Vec<u64> = histogram
+            .counts
+            .iter()
+            .scan(0, |total, count| {
+                *total += count;
+                Some(*total)
+            })
+            .collect();
+        HistogramReport {
+            buckets_ms: BUCKETS_MS.to_vec(),
+            count: counts.last().copied().unwrap_or(0),
+            counts,
+            sum_ms: histogram.sum.as_secs_f64() * 1000.0,
+        }
+    }
+}
+
+// A latency alert starting or ending for a mount
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertChange {
+    pub path: String,
+    // Which of the configured alerts, by position
+    pub alert: usize,
+    pub firing: bool,
+    // The percentile's latency over the alert's window
+    pub latency: Duration,
+}
+
+// Watches the checks of each mount for a percentile of their latency staying over a
+// threshold for a while, e.g. p95 over 500ms for 5 minutes
+#[derive(Default)]
+pub struct Alerts {
+    mounts: HashMap<String, Checks>,
+    // The alerts under way, by mount and position
+    firing: BTreeSet<(String, usize)>,
+}
+
+struct Checks {
+    first: Instant,
+    // As far back as the longest window
+    recent: VecDeque<(Instant, Duration)>,
+}
+
+impl Alerts {
+    // Add the latest checks, returning the alerts that started or ended with them
+    pub fn observe(
+        &mut self,
+        alerts: &[LatencyAlert],
+        probed: impl IntoIterator<Item = (String, Duration)>,
+        now: Instant,
+    ) -> Vec<AlertChange> {
+        let longest = alerts.iter().map(|a| a.window()).max().unwrap_or_default();
+        let mut paths = BTreeSet::new();
+        for (path, latency) in probed {
+            let checks = self.mounts.entry(path.clone()).or_insert(Checks {
+                first: now,
+                recent: VecDeque::new(),
+            });
+            checks.recent.push_back((now, latency));
+            while checks
+                .recent
+                .front()
+                .is_some_and(|(at, _)| now.duration_since(*at) > longest)
+            {
+                checks.recent.pop_front();
+            }
+            paths.insert(path);
+        }
+
+        let mut changes = Vec::new();
+        for path in paths {
+            let checks = &self.mounts[&path];
+            for (i, alert) in alerts.iter().enumerate() {
+                // Wait until there's a whole window to go on
+                if now.duration_since(checks.first) < alert.window() {
+                    continue;
+                }
+                let mut window: Vec<Duration> = checks
+                    .recent
+                    .iter()
+                    .filter(|(at, _)| now.duration_since(*at) <= alert.window())
+                    .map(|(_, latency)| *latency)
+                    .collect();
+                let Some(latency) = percentile(&mut window, alert.percentile) else {
+                    continue;
+                };
+                let over = latency > Duration::from_millis(alert.above_ms);
+                let key = (path.clone(), i);
+                let changed = if over {
+                    self.firing.insert(key)
+                } else {
+                    self.firing.remove(&key)
+                };
+                if changed {
+                    changes.push(AlertChange {
+                        path: path.clone(),
+                        alert: i,
+                        firing: over,
+                        latency,
+                    });
+                }
+            }
+        }
+        changes
+    }
+
+    // Forget mounts no longer monitored
+    pub fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        self.mounts.retain(|path, _| keep(path));
+        self.firing.retain(|(path, _)| keep(path));
+    }
+}
+
+// The given percentile of the latencies, by nearest rank
+fn percentile(latencies: &mut [Duration], percentile: f64) -> Option<Duration> {
+    if latencies.is_empty() {
+        return None;
+    }
+    latencies.sort();
+    let rank = (percentile / 100.0 * latencies.len() as f64).ceil() as usize;
+    Some(latencies[rank.clamp(1, latencies.len()) - 1])
+}
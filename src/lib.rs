@@ -0,0 +1,40 @@
+// Monitoring of NFS (and other) mount points, as used by the nofus daemon. Embed a
+// monitor::MountMonitor to track mounts from your own code. The subcommands of the
+// binary are in commands.
+mod command;
+pub mod commands;
+pub mod config;
+mod containers;
+pub mod control;
+pub mod daemon;
+mod dns;
+pub mod error;
+mod flap;
+mod heartbeat;
+pub mod history;
+mod journal;
+mod kerberos;
+mod latency;
+pub mod logging;
+mod metrics;
+pub mod monitor;
+pub mod mounts;
+mod notify;
+mod options;
+mod otlp;
+pub mod pidfile;
+mod platform;
+mod queue;
+mod remount;
+mod rpc;
+mod schedule;
+mod schema;
+mod sqlite;
+mod state;
+mod systemd;
+pub mod top;
+mod usage;
+mod web;
+
+pub use config::Config;
+pub use monitor::{MountEvent, MountEventKind, MountMonitor, MountState};
@@ -0,0 +1,140 @@
+use crate::journal::JournalLogger;
+use crate::schema::{names, Schema};
+use clap::ValueEnum;
+use env_filter::Filter;
+use env_logger::Env;
+use log::kv::{self, Key, Value, VisitSource};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::Deserialize;
+use serde_json::{Map, Value as JsonValue};
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{self, Write};
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+// The labels of each mount point that has any, added to the JSON and journal entries
+// about it
+static LABELS: RwLock<BTreeMap<String, BTreeMap<String, String>>> = RwLock::new(BTreeMap::new());
+
+// How log output is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Plain text on stderr
+    #[default]
+    Text,
+    /// One JSON object per line on stderr
+    Json,
+    /// Structured entries sent straight to the systemd journal
+    Journald,
+}
+
+impl Schema for LogFormat {
+    fn schema() -> serde_json::Value {
+        names::<Self>()
+    }
+}
+
+// Set up logging in the requested format, honouring RUST_LOG and --verbose
+pub fn init(format: LogFormat, verbose: bool) {
+    let filter = || {
+        let mut filter = env_filter::Builder::new();
+        filter.parse(&env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string()));
+        if verbose {
+            filter.filter_level(LevelFilter::Trace);
+        }
+        filter.build()
+    };
+
+    match format {
+        LogFormat::Text => {}
+        LogFormat::Json => {
+            JsonLogger::init(filter());
+            return;
+        }
+        LogFormat::Journald => match JournalLogger::init(filter()) {
+            Ok(()) => return,
+            Err(e) => eprintln!("Unable to log to journald, using text output: {}", e),
+        },
+    }
+
+    let mut builder = env_logger::Builder::from_env(Env::default().default_filter_or("info"));
+    if verbose {
+        builder.filter_level(LevelFilter::Trace);
+    }
+    builder.init();
+}
+
+// Set the labels of the mount points, as the config has them
+pub fn set_labels(labels: BTreeMap<String, BTreeMap<String, String>>) {
+    *LABELS.write().unwrap() = labels;
+}
+
+// The labels of a mount point, if it has any
+pub(crate) fn labels(mount: &str) -> Option<BTreeMap<String, String>> {
+    LABELS.read().unwrap().get(mount).cloned()
+}
+
+// Writes each log record to stderr as a single line of JSON
+struct JsonLogger {
+    filter: Filter,
+}
+
+impl JsonLogger {
+    fn init(filter: Filter) {
+        log::set_max_level(filter.filter());
+        // Only fails if a logger is already installed, in which case that one is used
+        let _ = log::set_boxed_logger(Box::new(JsonLogger { filter }));
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+
+        let mut object = Map::new();
+        let timestamp = humantime::format_rfc3339_millis(SystemTime::now());
+        object.insert("timestamp".into(), timestamp.to_string().into());
+        object.insert("level".into(), record.level().as_str().into());
+        object.insert("target".into(), record.target().into());
+        let _ = record.key_values().visit(&mut FieldVisitor(&mut object));
+        object.insert("message".into(), record.args().to_string().into());
+
+        let mut line = JsonValue::Object(object).to_string();
+        line.push('\n');
+        let _ = io::stderr().lock().write_all(line.as_bytes());
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+// Adds each key/value pair on a record to the JSON object
+struct FieldVisitor<'a>(&'a mut Map<String, JsonValue>);
+
+impl<'kvs> VisitSource<'kvs> for FieldVisitor<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        // Mount points are simply the "mount" in JSON output, along with their labels
+        let name = match key.as_str() {
+            "mount_point" => {
+                if let Some(labels) = labels(&value.to_string()) {
+                    let labels = serde_json::to_value(labels).map_err(kv::Error::boxed)?;
+                    self.0.insert("labels".to_string(), labels);
+                }
+                "mount"
+            }
+            name => name,
+        };
+        let value = serde_json::to_value(value).map_err(kv::Error::boxed)?;
+        self.0.insert(name.to_string(), value);
+        Ok(())
+    }
+}
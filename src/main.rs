@@ -1,248 +1,354 @@
-use clap::Parser;
-use env_logger::Env;
-use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
-use log::{debug, error, info, warn};
-use proc_mounts::MountIter;
-use serde::Deserialize;
-use std::collections::HashMap;
-use std::io::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use log::{debug, error};
+use nofus::commands::{self, Options};
+use nofus::config::{self, Config};
+use nofus::control::Request;
+use nofus::error::EXIT_USAGE;
+use nofus::logging::{self, LogFormat};
+use nofus::mounts;
 use std::path::PathBuf;
-use std::process::Command;
-use std::{env, fs, process, thread, time};
-
-#[derive(Debug, Deserialize)]
-struct Config {
-    mount_points: Vec<String>,
-    delay_seconds: u64,
-    all_mounted_cmd: String,
-    any_unmounted_cmd: String,
-}
+use std::process;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = "A reliable NFS mount monitor.")]
 struct Cli {
+    /// Log the commands and notifications instead of running or sending them
     #[clap(long, short, action)]
     dry_run: bool,
-    #[clap(long, short, action)]
+    /// Exit once a hook command fails, after its retries, rather than only logging it
+    #[clap(long, action)]
+    fail_fast: bool,
+    /// Forget the state saved with persist_state, acting on every mount as found
+    #[clap(long, action)]
+    reset_state: bool,
+    /// Show debug-level logging
+    #[clap(long, short, action, global = true)]
     verbose: bool,
-    #[clap(long, short)]
+    /// Path of the config file [default: /etc/nofus/config.yml, or
+    /// $XDG_CONFIG_HOME/nofus/config.yml when not root]
+    #[clap(long, short, global = true)]
     config: Option<String>,
+    /// Where and how to write log output, overriding the config [default: text]
+    #[clap(long, value_enum, global = true)]
+    log_format: Option<LogFormat>,
+    /// Path of the control socket, overriding the config [default:
+    /// /run/nofus/control.sock, or $XDG_RUNTIME_DIR/nofus/control.sock when not root]
+    #[clap(long, global = true)]
+    socket: Option<String>,
+    /// Path of the pid file that keeps a second daemon from starting, overriding the
+    /// config [default: /run/nofus.pid, or $XDG_RUNTIME_DIR/nofus.pid when not root]
+    #[clap(long, global = true)]
+    pid_file: Option<String>,
+    /// Check mounts as seen from this mount namespace, e.g. a container's
+    /// /proc/<pid>/ns/mnt, unless the config gives a mount another
+    #[clap(long, global = true)]
+    mnt_ns: Option<PathBuf>,
+    #[clap(subcommand)]
+    command: Option<Commands>,
 }
 
-// Handle the case where all the mounts are mounted
-fn all_mounted(cmd: &String, dry_run: bool) {
-    info!("All NFS mounts are available");
-    if !dry_run {
-        debug!("Running command: {}", cmd);
-        run_command(cmd).expect("Failed to run command");
-    } else {
-        info!(
-            "Dry run enabled, no commands will be executed.\n Would run: {}",
-            cmd
-        );
-    }
-}
-
-// Hanle the case where the mounts are not all mounted
-fn any_unmounted(cmd: &String, dry_run: bool) {
-    error!("One or more NFS mounts are disconnected!!");
-    if !dry_run {
-        debug!("Running command: {}", cmd);
-        run_command(cmd).expect("Failed to run command");
-    } else {
-        info!(
-            "Dry run enabled, no commands will be executed.\n Would run: {}",
-            cmd
-        );
-    }
-}
-
-// Check if the path is a mount point
-fn is_mount_point(path: &str) -> bool {
-    // Get the systems mount points from /proc/mounts
-    let Ok(canonical_path) = PathBuf::from(path).canonicalize() else {
-        return false;
-    };
-    let mounts = match MountIter::new() {
-        Ok(m) => m,
-        Err(_) => return false,
-    };
-
-    // Filter for the matching path.
-    mounts
-        .filter_map(Result::ok)
-        .filter_map(|m| m.dest.canonicalize().ok())
-        .any(|p| p == canonical_path)
-}
-
-// Run a command
-fn run_command(command_string: &str) -> Result<(), String> {
-    Command::new("sh")
-        .arg("-c")
-        .arg(command_string)
-        .status()
-        .map_err(|e| format!("Failed to execute command: {}", e))
-        .and_then(|status| {
-            if status.success() {
-                Ok(())
-            } else {
-                Err(format!("Command failed with status: {}", status))
-            }
-        })
+#[derive(Subcommand)]
+enum Commands {
+    /// Check all mount points once and exit: 0 if all are mounted, 1 if any are
+    /// missing, 78 if the config is wrong
+    Check,
+    /// Check the mount points once for a container HEALTHCHECK, printing a one-line
+    /// summary: exits 0 if all are mounted and 1 otherwise, including on errors and
+    /// when the timeout expires
+    Healthcheck {
+        /// Mount points to check instead of those in the config, or without a config
+        /// the network mounts in the mount table
+        paths: Vec<String>,
+        /// Count as unhealthy if the checks take longer than this, e.g. 5s
+        #[clap(long, default_value = "10s", value_parser = humantime::parse_duration)]
+        timeout: Duration,
+    },
+    /// Show the state of each mount point as seen by the running daemon
+    Status {
+        /// Print the raw JSON status
+        #[clap(long, action)]
+        json: bool,
+    },
+    /// Wait until all mount points, or just the given ones, are mounted. Exits 0 once
+    /// they are, 1 if the timeout expires first, 78 if the config is wrong
+    Wait {
+        /// Mount points to wait for instead of those in the config
+        paths: Vec<String>,
+        /// Give up after this long, e.g. 90s or 5m [default: wait forever]
+        #[clap(long, value_parser = humantime::parse_duration)]
+        timeout: Option<Duration>,
+    },
+    /// Have the running daemon hold off commands and notifications for a while, e.g.
+    /// during planned maintenance
+    Silence {
+        /// How long for, e.g. 30m or 2h
+        #[clap(value_parser = humantime::parse_duration)]
+        duration: Duration,
+        /// Only silence this mount point
+        #[clap(long)]
+        mount: Option<String>,
+    },
+    /// Write a config file by picking from the NFS and CIFS mounts found on this
+    /// machine and answering a few questions
+    Init,
+    /// Write a systemd unit, or a launchd job on macOS, that runs nofus as a service,
+    /// with this binary and config file, for the whole system or, with --user, for
+    /// the user running it
+    Install {
+        /// Install a unit for the user's own systemd instance [default: when not root]
+        #[clap(long, conflicts_with = "system")]
+        user: bool,
+        /// Install a unit for the system's systemd [default: when root]
+        #[clap(long)]
+        system: bool,
+        /// Also reload systemd and enable and start the service
+        #[clap(long)]
+        enable: bool,
+        /// Write the unit here instead, e.g. to look it over first
+        #[clap(long)]
+        output: Option<PathBuf>,
+    },
+    /// Print a JSON Schema of the config file, for editors and linters
+    Schema,
+    /// Print a completion script for the shell, which offers the mount points in the
+    /// config wherever one is asked for
+    Completions { shell: Shell },
+    /// Print a man page covering the options, subcommands, config file, signals and
+    /// exit codes, or with --output write it and one for each subcommand there
+    Man {
+        /// The directory to write nofus.1 and the nofus-<subcommand>.1 pages to
+        #[clap(long)]
+        output: Option<PathBuf>,
+    },
+    /// Monitor the given mount points without a config file, running a command when
+    /// any of them goes down and another once they are all back up
+    Watch {
+        /// Mount points to monitor
+        #[clap(required = true)]
+        paths: Vec<String>,
+        /// Command to run when any of them goes down
+        #[clap(long)]
+        on_down: Option<String>,
+        /// Command to run once they are all mounted
+        #[clap(long)]
+        on_up: Option<String>,
+        /// Seconds between checks
+        #[clap(long, default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+    },
+    /// Run as a Kubernetes sidecar: monitor the mount points given, in
+    /// NOFUS_MOUNT_POINTS or in a file such as one from the downward API, serving
+    /// their health at /healthz and /readyz. NOFUS_WEB_TOKEN turns on the rest of the
+    /// dashboard's API.
+    Sidecar {
+        /// Mount points to monitor, besides those in NOFUS_MOUNT_POINTS
+        paths: Vec<String>,
+        /// A file listing more mount points, separated by whitespace or commas
+        #[clap(long)]
+        mount_points_file: Option<PathBuf>,
+        /// Where to serve the health checks
+        #[clap(long, default_value = "0.0.0.0:8080")]
+        listen: String,
+        /// Exit with an error once a mount has been down this long, e.g. 5m
+        #[clap(long, value_parser = humantime::parse_duration)]
+        exit_after: Option<Duration>,
+        /// Seconds between checks
+        #[clap(long, default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+    },
+    /// Check the config file for mistakes without starting the daemon: unreadable
+    /// settings, missing mount points and commands that can't be found. Exits 0 if it
+    /// is fine, warnings aside, 78 if it doesn't load at all and 1 otherwise
+    Validate,
+    /// Walk through what the daemon would do if mounts went down or came up, without
+    /// touching them or running anything. Every other mount is taken to be mounted.
+    Simulate {
+        /// Mount points that go down
+        #[clap(long)]
+        down: Vec<String>,
+        /// Mount points that come back up, having been down
+        #[clap(long)]
+        up: Vec<String>,
+        /// Mount points that go stale
+        #[clap(long)]
+        stale: Vec<String>,
+    },
+    /// Show the state changes, checks and commands recorded in the history, oldest
+    /// first
+    History {
+        /// Only show this mount point
+        #[clap(long)]
+        mount: Option<String>,
+        /// Only show what happened within this long, e.g. 24h or 7d
+        #[clap(long, value_parser = humantime::parse_duration)]
+        since: Option<Duration>,
+    },
+    /// Show how available each mount was over a period, from the history: the share
+    /// of time it was mounted, how often and for how long it went down
+    Report {
+        /// How far back to look, e.g. 30d or 1week
+        #[clap(long, default_value = "30d", value_parser = humantime::parse_duration)]
+        period: Duration,
+        /// Print JSON instead of a table
+        #[clap(long, action, conflicts_with = "csv")]
+        json: bool,
+        /// Print CSV instead of a table
+        #[clap(long, action)]
+        csv: bool,
+    },
+    /// Show a live view of the mounts from the running daemon: their state, how long
+    /// they've been in it, their last change, how long checks take and the latest
+    /// changes. Press q to quit, any other key to refresh.
+    Top {
+        /// Seconds between refreshes
+        #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+    },
+    /// Lift silences early
+    Unsilence {
+        /// Only lift the silence for this mount point
+        #[clap(long)]
+        mount: Option<String>,
+    },
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Get CLI config
-    let cli = Cli::parse();
-
-    // Configure the logger
-    let mut builder = env_logger::Builder::from_env(Env::default().default_filter_or("info"));
-    if cli.verbose {
-        builder.filter_level(log::LevelFilter::Trace);
-    }
-    builder.init();
+fn main() {
+    // Get CLI config. Help and the version are printed to stdout and aren't errors.
+    let cli = Cli::try_parse().unwrap_or_else(|e| {
+        let _ = e.print();
+        process::exit(if e.use_stderr() { EXIT_USAGE } else { 0 })
+    });
 
     // Load configuration
-    // Path to the configuration file should default to $HOME/.config/nofus/config.yml or
-    // /etc/nofus/config.yml if no user context.
-    let config_path = match cli.config {
+    let config_path = match &cli.config {
         Some(path) => PathBuf::from(path), // Use the provided config path
-        None => {
-            // Fallback to default paths if no config is provided
-            match env::var("HOME") {
-                Ok(home) => PathBuf::from(home).join(".config/nofus/config.yml"),
-                Err(_) => PathBuf::from("/etc/nofus/config.yml"),
-            }
-        }
+        None => config::default_path(),    // Fallback to default paths if no config is provided
     };
-    debug!("Using config file at: {}", config_path.display());
 
-    // If the directory doesn't exist, create it
-    if !config_path.parent().unwrap().exists() {
-        debug!("Creating config directory");
-        fs::create_dir_all(config_path.parent().unwrap())?;
+    // Configure the logger, which may be set up in the config file
+    let log_format = cli.log_format.unwrap_or_else(|| {
+        Config::load(&config_path)
+            .ok()
+            .and_then(|c| c.log_format)
+            .unwrap_or_default()
+    });
+    logging::init(log_format, cli.verbose);
+    if let Some(namespace) = &cli.mnt_ns {
+        mounts::set_default_namespace(namespace.clone());
     }
 
-    // If the config file doesn't exist, create it
-    if !config_path.exists() {
-        warn!(
-            "Creating a default config file at {}, you'll want to edit it.",
-            config_path.display()
-        );
-        let default_config = include_str!("config.template.yml");
-        fs::write(config_path, default_config)?;
-        process::exit(1) // Just exit because they really should update that...
-    }
-    let config_content = fs::read_to_string(config_path)?;
-    let config: Config = match serde_yml::from_str(&config_content) {
-        Ok(c) => c,
-        Err(e) => panic!("Failed to parse configuration: {}", e),
-    };
-
-    // Initialize inotify
-    let mut inotify = Inotify::init()?;
-    let mut watches: HashMap<String, WatchDescriptor> = HashMap::new();
+    debug!("Using config file at: {}", config_path.display());
 
-    // Check initial state and set up watches
-    let mut current_state = true;
-    for path in &config.mount_points {
-        info!("Monitoring mount point: {}", path);
-        //  Check state and setup watch
-        if is_mount_point(path) {
-            if let Ok(watch) = inotify.watches().add(path, WatchMask::ALL_EVENTS) {
-                watches.insert(path.clone(), watch);
-            }
-        } else {
-            current_state = false;
+    let options = Options {
+        dry_run: cli.dry_run,
+        fail_fast: cli.fail_fast,
+        reset_state: cli.reset_state,
+        socket: cli.socket.clone(),
+        pid_file: cli.pid_file.clone(),
+    };
+    let result = match cli.command {
+        Some(Commands::Check) => process::exit(commands::check(&config_path)),
+        Some(Commands::Healthcheck { ref paths, timeout }) => {
+            let given = cli.config.is_some();
+            process::exit(commands::healthcheck(&config_path, given, paths, timeout))
         }
-    }
-
-    // Notify if dry run
-    if cli.dry_run {
-        warn!("== Dry run enabled, no commands will be executed. ==");
-    }
-
-    // Execute on initial state
-    info!("Initial state: ");
-    if current_state {
-        all_mounted(&config.all_mounted_cmd, cli.dry_run);
-    } else {
-        any_unmounted(&config.any_unmounted_cmd, cli.dry_run);
-    }
-
-    // Loop for observation of watchers
-    debug!(
-        "Starting observation loop ({} second delay)...",
-        config.delay_seconds
-    );
-
-    let mut buffer = [0; 4096];
-    loop {
-        // Benchmark the timing
-        let start_time = time::Instant::now();
-
-        // Process inotify events
-        let mut events = Vec::new();
-        match inotify.read_events(&mut buffer) {
-            Ok(read_events) => read_events.for_each(|event| events.push(event)),
-            Err(error) if error.kind() == ErrorKind::WouldBlock => continue,
-            _ => panic!("Error while reading events"),
+        Some(Commands::Status { json }) => {
+            process::exit(commands::status(&options, &config_path, json))
         }
-
-        for event in events {
-            if event.mask.contains(EventMask::IGNORED) {
-                // Remove invalidated watches
-                let path = watches
-                    .iter()
-                    .find(|(_, wd)| **wd == event.wd)
-                    .map(|(p, _)| p.clone());
-
-                if let Some(path) = path {
-                    watches.remove(&path);
-                }
-            }
+        Some(Commands::Wait { ref paths, timeout }) => {
+            process::exit(commands::wait(&config_path, paths, timeout))
         }
-
-        let mut new_state = true;
-        let mut state_changed = false;
-
-        // Update watches and check mount status
-        for path in &config.mount_points {
-            let is_mounted = is_mount_point(path);
-
-            // Update watches
-            if is_mounted && !watches.contains_key(path) {
-                if let Ok(watch) = inotify.watches().add(path, WatchMask::ALL_EVENTS) {
-                    watches.insert(path.clone(), watch);
-                }
-            }
-
-            // Update state
-            if !is_mounted {
-                new_state = false;
-            }
+        Some(Commands::Silence {
+            duration,
+            ref mount,
+        }) => {
+            let request = Request::Silence {
+                seconds: duration.as_secs(),
+                mount: mount.clone(),
+            };
+            process::exit(commands::control(&options, &config_path, &request))
         }
-
-        // Check if state changed
-        if new_state != current_state {
-            state_changed = true;
-            current_state = new_state;
+        Some(Commands::Simulate {
+            ref down,
+            ref up,
+            ref stale,
+        }) => process::exit(commands::simulate(&config_path, down, up, stale)),
+        Some(Commands::Unsilence { ref mount }) => {
+            let request = Request::Unsilence {
+                mount: mount.clone(),
+            };
+            process::exit(commands::control(&options, &config_path, &request))
         }
-
-        // Job done, how long did it take?
-        let elapsed = start_time.elapsed();
-        debug!("Processed events in {}ms", elapsed.as_millis());
-
-        // Trigger appropriate function if state changed
-        if state_changed {
-            if current_state {
-                all_mounted(&config.all_mounted_cmd, cli.dry_run);
-            } else {
-                any_unmounted(&config.any_unmounted_cmd, cli.dry_run);
-            }
+        Some(Commands::Init) => process::exit(commands::init(&config_path)),
+        Some(Commands::Install {
+            user,
+            system,
+            enable,
+            ref output,
+        }) => {
+            // Root installs for the system unless told otherwise, anyone else for themself
+            let user = user || (!system && unsafe { libc::geteuid() } != 0);
+            process::exit(commands::install(
+                &config_path,
+                user,
+                enable,
+                output.as_deref(),
+            ))
         }
-
-        // Periodic check every 5 seconds
-        thread::sleep(time::Duration::from_secs(config.delay_seconds));
+        Some(Commands::Schema) => {
+            println!("{:#}", config::schema());
+            Ok(())
+        }
+        Some(Commands::Completions { shell }) => {
+            commands::completions(Cli::command(), &config_path, shell);
+            Ok(())
+        }
+        Some(Commands::Man { ref output }) => {
+            process::exit(commands::man(Cli::command(), output.as_deref()))
+        }
+        Some(Commands::Watch {
+            ref paths,
+            ref on_down,
+            ref on_up,
+            interval,
+        }) => commands::watch(
+            &options,
+            paths,
+            on_down.as_deref(),
+            on_up.as_deref(),
+            interval,
+        ),
+        Some(Commands::Sidecar {
+            ref paths,
+            ref mount_points_file,
+            ref listen,
+            exit_after,
+            interval,
+        }) => commands::sidecar(
+            &options,
+            paths,
+            mount_points_file.as_deref(),
+            listen,
+            exit_after,
+            interval,
+        ),
+        Some(Commands::Validate) => process::exit(commands::validate(&config_path)),
+        Some(Commands::History { ref mount, since }) => {
+            process::exit(commands::history(&config_path, mount.as_deref(), since))
+        }
+        Some(Commands::Top { interval }) => {
+            let interval = Duration::from_secs(interval);
+            process::exit(commands::top(&options, &config_path, interval))
+        }
+        Some(Commands::Report { period, json, csv }) => {
+            process::exit(commands::report(&config_path, period, json, csv))
+        }
+        None => commands::run(&options, config_path),
+    };
+    if let Err(e) = result {
+        error!("{}", e);
+        process::exit(e.exit_code());
     }
 }
@@ -0,0 +1,345 @@
+use crate::config::{InfluxDbConfig, MetricsConfig};
+use crate::monitor::MountState;
+use crate::notify;
+use log::{debug, warn};
+use std::collections::BTreeMap;
+use std::net::UdpSocket;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use ureq::Agent;
+
+// How long to wait on InfluxDB
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How much goes in one statsd packet, to stay clear of fragmenting
+const MAX_PACKET: usize = 1400;
+
+// Each gets a gauge under statsd, which has no tags to give the state in
+const STATES: [MountState; 8] = [
+    MountState::Unknown,
+    MountState::Mounted,
+    MountState::Unmounted,
+    MountState::Stale,
+    MountState::Unresponsive,
+    MountState::Flapping,
+    MountState::Degraded,
+    MountState::ServerDown,
+];
+
+// Pushes the mount metrics to statsd and/or InfluxDB on a timer, from a thread of its
+// own so a slow server can't hold up monitoring. The thread flushes one last time and
+// stops once this is dropped.
+pub struct Metrics {
+    counts: Arc<Mutex<Counts>>,
+    _stop: Sender<()>,
+}
+
+#[derive(Default)]
+struct Counts {
+    states: BTreeMap<String, MountState>,
+    servers: BTreeMap<String, String>,
+    labels: BTreeMap<String, BTreeMap<String, String>>,
+    // State changes of each mount since starting
+    transitions: BTreeMap<String, u64>,
+    // How long each check took since the last flush
+    latencies: BTreeMap<String, Vec<Duration>>,
+}
+
+impl Metrics {
+    pub fn new(config: &MetricsConfig) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let counts = Arc::new(Mutex::new(Counts::default()));
+        let flusher = Flusher::new(config, Arc::clone(&counts));
+        thread::spawn(move || flusher.run(&stopped));
+        Metrics {
+            counts,
+            _stop: stop,
+        }
+    }
+
+    pub fn transition(&self, path: &str) {
+        *self
+            .counts
+            .lock()
+            .unwrap()
+            .transitions
+            .entry(path.to_string())
+            .or_default() += 1;
+    }
+
+    pub fn probed(&self, path: &str, latency: Duration) {
+        self.counts
+            .lock()
+            .unwrap()
+            .latencies
+            .entry(path.to_string())
+            .or_default()
+            .push(latency);
+    }
+
+    // Set the state of every monitored mount, forgetting those no longer given
+    pub fn states(&self, states: BTreeMap<String, MountState>) {
+        let mut counts = self.counts.lock().unwrap();
+        counts
+            .transitions
+            .retain(|path, _| states.contains_key(path));
+        counts.states = states;
+    }
+
+    // Set the server each mount comes from, for those it is known for
+    pub fn servers(&self, servers: BTreeMap<String, String>) {
+        self.counts.lock().unwrap().servers = servers;
+    }
+
+    // Set the labels of each mount that has any
+    pub fn labels(&self, labels: BTreeMap<String, BTreeMap<String, String>>) {
+        self.counts.lock().unwrap().labels = labels;
+    }
+}
+
+struct Flusher {
+    prefix: String,
+    interval: Duration,
+    host: String,
+    statsd: Option<String>,
+    influxdb: Option<InfluxDbConfig>,
+    agent: Agent,
+    counts: Arc<Mutex<Counts>>,
+    // The transition counts last sent to statsd, which only takes what was added
+    sent: BTreeMap<String, u64>,
+    // Whether the last flush to each failed, to only warn when that starts
+    statsd_failing: bool,
+    influxdb_failing: bool,
+}
+
+// What is sent about a mount on a flush
+struct Sample {
+    path: String,
+    server: Option<String>,
+    labels: BTreeMap<String, String>,
+    state: MountState,
+    transitions: u64,
+    latencies: Vec<Duration>,
+}
+
+impl Flusher {
+    fn new(config: &MetricsConfig, counts: Arc<Mutex<Counts>>) -> Self {
+        Flusher {
+            prefix: config.prefix.clone(),
+            interval: config.flush_interval(),
+            host: notify::hostname(),
+            statsd: config.statsd.clone(),
+            influxdb: config.influxdb.clone(),
+            agent: Agent::config_builder()
+                .timeout_global(Some(WRITE_TIMEOUT))
+                .build()
+                .into(),
+            counts,
+            sent: BTreeMap::new(),
+            statsd_failing: false,
+            influxdb_failing: false,
+        }
+    }
+
+    fn run(mut self, stopped: &Receiver<()>) {
+        loop {
+            let last = matches!(
+                stopped.recv_timeout(self.interval),
+                Ok(()) | Err(RecvTimeoutError::Disconnected)
+            );
+            self.flush();
+            if last {
+                return;
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        let samples: Vec<Sample> = {
+            let mut counts = self.counts.lock().unwrap();
+            let mut latencies = std::mem::take(&mut counts.latencies);
+            counts
+                .states
+                .iter()
+                .map(|(path, state)| Sample {
+                    path: path.clone(),
+                    server: counts.servers.get(path).cloned(),
+                    labels: counts.labels.get(path).cloned().unwrap_or_default(),
+                    state: *state,
+                    transitions: counts.transitions.get(path).copied().unwrap_or(0),
+                    latencies: latencies.remove(path).unwrap_or_default(),
+                })
+                .collect()
+        };
+        if samples.is_empty() {
+            return;
+        }
+        if let Some(address) = self.statsd.clone() {
+            let result = self.send_statsd(&address, &samples);
+            self.statsd_failing = report("statsd", &address, result, self.statsd_failing);
+        }
+        if let Some(influxdb) = self.influxdb.clone() {
+            let result = self.send_influxdb(&influxdb, &samples);
+            self.influxdb_failing =
+                report("InfluxDB", &influxdb.url, result, self.influxdb_failing);
+        }
+    }
+
+    // Each mount as <prefix>.<mount>.up and .state.<state> gauges, a .transitions
+    // counter and a .latency timer per check
+    fn send_statsd(&mut self, address: &str, samples: &[Sample]) -> Result<(), String> {
+        let mut lines = Vec::new();
+        for sample in samples {
+            let name = format!("{}.{}", self.prefix, statsd_name(&sample.path));
+            let up = sample.state.is_up() as u8;
+            lines.push(format!("{}.up:{}|g", name, up));
+            for state in STATES {
+                let current = (state == sample.state) as u8;
+                lines.push(format!("{}.state.{}:{}|g", name, state, current));
+            }
+            let sent = self.sent.get(&sample.path).copied().unwrap_or(0);
+            lines.push(format!(
+                "{}.transitions:{}|c",
+                name,
+                sample.transitions.saturating_sub(sent)
+            ));
+            for latency in &sample.latencies {
+                lines.push(format!("{}.latency:{}|ms", name, latency.as_millis()));
+            }
+        }
+
+        let socket = UdpSocket::bind(if address.starts_with('[') {
+            "[::]:0"
+        } else {
+            "0.0.0.0:0"
+        })
+        .map_err(|e| e.to_string())?;
+        socket.connect(address).map_err(|e| e.to_string())?;
+        let mut packet = String::new();
+        for line in lines {
+            if !packet.is_empty() && packet.len() + line.len() + 1 > MAX_PACKET {
+                socket.send(packet.as_bytes()).map_err(|e| e.to_string())?;
+                packet.clear();
+            }
+            if !packet.is_empty() {
+                packet.push('\n');
+            }
+            packet.push_str(&line);
+        }
+        socket.send(packet.as_bytes()).map_err(|e| e.to_string())?;
+        self.sent = samples
+            .iter()
+            .map(|s| (s.path.clone(), s.transitions))
+            .collect();
+        Ok(())
+    }
+
+    // A <prefix>_mount point per mount, tagged with the mount, its state, the host, the
+    // server if known and the mount's labels
+    fn send_influxdb(&self, influxdb: &InfluxDbConfig, samples: &[Sample]) -> Result<(), String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let lines: Vec<String> = samples
+            .iter()
+            .map(|sample| {
+                let mut fields = format!(
+                    "up={}i,transitions={}i",
+                    sample.state.is_up() as u8,
+                    sample.transitions
+                );
+                if let Some(slowest) = sample.latencies.iter().max() {
+                    let total: Duration = sample.latencies.iter().sum();
+                    let mean = total / sample.latencies.len() as u32;
+                    fields.push_str(&format!(
+                        ",latency_ms={},latency_max_ms={}",
+                        mean.as_secs_f64() * 1000.0,
+                        slowest.as_secs_f64() * 1000.0
+                    ));
+                }
+                // Tags can't be empty, so a mount with no known server goes without, as
+                // does a label with no value
+                let mut tags = sample
+                    .server
+                    .as_ref()
+                    .map(|s| format!(",server={}", influx_escape(s)))
+                    .unwrap_or_default();
+                for (name, value) in sample.labels.iter().filter(|(_, v)| !v.is_empty()) {
+                    tags.push_str(&format!(",{}={}", name, influx_escape(value)));
+                }
+                format!(
+                    "{}_mount,mount={},state={},host={}{} {} {}",
+                    influx_escape(&self.prefix),
+                    influx_escape(&sample.path),
+                    sample.state,
+                    influx_escape(&self.host),
+                    tags,
+                    fields,
+                    now
+                )
+            })
+            .collect();
+
+        let mut request = self
+            .agent
+            .post(&influxdb.url)
+            .header("Content-Type", "text/plain; charset=utf-8");
+        if let Some(token) = &influxdb.token {
+            request = request.header("Authorization", &format!("Token {}", token));
+        }
+        request
+            .send(lines.join("\n"))
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+// Log how a flush went, returning whether it failed
+fn report(sink: &str, target: &str, result: Result<(), String>, failing: bool) -> bool {
+    match result {
+        Ok(()) => {
+            debug!("Sent metrics to {} at {}", sink, target);
+            false
+        }
+        Err(e) => {
+            if failing {
+                debug!("Unable to send metrics to {} at {}: {}", sink, target, e);
+            } else {
+                warn!("Unable to send metrics to {} at {}: {}", sink, target, e);
+            }
+            true
+        }
+    }
+}
+
+// A mount point as part of a statsd metric name, e.g. /mnt/data as mnt_data
+fn statsd_name(path: &str) -> String {
+    let name: String = path
+        .trim_matches('/')
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.is_empty() {
+        "root".to_string()
+    } else {
+        name
+    }
+}
+
+// Escape a tag value or measurement name for the line protocol
+fn influx_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
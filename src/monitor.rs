@@ -0,0 +1,2811 @@
+use crate::command::{kill_running, render};
+use crate::config::{
+    CommandLine, Config, Hook, MaintenanceWindow, MetricsConfig, MountPoint, OtlpConfig,
+};
+use crate::control::{CommandReport, EventReport, HistogramReport, MountReport};
+use crate::dns::{Dns, Resolved};
+use crate::flap::{Flap, FlapDetector};
+use crate::heartbeat::Heartbeat;
+use crate::history::History;
+use crate::kerberos::{self, uses_kerberos};
+use crate::latency::{Alerts, Histogram};
+use crate::logging;
+use crate::metrics::Metrics;
+use crate::mounts::{
+    is_read_only, mount_source, probe, probe_all, server, Capacity, Probe, Target,
+};
+use crate::notify::{self, Notifications, StateChange, UsageChange};
+use crate::options;
+use crate::otlp::{Attribute, Otlp, Span};
+use crate::queue::{CommandQueue, Job, GLOBAL, LIFECYCLE};
+use crate::remount::spawn_remount;
+use crate::state::{self, SavedMount, SavedState};
+use crate::usage::{Level, Usage};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// The last observed state of a single mount point
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MountState {
+    Unknown,
+    Mounted,
+    Unmounted,
+    // Still mounted, but every operation fails with a stale file handle
+    Stale,
+    // Checking the mount didn't finish in time, e.g. a hard mount with its server down
+    Unresponsive,
+    // Changing state too often to act on, only ever reported by the daemon
+    Flapping,
+    // Mounted and answering, but slower than latency_warn_ms allows
+    Degraded,
+    // Not working because its server isn't answering, rather than anything wrong
+    // with the client
+    #[serde(rename = "server_down")]
+    ServerDown,
+}
+
+impl MountState {
+    // Whether the mount can be used, if only slowly
+    pub fn is_up(self) -> bool {
+        matches!(self, MountState::Mounted | MountState::Degraded)
+    }
+}
+
+impl fmt::Display for MountState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            MountState::Unknown => "unknown",
+            MountState::Mounted => "mounted",
+            MountState::Unmounted => "unmounted",
+            MountState::Stale => "stale",
+            MountState::Unresponsive => "unresponsive",
+            MountState::Flapping => "flapping",
+            MountState::Degraded => "degraded",
+            MountState::ServerDown => "server_down",
+        })
+    }
+}
+
+// A single mount point changing from one state to another
+struct Transition<'a> {
+    mount: &'a MountPoint,
+    from: MountState,
+    to: MountState,
+}
+
+// The result of a command run on behalf of one or more mount points
+#[derive(Debug, Clone)]
+pub struct CommandOutcome {
+    pub command: String,
+    pub at: SystemTime,
+    pub result: Result<(), String>,
+}
+
+// What happened to a mount point, by the state it changed to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MountEventKind {
+    Mounted,
+    Unmounted,
+    Stale,
+    // Answering, but slowly
+    Degraded,
+    // Not answering at all in time, e.g. a hard mount hung on its server
+    Unresponsive,
+    ServerDown,
+    // Changing state too often to act on
+    Flapping,
+    // Checking the mount failed, so its state can't be told
+    ProbeError,
+}
+
+impl From<MountState> for MountEventKind {
+    fn from(state: MountState) -> Self {
+        match state {
+            MountState::Mounted => MountEventKind::Mounted,
+            MountState::Unmounted => MountEventKind::Unmounted,
+            MountState::Stale => MountEventKind::Stale,
+            MountState::ServerDown => MountEventKind::ServerDown,
+            MountState::Degraded => MountEventKind::Degraded,
+            MountState::Unresponsive => MountEventKind::Unresponsive,
+            MountState::Flapping => MountEventKind::Flapping,
+            MountState::Unknown => MountEventKind::ProbeError,
+        }
+    }
+}
+
+// A mount point observed changing from one state to another
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountEvent {
+    pub path: String,
+    pub kind: MountEventKind,
+    pub from: MountState,
+    pub to: MountState,
+    pub at: SystemTime,
+    // Why the mount is in its new state, e.g. "stale file handle"
+    pub cause: String,
+}
+
+impl MountEvent {
+    fn new(path: &str, from: MountState, probe: Probe, at: SystemTime) -> Self {
+        MountEvent {
+            path: path.to_string(),
+            kind: probe.state.into(),
+            from,
+            to: probe.state,
+            at,
+            cause: probe.cause,
+        }
+    }
+}
+
+// The state of a single mount point and when it was entered
+#[derive(Debug, Clone)]
+struct MountStatus {
+    state: MountState,
+    since: Option<SystemTime>,
+    // A change seen but not yet believed: whether it's to mounted, how many times, and
+    // since when
+    pending: Option<(bool, u32, Instant)>,
+    // When the mount is next due to be checked, right away if never checked
+    next_check: Option<Instant>,
+    // How long the last few checks took, and the last few state changes, oldest first
+    latencies: VecDeque<Duration>,
+    recent: VecDeque<MountEvent>,
+    // How long every check took, and how full the mount was at the last
+    histogram: Histogram,
+    capacity: Option<Capacity>,
+}
+
+// How many checks and state changes to keep for each mount
+const RECENT_LATENCIES: usize = 30;
+const RECENT_EVENTS: usize = 10;
+
+impl MountStatus {
+    fn unknown() -> Self {
+        MountStatus {
+            state: MountState::Unknown,
+            since: None,
+            pending: None,
+            next_check: None,
+            latencies: VecDeque::new(),
+            recent: VecDeque::new(),
+            histogram: Histogram::default(),
+            capacity: None,
+        }
+    }
+
+    fn measured(&mut self, probe: &Probe) {
+        if self.latencies.len() == RECENT_LATENCIES {
+            self.latencies.pop_front();
+        }
+        self.latencies.push_back(probe.latency);
+        self.histogram.observe(probe.latency);
+        self.capacity = probe.capacity;
+    }
+
+    fn changed(&mut self, event: &MountEvent) {
+        if self.recent.len() == RECENT_EVENTS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(event.clone());
+    }
+}
+
+// How far a remount tried in the background has got
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RemountProgress {
+    Trying,
+    Failed,
+}
+
+type Remounted = Arc<dyn Fn(&str) + Send + Sync>;
+
+// Tracks the state of the configured mount points, reporting what changed on each
+// poll. This only detects changes; running commands and notifying is up to the caller.
+pub struct MountMonitor {
+    config: Config,
+    dry_run: bool,
+    mounts: HashMap<String, MountStatus>,
+    subscribers: Vec<Sender<MountEvent>>,
+    // Mounts taken to be in a state rather than probed, to simulate changes
+    forced: HashMap<String, MountState>,
+    // Checks that found a mount in another state, not yet believed
+    unconfirmed: Vec<(String, Probe)>,
+    // What each check found, for the metrics and alerts
+    probed: Vec<(String, Probe)>,
+    // Remounts being tried in the background, and those that failed until the mount
+    // is next checked
+    remounts: Arc<Mutex<HashMap<String, RemountProgress>>>,
+    // Told of each remount once it is done, to have the mounts checked again
+    remounted: Option<Remounted>,
+}
+
+impl MountMonitor {
+    pub fn new(config: Config) -> Self {
+        // Every mount starts out unknown until it has been checked once
+        let mut mounts = HashMap::new();
+        for mount in &config.mount_points {
+            mounts.insert(mount.path.clone(), MountStatus::unknown());
+        }
+        MountMonitor {
+            config,
+            dry_run: false,
+            mounts,
+            subscribers: Vec::new(),
+            unconfirmed: Vec::new(),
+            probed: Vec::new(),
+            forced: HashMap::new(),
+            remounts: Arc::default(),
+            remounted: None,
+        }
+    }
+
+    // Only log remount attempts rather than making them
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    // Have the mount point of each remount tried in the background handed over once
+    // it is done
+    pub fn on_remounted(&mut self, remounted: impl Fn(&str) + Send + Sync + 'static) {
+        self.remounted = Some(Arc::new(remounted));
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // The last observed state of a mount point, unknown if it isn't monitored
+    pub fn state(&self, path: &str) -> MountState {
+        self.mounts
+            .get(path)
+            .map_or(MountState::Unknown, |s| s.state)
+    }
+
+    // When a mount point entered its current state
+    pub fn since(&self, path: &str) -> Option<SystemTime> {
+        self.mounts.get(path).and_then(|s| s.since)
+    }
+
+    // How long the last few checks of a mount point took, oldest first
+    pub fn latencies(&self, path: &str) -> Vec<Duration> {
+        self.mounts
+            .get(path)
+            .map(|s| s.latencies.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    // How full a mount point was when last checked
+    pub(crate) fn capacity(&self, path: &str) -> Option<Capacity> {
+        self.mounts.get(path).and_then(|s| s.capacity)
+    }
+
+    // How long every check of a mount point since starting took
+    pub(crate) fn histogram(&self, path: &str) -> Histogram {
+        self.mounts
+            .get(path)
+            .map(|s| s.histogram.clone())
+            .unwrap_or_default()
+    }
+
+    // The last few state changes of a mount point, oldest first
+    pub fn recent_events(&self, path: &str) -> Vec<MountEvent> {
+        self.mounts
+            .get(path)
+            .map(|s| s.recent.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // When a mount point is next checked and any change seen but not yet believed,
+    // for a state dump
+    pub(crate) fn describe(&self, path: &str) -> String {
+        let Some(status) = self.mounts.get(path) else {
+            return "not monitored".to_string();
+        };
+        let now = Instant::now();
+        let mut parts = vec![match status.next_check {
+            Some(next) if next > now => format!("next check in {}s", (next - now).as_secs()),
+            _ => "check due now".to_string(),
+        }];
+        if let Some((up, seen, first_seen)) = status.pending {
+            parts.push(format!(
+                "seen {} {} time(s) in a row over {}s",
+                if up { "mounted" } else { "down" },
+                seen,
+                (now - first_seen).as_secs()
+            ));
+        }
+        if let Some(state) = self.forced.get(path) {
+            parts.push(format!("taken to be {}", state));
+        }
+        parts.join(", ")
+    }
+
+    // Take a mount point to be in the state an earlier run left it in
+    pub(crate) fn restore(&mut self, path: &str, state: MountState, since: Option<SystemTime>) {
+        if let Some(status) = self.mounts.get_mut(path) {
+            status.state = state;
+            status.since = since;
+        }
+    }
+
+    // Start monitoring another mount point, which is checked on the next poll
+    pub fn add(&mut self, mount: MountPoint) {
+        self.mounts
+            .insert(mount.path.clone(), MountStatus::unknown());
+        self.config.mount_points.push(mount);
+    }
+
+    // Stop monitoring a mount point, without reporting any change
+    pub fn remove(&mut self, path: &str) {
+        self.mounts.remove(path);
+        self.config.mount_points.retain(|m| m.path != path);
+    }
+
+    // Take a mount to be in the given state from now on instead of probing it, or go
+    // back to probing it
+    pub fn force(&mut self, path: &str, state: Option<MountState>) {
+        match state {
+            Some(state) => self.forced.insert(path.to_string(), state),
+            None => self.forced.remove(path),
+        };
+    }
+
+    // Subscribe to every event from now on, as they are found by poll() or reload()
+    pub fn events(&mut self) -> Receiver<MountEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    // Hand events to subscribers, forgetting those that have gone away
+    fn publish(&mut self, events: &[MountEvent]) {
+        self.subscribers
+            .retain(|s| events.iter().all(|e| s.send(e.clone()).is_ok()));
+    }
+
+    // When the next mount is due to be checked by poll_due(), if any are monitored
+    pub fn next_due(&self) -> Option<Instant> {
+        self.mounts
+            .values()
+            .map(|s| s.next_check.unwrap_or_else(Instant::now))
+            .min()
+    }
+
+    // Check every mount, in configuration order, and return those that changed state
+    pub fn poll(&mut self) -> Vec<MountEvent> {
+        self.poll_mounts(false)
+    }
+
+    // Like poll(), but only check the mounts whose interval has come around
+    pub fn poll_due(&mut self) -> Vec<MountEvent> {
+        self.poll_mounts(true)
+    }
+
+    fn poll_mounts(&mut self, only_due: bool) -> Vec<MountEvent> {
+        let config = &self.config;
+        let now = Instant::now();
+        let due: Vec<&MountPoint> = config
+            .mount_points
+            .iter()
+            .filter(|m| {
+                !only_due
+                    || self
+                        .mounts
+                        .get(&m.path)
+                        .and_then(|s| s.next_check)
+                        .is_none_or(|t| t <= now)
+            })
+            .collect();
+        let targets: Vec<Target> = due
+            .iter()
+            .filter(|m| !self.forced.contains_key(&m.path))
+            .map(|m| config.target(m))
+            .collect();
+        let mut probed =
+            probe_all(&targets, config.probe_timeout(), config.probe_workers).into_iter();
+        let probes: Vec<Probe> = due
+            .iter()
+            .map(|m| match self.forced.get(&m.path) {
+                Some(state) => Probe {
+                    state: *state,
+                    cause: "simulated".to_string(),
+                    latency: Duration::ZERO,
+                    capacity: None,
+                    options: Vec::new(),
+                },
+                None => probed.next().unwrap_or(Probe {
+                    state: MountState::Unknown,
+                    cause: "not probed".to_string(),
+                    latency: Duration::ZERO,
+                    capacity: None,
+                    options: Vec::new(),
+                }),
+            })
+            .collect();
+
+        let mut events = Vec::new();
+        for (mount, probe) in due.into_iter().zip(probes) {
+            let probe = grade(
+                probe,
+                config.latency_warn(mount),
+                config.latency_critical(mount),
+            );
+            let path = &mount.path;
+            let status = self
+                .mounts
+                .entry(path.clone())
+                .or_insert_with(MountStatus::unknown);
+            if !self.forced.contains_key(path) {
+                status.measured(&probe);
+                self.probed.push((path.clone(), probe.clone()));
+            }
+            // Keep to the schedule so mounts that line up are checked together
+            let interval = config.interval(mount);
+            status.next_check = match status.next_check {
+                Some(due) if only_due && due + interval > now => Some(due + interval),
+                _ => Some(now + interval),
+            };
+            // A failed remount is only remembered until the mount is checked again
+            if probe.state.is_up() {
+                let mut remounts = self.remounts.lock().unwrap();
+                if remounts.get(path) == Some(&RemountProgress::Failed) {
+                    remounts.remove(path);
+                }
+            }
+            if status.state == probe.state {
+                status.pending = None;
+                continue;
+            }
+
+            // Only believe a change once it has been seen enough times in a row, except
+            // on the first check when there is nothing to compare against. Getting slow
+            // counts as going down, and a slow mount coming back as coming up.
+            let up = probe.state == MountState::Mounted
+                || (probe.state == MountState::Degraded && !status.state.is_up());
+            if status.state != MountState::Unknown {
+                let (seen, first_seen) = match status.pending {
+                    Some((pending_up, seen, first_seen)) if pending_up == up => {
+                        (seen + 1, first_seen)
+                    }
+                    _ => (1, now),
+                };
+                let needed = if up {
+                    config.successes_before_up(mount)
+                } else {
+                    config.failures_before_down(mount)
+                };
+                if seen < needed {
+                    debug!(
+                        "{} looks {} ({} of {} checks)",
+                        path, probe.state, seen, needed
+                    );
+                    status.pending = Some((up, seen, first_seen));
+                    self.unconfirmed.push((path.clone(), probe));
+                    continue;
+                }
+                // A mount that came back must also stay up for a while, so check it
+                // again when that time is up
+                let confirmation = if up {
+                    config.recovery_confirmation(mount)
+                } else {
+                    Duration::ZERO
+                };
+                let confirmed_at = first_seen + confirmation;
+                if confirmed_at > now {
+                    debug!(
+                        "{} looks {}, waiting {}s to confirm",
+                        path,
+                        probe.state,
+                        (confirmed_at - now).as_secs()
+                    );
+                    status.pending = Some((up, seen, first_seen));
+                    status.next_check = status.next_check.map(|t| t.min(confirmed_at));
+                    self.unconfirmed.push((path.clone(), probe));
+                    continue;
+                }
+            }
+            status.pending = None;
+
+            // Give a mount that has just vanished a chance to come back. That can take
+            // a while, so it is tried in the background, and the mount keeps its state
+            // until the check after the remount failed.
+            if probe.state == MountState::Unmounted && status.state.is_up() {
+                if let Some(action) = config.remount_action(mount) {
+                    if self.dry_run {
+                        info!("Dry run enabled, would remount: {}", path);
+                    } else {
+                        let mut remounts = self.remounts.lock().unwrap();
+                        match remounts.get(path) {
+                            Some(RemountProgress::Trying) => continue,
+                            Some(RemountProgress::Failed) => {
+                                remounts.remove(path);
+                            }
+                            None => {
+                                remounts.insert(path.clone(), RemountProgress::Trying);
+                                let remounts = Arc::clone(&self.remounts);
+                                let remounted = self.remounted.clone();
+                                let path = path.clone();
+                                spawn_remount(
+                                    &config.target(mount),
+                                    &action,
+                                    config.remount_attempts,
+                                    Duration::from_secs(config.remount_backoff_seconds),
+                                    config.probe_timeout(),
+                                    config.command_timeout(),
+                                    move |attempts| {
+                                        let mut remounts = remounts.lock().unwrap();
+                                        match attempts {
+                                            Some(_) => remounts.remove(&path),
+                                            None => remounts
+                                                .insert(path.clone(), RemountProgress::Failed),
+                                        };
+                                        drop(remounts);
+                                        if let Some(remounted) = remounted {
+                                            remounted(&path);
+                                        }
+                                    },
+                                );
+                                continue;
+                            }
+                        }
+                    }
+                }
+            }
+
+            // Update state
+            let at = SystemTime::now();
+            let from = status.state;
+            status.state = probe.state;
+            status.since = Some(at);
+            let event = MountEvent::new(path, from, probe, at);
+            status.changed(&event);
+            events.push(event);
+        }
+        self.publish(&events);
+        events
+    }
+
+    // The checks since the last call that found a mount in another state without
+    // changing it yet, for the history
+    pub(crate) fn take_unconfirmed(&mut self) -> Vec<(String, Probe)> {
+        std::mem::take(&mut self.unconfirmed)
+    }
+
+    // What each check since the last call found
+    pub(crate) fn take_probed(&mut self) -> Vec<(String, Probe)> {
+        std::mem::take(&mut self.probed)
+    }
+
+    // Swap in a new config, forgetting mount points that are no longer configured and
+    // recording the current state of new ones, which are returned as events
+    pub fn reload(&mut self, new_config: Config) -> Vec<MountEvent> {
+        self.mounts.retain(|path, _| {
+            let keep = new_config.mount_points.iter().any(|m| &m.path == path);
+            if !keep {
+                info!("No longer monitoring mount point: {}", path);
+            }
+            keep
+        });
+        self.config = new_config;
+
+        let mut events = Vec::new();
+        for mount in &self.config.mount_points {
+            if !self.mounts.contains_key(&mount.path) {
+                let probe = probe(&self.config.target(mount), self.config.probe_timeout());
+                let at = SystemTime::now();
+                info!("Monitoring mount point: {} ({})", mount.path, probe.state);
+                let mut status = MountStatus {
+                    state: probe.state,
+                    since: Some(at),
+                    next_check: Some(Instant::now() + self.config.interval(mount)),
+                    ..MountStatus::unknown()
+                };
+                status.measured(&probe);
+                let event = MountEvent::new(&mount.path, MountState::Unknown, probe, at);
+                status.changed(&event);
+                self.mounts.insert(mount.path.clone(), status);
+                events.push(event);
+            }
+        }
+        self.publish(&events);
+        events
+    }
+}
+
+// How far a mount that is down has been followed up on
+struct Reminder {
+    down_since: Instant,
+    next: Instant,
+    count: u32,
+    // How many escalations have been reached
+    level: usize,
+}
+
+// Watches the configured mount points and runs commands as they change state
+pub struct Monitor {
+    mounts: MountMonitor,
+    dry_run: bool,
+    // Runs the commands one at a time, remembering how the last one for each mount went
+    commands: CommandQueue,
+    // Whether every mount any_unmounted_cmd covers was last seen up, and every mount
+    // all_mounted_cmd covers. They only differ with mounts that have a command of
+    // their own for one of the two.
+    current_state: Option<bool>,
+    mounted_state: Option<bool>,
+    // Mount points being monitored because of their filesystem type, and the mount
+    // table they were last discovered from
+    discovered: BTreeSet<String>,
+    mount_table: BTreeMap<PathBuf, String>,
+    // Until when missing mounts are only warned about, and which ones were
+    grace_until: Option<Instant>,
+    deferred: Vec<String>,
+    // Whether each group was last seen with quorum
+    group_states: HashMap<String, bool>,
+    // Whether each server with several mounts was last seen with all of them up, or
+    // all of them down
+    server_states: HashMap<String, bool>,
+    // Mounts in a maintenance window or silenced, and the state those that changed
+    // meanwhile were last acted on in
+    silenced: BTreeSet<String>,
+    held: HashMap<String, MountState>,
+    // Silences asked for at runtime, for one mount or all of them, and until when
+    silences: Vec<(Option<String>, SystemTime)>,
+    // Mounts that are down and when they're next due a reminder, and the same for the
+    // global commands
+    reminders: HashMap<String, Reminder>,
+    global_reminder: Option<Instant>,
+    flaps: FlapDetector,
+    notifications: Notifications,
+    heartbeat: Heartbeat,
+    dns: Dns,
+    hostname: String,
+    // The state as last saved
+    saved: String,
+    // Where state changes and commands are recorded, and the path and retention it
+    // was opened with
+    history: Option<Arc<History>>,
+    history_config: Option<(PathBuf, Duration)>,
+    // Where traces and metrics are sent, and the settings it was set up with
+    otlp: Option<Arc<Otlp>>,
+    otlp_config: Option<OtlpConfig>,
+    // Where metrics are pushed, and the settings it was set up with
+    metrics: Option<Metrics>,
+    metrics_config: Option<MetricsConfig>,
+    latency_alerts: Alerts,
+    // How full each mount was found, against its thresholds
+    usage: Usage,
+    // Mounts meant to be read-write that were last found mounted read-only
+    read_only: BTreeSet<String>,
+    // How the options of each mount last differed from those expected, if they did
+    drift: BTreeMap<String, Vec<String>>,
+    // How the Kerberos credentials of each sec=krb5 mount were last found
+    credentials: BTreeMap<String, kerberos::Status>,
+}
+
+impl Monitor {
+    pub fn new(config: Config, dry_run: bool) -> Self {
+        let grace = Duration::from_secs(config.startup_grace_seconds);
+        let monitor = Monitor {
+            grace_until: (!grace.is_zero()).then(|| Instant::now() + grace),
+            deferred: Vec::new(),
+            group_states: HashMap::new(),
+            server_states: HashMap::new(),
+            reminders: HashMap::new(),
+            global_reminder: None,
+            silenced: BTreeSet::new(),
+            held: HashMap::new(),
+            silences: Vec::new(),
+            discovered: BTreeSet::new(),
+            mount_table: BTreeMap::new(),
+            flaps: FlapDetector::new(config.flap_threshold, config.flap_window()),
+            notifications: Notifications::from_config(&config.notifications),
+            heartbeat: Heartbeat::default(),
+            dns: Dns::default(),
+            hostname: notify::hostname(),
+            saved: String::new(),
+            history: None,
+            history_config: None,
+            otlp: None,
+            otlp_config: None,
+            metrics: None,
+            metrics_config: None,
+            latency_alerts: Alerts::default(),
+            usage: Usage::default(),
+            read_only: BTreeSet::new(),
+            drift: BTreeMap::new(),
+            credentials: BTreeMap::new(),
+            mounts: MountMonitor::new(config).with_dry_run(dry_run),
+            dry_run,
+            commands: CommandQueue::new(),
+            current_state: None,
+            mounted_state: None,
+        };
+        logging::set_labels(monitor.labels());
+        monitor
+    }
+
+    pub fn config(&self) -> &Config {
+        self.mounts.config()
+    }
+
+    // Check all mounts, then run per-mount and global commands for whatever changed
+    pub fn evaluate(&mut self) {
+        self.check(false);
+    }
+
+    // Like evaluate(), but only check the mounts that are due
+    pub fn evaluate_due(&mut self) {
+        self.check(true);
+    }
+
+    fn check(&mut self, only_due: bool) {
+        let start = SystemTime::now();
+        let events = if only_due {
+            self.mounts.poll_due()
+        } else {
+            self.mounts.poll()
+        };
+        let changes = events.len();
+        self.act(events);
+        self.remind();
+        self.beat();
+        self.watch_dns();
+        if let Some(otlp) = &self.otlp {
+            otlp.span(Span {
+                name: "check",
+                start,
+                end: SystemTime::now(),
+                attributes: vec![
+                    ("nofus.only_due", Attribute::Bool(only_due)),
+                    ("nofus.changes", Attribute::Int(changes as i64)),
+                ],
+                error: None,
+            });
+        }
+    }
+
+    // When evaluate_due() should next be called, for a mount check, the heartbeat or
+    // resolving the servers
+    pub fn next_check(&self) -> Instant {
+        let heartbeat = self.heartbeat.next_due(self.config().heartbeat.as_ref());
+        let dns = self.dns.next_due(self.config().dns_check.as_ref());
+        self.mounts
+            .next_due()
+            .into_iter()
+            .chain(heartbeat)
+            .chain(dns)
+            .min()
+            .unwrap_or_else(|| Instant::now() + Duration::from_secs(self.config().delay_seconds))
+    }
+
+    // Subscribe to every state change from now on, see MountMonitor::events()
+    pub fn events(&mut self) -> Receiver<MountEvent> {
+        self.mounts.events()
+    }
+
+    // Take a mount to be in the given state rather than probing it, see
+    // MountMonitor::force()
+    pub fn force(&mut self, path: &str, state: Option<MountState>) {
+        self.mounts.force(path, state);
+    }
+
+    // Hold off commands and notifications until then, for one mount or all of them
+    pub fn silence(&mut self, mount: Option<String>, until: SystemTime) {
+        self.silences.retain(|(path, _)| *path != mount);
+        self.silences.push((mount, until));
+    }
+
+    // Lift the silences for a mount, or all of them
+    pub fn unsilence(&mut self, mount: Option<&str>) {
+        self.silences
+            .retain(|(path, _)| mount.is_some() && path.as_deref() != mount);
+    }
+
+    // Work out which mounts are in a maintenance window or silenced right now
+    fn update_silenced(&mut self) {
+        let config = self.mounts.config();
+        let now = SystemTime::now();
+        self.silences.retain(|(_, until)| *until > now);
+        let open: Vec<&MaintenanceWindow> = config
+            .maintenance
+            .iter()
+            .filter(|w| w.is_open(now))
+            .collect();
+        let silenced: BTreeSet<String> = config
+            .mount_points
+            .iter()
+            .filter(|m| {
+                open.iter().any(|w| w.covers(&m.path))
+                    || self
+                        .silences
+                        .iter()
+                        .any(|(path, _)| path.as_ref().is_none_or(|p| *p == m.path))
+            })
+            .map(|m| m.path.clone())
+            .collect();
+        for path in silenced.difference(&self.silenced) {
+            info!(event = "silence", mount_point = path; "Silenced {}", path);
+        }
+        for path in self.silenced.difference(&silenced) {
+            info!(event = "silence", mount_point = path; "No longer silenced: {}", path);
+        }
+        self.silenced = silenced;
+    }
+
+    // Follow up on mounts that are still down, notifying again and re-running their
+    // down command each interval, and escalating once they have been down long enough
+    fn remind(&mut self) {
+        let config = self.mounts.config();
+        let Some(reminders) = &config.reminders else {
+            self.reminders.clear();
+            self.global_reminder = None;
+            return;
+        };
+        let now = Instant::now();
+        let interval = reminders.interval();
+
+        // Only mounts whose going down has been acted on are reminded about
+        let down: Vec<&MountPoint> = config
+            .mount_points
+            .iter()
+            .filter(|m| {
+                let state = self.mounts.state(&m.path);
+                !state.is_up()
+                    && state != MountState::Unknown
+                    && !self.flaps.is_flapping(&m.path)
+                    && !self.deferred.contains(&m.path)
+                    && !self.silenced.contains(&m.path)
+            })
+            .collect();
+        self.reminders
+            .retain(|path, _| down.iter().any(|m| &m.path == path));
+        for mount in down {
+            let reminder = self
+                .reminders
+                .entry(mount.path.clone())
+                .or_insert_with(|| Reminder {
+                    down_since: now,
+                    next: now + interval,
+                    count: 0,
+                    level: 0,
+                });
+            let down_for = now - reminder.down_since;
+            let level = reminders
+                .escalations
+                .iter()
+                .take_while(|e| e.after() <= down_for)
+                .count();
+            let escalating = level > reminder.level;
+            if !escalating && reminder.next > now {
+                continue;
+            }
+            if !escalating {
+                reminder.count += 1;
+            }
+            reminder.level = level;
+            reminder.next = now + interval;
+            let count = reminder.count;
+
+            let state = self.mounts.state(&mount.path);
+            let down_seconds = down_for.as_secs();
+            if escalating {
+                error!(
+                    event = "escalation", mount_point = mount.path, state:% = state;
+                    "Mount point has been {} for {}s, escalating (level {}): {}",
+                    state, down_seconds, level, mount.path
+                );
+            } else {
+                warn!(
+                    event = "reminder", mount_point = mount.path, state:% = state;
+                    "Mount point is still {} after {}s: {}", state, down_seconds, mount.path
+                );
+            }
+            self.send_notification(StateChange {
+                mount: mount.path.clone(),
+                old_state: state,
+                new_state: state,
+                timestamp: format_time(SystemTime::now()),
+                hostname: self.hostname.clone(),
+                down_seconds: Some(down_seconds),
+                escalation: level,
+                usage: None,
+                server: known_server(mount),
+                server_mounts: Vec::new(),
+                labels: mount.labels.clone(),
+            });
+
+            // Once escalated, the escalation's command takes over from the usual one
+            let hook = match level.checked_sub(1).map(|i| &reminders.escalations[i]) {
+                Some(escalation) if escalation.command.is_some() => escalation.command.as_ref(),
+                _ if escalating || !reminders.rerun_commands => None,
+                // Mounts left to the global commands are reminded about below
+                _ => state_hook(mount, state, config),
+            };
+            let Some(hook) = hook else {
+                continue;
+            };
+            let event = if escalating { "escalation" } else { "reminder" };
+            let mut env = mount_env(mount, event, state, state, &self.hostname);
+            env.push(("NOFUS_DOWN_SECONDS", down_seconds.to_string()));
+            env.push(("NOFUS_REMINDER", count.to_string()));
+            env.push(("NOFUS_ESCALATION", level.to_string()));
+            let paths = vec![mount.path.clone()];
+            if let Some(job) = execute_with_env(hook, env, config, self.dry_run, &mount.path, paths)
+            {
+                self.commands.push(job, false);
+            }
+        }
+
+        // Run any_unmounted_cmd again while the global commands are still down
+        let silenced = config
+            .mount_points
+            .iter()
+            .any(|m| config.uses_global_command(m, false) && self.silenced.contains(&m.path));
+        if !reminders.rerun_commands || self.current_state != Some(false) || silenced {
+            self.global_reminder = None;
+            return;
+        }
+        let next = *self.global_reminder.get_or_insert(now + interval);
+        if next > now {
+            return;
+        }
+        self.global_reminder = Some(now + interval);
+        let global_mounts: Vec<&MountPoint> = config
+            .mount_points
+            .iter()
+            .filter(|m| config.uses_global_command(m, false))
+            .collect();
+        let mut env = summary_env(
+            &self.mounts,
+            &global_mounts,
+            false,
+            Some(false),
+            &self.hostname,
+        );
+        env.retain(|(key, _)| *key != "NOFUS_EVENT");
+        env.push(("NOFUS_EVENT", "reminder".to_string()));
+        let paths = global_mounts.iter().map(|m| m.path.clone()).collect();
+        warn!(event = "reminder"; "NFS mounts are still disconnected");
+        if let Some(job) = execute_with_env(
+            &config.any_unmounted_cmd,
+            env,
+            config,
+            self.dry_run,
+            GLOBAL,
+            paths,
+        ) {
+            self.commands.push(job, false);
+        }
+    }
+
+    // Send the heartbeat if it is due
+    fn beat(&mut self) {
+        let config = self.mounts.config();
+        let env = command_env("heartbeat", &self.hostname);
+        self.heartbeat.beat(
+            config.heartbeat.as_ref(),
+            env,
+            config.command_timeout(),
+            self.dry_run,
+        );
+    }
+
+    // Resolve the mount servers if that is due, and act on any that stopped
+    // resolving, resolve again or resolve to other addresses
+    fn watch_dns(&mut self) {
+        let config = self.mounts.config();
+        let changes = self.dns.check(config.dns_check.as_ref(), || {
+            config
+                .mount_points
+                .iter()
+                .map(server_of)
+                .filter(|s| !s.is_empty())
+                .collect()
+        });
+        for change in changes {
+            let server = change.server.as_str();
+            match (&change.from, &change.to) {
+                (_, Resolved::Failed(e)) => {
+                    error!(event = "dns", server = server; "Unable to resolve {}: {}", server, e)
+                }
+                (Some(Resolved::Failed(_)) | None, to) => {
+                    info!(event = "dns", server = server; "{} resolves again, to {}", server, to)
+                }
+                (Some(from), to) => warn!(
+                    event = "dns", server = server;
+                    "{} now resolves to {} instead of {}", server, to, from
+                ),
+            }
+            let paths: Vec<String> = config
+                .mount_points
+                .iter()
+                .filter(|m| server_of(m) == server)
+                .map(|m| m.path.clone())
+                .collect();
+            if paths.iter().all(|p| self.silenced.contains(p)) {
+                continue;
+            }
+            let Some(hook) = config.dns_check.as_ref().and_then(|d| d.command.as_ref()) else {
+                continue;
+            };
+            let event = if change.is_failure() {
+                "dns_failure"
+            } else {
+                "dns_recovered"
+            };
+            let mut env = command_env(event, &self.hostname);
+            env.push(("NOFUS_SERVER", server.to_string()));
+            env.push(("NOFUS_MOUNT", paths.first().cloned().unwrap_or_default()));
+            env.push(("NOFUS_MOUNTS", paths.join("\n")));
+            match &change.to {
+                Resolved::Addresses(_) => env.push(("NOFUS_ADDRESSES", change.to.to_string())),
+                Resolved::Failed(e) => env.push(("NOFUS_DNS_ERROR", e.clone())),
+            }
+            if let Some(from @ Resolved::Addresses(_)) = &change.from {
+                env.push(("NOFUS_PREV_ADDRESSES", from.to_string()));
+            }
+            if let Some(job) = execute_with_env(hook, env, config, self.dry_run, server, paths) {
+                self.commands.push(job, false);
+            }
+        }
+    }
+
+    fn act(&mut self, events: Vec<MountEvent>) {
+        self.record(&events);
+        let in_grace = self.grace_until.is_some_and(|t| Instant::now() < t);
+        self.update_silenced();
+        let covered = self.evaluate_servers(&events, in_grace);
+        for event in events {
+            let config = self.mounts.config();
+            let Some(mount) = config.mount_points.iter().find(|m| m.path == event.path) else {
+                continue;
+            };
+            // Keep the state it was last acted on in, to catch up from afterwards
+            if self.silenced.contains(&event.path) {
+                info!(
+                    event = "silence", mount_point = mount.path, state:% = event.to;
+                    "Mount point is {} while silenced, not acting on it: {}",
+                    event.to, mount.path
+                );
+                self.held.entry(event.path).or_insert(event.from);
+                continue;
+            }
+            self.held.remove(&event.path);
+            if in_grace && !event.to.is_up() {
+                warn!(
+                    "Mount point {} is {}, waiting for it during the startup grace period",
+                    mount.path, event.to
+                );
+                if !self.deferred.contains(&event.path) {
+                    self.deferred.push(event.path);
+                }
+                continue;
+            }
+            let mut transition = Transition {
+                mount,
+                from: event.from,
+                to: event.to,
+            };
+            // The first check of a mount isn't a change worth counting
+            let flap = if event.from == MountState::Unknown {
+                Flap::Normal
+            } else {
+                self.flaps.record(&event.path)
+            };
+            match flap {
+                Flap::Normal => {}
+                Flap::Started => {
+                    warn!(
+                        event = "flapping", mount_point = mount.path;
+                        "Mount point is flapping, suppressing commands until it settles: {}",
+                        mount.path
+                    );
+                    transition.to = MountState::Flapping;
+                    self.notify(&transition);
+                    continue;
+                }
+                Flap::Suppressed => {
+                    debug!(
+                        "Ignoring {} becoming {} while flapping",
+                        mount.path, event.to
+                    );
+                    continue;
+                }
+            }
+            if covered.contains(&event.path) {
+                log_transition(&transition);
+                continue;
+            }
+            self.notify(&transition);
+            for job in mount_changed(&transition, config, &self.hostname, self.dry_run) {
+                self.commands.push(job, config.cancel_reverted_commands);
+            }
+        }
+
+        // Act on mounts that are no longer silenced, if they are in a different state
+        // than before, unless they are still flapping and will be acted on once settled
+        let released: Vec<(String, MountState)> = self
+            .held
+            .iter()
+            .filter(|(path, _)| !self.silenced.contains(*path))
+            .map(|(path, from)| (path.clone(), *from))
+            .collect();
+        for (path, from) in released {
+            self.held.remove(&path);
+            let config = self.mounts.config();
+            let to = self.mounts.state(&path);
+            let Some(mount) = config.mount_points.iter().find(|m| m.path == path) else {
+                continue;
+            };
+            if to == from || self.flaps.is_flapping(&path) {
+                continue;
+            }
+            let transition = Transition { mount, from, to };
+            self.notify(&transition);
+            for job in mount_changed(&transition, config, &self.hostname, self.dry_run) {
+                self.commands.push(job, config.cancel_reverted_commands);
+            }
+        }
+
+        // Once everything is mounted or time is up, act on whatever is still missing
+        if self.grace_until.is_some() {
+            let all_mounted = self
+                .config()
+                .mount_points
+                .iter()
+                .all(|m| self.mounts.state(&m.path).is_up());
+            if in_grace && !all_mounted {
+                return;
+            }
+            self.grace_until = None;
+            for path in std::mem::take(&mut self.deferred) {
+                let config = self.mounts.config();
+                let state = self.mounts.state(&path);
+                let Some(mount) = config.mount_points.iter().find(|m| m.path == path) else {
+                    continue;
+                };
+                if state.is_up() {
+                    continue;
+                }
+                if self.silenced.contains(&path) {
+                    self.held.entry(path).or_insert(MountState::Unknown);
+                    continue;
+                }
+                let transition = Transition {
+                    mount,
+                    from: MountState::Unknown,
+                    to: state,
+                };
+                self.notify(&transition);
+                for job in mount_changed(&transition, config, &self.hostname, self.dry_run) {
+                    self.commands.push(job, config.cancel_reverted_commands);
+                }
+            }
+        }
+
+        // Act on whatever state flapping mounts have finally settled in
+        for path in self.flaps.settled() {
+            let config = self.mounts.config();
+            let Some(mount) = config.mount_points.iter().find(|m| m.path == path) else {
+                continue;
+            };
+            if self.silenced.contains(&path) {
+                self.held.entry(path).or_insert(MountState::Flapping);
+                continue;
+            }
+            let transition = Transition {
+                mount,
+                from: MountState::Flapping,
+                to: self.mounts.state(&path),
+            };
+            info!(
+                event = "flapping", mount_point = mount.path;
+                "Mount point has settled: {} is {}", mount.path, transition.to
+            );
+            self.notify(&transition);
+            for job in mount_changed(&transition, config, &self.hostname, self.dry_run) {
+                self.commands.push(job, config.cancel_reverted_commands);
+            }
+        }
+
+        self.evaluate_groups();
+
+        // The global commands wait for every mount they cover to stop flapping and no
+        // longer be silenced
+        let config = self.mounts.config();
+        if config.mount_points.iter().any(|m| {
+            config.uses_global_commands(m)
+                && (self.flaps.is_flapping(&m.path) || self.silenced.contains(&m.path))
+        }) {
+            return;
+        }
+
+        // any_unmounted_cmd runs once a mount it covers goes down, and
+        // all_mounted_cmd once all it covers are up again, each over its own mounts
+        for up in [false, true] {
+            let Some(new_state) = global_state(&self.mounts, up) else {
+                continue;
+            };
+            let previous = if up {
+                &mut self.mounted_state
+            } else {
+                &mut self.current_state
+            };
+            if *previous == Some(new_state) {
+                continue;
+            }
+            let from = previous.replace(new_state);
+            if new_state != up {
+                continue;
+            }
+            let global_mounts: Vec<&MountPoint> = config
+                .mount_points
+                .iter()
+                .filter(|m| config.uses_global_command(m, up))
+                .collect();
+            let env = summary_env(&self.mounts, &global_mounts, up, from, &self.hostname);
+            let paths = global_mounts.iter().map(|m| m.path.clone()).collect();
+            let job = if up {
+                all_mounted(&config.all_mounted_cmd, env, config, self.dry_run, paths)
+            } else {
+                any_unmounted(&config.any_unmounted_cmd, env, config, self.dry_run, paths)
+            };
+            if let Some(job) = job {
+                self.commands.push(job, config.cancel_reverted_commands);
+            }
+        }
+    }
+
+    // Run the on_start command, once the mounts have been checked for the first time
+    pub fn start(&self) {
+        let config = self.config();
+        let job = config.on_start.as_ref().and_then(|hook| {
+            let env = command_env("start", &self.hostname);
+            execute_with_env(hook, env, config, self.dry_run, LIFECYCLE, Vec::new())
+        });
+        if let Some(job) = job {
+            self.commands.push(job, false);
+        }
+    }
+
+    // A mount that has been down for longer than exit_when_down_seconds, with how long,
+    // leaving out those silenced on purpose
+    pub fn down_too_long(&self) -> Option<(String, Duration)> {
+        let limit = Duration::from_secs(self.config().exit_when_down_seconds?);
+        let now = SystemTime::now();
+        self.config().mount_points.iter().find_map(|mount| {
+            let state = self.mounts.state(&mount.path);
+            if state.is_up() || state == MountState::Unknown || self.silenced.contains(&mount.path)
+            {
+                return None;
+            }
+            let down_for = now.duration_since(self.mounts.since(&mount.path)?).ok()?;
+            (down_for > limit).then(|| (mount.path.clone(), down_for))
+        })
+    }
+
+    // Have the mount point of each remount tried in the background handed over once
+    // it is done, to check the mounts again then
+    pub fn on_remounted(&mut self, remounted: impl Fn(&str) + Send + Sync + 'static) {
+        self.mounts.on_remounted(remounted);
+    }
+
+    // Exit once a hook fails, rather than only logging it
+    pub fn set_fail_fast(&self, fail_fast: bool) {
+        self.commands.set_fail_fast(fail_fast);
+    }
+
+    // With fail_fast, the first hook to have failed since last asked, after its
+    // retries
+    pub fn failed_command(&self) -> Option<String> {
+        self.commands.take_failure()
+    }
+
+    // Let the queued commands finish, killing any still going after the shutdown
+    // timeout, then run the on_stop command with as long again
+    pub fn stop(&self) {
+        let config = self.config();
+        let timeout = config.shutdown_timeout();
+        let drain = || {
+            if !self.commands.drain(timeout) {
+                warn!(
+                    event = "shutdown";
+                    "Commands still running after {}s, killing them", timeout.as_secs()
+                );
+                kill_running();
+                // Killed, they only have to be reaped
+                self.commands.drain(timeout);
+            }
+        };
+        drain();
+        let job = config.on_stop.as_ref().and_then(|hook| {
+            let env = command_env("stop", &self.hostname);
+            execute_with_env(hook, env, config, self.dry_run, LIFECYCLE, Vec::new())
+        });
+        if let Some(job) = job {
+            self.commands.push(job, false);
+            drain();
+        }
+    }
+
+    // Act once for each server with several mounts that all went down together, or
+    // are all back, returning the mounts whose own changes that stands in for
+    fn evaluate_servers(&mut self, events: &[MountEvent], in_grace: bool) -> BTreeSet<String> {
+        let config = self.mounts.config();
+        let mut covered = BTreeSet::new();
+        if config.server_down_cmd.is_none() && config.server_up_cmd.is_none() {
+            self.server_states.clear();
+            return covered;
+        }
+        let mut servers: BTreeMap<String, Vec<&MountPoint>> = BTreeMap::new();
+        for mount in &config.mount_points {
+            if let Some(server) = known_server(mount) {
+                servers.entry(server).or_default().push(mount);
+            }
+        }
+        self.server_states
+            .retain(|server, _| servers.contains_key(server));
+        for (server, mounts) in servers {
+            // Like the groups, wait for the mounts to stop flapping and no longer be
+            // silenced
+            if mounts.len() < 2
+                || mounts
+                    .iter()
+                    .any(|m| self.flaps.is_flapping(&m.path) || self.silenced.contains(&m.path))
+            {
+                continue;
+            }
+            let states: Vec<MountState> =
+                mounts.iter().map(|m| self.mounts.state(&m.path)).collect();
+            // Until all of them are up or all of them down, the server stays as it was
+            let up = if states.iter().all(|s| s.is_up()) {
+                true
+            } else if states
+                .iter()
+                .all(|s| !s.is_up() && *s != MountState::Unknown)
+            {
+                false
+            } else {
+                continue;
+            };
+            if in_grace && !up {
+                continue;
+            }
+            let previous = self.server_states.insert(server.clone(), up);
+            // A server found up at startup hasn't changed
+            if previous == Some(up) || (previous.is_none() && up) {
+                continue;
+            }
+            let paths: Vec<String> = mounts.iter().map(|m| m.path.clone()).collect();
+            covered.extend(
+                events
+                    .iter()
+                    .filter(|e| paths.contains(&e.path))
+                    .map(|e| e.path.clone()),
+            );
+
+            let (event, hook) = if up {
+                info!(
+                    event = "server", server = server;
+                    "Server {} is back, with all {} of its mounts", server, paths.len()
+                );
+                ("server_up", config.server_up_cmd.as_ref())
+            } else {
+                error!(
+                    event = "server", server = server;
+                    "Server {} is down, with all {} of its mounts", server, paths.len()
+                );
+                ("server_down", config.server_down_cmd.as_ref())
+            };
+            let (from, to) = if up {
+                (MountState::Unmounted, MountState::Mounted)
+            } else {
+                (MountState::Mounted, MountState::Unmounted)
+            };
+            self.send_notification(StateChange {
+                mount: server.clone(),
+                old_state: from,
+                new_state: to,
+                timestamp: format_time(SystemTime::now()),
+                hostname: self.hostname.clone(),
+                down_seconds: None,
+                escalation: 0,
+                usage: None,
+                server: Some(server.clone()),
+                server_mounts: paths.clone(),
+                labels: BTreeMap::new(),
+            });
+            let Some(hook) = hook else {
+                continue;
+            };
+            let summary = |up| if up { "up" } else { "down" };
+            let mut env = command_env(event, &self.hostname);
+            env.push(("NOFUS_SERVER", server.clone()));
+            env.push(("NOFUS_MOUNTS", paths.join("\n")));
+            env.push(("NOFUS_MOUNT", paths[0].clone()));
+            env.push(("NOFUS_STATE", summary(up).to_string()));
+            env.push((
+                "NOFUS_PREV_STATE",
+                previous.map_or("unknown", summary).to_string(),
+            ));
+            let scope = format!("server:{}", server);
+            if let Some(job) = execute_with_env(hook, env, config, self.dry_run, &scope, paths) {
+                self.commands.push(job, config.cancel_reverted_commands);
+            }
+        }
+        covered
+    }
+
+    // Run the commands of each group that gained or lost quorum
+    fn evaluate_groups(&mut self) {
+        let config = self.mounts.config();
+        for (name, group) in &config.groups {
+            // Like the global commands, wait for the group's mounts to stop flapping and
+            // no longer be silenced
+            if group
+                .mount_points
+                .iter()
+                .any(|p| self.flaps.is_flapping(p) || self.silenced.contains(p))
+            {
+                continue;
+            }
+            let mounted = group_mounted(&self.mounts, &group.mount_points);
+            let up = mounted >= group.quorum();
+            let previous = self.group_states.insert(name.clone(), up);
+            if previous == Some(up) {
+                continue;
+            }
+            let members: Vec<&MountPoint> = config
+                .mount_points
+                .iter()
+                .filter(|m| group.mount_points.contains(&m.path))
+                .collect();
+            let mut env = summary_env(&self.mounts, &members, up, previous, &self.hostname);
+            env.push(("NOFUS_GROUP", name.clone()));
+            let cmd = if up {
+                info!(
+                    event = "group", group = name;
+                    "Mount group {} is available ({} of {} mounted)",
+                    name, mounted, group.mount_points.len()
+                );
+                group.all_mounted_cmd.as_ref()
+            } else {
+                error!(
+                    event = "group", group = name;
+                    "Mount group {} has lost quorum ({} of {} mounted, {} needed)",
+                    name, mounted, group.mount_points.len(), group.quorum()
+                );
+                group.any_unmounted_cmd.as_ref()
+            };
+            let scope = format!("group:{}", name);
+            let paths = group.mount_points.clone();
+            if let Some(job) =
+                cmd.and_then(|cmd| execute_with_env(cmd, env, config, self.dry_run, &scope, paths))
+            {
+                self.commands.push(job, config.cancel_reverted_commands);
+            }
+        }
+    }
+
+    // Follow the mount table, monitoring the mounts that match a pattern or one of the
+    // discovered filesystem types. A discovered mount that leaves the table was
+    // unmounted on purpose, since a failing mount stays listed, so it's dropped rather
+    // than reported.
+    pub fn discover(&mut self, mount_table: &BTreeMap<PathBuf, String>) {
+        self.mount_table = mount_table.clone();
+        let config = self.mounts.config();
+        let mut wanted: BTreeMap<String, MountPoint> = config
+            .matching_mounts(mount_table)
+            .into_iter()
+            .filter(|mount| {
+                self.discovered.contains(&mount.path)
+                    || !config.mount_points.iter().any(|m| m.path == mount.path)
+            })
+            .map(|mount| (mount.path.clone(), mount))
+            .collect();
+
+        for path in &self.discovered {
+            if !wanted.contains_key(path) {
+                info!("Discovered mount point was unmounted: {}", path);
+                self.mounts.remove(path);
+            }
+        }
+        let discovered = wanted.keys().cloned().collect();
+        wanted.retain(|path, _| !self.discovered.contains(path));
+        for mount in wanted.into_values() {
+            info!("Discovered mount point: {}", mount.path);
+            self.mounts.add(mount);
+        }
+        self.discovered = discovered;
+        logging::set_labels(self.labels());
+        let config = self.mounts.config();
+        self.commands
+            .retain(|path| config.mount_points.iter().any(|m| m.path == path));
+        self.latency_alerts
+            .retain(|path| config.mount_points.iter().any(|m| m.path == path));
+        self.usage
+            .retain(|path| config.mount_points.iter().any(|m| m.path == path));
+        self.read_only
+            .retain(|path| config.mount_points.iter().any(|m| &m.path == path));
+        self.drift
+            .retain(|path, _| config.mount_points.iter().any(|m| &m.path == path));
+        self.credentials
+            .retain(|path, _| config.mount_points.iter().any(|m| &m.path == path));
+    }
+
+    // Swap in a freshly loaded config, adjusting state for added or removed
+    // mount points without running any commands
+    pub fn reload(&mut self, new_config: Config) {
+        self.notifications = Notifications::from_config(&new_config.notifications);
+        let events = self.mounts.reload(new_config);
+        logging::set_labels(self.labels());
+        self.open_history();
+        self.open_otlp();
+        self.open_metrics();
+        self.record(&events);
+        let config = self.mounts.config();
+        self.commands
+            .retain(|path| config.mount_points.iter().any(|m| m.path == path));
+        self.latency_alerts
+            .retain(|path| config.mount_points.iter().any(|m| m.path == path));
+        self.usage
+            .retain(|path| config.mount_points.iter().any(|m| m.path == path));
+        self.read_only
+            .retain(|path| config.mount_points.iter().any(|m| &m.path == path));
+        self.drift
+            .retain(|path, _| config.mount_points.iter().any(|m| &m.path == path));
+        self.credentials
+            .retain(|path, _| config.mount_points.iter().any(|m| &m.path == path));
+        self.flaps
+            .reconfigure(config.flap_threshold, config.flap_window(), |path| {
+                config.mount_points.iter().any(|m| m.path == path)
+            });
+
+        // New mount points are announced, but their state is just their starting point
+        for event in events {
+            if let Some(mount) = config.mount_points.iter().find(|m| m.path == event.path) {
+                self.notify(&Transition {
+                    mount,
+                    from: event.from,
+                    to: event.to,
+                });
+            }
+        }
+        // The new config doesn't know about discovered mounts, so find them again
+        self.discovered.clear();
+        let mount_table = std::mem::take(&mut self.mount_table);
+        self.discover(&mount_table);
+
+        if self.current_state.is_some() {
+            self.current_state = global_state(&self.mounts, false);
+        }
+        if self.mounted_state.is_some() {
+            self.mounted_state = global_state(&self.mounts, true);
+        }
+        // Groups that were already known pick up their new state without running
+        // anything, while new ones are acted on at the next check
+        let config = self.mounts.config();
+        self.group_states
+            .retain(|name, _| config.groups.contains_key(name));
+        for (name, up) in self.group_states.iter_mut() {
+            let group = &config.groups[name];
+            *up = group_mounted(&self.mounts, &group.mount_points) >= group.quorum();
+        }
+    }
+
+    // Tell the configured notifiers about a state change
+    fn notify(&self, transition: &Transition) {
+        self.send_notification(StateChange {
+            mount: transition.mount.path.clone(),
+            old_state: transition.from,
+            new_state: transition.to,
+            timestamp: format_time(SystemTime::now()),
+            hostname: self.hostname.clone(),
+            down_seconds: None,
+            escalation: 0,
+            usage: None,
+            server: known_server(transition.mount),
+            server_mounts: Vec::new(),
+            labels: transition.mount.labels.clone(),
+        });
+    }
+
+    fn send_notification(&self, change: StateChange) {
+        if self.notifications.is_empty() {
+            return;
+        }
+        if self.dry_run {
+            info!(
+                "Dry run enabled, would notify {}: {}",
+                self.notifications.names().join(", "),
+                change.headline()
+            );
+            return;
+        }
+        self.notifications.send(change);
+    }
+
+    // Start recording to the history if the config asks for it, or switch to the
+    // one it now asks for. A dry run records nothing, since nothing is acted on.
+    pub fn open_history(&mut self) {
+        let wanted = (!self.dry_run)
+            .then(|| self.config().history.as_ref())
+            .flatten()
+            .map(|h| (h.path(), h.retention()));
+        if wanted == self.history_config {
+            return;
+        }
+        self.history =
+            wanted
+                .as_ref()
+                .and_then(|(path, retention)| match History::open(path, *retention) {
+                    Ok(history) => Some(Arc::new(history)),
+                    Err(e) => {
+                        warn!("Unable to open the history in {}: {}", path.display(), e);
+                        None
+                    }
+                });
+        self.history_config = wanted;
+        self.commands.set_history(self.history.clone());
+    }
+
+    // Start sending traces and metrics if the config asks for it, or switch to where
+    // it now asks for them to go
+    pub fn open_otlp(&mut self) {
+        let wanted = self.config().otlp.clone();
+        if wanted == self.otlp_config {
+            return;
+        }
+        self.otlp = wanted.as_ref().map(|config| Arc::new(Otlp::new(config)));
+        self.otlp_config = wanted;
+        self.commands.set_otlp(self.otlp.clone());
+        self.update_otlp(&[]);
+    }
+
+    // Count the state changes, and pass on the state of every mount
+    fn update_otlp(&self, events: &[MountEvent]) {
+        let Some(otlp) = &self.otlp else {
+            return;
+        };
+        for event in events {
+            otlp.transition(&event.path, event.to);
+        }
+        otlp.states(self.states());
+        otlp.servers(self.servers());
+        otlp.labels(self.labels());
+    }
+
+    // Start pushing metrics if the config asks for it, or switch to where it now asks
+    // for them to go
+    pub fn open_metrics(&mut self) {
+        let wanted = self.config().metrics.clone();
+        if wanted == self.metrics_config {
+            return;
+        }
+        self.metrics = wanted.as_ref().map(Metrics::new);
+        self.metrics_config = wanted;
+        self.update_metrics(&[], &[]);
+    }
+
+    fn update_metrics(&self, events: &[MountEvent], probed: &[(String, Probe)]) {
+        let Some(metrics) = &self.metrics else {
+            return;
+        };
+        for event in events {
+            metrics.transition(&event.path);
+        }
+        for (path, probe) in probed {
+            metrics.probed(path, probe.latency);
+        }
+        metrics.states(self.states());
+        metrics.servers(self.servers());
+        metrics.labels(self.labels());
+    }
+
+    // Run the latency alerts' commands for mounts whose checks have stayed slow for
+    // long enough
+    fn watch_latency(&mut self, probed: &[(String, Probe)]) {
+        let config = self.mounts.config();
+        if config.latency_alerts.is_empty() {
+            return;
+        }
+        let latencies = probed.iter().map(|(path, p)| (path.clone(), p.latency));
+        let changes =
+            self.latency_alerts
+                .observe(&config.latency_alerts, latencies, Instant::now());
+        for change in changes {
+            let alert = &config.latency_alerts[change.alert];
+            let Some(mount) = config.mount_points.iter().find(|m| m.path == change.path) else {
+                continue;
+            };
+            let latency = change.latency.as_millis();
+            if !change.firing {
+                info!(
+                    event = "latency_alert", mount_point = mount.path;
+                    "p{} latency of {} is back under {}ms, at {}ms",
+                    alert.percentile, mount.path, alert.above_ms, latency
+                );
+                continue;
+            }
+            warn!(
+                event = "latency_alert", mount_point = mount.path;
+                "p{} latency of {} has been over {}ms for {}s, at {}ms",
+                alert.percentile, mount.path, alert.above_ms, alert.for_seconds, latency
+            );
+            let Some(hook) = &alert.command else {
+                continue;
+            };
+            if self.silenced.contains(&mount.path) {
+                continue;
+            }
+            let state = self.mounts.state(&mount.path);
+            let mut env = mount_env(mount, "latency_alert", state, state, &self.hostname);
+            env.push(("NOFUS_PERCENTILE", alert.percentile.to_string()));
+            env.push(("NOFUS_LATENCY_MS", latency.to_string()));
+            env.push(("NOFUS_THRESHOLD_MS", alert.above_ms.to_string()));
+            let paths = vec![mount.path.clone()];
+            if let Some(job) = execute_with_env(hook, env, config, self.dry_run, &mount.path, paths)
+            {
+                self.commands.push(job, false);
+            }
+        }
+    }
+
+    // Run the usage commands and notify for mounts that passed a usage threshold, or
+    // dropped back below them
+    fn watch_usage(&mut self, probed: &[(String, Probe)]) {
+        let config = self.mounts.config();
+        for (path, probe) in probed {
+            let Some(capacity) = probe.capacity else {
+                continue;
+            };
+            let Some(mount) = config.mount_points.iter().find(|m| &m.path == path) else {
+                continue;
+            };
+            let resources = [
+                (
+                    "disk",
+                    "disk_usage",
+                    capacity.used_percent(),
+                    config.disk_usage(mount),
+                ),
+                (
+                    "inodes",
+                    "inode_usage",
+                    capacity.inodes_used_percent(),
+                    config.inode_usage(mount),
+                ),
+            ];
+            for (resource, event, used, limits) in resources {
+                let Some(used) = used else {
+                    continue;
+                };
+                let level = Level::of(used, &limits);
+                let Some(previous) = self.usage.update(path, resource, level) else {
+                    continue;
+                };
+                let change = UsageChange {
+                    resource,
+                    level,
+                    used_percent: used,
+                };
+                let state = self.mounts.state(path);
+                let notification = StateChange {
+                    mount: path.clone(),
+                    old_state: state,
+                    new_state: state,
+                    timestamp: format_time(SystemTime::now()),
+                    hostname: self.hostname.clone(),
+                    down_seconds: None,
+                    escalation: 0,
+                    usage: Some(change),
+                    server: known_server(mount),
+                    server_mounts: Vec::new(),
+                    labels: mount.labels.clone(),
+                };
+                let headline = notification.headline();
+                match level {
+                    Level::Ok => info!(
+                        event = "usage", mount_point = path, level:% = level; "{}", headline
+                    ),
+                    Level::Warning => warn!(
+                        event = "usage", mount_point = path, level:% = level; "{}", headline
+                    ),
+                    Level::Critical => error!(
+                        event = "usage", mount_point = path, level:% = level; "{}", headline
+                    ),
+                }
+                if self.silenced.contains(path) {
+                    continue;
+                }
+                self.send_notification(notification);
+                let Some(hook) = limits.command else {
+                    continue;
+                };
+                let mut env = mount_env(mount, event, state, state, &self.hostname);
+                env.push(("NOFUS_USAGE_LEVEL", level.to_string()));
+                env.push(("NOFUS_PREV_USAGE_LEVEL", previous.to_string()));
+                env.push(("NOFUS_USED_PERCENT", format!("{:.1}", used)));
+                let paths = vec![path.clone()];
+                if let Some(job) = execute_with_env(hook, env, config, self.dry_run, path, paths) {
+                    self.commands.push(job, false);
+                }
+            }
+        }
+    }
+
+    // Run the read-only command for mounts meant to be read-write that were found
+    // mounted read-only, as the kernel does to a filesystem after some errors
+    fn watch_read_only(&mut self, probed: &[(String, Probe)]) {
+        let config = self.mounts.config();
+        for (path, probe) in probed {
+            // Only a working mount says how it is mounted
+            if probe.options.is_empty() {
+                continue;
+            }
+            let Some(mount) = config.mount_points.iter().find(|m| &m.path == path) else {
+                continue;
+            };
+            if !is_read_only(&probe.options) || config.read_only(mount) {
+                if self.read_only.remove(path) {
+                    info!(
+                        event = "readonly", mount_point = path;
+                        "Mount point is read-write again: {}", path
+                    );
+                }
+                continue;
+            }
+            if !self.read_only.insert(path.clone()) {
+                continue;
+            }
+            error!(
+                event = "readonly", mount_point = path;
+                "Mount point has become read-only: {} ({})", path, probe.options.join(",")
+            );
+            if self.silenced.contains(path) {
+                continue;
+            }
+            let Some(hook) = mount
+                .on_readonly
+                .as_ref()
+                .or(config.on_readonly_cmd.as_ref())
+            else {
+                continue;
+            };
+            let state = self.mounts.state(path);
+            let mut env = mount_env(mount, "readonly", state, state, &self.hostname);
+            env.push(("NOFUS_OPTIONS", probe.options.join(",")));
+            let paths = vec![path.clone()];
+            if let Some(job) = execute_with_env(hook, env, config, self.dry_run, path, paths) {
+                self.commands.push(job, false);
+            }
+        }
+    }
+
+    // Warn and run the drift command for mounts no longer mounted with the options
+    // expected of them, e.g. soft after an fstab edit, each time how they differ changes
+    fn watch_options(&mut self, probed: &[(String, Probe)]) {
+        let config = self.mounts.config();
+        for (path, probe) in probed {
+            if probe.options.is_empty() {
+                continue;
+            }
+            let Some(mount) = config.mount_points.iter().find(|m| &m.path == path) else {
+                continue;
+            };
+            let drift = options::drift(config.expected_options(mount), &probe.options);
+            if drift.is_empty() {
+                if self.drift.remove(path).is_some() {
+                    info!(
+                        event = "option_drift", mount_point = path;
+                        "Mount options of {} are as expected again", path
+                    );
+                }
+                continue;
+            }
+            if self.drift.get(path) == Some(&drift) {
+                continue;
+            }
+            self.drift.insert(path.clone(), drift.clone());
+            warn!(
+                event = "option_drift", mount_point = path;
+                "Mount options of {} differ from those expected: {}", path, drift.join("; ")
+            );
+            if self.silenced.contains(path) {
+                continue;
+            }
+            let Some(hook) = mount
+                .on_option_drift
+                .as_ref()
+                .or(config.on_option_drift_cmd.as_ref())
+            else {
+                continue;
+            };
+            let state = self.mounts.state(path);
+            let mut env = mount_env(mount, "option_drift", state, state, &self.hostname);
+            env.push(("NOFUS_OPTIONS", probe.options.join(",")));
+            env.push(("NOFUS_DRIFT", drift.join("; ")));
+            let paths = vec![path.clone()];
+            if let Some(job) = execute_with_env(hook, env, config, self.dry_run, path, paths) {
+                self.commands.push(job, false);
+            }
+        }
+    }
+
+    // Log and run the Kerberos command for sec=krb5 mounts whose credentials are
+    // expiring or have expired, before reading them starts failing, and once they are
+    // renewed
+    fn watch_credentials(&mut self, probed: &[(String, Probe)]) {
+        let config = self.mounts.config();
+        let Some(check) = &config.kerberos_check else {
+            return;
+        };
+        // A mount that fails its check doesn't say how it is mounted, but one known to
+        // use Kerberos still does
+        let mounts: Vec<&MountPoint> = probed
+            .iter()
+            .filter(|(path, probe)| {
+                uses_kerberos(&probe.options)
+                    || (probe.options.is_empty() && self.credentials.contains_key(path))
+            })
+            .filter_map(|(path, _)| config.mount_points.iter().find(|m| &m.path == path))
+            .collect();
+        if mounts.is_empty() {
+            return;
+        }
+        let (status, credentials) = kerberos::check(check.ccache.as_deref(), check.warn());
+        let cause = match &credentials {
+            Ok(c) if status == kerberos::Status::Expired => format!(
+                "credentials of {} expired at {}",
+                c.principal,
+                format_time(c.expires)
+            ),
+            Ok(c) => format!(
+                "credentials of {} expire at {}",
+                c.principal,
+                format_time(c.expires)
+            ),
+            Err(e) => e.clone(),
+        };
+        for mount in mounts {
+            let path = &mount.path;
+            let previous = self
+                .credentials
+                .insert(path.clone(), status)
+                .unwrap_or_default();
+            if previous == status {
+                continue;
+            }
+            match status {
+                kerberos::Status::Valid => info!(
+                    event = "credentials", mount_point = path;
+                    "Kerberos credentials for {} are valid again: {}", path, cause
+                ),
+                kerberos::Status::Expiring => warn!(
+                    event = "credentials", mount_point = path;
+                    "Kerberos credentials for {} are expiring: {}", path, cause
+                ),
+                kerberos::Status::Expired => error!(
+                    event = "credentials", mount_point = path;
+                    "Kerberos credentials for {} have expired: {}", path, cause
+                ),
+            }
+            if self.silenced.contains(path) {
+                continue;
+            }
+            let Some(hook) = &check.command else {
+                continue;
+            };
+            let state = self.mounts.state(path);
+            let event = format!("credentials_{}", status);
+            let mut env = mount_env(mount, &event, state, state, &self.hostname);
+            env.push(("NOFUS_CREDENTIALS", status.to_string()));
+            env.push(("NOFUS_PREV_CREDENTIALS", previous.to_string()));
+            if let Ok(credentials) = &credentials {
+                env.push(("NOFUS_PRINCIPAL", credentials.principal.clone()));
+                env.push(("NOFUS_EXPIRES", format_time(credentials.expires)));
+            }
+            let paths = vec![path.clone()];
+            if let Some(job) = execute_with_env(hook, env, config, self.dry_run, path, paths) {
+                self.commands.push(job, false);
+            }
+        }
+    }
+
+    // The state of every configured mount
+    fn states(&self) -> BTreeMap<String, MountState> {
+        self.config()
+            .mount_points
+            .iter()
+            .map(|m| (m.path.clone(), self.mounts.state(&m.path)))
+            .collect()
+    }
+
+    // The server of every configured mount that can be told
+    fn servers(&self) -> BTreeMap<String, String> {
+        self.config()
+            .mount_points
+            .iter()
+            .filter_map(|m| Some((m.path.clone(), known_server(m)?)))
+            .collect()
+    }
+
+    // The labels of every configured mount that has any
+    fn labels(&self) -> BTreeMap<String, BTreeMap<String, String>> {
+        self.config()
+            .mount_points
+            .iter()
+            .filter(|m| !m.labels.is_empty())
+            .map(|m| (m.path.clone(), m.labels.clone()))
+            .collect()
+    }
+
+    // Add the state changes, and the checks that hinted at one, to the history and
+    // the metrics, and watch how long the checks took
+    fn record(&mut self, events: &[MountEvent]) {
+        self.update_otlp(events);
+        let probed = self.mounts.take_probed();
+        self.update_metrics(events, &probed);
+        self.watch_latency(&probed);
+        self.watch_usage(&probed);
+        self.watch_read_only(&probed);
+        self.watch_options(&probed);
+        self.watch_credentials(&probed);
+        let unconfirmed = self.mounts.take_unconfirmed();
+        let Some(history) = &self.history else {
+            return;
+        };
+        for (path, probe) in unconfirmed {
+            history.probe(&path, probe.state, &probe.cause);
+        }
+        for event in events {
+            history.transition(&event.path, event.from, event.to, &event.cause);
+        }
+    }
+
+    // Pick up the state an earlier run saved, if it is kept across restarts
+    pub fn load_state(&mut self) {
+        let config = self.config();
+        if !config.persist_state {
+            return;
+        }
+        let path = config.state_file();
+        match state::load(&path) {
+            Ok(Some(saved)) => {
+                info!(event = "state"; "Picking up from the state saved in {}", path.display());
+                self.restore(saved);
+            }
+            Ok(None) => {}
+            Err(e) => warn!(
+                "Unable to read the state saved in {}, starting afresh: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+
+    // Save the state for the next run, if it is kept and has changed. A dry run
+    // leaves it alone, since nothing was acted on.
+    pub fn save_state(&mut self) {
+        let config = self.config();
+        if !config.persist_state || self.dry_run {
+            return;
+        }
+        let json = state::to_json(&self.saved_state());
+        if json == self.saved {
+            return;
+        }
+        let path = config.state_file();
+        match state::save(&path, &json) {
+            Ok(()) => self.saved = json,
+            Err(e) => warn!("Unable to save the state to {}: {}", path.display(), e),
+        }
+    }
+
+    // What to keep across a restart
+    fn saved_state(&self) -> SavedState {
+        let mounts = self
+            .config()
+            .mount_points
+            .iter()
+            .map(|mount| {
+                let since = self.mounts.since(&mount.path);
+                let saved = SavedMount {
+                    state: self.mounts.state(&mount.path),
+                    since: since
+                        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                        .map(|d| d.as_secs()),
+                    last_command: self
+                        .commands
+                        .last_command(&mount.path)
+                        .map(CommandReport::from),
+                };
+                (mount.path.clone(), saved)
+            })
+            .collect();
+        SavedState {
+            mounts,
+            all_mounted: self.current_state,
+            mounted: self.mounted_state,
+            groups: self.group_states.clone().into_iter().collect(),
+        }
+    }
+
+    // Pick up where an earlier run left off, taking each mount to still be as it was
+    // so that only changes since are acted on
+    fn restore(&mut self, saved: SavedState) {
+        for (path, mount) in saved.mounts {
+            let since = mount.since.map(|s| UNIX_EPOCH + Duration::from_secs(s));
+            self.mounts.restore(&path, mount.state, since);
+            if let Some(outcome) = mount.last_command.and_then(|c| c.try_into().ok()) {
+                self.commands.restore(&path, outcome);
+            }
+        }
+        self.current_state = saved.all_mounted;
+        self.mounted_state = saved.mounted.or(saved.all_mounted);
+        let groups = &self.config().groups;
+        let restored = saved
+            .groups
+            .into_iter()
+            .filter(|(name, _)| groups.contains_key(name))
+            .collect();
+        self.group_states = restored;
+    }
+
+    // Log everything being kept track of, to see why nothing has happened yet
+    pub fn dump(&self) {
+        let now = Instant::now();
+        let secs = |at: Instant| at.saturating_duration_since(now).as_secs();
+        let overall = match self.current_state {
+            Some(true) => "all mounted",
+            Some(false) => "not all mounted",
+            None => "not known yet",
+        };
+        info!(event = "dump"; "Overall: {}, next check in {}s", overall, secs(self.next_check()));
+        for report in self.report() {
+            let path = report.path.as_str();
+            let mut parts = vec![report.state.to_string()];
+            parts.extend(report.since.map(|since| format!("since {}", since)));
+            parts.push(self.mounts.describe(path));
+            if report.queued > 0 {
+                parts.push(format!("{} command(s) queued", report.queued));
+            }
+            if report.silenced {
+                parts.push("silenced".to_string());
+            }
+            if let Some(state) = self.held.get(path) {
+                parts.push(format!("last acted on as {}", state));
+            }
+            if self.deferred.iter().any(|p| p == path) {
+                parts.push("waiting out the startup grace period".to_string());
+            }
+            if let Some(reminder) = self.reminders.get(path) {
+                parts.push(format!(
+                    "down {}s, {} reminder(s) sent, next in {}s",
+                    (now - reminder.down_since).as_secs(),
+                    reminder.count,
+                    secs(reminder.next)
+                ));
+            }
+            info!(event = "dump", mount_point = path; "{}: {}", path, parts.join(", "));
+        }
+        for (name, up) in &self.group_states {
+            let state = if *up { "up" } else { "down" };
+            info!(event = "dump"; "Group {}: {}", name, state);
+        }
+        if let Some(until) = self.grace_until.filter(|until| *until > now) {
+            info!(event = "dump"; "Startup grace period ends in {}s", secs(until));
+        }
+        for (mount, until) in &self.silences {
+            let mount = mount.as_deref().unwrap_or("every mount");
+            info!(event = "dump"; "Silenced {} until {}", mount, format_time(*until));
+        }
+        if let Some(next) = self.global_reminder {
+            info!(event = "dump"; "Next global reminder in {}s", secs(next));
+        }
+        if let Some(next) = self.heartbeat.next_due(self.config().heartbeat.as_ref()) {
+            info!(event = "dump"; "Next heartbeat in {}s", secs(next));
+        }
+        if let Some(next) = self.dns.next_due(self.config().dns_check.as_ref()) {
+            info!(event = "dump"; "Next DNS check in {}s", secs(next));
+        }
+    }
+
+    // Summarize the status of every mount point, in configuration order
+    pub fn report(&self) -> Vec<MountReport> {
+        self.config()
+            .mount_points
+            .iter()
+            .map(|mount| MountReport {
+                path: mount.path.clone(),
+                state: if self.flaps.is_flapping(&mount.path) {
+                    MountState::Flapping
+                } else {
+                    self.mounts.state(&mount.path)
+                },
+                since: self.mounts.since(&mount.path).map(format_time),
+                last_command: self
+                    .commands
+                    .last_command(&mount.path)
+                    .map(CommandReport::from),
+                queued: self.commands.pending(&mount.path),
+                silenced: self.silenced.contains(&mount.path),
+                latencies_ms: self
+                    .mounts
+                    .latencies(&mount.path)
+                    .iter()
+                    .map(|l| l.as_millis() as u64)
+                    .collect(),
+                latency_histogram: HistogramReport::from(&self.mounts.histogram(&mount.path)),
+                disk_used_percent: self
+                    .mounts
+                    .capacity(&mount.path)
+                    .and_then(|c| c.used_percent()),
+                inodes_used_percent: self
+                    .mounts
+                    .capacity(&mount.path)
+                    .and_then(|c| c.inodes_used_percent()),
+                server: known_server(mount),
+                labels: mount.labels.clone(),
+                events: self
+                    .mounts
+                    .recent_events(&mount.path)
+                    .into_iter()
+                    .map(|e| EventReport {
+                        at: format_time(e.at),
+                        from: e.from,
+                        to: e.to,
+                        cause: e.cause,
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+impl From<CommandOutcome> for CommandReport {
+    fn from(outcome: CommandOutcome) -> Self {
+        CommandReport {
+            command: outcome.command,
+            at: format_time(outcome.at),
+            success: outcome.result.is_ok(),
+            error: outcome.result.err(),
+        }
+    }
+}
+
+impl TryFrom<CommandReport> for CommandOutcome {
+    type Error = humantime::TimestampError;
+
+    fn try_from(report: CommandReport) -> Result<Self, Self::Error> {
+        Ok(CommandOutcome {
+            command: report.command,
+            at: humantime::parse_rfc3339(&report.at)?,
+            result: match report.error {
+                Some(error) if !report.success => Err(error),
+                _ if !report.success => Err(String::new()),
+                _ => Ok(()),
+            },
+        })
+    }
+}
+
+fn format_time(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+// The context every command gets in its environment
+fn command_env(event: &str, hostname: &str) -> Vec<(&'static str, String)> {
+    vec![
+        ("NOFUS_EVENT", event.to_string()),
+        ("NOFUS_TIMESTAMP", format_time(SystemTime::now())),
+        ("NOFUS_HOSTNAME", hostname.to_string()),
+    ]
+}
+
+// The environment for a command covering several mounts, naming the ones that are
+// missing, or all of them once they are mounted, in NOFUS_MOUNTS (one per line) and
+// the first of those in NOFUS_MOUNT
+fn summary_env(
+    mounts: &MountMonitor,
+    covered: &[&MountPoint],
+    up: bool,
+    previous: Option<bool>,
+    hostname: &str,
+) -> Vec<(&'static str, String)> {
+    let summary = |up| if up { "all_mounted" } else { "any_unmounted" };
+    let named: Vec<&MountPoint> = covered
+        .iter()
+        .filter(|m| up || !mounts.state(&m.path).is_up())
+        .copied()
+        .collect();
+    let paths: Vec<&str> = named.iter().map(|m| m.path.as_str()).collect();
+    let mut env = command_env(summary(up), hostname);
+    env.push(("NOFUS_MOUNTS", paths.join("\n")));
+    env.push(("NOFUS_MOUNT", paths.first().unwrap_or(&"").to_string()));
+    env.push((
+        "NOFUS_SERVER",
+        named.first().map(|m| server_of(m)).unwrap_or_default(),
+    ));
+    env.push(("NOFUS_STATE", summary(up).to_string()));
+    env.push((
+        "NOFUS_PREV_STATE",
+        previous.map_or("unknown", summary).to_string(),
+    ));
+    env
+}
+
+// The server a mount comes from, or nothing if that can't be told
+fn server_of(mount: &MountPoint) -> String {
+    mount_source(&mount.path, mount.namespace.as_deref().map(Path::new))
+        .and_then(|source| server(&source).map(str::to_string))
+        .unwrap_or_default()
+}
+
+fn known_server(mount: &MountPoint) -> Option<String> {
+    Some(server_of(mount)).filter(|s| !s.is_empty())
+}
+
+// Prepare a command with extra environment variables, and its placeholders filled in
+// from them, to be queued on behalf of the mount points, unless this is a dry run
+fn execute_with_env(
+    hook: &Hook,
+    env: Vec<(&'static str, String)>,
+    config: &Config,
+    dry_run: bool,
+    scope: &str,
+    paths: Vec<String>,
+) -> Option<Job> {
+    // Running without a config file, a command may not have been given at all
+    if hook.commands.is_empty() {
+        return None;
+    }
+    let templates: Vec<String> = hook.commands.iter().map(ToString::to_string).collect();
+    let commands: Vec<CommandLine> = hook.commands.iter().map(|c| render(c, &env)).collect();
+    if dry_run {
+        for command in &commands {
+            info!(
+                "Dry run enabled, no commands will be executed.\n Would run: {}",
+                command
+            );
+        }
+        return None;
+    }
+    let var = |name| {
+        env.iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default()
+    };
+    Some(Job {
+        scope: scope.to_string(),
+        from: var("NOFUS_PREV_STATE"),
+        to: var("NOFUS_STATE"),
+        paths,
+        commands,
+        on_error: hook.on_error,
+        run_as: hook.run_as.clone(),
+        timeout: config.command_timeout(),
+        retries: config.retries(hook),
+        backoff: config.backoff(hook),
+        // The same hook run for another mount isn't held back
+        cooldown: config.cooldown(hook),
+        hook: format!("{}: {}", scope, templates.join(" && ")),
+        env,
+    })
+}
+
+// Handle the case where all the mounts are mounted
+fn all_mounted(
+    cmd: &Hook,
+    env: Vec<(&'static str, String)>,
+    config: &Config,
+    dry_run: bool,
+    paths: Vec<String>,
+) -> Option<Job> {
+    info!(event = "all_mounted"; "All NFS mounts are available");
+    execute_with_env(cmd, env, config, dry_run, GLOBAL, paths)
+}
+
+// Handle the case where the mounts are not all mounted
+fn any_unmounted(
+    cmd: &Hook,
+    env: Vec<(&'static str, String)>,
+    config: &Config,
+    dry_run: bool,
+    paths: Vec<String>,
+) -> Option<Job> {
+    error!(event = "any_unmounted"; "One or more NFS mounts are disconnected!!");
+    execute_with_env(cmd, env, config, dry_run, GLOBAL, paths)
+}
+
+// Handle a single mount changing state, preparing its own command if it has one.
+// Its dependents are stopped before that command once it goes down, including when
+// it is found down on starting, and started after it once it is back.
+fn mount_changed(
+    transition: &Transition,
+    config: &Config,
+    hostname: &str,
+    dry_run: bool,
+) -> Vec<Job> {
+    let mount = transition.mount;
+    let event = log_transition(transition);
+    let env = || mount_env(mount, event, transition.from, transition.to, hostname);
+    let paths = || vec![mount.path.clone()];
+    let dependents = |up| {
+        let hook = config.dependents_hook(mount, up)?;
+        let scope = format!("{} dependents", mount.path);
+        execute_with_env(&hook, env(), config, dry_run, &scope, paths())
+    };
+    let (from, to) = (transition.from, transition.to);
+    let mut jobs = Vec::new();
+    if !to.is_up() && (from.is_up() || from == MountState::Unknown) {
+        jobs.extend(dependents(false));
+    }
+    if let Some(cmd) = state_hook(mount, to, config) {
+        jobs.extend(execute_with_env(
+            cmd,
+            env(),
+            config,
+            dry_run,
+            &mount.path,
+            paths(),
+        ));
+    }
+    if to.is_up() && !from.is_up() && from != MountState::Unknown {
+        jobs.extend(dependents(true));
+    }
+    jobs
+}
+
+// Log a mount changing state, returning the event its commands get for it
+fn log_transition(transition: &Transition) -> &'static str {
+    let mount = transition.mount;
+    match transition.to {
+        MountState::Mounted => {
+            info!(
+                event = "transition", mount_point = mount.path, state:% = transition.to;
+                "Mount point available: {} (was {})", mount.path, transition.from
+            );
+            "mounted"
+        }
+        MountState::Stale => {
+            error!(
+                event = "transition", mount_point = mount.path, state:% = transition.to;
+                "Mount point has a stale file handle: {} (was {})", mount.path, transition.from
+            );
+            "stale"
+        }
+        MountState::Degraded => {
+            warn!(
+                event = "transition", mount_point = mount.path, state:% = transition.to;
+                "Mount point is slow to respond: {} (was {})", mount.path, transition.from
+            );
+            "degraded"
+        }
+        MountState::ServerDown => {
+            error!(
+                event = "transition", mount_point = mount.path, state:% = transition.to;
+                "Server of mount point is down: {} (was {})", mount.path, transition.from
+            );
+            "server_down"
+        }
+        _ => {
+            warn!(
+                event = "transition", mount_point = mount.path, state:% = transition.to;
+                "Mount point unavailable: {} (was {})", mount.path, transition.from
+            );
+            "unmounted"
+        }
+    }
+}
+
+// The command a mount runs on entering a state, if it has one
+fn state_hook<'a>(
+    mount: &'a MountPoint,
+    state: MountState,
+    config: &'a Config,
+) -> Option<&'a Hook> {
+    match state {
+        MountState::Mounted => mount.on_mounted.as_ref(),
+        // Stale mounts need their own remedy, so fall back to the global one
+        MountState::Stale => mount.on_stale.as_ref().or(config.on_stale_cmd.as_ref()),
+        MountState::Degraded => mount
+            .on_degraded
+            .as_ref()
+            .or(config.on_degraded_cmd.as_ref()),
+        // Waiting is usually all there is to do, but before there was a state of its
+        // own it got on_unmounted, which it still falls back to
+        MountState::ServerDown => mount
+            .on_server_down
+            .as_ref()
+            .or(config.on_server_down_cmd.as_ref())
+            .or(mount.on_unmounted.as_ref()),
+        _ => mount.on_unmounted.as_ref(),
+    }
+}
+
+// A mount that answered, but more slowly than its thresholds allow, is degraded or,
+// past the critical one, as good as unresponsive
+fn grade(mut probe: Probe, warn: Option<Duration>, critical: Option<Duration>) -> Probe {
+    if probe.state != MountState::Mounted {
+        return probe;
+    }
+    let (state, threshold, name) = match (warn, critical) {
+        (_, Some(critical)) if probe.latency > critical => {
+            (MountState::Unresponsive, critical, "critical")
+        }
+        (Some(warn), _) if probe.latency > warn => (MountState::Degraded, warn, "warning"),
+        _ => return probe,
+    };
+    probe.state = state;
+    probe.cause = format!(
+        "took {}ms, over the {} threshold of {}ms",
+        probe.latency.as_millis(),
+        name,
+        threshold.as_millis()
+    );
+    probe
+}
+
+// The environment for a command run for a single mount
+fn mount_env(
+    mount: &MountPoint,
+    event: &str,
+    from: MountState,
+    to: MountState,
+    hostname: &str,
+) -> Vec<(&'static str, String)> {
+    let mut env = command_env(event, hostname);
+    env.push(("NOFUS_MOUNT", mount.path.clone()));
+    env.push(("NOFUS_SERVER", server_of(mount)));
+    env.push(("NOFUS_STATE", to.to_string()));
+    env.push(("NOFUS_PREV_STATE", from.to_string()));
+    env
+}
+
+// Whether all mounts covered by the global command for coming up, or for going down,
+// are mounted, if there are any
+fn global_state(mounts: &MountMonitor, up: bool) -> Option<bool> {
+    // Only mounts without their own command fall back to the global one
+    let config = mounts.config();
+    let global_mounts: Vec<&MountPoint> = config
+        .mount_points
+        .iter()
+        .filter(|m| config.uses_global_command(m, up))
+        .collect();
+    if global_mounts.is_empty() {
+        return None;
+    }
+    Some(global_mounts.iter().all(|m| mounts.state(&m.path).is_up()))
+}
+
+// How many of the paths are mounted
+fn group_mounted(mounts: &MountMonitor, paths: &[String]) -> usize {
+    paths.iter().filter(|p| mounts.state(p).is_up()).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigFormat;
+
+    const A: &str = "/nofus-test/a";
+    const B: &str = "/nofus-test/b";
+    const C: &str = "/nofus-test/c";
+
+    fn config(extra: &str) -> Config {
+        let yaml = format!(
+            "mount_points: [{}, {}, {}]\ndelay_seconds: 5\nall_mounted_cmd: up\nany_unmounted_cmd: down\n{}",
+            A, B, C, extra
+        );
+        let mut config = Config::parse(&yaml, ConfigFormat::Yaml).unwrap();
+        config.add_group_mounts();
+        config
+    }
+
+    fn force_all(monitor: &mut Monitor, state: MountState) {
+        for path in [A, B, C] {
+            monitor.force(path, Some(state));
+        }
+    }
+
+    #[test]
+    fn first_check_reports_every_mount() {
+        let mut mounts = MountMonitor::new(config(""));
+        mounts.force(A, Some(MountState::Mounted));
+        mounts.force(B, Some(MountState::Unmounted));
+        mounts.force(C, Some(MountState::Mounted));
+        let events = mounts.poll();
+        assert_eq!(events.len(), 3);
+        assert!(events.iter().all(|e| e.from == MountState::Unknown));
+        assert_eq!(events[1].kind, MountEventKind::Unmounted);
+        assert!(mounts.poll().is_empty());
+    }
+
+    #[test]
+    fn global_state_follows_every_covered_mount() {
+        let mut monitor = Monitor::new(config(""), true);
+        force_all(&mut monitor, MountState::Mounted);
+        monitor.evaluate();
+        assert_eq!(
+            (monitor.current_state, monitor.mounted_state),
+            (Some(true), Some(true))
+        );
+
+        monitor.force(B, Some(MountState::Unresponsive));
+        monitor.evaluate();
+        assert_eq!(
+            (monitor.current_state, monitor.mounted_state),
+            (Some(false), Some(false))
+        );
+    }
+
+    #[test]
+    fn own_commands_take_mounts_out_of_one_global_state() {
+        let mut config = config("");
+        config.mount_points[1].on_unmounted = Some(config.any_unmounted_cmd.clone());
+        let mut monitor = Monitor::new(config, true);
+        force_all(&mut monitor, MountState::Mounted);
+        monitor.force(B, Some(MountState::Unmounted));
+        monitor.evaluate();
+        // B going down is left to its own command, but it still holds back coming up
+        assert_eq!(monitor.current_state, Some(true));
+        assert_eq!(monitor.mounted_state, Some(false));
+    }
+
+    #[test]
+    fn changes_are_believed_once_seen_enough_times() {
+        let mut mounts =
+            MountMonitor::new(config("failures_before_down: 2\nsuccesses_before_up: 3\n"));
+        mounts.force(A, Some(MountState::Mounted));
+        mounts.poll();
+
+        mounts.force(A, Some(MountState::Unmounted));
+        assert!(mounts.poll().iter().all(|e| e.path != A));
+        assert_eq!(mounts.state(A), MountState::Mounted);
+        assert_eq!(mounts.take_unconfirmed().len(), 1);
+        let events = mounts.poll();
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            (events[0].from, events[0].to),
+            (MountState::Mounted, MountState::Unmounted)
+        );
+
+        // Going back up, a check in between that disagrees starts the count over
+        mounts.force(A, Some(MountState::Mounted));
+        mounts.poll();
+        mounts.poll();
+        mounts.force(A, Some(MountState::Unmounted));
+        mounts.poll();
+        mounts.force(A, Some(MountState::Mounted));
+        mounts.poll();
+        mounts.poll();
+        assert_eq!(mounts.state(A), MountState::Unmounted);
+        assert_eq!(mounts.poll().len(), 1);
+        assert_eq!(mounts.state(A), MountState::Mounted);
+    }
+
+    #[test]
+    fn flapping_mounts_are_left_alone() {
+        let mut monitor = Monitor::new(
+            config("flap_threshold: 2\nflap_window_seconds: 3600\n"),
+            true,
+        );
+        force_all(&mut monitor, MountState::Mounted);
+        monitor.evaluate();
+        monitor.force(A, Some(MountState::Unmounted));
+        monitor.evaluate();
+        assert_eq!(monitor.current_state, Some(false));
+        monitor.force(A, Some(MountState::Mounted));
+        monitor.evaluate();
+        assert!(monitor.flaps.is_flapping(A));
+        // Coming back while flapping isn't acted on until it settles
+        assert_eq!(monitor.current_state, Some(false));
+    }
+
+    #[test]
+    fn startup_grace_defers_missing_mounts() {
+        let mut monitor = Monitor::new(config("startup_grace_seconds: 3600\n"), true);
+        force_all(&mut monitor, MountState::Mounted);
+        monitor.force(C, Some(MountState::Unmounted));
+        monitor.evaluate();
+        assert_eq!(monitor.deferred, [C]);
+        assert!(monitor.grace_until.is_some());
+        assert_eq!(monitor.current_state, None);
+
+        // Once everything is up the grace period ends early
+        monitor.force(C, Some(MountState::Mounted));
+        monitor.evaluate();
+        assert!(monitor.deferred.is_empty());
+        assert!(monitor.grace_until.is_none());
+        assert_eq!(monitor.current_state, Some(true));
+    }
+
+    #[test]
+    fn recovery_waits_for_the_confirmation_time() {
+        let mut mounts = MountMonitor::new(config("recovery_confirmation_seconds: 3600\n"));
+        mounts.force(A, Some(MountState::Unmounted));
+        mounts.poll();
+        mounts.force(A, Some(MountState::Mounted));
+        mounts.poll();
+        assert_eq!(mounts.state(A), MountState::Unmounted);
+        // Going down again needs no confirmation
+        mounts.force(A, Some(MountState::Stale));
+        assert_eq!(mounts.poll().len(), 1);
+    }
+
+    #[test]
+    fn groups_keep_quorum_until_too_few_are_up() {
+        let groups = format!("groups:\n  pair:\n    mount_points: [{}, {}, {}]\n    quorum: 2\n    any_unmounted_cmd: lost\n", A, B, C);
+        let mut monitor = Monitor::new(config(&groups), true);
+        force_all(&mut monitor, MountState::Mounted);
+        monitor.evaluate();
+        assert_eq!(monitor.group_states.get("pair"), Some(&true));
+
+        monitor.force(A, Some(MountState::Unmounted));
+        monitor.evaluate();
+        assert_eq!(monitor.group_states.get("pair"), Some(&true));
+
+        monitor.force(B, Some(MountState::Stale));
+        monitor.evaluate();
+        assert_eq!(monitor.group_states.get("pair"), Some(&false));
+
+        monitor.force(A, Some(MountState::Mounted));
+        monitor.evaluate();
+        assert_eq!(monitor.group_states.get("pair"), Some(&true));
+    }
+
+    #[test]
+    fn hung_and_slow_mounts_are_told_apart() {
+        let kind = MountEventKind::from;
+        assert_eq!(kind(MountState::Degraded), MountEventKind::Degraded);
+        assert_eq!(kind(MountState::Unresponsive), MountEventKind::Unresponsive);
+        assert_eq!(kind(MountState::Flapping), MountEventKind::Flapping);
+        assert_eq!(kind(MountState::ServerDown), MountEventKind::ServerDown);
+        assert_eq!(kind(MountState::Unknown), MountEventKind::ProbeError);
+    }
+
+    #[test]
+    fn hooks_without_commands_queue_nothing() {
+        let config = Config::for_mounts(&[A.to_string()], 5, None, Some("down")).unwrap();
+        let env = vec![("NOFUS_STATE", "mounted".to_string())];
+        let hook = &config.all_mounted_cmd;
+        assert!(execute_with_env(hook, env.clone(), &config, false, GLOBAL, Vec::new()).is_none());
+        let hook = &config.any_unmounted_cmd;
+        assert!(execute_with_env(hook, env, &config, false, GLOBAL, Vec::new()).is_some());
+    }
+
+    #[test]
+    fn restored_state_is_only_acted_on_once_it_changes() {
+        let mut before = Monitor::new(config(""), true);
+        force_all(&mut before, MountState::Mounted);
+        before.force(B, Some(MountState::Stale));
+        before.evaluate();
+
+        let mut after = Monitor::new(config(""), true);
+        after.restore(before.saved_state());
+        assert_eq!(after.mounts.state(B), MountState::Stale);
+        assert_eq!(after.current_state, Some(false));
+        let events = after.events();
+        force_all(&mut after, MountState::Mounted);
+        after.force(B, Some(MountState::Stale));
+        after.evaluate();
+        assert!(events.try_recv().is_err());
+
+        after.force(B, Some(MountState::Mounted));
+        after.evaluate();
+        assert_eq!(events.try_recv().unwrap().path, B);
+        assert_eq!(after.current_state, Some(true));
+    }
+}
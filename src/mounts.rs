@@ -0,0 +1,677 @@
+use crate::monitor::MountState;
+use crate::platform::{enter_namespace, mount_entries, MountSource, NativeSource};
+use crate::rpc;
+use proc_mounts::{MountInfo, MountTab};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// The error NFS returns for every operation once the server no longer recognises the
+// file handle, e.g. after the export was recreated
+const ESTALE: i32 = libc::ESTALE;
+
+fn is_stale(error: &io::Error) -> bool {
+    error.raw_os_error() == Some(ESTALE)
+}
+
+// What probing a mount point found, and why
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Probe {
+    pub state: MountState,
+    pub cause: String,
+    // How long the check took, or zero if it wasn't made
+    pub latency: Duration,
+    // How full the mount is, if it could be read
+    pub capacity: Option<Capacity>,
+    // The options the mount is mounted with, if it was found working
+    pub options: Vec<String>,
+}
+
+impl Probe {
+    fn new(state: MountState, cause: impl Into<String>) -> Self {
+        Probe {
+            state,
+            cause: cause.into(),
+            latency: Duration::ZERO,
+            capacity: None,
+            options: Vec::new(),
+        }
+    }
+}
+
+// The size of a mount and how much of it is free, in blocks and inodes, as statvfs
+// gives them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capacity {
+    pub blocks: u64,
+    pub blocks_free: u64,
+    // Free to users other than root
+    pub blocks_available: u64,
+    pub files: u64,
+    pub files_free: u64,
+}
+
+impl Capacity {
+    // How much of the space users may have is used, as df counts it
+    pub fn used_percent(&self) -> Option<f64> {
+        let used = self.blocks.saturating_sub(self.blocks_free);
+        let usable = used + self.blocks_available;
+        (usable > 0).then(|| used as f64 / usable as f64 * 100.0)
+    }
+
+    // How many of the inodes are used, if the filesystem says how many it has, which
+    // some servers don't
+    pub fn inodes_used_percent(&self) -> Option<f64> {
+        let used = self.files.saturating_sub(self.files_free);
+        (self.files > 0).then(|| used as f64 / self.files as f64 * 100.0)
+    }
+}
+
+fn capacity(path: &Path) -> Option<Capacity> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(Capacity {
+        blocks: stat.f_blocks as u64,
+        blocks_free: stat.f_bfree as u64,
+        blocks_available: stat.f_bavail as u64,
+        files: stat.f_files as u64,
+        files_free: stat.f_ffree as u64,
+    })
+}
+
+// A mount point to probe and what it takes to count as mounted
+#[derive(Debug, Clone)]
+pub struct Target<'a> {
+    pub path: &'a str,
+    // If any are given, the mount only counts if it is one of them
+    pub fs_types: &'a [String],
+    // A file to write, read back and delete on each check, to catch mounts that list
+    // fine but can't be written to
+    pub canary: Option<PathBuf>,
+    // Whether to ask the server of a mount that is down if it still serves NFS, and
+    // how long to give it
+    pub ping_server: bool,
+    pub server_timeout: Duration,
+    // How long to give the server of an NFS mount to accept a connection before
+    // probing it, if it should be
+    pub tcp_precheck: Option<Duration>,
+    // Whether to make sure the server of an NFS mount has NFS registered with its
+    // rpcbind before probing it
+    pub rpcbind_check: bool,
+    // Whether to access a mount autofs has let expire to have it mounted again,
+    // rather than leave its state unknown
+    pub trigger_automount: bool,
+    // The mount namespace to look at the mount from, e.g. /proc/<pid>/ns/mnt for
+    // that of a container, rather than nofus's own
+    pub namespace: Option<&'a Path>,
+}
+
+// The mount namespace mounts are looked at from unless they say otherwise, as given
+// with --mnt-ns
+static DEFAULT_NAMESPACE: OnceLock<PathBuf> = OnceLock::new();
+
+pub fn set_default_namespace(namespace: PathBuf) {
+    let _ = DEFAULT_NAMESPACE.set(namespace);
+}
+
+// The mount namespace to look at a mount from: its own, if it has one, or else the
+// default
+fn namespace_of(own: Option<&Path>) -> Option<&Path> {
+    own.or(DEFAULT_NAMESPACE.get().map(PathBuf::as_path))
+}
+
+// Read the mount table or fstab from the mount namespace, if there is one to look
+// from, on a thread of its own as a thread that enters one can't leave it. Reading
+// them can't hang, so the thread is simply waited for.
+fn in_namespace<T: Send>(
+    namespace: Option<&Path>,
+    read: impl FnOnce() -> Option<T> + Send,
+) -> Option<T> {
+    let Some(namespace) = namespace else {
+        return read();
+    };
+    thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                enter_namespace(namespace).ok()?;
+                read()
+            })
+            .join()
+            .ok()?
+    })
+}
+
+impl<'a> Target<'a> {
+    pub fn new(path: &'a str, fs_types: &'a [String]) -> Self {
+        Target {
+            path,
+            fs_types,
+            canary: None,
+            ping_server: false,
+            server_timeout: Duration::from_secs(1),
+            tcp_precheck: None,
+            rpcbind_check: false,
+            trigger_automount: true,
+            namespace: namespace_of(None),
+        }
+    }
+}
+
+// Paths with a probe still stuck in the kernel from an earlier check
+static HUNG: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+// Probe a mount on a worker thread, giving up after the timeout so a hard mount whose
+// server is down can't block the caller. Whatever the server checks asked for find
+// tells a server that is down from a mount that is gone or stale.
+pub fn probe(target: &Target, timeout: Duration) -> Probe {
+    // Don't try a mount whose server can't even be reached, which would only hang
+    if let Some(precheck) = target.tcp_precheck {
+        if let Some(cause) = unreachable(target, precheck) {
+            return Probe::new(MountState::ServerDown, cause);
+        }
+    }
+    if target.rpcbind_check {
+        if let Some(cause) = unregistered(target, target.server_timeout) {
+            return Probe::new(MountState::ServerDown, cause);
+        }
+    }
+    let mut probe = probe_mount(target, timeout);
+    if target.ping_server && !probe.state.is_up() {
+        if let Some(status) = server_status(target, target.server_timeout) {
+            let answering = status.is_ok();
+            let status = status.unwrap_or_else(|e| e);
+            probe.cause = format!("{}; {}", probe.cause, status);
+            // A stale handle is the client's to sort out, whatever the server is doing
+            if !answering && probe.state != MountState::Stale {
+                probe.state = MountState::ServerDown;
+            }
+        }
+    }
+    probe
+}
+
+fn probe_mount(target: &Target, timeout: Duration) -> Probe {
+    let path = target.path;
+    // A thread blocked on a hung mount can't be cancelled, so don't pile up more
+    if HUNG.lock().unwrap().contains(path) {
+        return Probe::new(
+            MountState::Unresponsive,
+            "still waiting on an earlier check",
+        );
+    }
+
+    let started = Instant::now();
+    let (sender, receiver) = mpsc::channel();
+    let owned = path.to_string();
+    let fs_types = target.fs_types.to_vec();
+    let canary = target.canary.clone();
+    let trigger = target.trigger_automount;
+    let namespace = target.namespace.map(Path::to_path_buf);
+    thread::spawn(move || {
+        let probe = match namespace.as_deref().map(enter_namespace) {
+            Some(Err(e)) => Probe::new(
+                MountState::Unknown,
+                format!(
+                    "unable to enter mount namespace {}: {}",
+                    namespace.unwrap_or_default().display(),
+                    e
+                ),
+            ),
+            _ => probe_blocking(&owned, &fs_types, canary.as_deref(), trigger),
+        };
+        // Hold the lock so the caller can't time out in between
+        let mut hung = HUNG.lock().unwrap();
+        let _ = sender.send(probe);
+        hung.remove(&owned);
+    });
+    let mut probe = receiver.recv_timeout(timeout).unwrap_or_else(|_| {
+        let mut hung = HUNG.lock().unwrap();
+        if let Ok(probe) = receiver.try_recv() {
+            return probe;
+        }
+        hung.insert(path.to_string());
+        Probe::new(
+            MountState::Unresponsive,
+            format!("no response within {}s", timeout.as_secs()),
+        )
+    });
+    probe.latency = started.elapsed();
+    probe
+}
+
+// Probe several mounts at once on up to `workers` threads, returning the results in
+// the same order, so slow servers don't add up across mounts
+pub fn probe_all(mounts: &[Target], timeout: Duration, workers: usize) -> Vec<Probe> {
+    let next = AtomicUsize::new(0);
+    let probes = Mutex::new(vec![Probe::new(MountState::Unknown, ""); mounts.len()]);
+    thread::scope(|scope| {
+        for _ in 0..workers.min(mounts.len()) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(target) = mounts.get(i) else {
+                    break;
+                };
+                let probe = probe(target, timeout);
+                probes.lock().unwrap()[i] = probe;
+            });
+        }
+    });
+    probes.into_inner().unwrap()
+}
+
+// Check whether the path is a mount point of the right type and, if it is, whether it
+// still responds
+fn probe_blocking(path: &str, fs_types: &[String], canary: Option<&Path>, trigger: bool) -> Probe {
+    // An automount that expired for being idle may not be down. It can be mounted
+    // again by accessing it, which the timeout covers like any other check, and
+    // without that there is no telling whether it would work.
+    if automount_expired(Path::new(path)) {
+        if !trigger {
+            return Probe::new(MountState::Unknown, "expired, autofs mounts it on access");
+        }
+        let _ = fs::read_dir(path);
+        if automount_expired(Path::new(path)) {
+            return Probe::new(MountState::Unmounted, "autofs didn't mount it on access");
+        }
+    }
+
+    // Resolving a path through a stale mount fails, so fall back to the path as given
+    let (path, resolved) = match PathBuf::from(path).canonicalize() {
+        Ok(p) => (p, true),
+        Err(e) if is_stale(&e) => (PathBuf::from(path), false),
+        Err(e) if is_disconnected(&e) => {
+            return Probe::new(
+                MountState::Unmounted,
+                "disconnected, its FUSE process is gone",
+            )
+        }
+        Err(e) => {
+            return Probe::new(
+                MountState::Unmounted,
+                format!("unable to resolve path: {}", e),
+            )
+        }
+    };
+    let options = match mounted(&path) {
+        // Something else mounted over the path, or under it once the share went away
+        Ok(Some(m)) if !fs_types.is_empty() && !fs_types.contains(&m.fstype) => {
+            return Probe::new(
+                MountState::Unmounted,
+                format!(
+                    "mounted as {}, expected {}",
+                    m.fstype,
+                    fs_types.join(" or ")
+                ),
+            )
+        }
+        Ok(Some(m)) => m.options,
+        Ok(None) => return Probe::new(MountState::Unmounted, "not in the mount table"),
+        Err(e) => {
+            return Probe::new(
+                MountState::Unknown,
+                format!("unable to read the mount table: {}", e),
+            )
+        }
+    };
+    if !resolved {
+        return Probe::new(MountState::Stale, "stale file handle");
+    }
+
+    // Still listed in /proc/mounts doesn't mean it works, so actually read it
+    let mut probe = match fs::read_dir(&path) {
+        Ok(_) => Probe::new(MountState::Mounted, "mounted and readable"),
+        Err(e) if is_stale(&e) => return Probe::new(MountState::Stale, "stale file handle"),
+        // Not being allowed in still means the server answered
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            Probe::new(MountState::Mounted, "mounted, but not readable by nofus")
+        }
+        Err(e) => return Probe::new(MountState::Unknown, format!("unable to read mount: {}", e)),
+    };
+    if let Some(canary) = canary {
+        if let Err(failed) = round_trip(canary) {
+            return failed;
+        }
+        probe.cause = "mounted, readable and writable".to_string();
+    }
+    probe.capacity = capacity(&path);
+    probe.options = options;
+    probe
+}
+
+// A FUSE mount, e.g. sshfs, whose process died or lost its connection stays in the
+// mount table, but anything done with it fails
+fn is_disconnected(error: &io::Error) -> bool {
+    error.raw_os_error() == Some(libc::ENOTCONN)
+}
+
+// Whether the mount at the path is a FUSE mount left behind by its process
+pub fn disconnected(path: &str) -> bool {
+    fs::metadata(path).is_err_and(|e| is_disconnected(&e))
+}
+
+// Write a timestamped file, read it back and delete it
+fn round_trip(canary: &Path) -> Result<(), Probe> {
+    let stamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let written = format!("nofus {} {}\n", process::id(), stamp.as_nanos());
+    let failed = |step: &str, e: io::Error| {
+        let state = if is_stale(&e) {
+            MountState::Stale
+        } else {
+            MountState::Unknown
+        };
+        Probe::new(
+            state,
+            format!(
+                "unable to {} canary file {}: {}",
+                step,
+                canary.display(),
+                describe(&e)
+            ),
+        )
+    };
+    fs::write(canary, &written).map_err(|e| failed("write", e))?;
+    let read = fs::read(canary);
+    // Clean up even when reading it failed
+    let removed = fs::remove_file(canary);
+    if read.map_err(|e| failed("read", e))? != written.as_bytes() {
+        return Err(Probe::new(
+            MountState::Unknown,
+            format!(
+                "canary file {} read back different contents",
+                canary.display()
+            ),
+        ));
+    }
+    removed.map_err(|e| failed("delete", e))
+}
+
+// What went wrong with a file operation, told apart for the failures a broken mount
+// tends to give
+fn describe(error: &io::Error) -> String {
+    match error.raw_os_error() {
+        Some(ESTALE) => "stale file handle".to_string(),
+        Some(libc::EACCES) | Some(libc::EPERM) => "permission denied".to_string(),
+        Some(libc::EROFS) => "read-only filesystem".to_string(),
+        Some(libc::EIO) => "I/O error".to_string(),
+        Some(libc::ENOSPC) | Some(libc::EDQUOT) => "out of space".to_string(),
+        Some(libc::ETIMEDOUT) => "timed out".to_string(),
+        Some(libc::ENOTCONN) => "disconnected".to_string(),
+        _ => error.to_string(),
+    }
+}
+
+// Whether autofs manages the path, as a direct mount of its own or an entry of an
+// indirect map mounted on its parent, and nothing is mounted there right now
+fn automount_expired(path: &Path) -> bool {
+    let Ok(mounts) = mount_entries() else {
+        return false;
+    };
+    match mounts.iter().rfind(|m| m.dest == path) {
+        Some(mount) => mount.fstype == "autofs",
+        None => path.parent().is_some_and(|parent| {
+            mounts
+                .iter()
+                .any(|m| m.dest == parent && m.fstype == "autofs")
+        }),
+    }
+}
+
+// What is mounted at the path according to /proc/mounts, if anything. The kernel
+// lists mount points resolved already, so they are compared as they are: resolving
+// them would touch every other mount, and hang on any that is hung.
+fn mounted(path: &Path) -> io::Result<Option<MountInfo>> {
+    let mounts = mount_entries()?;
+
+    // Filter for the matching path, the last of which is the one that's visible
+    Ok(mounts.into_iter().rfind(|m| m.dest == path))
+}
+
+// Where the mount at the path comes from, e.g. nas:/export, going by the mount table
+// or, for a mount that is gone, fstab or where it came from last. Both are those of
+// the mount namespace the mount is looked at from, given its own if it has one.
+pub fn mount_source(path: &str, namespace: Option<&Path>) -> Option<String> {
+    let entry = mount_entry(path, namespace_of(namespace))?;
+    Some(entry.source.to_string_lossy().into_owned())
+}
+
+// The mount table entry each path was last found with in each namespace, for once it
+// is gone
+type Seen = BTreeMap<(Option<PathBuf>, String), MountInfo>;
+static LAST_SEEN: Mutex<Seen> = Mutex::new(BTreeMap::new());
+
+// The entry for the mount at the path in the mount table of the namespace or, if it
+// is gone, fstab or the mount table when it was last there
+fn mount_entry(path: &str, namespace: Option<&Path>) -> Option<MountInfo> {
+    let (entry, mounted) = in_namespace(namespace, || {
+        let mounted = mount_entries()
+            .ok()?
+            .into_iter()
+            .rfind(|m| m.dest == Path::new(path));
+        Some(match mounted {
+            Some(info) => (Some(info), true),
+            None => (fstab_entry(path), false),
+        })
+    })?;
+    let key = (namespace.map(Path::to_path_buf), path.to_string());
+    let mut last_seen = LAST_SEEN.lock().unwrap();
+    match entry {
+        Some(info) if mounted => {
+            last_seen.insert(key, info.clone());
+            Some(info)
+        }
+        Some(info) => Some(info),
+        None => last_seen.get(&key).cloned(),
+    }
+}
+
+// The entry for the mount at the path in /etc/fstab, if it is listed
+fn fstab_entry(path: &str) -> Option<MountInfo> {
+    let tab: MountTab = fs::read_to_string("/etc/fstab").ok()?.parse().ok()?;
+    let entry = tab
+        .iter_mounts()
+        .find(|m| m.dest == Path::new(path))
+        .cloned();
+    entry
+}
+
+// The value of a mount option such as port=2049
+fn option<'a>(options: &'a [String], name: &str) -> Option<&'a str> {
+    options
+        .iter()
+        .filter_map(|o| o.split_once('='))
+        .filter(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+        .next_back()
+}
+
+// Where an NFS mount's server listens, and the NFS version the mount speaks
+struct NfsServer {
+    host: String,
+    port: u16,
+    version: u32,
+}
+
+fn nfs_server(target: &Target) -> Option<NfsServer> {
+    let entry = mount_entry(target.path, target.namespace)?;
+    if !NFS_TYPES.contains(&entry.fstype.as_str()) {
+        return None;
+    }
+    let source = entry.source.to_string_lossy();
+    let host = server(&source)?.to_string();
+    let port = option(&entry.options, "port")
+        .and_then(|p| p.parse().ok())
+        .filter(|p| *p != 0)
+        .unwrap_or(NFS_PORT);
+    let version = if entry.fstype == "nfs4" {
+        4
+    } else {
+        option(&entry.options, "vers")
+            .or(option(&entry.options, "nfsvers"))
+            .and_then(|v| v.split('.').next()?.parse().ok())
+            .unwrap_or(3)
+    };
+    Some(NfsServer {
+        host,
+        port,
+        version,
+    })
+}
+
+// Whether the server of an NFS mount has the programs the mount needs registered
+// with rpcbind, which right after a reboot can lag behind the NFS port opening.
+// Gives why not if it hasn't.
+fn unregistered(target: &Target, timeout: Duration) -> Option<String> {
+    let nfs = nfs_server(target)?;
+    let mut programs = vec![("nfs", rpc::NFS_PROGRAM, nfs.version)];
+    // NFSv4 does without the separate mount daemon
+    if nfs.version < 4 {
+        programs.push(("mountd", rpc::MOUNT_PROGRAM, 3));
+    }
+    for (name, program, version) in programs {
+        match rpc::port(&nfs.host, program, version, timeout) {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                return Some(format!(
+                    "server {} has no {} version {} registered with rpcbind",
+                    nfs.host, name, version
+                ))
+            }
+            Err(e) => return Some(format!("unable to ask rpcbind on {}: {}", nfs.host, e)),
+        }
+    }
+    None
+}
+
+// Ask the server of an NFS mount whether it still serves NFS, which tells a server
+// that is down from a client that lost the mount, and works while the mount itself
+// hangs. Says which, as an error if it isn't.
+fn server_status(target: &Target, timeout: Duration) -> Option<Result<String, String>> {
+    let nfs = nfs_server(target)?;
+    Some(
+        match rpc::ping(&nfs.host, nfs.port, rpc::NFS_PROGRAM, nfs.version, timeout) {
+            Ok(()) => Ok(format!("server {} is answering NFS", nfs.host)),
+            Err(e) => Err(format!("server {} is not answering NFS: {}", nfs.host, e)),
+        },
+    )
+}
+
+// Whether the server of an NFS mount accepts connections, which is much cheaper to
+// find out than whether the mount works, and can't hang. Gives why not if it
+// doesn't.
+fn unreachable(target: &Target, timeout: Duration) -> Option<String> {
+    let nfs = nfs_server(target)?;
+    let error = rpc::connect(&nfs.host, nfs.port, timeout).err()?;
+    Some(format!(
+        "server {} is unreachable on port {}: {}",
+        nfs.host, nfs.port, error
+    ))
+}
+
+// The server named by a mount source: nas for nas:/export, //nas/share or user@nas:dir
+pub fn server(source: &str) -> Option<&str> {
+    let host = match source.strip_prefix("//") {
+        Some(share) => share.split('/').next()?,
+        None => source.split_once(':')?.0,
+    };
+    let host = host.rsplit('@').next()?;
+    // IPv6 addresses are written in brackets, e.g. [fd00::1]:/export
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    (!host.is_empty()).then_some(host)
+}
+
+// The filesystem types discovered from fstab
+const NFS_TYPES: [&str; 2] = ["nfs", "nfs4"];
+
+// Where NFS servers listen unless the mount says otherwise
+const NFS_PORT: u16 = 2049;
+
+// The options the mount at the path is listed with in /etc/fstab, if it is, in the
+// mount namespace it is looked at from
+pub fn fstab_options(path: &str, namespace: Option<&Path>) -> Option<Vec<String>> {
+    let entry = in_namespace(namespace_of(namespace), || fstab_entry(path))?;
+    Some(entry.options)
+}
+
+// Whether mount options have a mount read-only
+pub fn is_read_only(options: &[String]) -> bool {
+    options.iter().any(|o| o == "ro")
+}
+
+// The mount points of the NFS mounts listed in an fstab
+pub fn fstab_mount_points(fstab: &Path) -> io::Result<Vec<String>> {
+    let tab: MountTab = fs::read_to_string(fstab)?.parse()?;
+    Ok(tab
+        .iter_mounts()
+        .filter(|m| NFS_TYPES.contains(&m.fstype.as_str()))
+        .map(|m| m.dest.to_string_lossy().into_owned())
+        .collect())
+}
+
+// Every mount point listed in an fstab, whatever its filesystem, with its type
+pub fn fstab_entries(fstab: &Path) -> io::Result<Vec<(String, String)>> {
+    let tab: MountTab = fs::read_to_string(fstab)?.parse()?;
+    Ok(tab
+        .iter_mounts()
+        .map(|m| (m.dest.to_string_lossy().into_owned(), m.fstype.clone()))
+        .collect())
+}
+
+// The mount table, kept up to date as mounts come and go
+pub struct MountTable {
+    source: Box<dyn MountSource>,
+    // Each mount point and its filesystem type
+    mounts: BTreeMap<PathBuf, String>,
+}
+
+impl MountTable {
+    pub fn open() -> io::Result<Self> {
+        let mut source = NativeSource::open()?;
+        let mounts = source.read()?;
+        Ok(MountTable {
+            source: Box::new(source),
+            mounts,
+        })
+    }
+
+    pub fn mounts(&self) -> &BTreeMap<PathBuf, String> {
+        &self.mounts
+    }
+
+    // Re-read the table after a wakeup, returning the mount points that came or went
+    pub fn refresh(&mut self) -> io::Result<Vec<PathBuf>> {
+        self.source.clear()?;
+        let mounts = self.source.read()?;
+        let changed = self
+            .mounts
+            .keys()
+            .chain(mounts.keys())
+            .filter(|path| self.mounts.get(*path) != mounts.get(*path))
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        self.mounts = mounts;
+        Ok(changed)
+    }
+}
+
+impl AsRawFd for MountTable {
+    fn as_raw_fd(&self) -> RawFd {
+        self.source.as_raw_fd()
+    }
+}
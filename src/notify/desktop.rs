@@ -0,0 +1,68 @@
+use super::{Notifier, StateChange};
+use crate::monitor::MountState;
+use log::{debug, warn};
+use notify_rust::{Notification, Urgency};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Shows a desktop notification over D-Bus, for nofus running as a user service
+#[derive(Default)]
+pub struct Desktop {
+    // Whether a missing session bus has already been reported
+    warned: AtomicBool,
+}
+
+impl Notifier for Desktop {
+    fn name(&self) -> &'static str {
+        "desktop"
+    }
+
+    fn notify(&self, change: &StateChange) -> Result<(), String> {
+        let (summary, urgency) = if !change.is_mount_change() {
+            let urgency = if change.is_recovery() {
+                Urgency::Normal
+            } else {
+                Urgency::Critical
+            };
+            (change.headline(), urgency)
+        } else if change.new_state == MountState::Mounted {
+            (
+                format!("{} is available again", change.mount),
+                Urgency::Normal,
+            )
+        } else {
+            (change.headline(), Urgency::Critical)
+        };
+        let mut notification = Notification::new();
+        notification
+            .appname(env!("CARGO_PKG_NAME"))
+            .summary(&summary)
+            .body(&if change.is_mount_change() {
+                format!(
+                    "Changed from {} to {} at {}",
+                    change.old_state, change.new_state, change.timestamp
+                )
+            } else {
+                format!("At {}", change.timestamp)
+            })
+            .icon("drive-harddisk");
+        // macOS has no urgency, but can sound the alarm
+        #[cfg(not(target_os = "macos"))]
+        notification.urgency(urgency);
+        #[cfg(target_os = "macos")]
+        if urgency == Urgency::Critical {
+            notification.sound_name("Basso");
+        }
+        let result = notification.show();
+
+        // Without a session bus there's no desktop to notify, which isn't worth
+        // an error every time a mount changes state
+        if let Err(e) = result {
+            if self.warned.swap(true, Ordering::Relaxed) {
+                debug!("Desktop notification not shown: {}", e);
+            } else {
+                warn!("Desktop notifications unavailable: {}", e);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use super::{Notifier, StateChange};
+use crate::config::{EmailConfig, TlsMode};
+use crate::monitor::MountState;
+use lettre::message::{header::ContentType, Mailbox};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use std::time::Duration;
+
+// Emails a summary when a mount goes down and a recovery message when it comes back
+pub struct Email {
+    from: Mailbox,
+    to: Vec<Mailbox>,
+    transport: SmtpTransport,
+}
+
+impl Email {
+    pub fn new(config: &EmailConfig) -> Result<Self, String> {
+        let parse = |address: &str| {
+            address
+                .parse::<Mailbox>()
+                .map_err(|e| format!("invalid email address {:?}: {}", address, e))
+        };
+        let from = parse(&config.from)?;
+        let to = config
+            .to
+            .iter()
+            .map(|a| parse(a))
+            .collect::<Result<Vec<_>, _>>()?;
+        if to.is_empty() {
+            return Err("no email recipients are configured".to_string());
+        }
+
+        let builder = match config.tls {
+            TlsMode::None => SmtpTransport::builder_dangerous(&config.server).port(25),
+            TlsMode::Starttls => SmtpTransport::starttls_relay(&config.server)
+                .map_err(|e| e.to_string())?
+                .port(587),
+            TlsMode::Tls => SmtpTransport::relay(&config.server).map_err(|e| e.to_string())?,
+        };
+        let mut builder = builder.timeout(Some(Duration::from_secs(30)));
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(Email {
+            from,
+            to,
+            transport: builder.build(),
+        })
+    }
+
+    // Subject and body of the message for a state change
+    fn compose(change: &StateChange) -> (String, String) {
+        // The server and labels, for the lines after the states
+        let mut details = change
+            .server
+            .as_ref()
+            .map(|s| format!("Server: {}\n", s))
+            .unwrap_or_default();
+        if !change.labels.is_empty() {
+            details.push_str(&format!("Labels: {}\n", change.label_pairs().join(", ")));
+        }
+        if !change.is_mount_change() {
+            (
+                format!("[nofus] {}: {}", change.hostname, change.headline()),
+                format!(
+                    "{} on {}.\n\nDetected at: {}\n",
+                    change.headline(),
+                    change.hostname,
+                    change.timestamp
+                ),
+            )
+        } else if change.new_state == MountState::Mounted {
+            (
+                format!("[nofus] {}: {} recovered", change.hostname, change.mount),
+                format!(
+                    "Mount point {} on {} is available again.\n\nRecovered at: {}\nPrevious state: {}\n{}",
+                    change.mount, change.hostname, change.timestamp, change.old_state, details
+                ),
+            )
+        } else {
+            (
+                format!("[nofus] {}: {}", change.hostname, change.headline()),
+                format!(
+                    "Mount point {} on {} is no longer available.\n\nDetected at: {}\nPrevious state: {}\nCurrent state: {}\n{}",
+                    change.mount, change.hostname, change.timestamp, change.old_state, change.new_state, details
+                ),
+            )
+        }
+    }
+}
+
+impl Notifier for Email {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    fn notify(&self, change: &StateChange) -> Result<(), String> {
+        let (subject, body) = Email::compose(change);
+        let mut message = Message::builder()
+            .from(self.from.clone())
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN);
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+        let message = message.body(body).map_err(|e| e.to_string())?;
+        self.transport
+            .send(&message)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,220 @@
+mod desktop;
+mod email;
+mod mqtt;
+mod ntfy;
+mod webhook;
+
+use crate::config::NotificationsConfig;
+use crate::monitor::MountState;
+use crate::usage::Level;
+use log::{debug, error};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+pub use desktop::Desktop;
+pub use email::Email;
+pub use mqtt::Mqtt;
+pub use ntfy::Ntfy;
+pub use webhook::Webhook;
+
+// A mount point changing state, as delivered to notifiers
+#[derive(Debug, Clone, Serialize)]
+pub struct StateChange {
+    pub mount: String,
+    pub old_state: MountState,
+    pub new_state: MountState,
+    pub timestamp: String,
+    pub hostname: String,
+    // For a reminder that the mount is still down, how long it has been down, and
+    // how many escalations it has been through
+    pub down_seconds: Option<u64>,
+    pub escalation: usize,
+    // For a mount filling up, or no longer, rather than changing state, how full it is
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<UsageChange>,
+    // The server the mount comes from, if that can be told
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    // For a whole server going down or coming back, its mounts, with `mount` being the
+    // server itself
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub server_mounts: Vec<String>,
+    // The labels of the mount in the config
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageChange {
+    // What is running out, "disk" or "inodes"
+    pub resource: &'static str,
+    pub level: Level,
+    pub used_percent: f64,
+}
+
+impl StateChange {
+    // A mount simply found mounted, at startup or after being added on reload
+    pub fn is_initial_mount(&self) -> bool {
+        self.old_state == MountState::Unknown && self.new_state == MountState::Mounted
+    }
+
+    // Whether this is a single mount changing state, rather than filling up or a whole
+    // server changing
+    pub fn is_mount_change(&self) -> bool {
+        self.usage.is_none() && self.server_mounts.is_empty()
+    }
+
+    // Whether the mount is back to how it should be
+    pub fn is_recovery(&self) -> bool {
+        match &self.usage {
+            Some(usage) => usage.level == Level::Ok,
+            None => self.new_state == MountState::Mounted,
+        }
+    }
+
+    // The labels as key=value pairs, for notifiers that take text
+    pub fn label_pairs(&self) -> Vec<String> {
+        self.labels
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect()
+    }
+
+    // What happened to the mount for a title, e.g. "/mnt/a is unmounted" or, for a
+    // reminder, "/mnt/a is still unmounted after 2h"
+    pub fn headline(&self) -> String {
+        if let Some(usage) = &self.usage {
+            let full = match usage.resource {
+                "disk" => format!("{} is {:.0}% full", self.mount, usage.used_percent),
+                resource => format!(
+                    "{} has used {:.0}% of its {}",
+                    self.mount, usage.used_percent, resource
+                ),
+            };
+            return match usage.level {
+                Level::Ok => format!("{}, back below its thresholds", full),
+                level => format!("{} ({})", full, level),
+            };
+        }
+        if !self.server_mounts.is_empty() {
+            let count = self.server_mounts.len();
+            return if self.is_recovery() {
+                format!(
+                    "Server {} is back, with all {} of its mounts",
+                    self.mount, count
+                )
+            } else {
+                format!(
+                    "Server {} is down, with all {} of its mounts",
+                    self.mount, count
+                )
+            };
+        }
+        match self.down_seconds {
+            Some(seconds) => format!(
+                "{} is still {} after {}",
+                self.mount,
+                self.new_state,
+                humantime::format_duration(Duration::from_secs(seconds))
+            ),
+            None => format!("{} is {}", self.mount, self.new_state),
+        }
+    }
+}
+
+// Something that can tell the outside world about a state change
+pub trait Notifier: Send {
+    fn name(&self) -> &'static str;
+    fn notify(&self, change: &StateChange) -> Result<(), String>;
+
+    // Most notifiers only care about actual changes, not mounts found mounted
+    fn wants_initial(&self) -> bool {
+        false
+    }
+}
+
+// Delivers state changes to every configured notifier, each on its own task so a
+// slow or retrying one doesn't hold up the others or the monitoring loop
+pub struct Notifications {
+    senders: Vec<UnboundedSender<StateChange>>,
+    // The name of each notifier, in the same order
+    names: Vec<&'static str>,
+}
+
+impl Notifications {
+    pub fn from_config(config: &NotificationsConfig) -> Self {
+        let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+        if let Some(webhook) = &config.webhook {
+            notifiers.push(Box::new(Webhook::new(webhook.clone())));
+        }
+        if let Some(email) = &config.email {
+            match Email::new(email) {
+                Ok(email) => notifiers.push(Box::new(email)),
+                Err(e) => error!("Email notifications disabled: {}", e),
+            }
+        }
+        if let Some(ntfy) = &config.ntfy {
+            notifiers.push(Box::new(Ntfy::new(ntfy.clone())));
+        }
+        if config.desktop {
+            notifiers.push(Box::new(Desktop::default()));
+        }
+        if let Some(mqtt) = &config.mqtt {
+            notifiers.push(Box::new(Mqtt::new(mqtt)));
+        }
+
+        let names = notifiers.iter().map(|n| n.name()).collect();
+        let senders = notifiers
+            .into_iter()
+            .map(|notifier| {
+                let (sender, mut receiver) = mpsc::unbounded_channel::<StateChange>();
+                // Notifiers block on the network, so each gets a blocking task which
+                // exits once the sender is dropped, e.g. on reload
+                tokio::task::spawn_blocking(move || {
+                    while let Some(change) = receiver.blocking_recv() {
+                        if change.is_initial_mount() && !notifier.wants_initial() {
+                            continue;
+                        }
+                        match notifier.notify(&change) {
+                            Ok(()) => {
+                                debug!("Sent {} notification for {}", notifier.name(), change.mount)
+                            }
+                            Err(e) => error!(
+                                "Failed to send {} notification for {}: {}",
+                                notifier.name(),
+                                change.mount,
+                                e
+                            ),
+                        }
+                    }
+                });
+                sender
+            })
+            .collect();
+        Notifications { senders, names }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.senders.is_empty()
+    }
+
+    pub fn names(&self) -> &[&'static str] {
+        &self.names
+    }
+
+    pub fn send(&self, change: StateChange) {
+        for sender in &self.senders {
+            let _ = sender.send(change.clone());
+        }
+    }
+}
+
+// The name of this machine, for telling notifications from different hosts apart
+pub fn hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|h| h.trim().to_string())
+        .unwrap_or_else(|_| "unknown".to_string())
+}
@@ -0,0 +1,170 @@
+use super::{hostname, Notifier, StateChange};
+use crate::config::MqttConfig;
+use log::{debug, warn};
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+use serde_json::json;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Publishes the state of each mount to an MQTT broker, announcing each one to Home
+// Assistant as a binary_sensor the first time it's seen
+pub struct Mqtt {
+    client: Client,
+    stopped: Arc<AtomicBool>,
+    topic_prefix: String,
+    discovery_prefix: Option<String>,
+    hostname: String,
+    announced: Mutex<HashSet<String>>,
+}
+
+impl Mqtt {
+    pub fn new(config: &MqttConfig) -> Self {
+        let hostname = hostname();
+        let topic_prefix = config
+            .topic_prefix
+            .clone()
+            .unwrap_or_else(|| format!("nofus/{}", hostname));
+        let client_id = config
+            .client_id
+            .clone()
+            .unwrap_or_else(|| format!("nofus-{}", hostname));
+        let availability = format!("{}/status", topic_prefix);
+
+        let mut options = MqttOptions::new(client_id, &config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        options.set_last_will(LastWill::new(
+            &availability,
+            "offline",
+            QoS::AtLeastOnce,
+            true,
+        ));
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            options.set_credentials(username, password);
+        }
+        let (client, mut connection) = Client::new(options, 64);
+
+        // Drive the connection, marking nofus online whenever it (re)connects, until
+        // this notifier is dropped
+        let online = client.clone();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stop = Arc::clone(&stopped);
+        thread::spawn(move || {
+            for event in connection.iter() {
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                match event {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        debug!("Connected to MQTT broker");
+                        let _ = online.try_publish(&availability, QoS::AtLeastOnce, true, "online");
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("MQTT connection error: {}", e);
+                        thread::sleep(Duration::from_secs(5));
+                    }
+                }
+            }
+        });
+
+        Mqtt {
+            client,
+            stopped,
+            topic_prefix,
+            discovery_prefix: config.discovery.then(|| config.discovery_prefix.clone()),
+            hostname,
+            announced: Mutex::new(HashSet::new()),
+        }
+    }
+
+    // Turn a mount path into something usable in a topic or entity id
+    fn object_id(mount: &str) -> String {
+        let id: String = mount
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        id.trim_matches('_').to_string()
+    }
+
+    fn state_topic(&self, object_id: &str) -> String {
+        format!("{}/{}/state", self.topic_prefix, object_id)
+    }
+
+    // Publish the Home Assistant discovery config for a mount
+    fn announce(&self, mount: &str, object_id: &str) -> Result<(), String> {
+        let Some(discovery_prefix) = &self.discovery_prefix else {
+            return Ok(());
+        };
+        let unique_id = format!("nofus_{}_{}", Mqtt::object_id(&self.hostname), object_id);
+        let config = json!({
+            "name": mount,
+            "unique_id": unique_id,
+            "object_id": unique_id,
+            "state_topic": self.state_topic(object_id),
+            "availability_topic": format!("{}/status", self.topic_prefix),
+            "device_class": "connectivity",
+            "payload_on": "ON",
+            "payload_off": "OFF",
+            "device": {
+                "identifiers": [format!("nofus_{}", self.hostname)],
+                "name": format!("nofus on {}", self.hostname),
+                "manufacturer": "nofus",
+                "sw_version": env!("CARGO_PKG_VERSION"),
+            },
+        });
+        self.client
+            .publish(
+                format!("{}/binary_sensor/{}/config", discovery_prefix, unique_id),
+                QoS::AtLeastOnce,
+                true,
+                config.to_string(),
+            )
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl Drop for Mqtt {
+    fn drop(&mut self) {
+        // Leave cleanly, rather than having the broker publish the last will
+        self.stopped.store(true, Ordering::Relaxed);
+        let _ = self.client.try_disconnect();
+    }
+}
+
+impl Notifier for Mqtt {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    fn wants_initial(&self) -> bool {
+        true
+    }
+
+    fn notify(&self, change: &StateChange) -> Result<(), String> {
+        // The sensor only shows whether the mount is up
+        if !change.is_mount_change() {
+            return Ok(());
+        }
+        let object_id = Mqtt::object_id(&change.mount);
+        if self.announced.lock().unwrap().insert(object_id.clone()) {
+            self.announce(&change.mount, &object_id)?;
+        }
+
+        let payload = if change.new_state.is_up() {
+            "ON"
+        } else {
+            "OFF"
+        };
+        self.client
+            .publish(
+                self.state_topic(&object_id),
+                QoS::AtLeastOnce,
+                true,
+                payload,
+            )
+            .map_err(|e| e.to_string())
+    }
+}
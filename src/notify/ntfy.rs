@@ -0,0 +1,103 @@
+use super::{Notifier, StateChange};
+use crate::config::NtfyConfig;
+use crate::monitor::MountState;
+use std::time::Duration;
+use ureq::Agent;
+
+// Publishes state changes to an ntfy topic, so they show up as phone pushes
+pub struct Ntfy {
+    config: NtfyConfig,
+    agent: Agent,
+}
+
+impl Ntfy {
+    pub fn new(config: NtfyConfig) -> Self {
+        let agent = Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(10)))
+            .build()
+            .into();
+        Ntfy { config, agent }
+    }
+}
+
+impl Notifier for Ntfy {
+    fn name(&self) -> &'static str {
+        "ntfy"
+    }
+
+    fn notify(&self, change: &StateChange) -> Result<(), String> {
+        let (title, priority, tags) = if !change.is_mount_change() {
+            let (priority, tags) = if change.is_recovery() {
+                (&self.config.recovery_priority, "white_check_mark")
+            } else {
+                (&self.config.priority, "warning")
+            };
+            (change.headline(), priority, tags)
+        } else if change.new_state == MountState::Mounted {
+            (
+                format!("{} recovered", change.mount),
+                &self.config.recovery_priority,
+                "white_check_mark",
+            )
+        } else {
+            (
+                change.headline(),
+                // Escalated outages can't wait for someone to look
+                if change.escalation > 0 {
+                    &self.config.escalation_priority
+                } else {
+                    &self.config.priority
+                },
+                "warning",
+            )
+        };
+        let message = if change.is_mount_change() {
+            let server = change
+                .server
+                .as_ref()
+                .map(|s| format!(" (from {})", s))
+                .unwrap_or_default();
+            format!(
+                "{}{} on {} changed from {} to {} at {}",
+                change.mount,
+                server,
+                change.hostname,
+                change.old_state,
+                change.new_state,
+                change.timestamp
+            )
+        } else {
+            format!(
+                "{} on {} at {}",
+                change.headline(),
+                change.hostname,
+                change.timestamp
+            )
+        };
+
+        // ntfy shows tags that aren't emoji names as they are
+        let tags = [tags.to_string()]
+            .into_iter()
+            .chain(change.label_pairs())
+            .collect::<Vec<_>>()
+            .join(",");
+        let url = format!(
+            "{}/{}",
+            self.config.server.trim_end_matches('/'),
+            self.config.topic
+        );
+        let mut request = self
+            .agent
+            .post(&url)
+            .header("Title", &title)
+            .header("Priority", priority)
+            .header("Tags", &tags);
+        if let Some(token) = &self.config.token {
+            request = request.header("Authorization", &format!("Bearer {}", token));
+        }
+        request
+            .send(&message)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,66 @@
+use super::{Notifier, StateChange};
+use crate::config::WebhookConfig;
+use log::warn;
+use std::thread;
+use std::time::Duration;
+use ureq::Agent;
+
+// Never wait longer than this between retries
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+// POSTs each state change as JSON, retrying with exponential backoff
+pub struct Webhook {
+    config: WebhookConfig,
+    agent: Agent,
+}
+
+impl Webhook {
+    pub fn new(config: WebhookConfig) -> Self {
+        let agent = Agent::config_builder()
+            .timeout_global(Some(Duration::from_secs(10)))
+            .build()
+            .into();
+        Webhook { config, agent }
+    }
+
+    fn post(&self, body: &str) -> Result<(), ureq::Error> {
+        let mut request = self
+            .agent
+            .post(&self.config.url)
+            .header("Content-Type", "application/json");
+        for (name, value) in &self.config.headers {
+            request = request.header(name, value);
+        }
+        request.send(body).map(|_| ())
+    }
+}
+
+impl Notifier for Webhook {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn notify(&self, change: &StateChange) -> Result<(), String> {
+        let body = serde_json::to_string(change).map_err(|e| e.to_string())?;
+        let mut backoff = Duration::from_secs(self.config.backoff_seconds);
+        let mut attempt = 0;
+        loop {
+            match self.post(&body) {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < self.config.retries => {
+                    attempt += 1;
+                    warn!(
+                        "Webhook delivery failed ({}), retry {} of {} in {}s",
+                        e,
+                        attempt,
+                        self.config.retries,
+                        backoff.as_secs()
+                    );
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use serde::Deserialize;
+
+// A mount option a mount is expected to have, written as it appears in the mount
+// table: "hard" to be set, "!soft" to not be, "vers=4.2" for a value, or
+// "rsize>=1048576" and "timeo<=600" for a number at least or at most
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum ExpectedOption {
+    Set(String),
+    Unset(String),
+    Equals(String, String),
+    AtLeast(String, u64),
+    AtMost(String, u64),
+}
+
+impl TryFrom<String> for ExpectedOption {
+    type Error = String;
+
+    fn try_from(spec: String) -> Result<Self, Self::Error> {
+        let invalid = |reason: &str| format!("invalid expected option {:?}: {}", spec, reason);
+        let number = |value: &str| {
+            value
+                .parse::<u64>()
+                .map_err(|_| invalid("only a number can be compared"))
+        };
+        let expected = if let Some((name, value)) = spec.split_once(">=") {
+            ExpectedOption::AtLeast(name.to_string(), number(value)?)
+        } else if let Some((name, value)) = spec.split_once("<=") {
+            ExpectedOption::AtMost(name.to_string(), number(value)?)
+        } else if let Some((name, value)) = spec.split_once('=') {
+            ExpectedOption::Equals(name.to_string(), value.to_string())
+        } else if let Some(name) = spec.strip_prefix('!') {
+            ExpectedOption::Unset(name.to_string())
+        } else {
+            ExpectedOption::Set(spec.clone())
+        };
+        let name = expected.name();
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || "=<>!,".contains(c)) {
+            return Err(invalid("needs an option name"));
+        }
+        Ok(expected)
+    }
+}
+
+impl ExpectedOption {
+    fn name(&self) -> &str {
+        match self {
+            ExpectedOption::Set(name)
+            | ExpectedOption::Unset(name)
+            | ExpectedOption::Equals(name, _)
+            | ExpectedOption::AtLeast(name, _)
+            | ExpectedOption::AtMost(name, _) => name,
+        }
+    }
+
+    // How the options a mount is mounted with differ from this, if they do
+    pub fn drift(&self, options: &[String]) -> Option<String> {
+        let name = self.name();
+        let set = options
+            .iter()
+            .any(|o| o == name || o.split_once('=').is_some_and(|(key, _)| key == name));
+        // The last one given is the one that counts
+        let value = options
+            .iter()
+            .filter_map(|o| o.split_once('='))
+            .filter(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+            .next_back();
+        match self {
+            ExpectedOption::Set(_) => (!set).then(|| format!("{} is not set", name)),
+            ExpectedOption::Unset(_) => set.then(|| format!("{} is set", name)),
+            ExpectedOption::Equals(_, expected) => match value {
+                Some(value) if value == expected => None,
+                Some(value) => Some(format!("{} is {}, expected {}", name, value, expected)),
+                None => Some(format!("{} is not set, expected {}", name, expected)),
+            },
+            ExpectedOption::AtLeast(_, least) => match value.map(str::parse::<u64>) {
+                Some(Ok(value)) if value >= *least => None,
+                Some(_) => Some(format!(
+                    "{} is {}, expected at least {}",
+                    name,
+                    value.unwrap_or_default(),
+                    least
+                )),
+                None => Some(format!("{} is not set, expected at least {}", name, least)),
+            },
+            ExpectedOption::AtMost(_, most) => match value.map(str::parse::<u64>) {
+                Some(Ok(value)) if value <= *most => None,
+                Some(_) => Some(format!(
+                    "{} is {}, expected at most {}",
+                    name,
+                    value.unwrap_or_default(),
+                    most
+                )),
+                None => Some(format!("{} is not set, expected at most {}", name, most)),
+            },
+        }
+    }
+}
+
+// Every way the options differ from what is expected
+pub fn drift(expected: &[ExpectedOption], options: &[String]) -> Vec<String> {
+    expected.iter().filter_map(|e| e.drift(options)).collect()
+}
@@ -0,0 +1,330 @@
+use crate::config::OtlpConfig;
+use crate::monitor::MountState;
+use crate::notify;
+use log::{debug, warn};
+use serde_json::{json, Value};
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use ureq::Agent;
+
+// How long to wait on the collector
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The span kind and status codes OTLP uses
+const SPAN_KIND_INTERNAL: u8 = 1;
+const STATUS_OK: u8 = 1;
+const STATUS_ERROR: u8 = 2;
+
+// Sends spans to an OpenTelemetry collector as they finish, and the mount metrics on
+// a timer, from a thread of its own so a slow collector can't hold up monitoring.
+// The thread sends the metrics one last time and stops once this is dropped.
+pub struct Otlp {
+    spans: Sender<Span>,
+    metrics: Arc<Mutex<Metrics>>,
+}
+
+// A finished unit of work
+pub struct Span {
+    pub name: &'static str,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub attributes: Vec<(&'static str, Attribute)>,
+    // Why it failed, if it did
+    pub error: Option<String>,
+}
+
+pub enum Attribute {
+    String(String),
+    Int(i64),
+    Bool(bool),
+}
+
+#[derive(Default)]
+struct Metrics {
+    // When counting started, and each mount's current state and how many times it has
+    // changed to each state since
+    start: Option<SystemTime>,
+    states: BTreeMap<String, MountState>,
+    servers: BTreeMap<String, String>,
+    labels: BTreeMap<String, BTreeMap<String, String>>,
+    transitions: BTreeMap<(String, String), u64>,
+}
+
+impl Otlp {
+    pub fn new(config: &OtlpConfig) -> Self {
+        let (spans, receiver) = mpsc::channel();
+        let metrics = Arc::new(Mutex::new(Metrics {
+            start: Some(SystemTime::now()),
+            ..Metrics::default()
+        }));
+        let exporter = Exporter::new(config, Arc::clone(&metrics));
+        thread::spawn(move || exporter.run(&receiver));
+        Otlp { spans, metrics }
+    }
+
+    pub fn span(&self, span: Span) {
+        let _ = self.spans.send(span);
+    }
+
+    // Record a mount changing state
+    pub fn transition(&self, path: &str, to: MountState) {
+        let mut metrics = self.metrics.lock().unwrap();
+        *metrics
+            .transitions
+            .entry((path.to_string(), to.to_string()))
+            .or_default() += 1;
+    }
+
+    // Set the state of every monitored mount, forgetting those no longer given
+    pub fn states(&self, states: BTreeMap<String, MountState>) {
+        self.metrics.lock().unwrap().states = states;
+    }
+
+    // Set the server each mount comes from, for those it is known for
+    pub fn servers(&self, servers: BTreeMap<String, String>) {
+        self.metrics.lock().unwrap().servers = servers;
+    }
+
+    // Set the labels of each mount that has any
+    pub fn labels(&self, labels: BTreeMap<String, BTreeMap<String, String>>) {
+        self.metrics.lock().unwrap().labels = labels;
+    }
+}
+
+struct Exporter {
+    endpoint: String,
+    headers: BTreeMap<String, String>,
+    interval: Duration,
+    resource: Value,
+    agent: Agent,
+    metrics: Arc<Mutex<Metrics>>,
+    // Whether the last export failed, to only warn when that starts
+    failing: bool,
+}
+
+impl Exporter {
+    fn new(config: &OtlpConfig, metrics: Arc<Mutex<Metrics>>) -> Self {
+        let mut resource = BTreeMap::from([
+            ("service.name".to_string(), "nofus".to_string()),
+            (
+                "service.version".to_string(),
+                env!("CARGO_PKG_VERSION").to_string(),
+            ),
+            ("host.name".to_string(), notify::hostname()),
+        ]);
+        resource.extend(config.resource_attributes.clone());
+        let attributes: Vec<(&str, Attribute)> = resource
+            .iter()
+            .map(|(key, value)| (key.as_str(), Attribute::String(value.clone())))
+            .collect();
+        Exporter {
+            endpoint: config.endpoint.trim_end_matches('/').to_string(),
+            headers: config.headers.clone(),
+            interval: config.metrics_interval(),
+            resource: json!({ "attributes": attributes_json(&attributes) }),
+            agent: Agent::config_builder()
+                .timeout_global(Some(EXPORT_TIMEOUT))
+                .build()
+                .into(),
+            metrics,
+            failing: false,
+        }
+    }
+
+    fn run(mut self, spans: &Receiver<Span>) {
+        let mut next_metrics = Instant::now() + self.interval;
+        loop {
+            let wait = next_metrics.saturating_duration_since(Instant::now());
+            match spans.recv_timeout(wait) {
+                // Send whatever else has finished meanwhile along with it
+                Ok(span) => {
+                    let batch: Vec<Span> = [span].into_iter().chain(spans.try_iter()).collect();
+                    self.send_spans(&batch);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.send_metrics();
+                    return;
+                }
+            }
+            if Instant::now() >= next_metrics {
+                self.send_metrics();
+                next_metrics += self.interval;
+            }
+        }
+    }
+
+    fn send_spans(&mut self, spans: &[Span]) {
+        let spans: Vec<Value> = spans
+            .iter()
+            .map(|span| {
+                let status = match &span.error {
+                    Some(error) => json!({ "code": STATUS_ERROR, "message": error }),
+                    None => json!({ "code": STATUS_OK }),
+                };
+                json!({
+                    "traceId": format!("{:016x}{:016x}", random_id(), random_id()),
+                    "spanId": format!("{:016x}", random_id()),
+                    "name": span.name,
+                    "kind": SPAN_KIND_INTERNAL,
+                    "startTimeUnixNano": nanos(span.start),
+                    "endTimeUnixNano": nanos(span.end),
+                    "attributes": attributes_json(&span.attributes),
+                    "status": status,
+                })
+            })
+            .collect();
+        let body = json!({
+            "resourceSpans": [{
+                "resource": self.resource,
+                "scopeSpans": [{ "scope": scope(), "spans": spans }],
+            }],
+        });
+        self.post("/v1/traces", &body);
+    }
+
+    fn send_metrics(&mut self) {
+        let now = nanos(SystemTime::now());
+        let body = {
+            let metrics = self.metrics.lock().unwrap();
+            let start = nanos(metrics.start.unwrap_or(UNIX_EPOCH));
+            let up: Vec<Value> = metrics
+                .states
+                .iter()
+                .map(|(path, state)| {
+                    let mut attributes = vec![
+                        ("mount", Attribute::String(path.clone())),
+                        ("state", Attribute::String(state.to_string())),
+                    ];
+                    if let Some(server) = metrics.servers.get(path) {
+                        attributes.push(("server", Attribute::String(server.clone())));
+                    }
+                    attributes.extend(labels(&metrics, path));
+                    json!({
+                        "attributes": attributes_json(&attributes),
+                        "timeUnixNano": now,
+                        "asInt": (state.is_up() as u8).to_string(),
+                    })
+                })
+                .collect();
+            let transitions: Vec<Value> = metrics
+                .transitions
+                .iter()
+                .map(|((path, to), count)| {
+                    let mut attributes = vec![
+                        ("mount", Attribute::String(path.clone())),
+                        ("state", Attribute::String(to.clone())),
+                    ];
+                    attributes.extend(labels(&metrics, path));
+                    json!({
+                        "attributes": attributes_json(&attributes),
+                        "startTimeUnixNano": start,
+                        "timeUnixNano": now,
+                        "asInt": count.to_string(),
+                    })
+                })
+                .collect();
+            json!({
+                "resourceMetrics": [{
+                    "resource": self.resource,
+                    "scopeMetrics": [{
+                        "scope": scope(),
+                        "metrics": [
+                            {
+                                "name": "nofus.mount.up",
+                                "description": "Whether the mount is mounted, if only slowly, with its state",
+                                "unit": "1",
+                                "gauge": { "dataPoints": up },
+                            },
+                            {
+                                "name": "nofus.mount.transitions",
+                                "description": "How many times the mount has changed to each state",
+                                "unit": "1",
+                                // Cumulative
+                                "sum": {
+                                    "aggregationTemporality": 2,
+                                    "isMonotonic": true,
+                                    "dataPoints": transitions,
+                                },
+                            },
+                        ],
+                    }],
+                }],
+            })
+        };
+        self.post("/v1/metrics", &body);
+    }
+
+    fn post(&mut self, path: &str, body: &Value) {
+        let url = format!("{}{}", self.endpoint, path);
+        let mut request = self
+            .agent
+            .post(&url)
+            .header("Content-Type", "application/json");
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        match request.send(body.to_string()) {
+            Ok(_) => {
+                debug!("Sent telemetry to {}", url);
+                self.failing = false;
+            }
+            Err(e) if !self.failing => {
+                warn!("Unable to send telemetry to {}: {}", url, e);
+                self.failing = true;
+            }
+            Err(e) => debug!("Unable to send telemetry to {}: {}", url, e),
+        }
+    }
+}
+
+fn scope() -> Value {
+    json!({ "name": "nofus", "version": env!("CARGO_PKG_VERSION") })
+}
+
+// A mount's labels, as attributes of its data points
+fn labels<'a>(metrics: &'a Metrics, path: &str) -> impl Iterator<Item = (&'a str, Attribute)> {
+    metrics
+        .labels
+        .get(path)
+        .into_iter()
+        .flatten()
+        .map(|(name, value)| (name.as_str(), Attribute::String(value.clone())))
+}
+
+fn attributes_json(attributes: &[(&str, Attribute)]) -> Vec<Value> {
+    attributes
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Attribute::String(s) => json!({ "stringValue": s }),
+                // 64-bit integers go as strings in OTLP's JSON
+                Attribute::Int(i) => json!({ "intValue": i.to_string() }),
+                Attribute::Bool(b) => json!({ "boolValue": b }),
+            };
+            json!({ "key": key, "value": value })
+        })
+        .collect()
+}
+
+fn nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+// A random id for a trace or span, which only has to be unlikely to repeat
+fn random_id() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    hasher.write_u128(now.as_nanos());
+    hasher.finish()
+}
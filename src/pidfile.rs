@@ -0,0 +1,83 @@
+use crate::config;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+// Where the daemon records its pid, unless told otherwise. The BSDs have no /run.
+#[cfg(target_os = "linux")]
+pub const DEFAULT_PID_FILE: &str = "/run/nofus.pid";
+#[cfg(not(target_os = "linux"))]
+pub const DEFAULT_PID_FILE: &str = "/var/run/nofus.pid";
+
+// The pid file path from the config, or the default, which for a user instance is in
+// $XDG_RUNTIME_DIR
+pub fn pid_file_path(configured: Option<&str>) -> PathBuf {
+    configured
+        .map(PathBuf::from)
+        .or_else(|| config::runtime_path("nofus.pid"))
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_PID_FILE))
+}
+
+// A pid file holding an exclusive lock for as long as the daemon runs, so a second
+// copy can tell it isn't alone. The lock goes with the process, however it ends.
+pub struct PidFile {
+    path: PathBuf,
+    // Kept open to keep the lock
+    _file: File,
+}
+
+// Why a pid file couldn't be taken
+pub enum LockError {
+    // Another process holds it, with the pid it wrote if that could be read
+    Running(Option<u32>),
+    Io(io::Error),
+}
+
+impl PidFile {
+    // Take the lock and write our pid, failing if another process has it
+    pub fn lock(path: &Path) -> Result<Self, LockError> {
+        let mut file = loop {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)
+                .map_err(LockError::Io)?;
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+                let e = io::Error::last_os_error();
+                if e.raw_os_error() != Some(libc::EWOULDBLOCK) {
+                    return Err(LockError::Io(e));
+                }
+                let mut pid = String::new();
+                let _ = file.read_to_string(&mut pid);
+                return Err(LockError::Running(pid.trim().parse().ok()));
+            }
+            // The last holder may have removed the file between our opening and
+            // locking it, leaving us a lock no one else will see
+            let same = |locked: &fs::Metadata| {
+                fs::metadata(path).is_ok_and(|m| m.dev() == locked.dev() && m.ino() == locked.ino())
+            };
+            if file.metadata().is_ok_and(|m| same(&m)) {
+                break file;
+            }
+        };
+        file.set_len(0)
+            .and_then(|_| file.rewind())
+            .and_then(|_| writeln!(file, "{}", std::process::id()))
+            .map_err(LockError::Io)?;
+        Ok(PidFile {
+            path: path.to_path_buf(),
+            _file: file,
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Remove it while still holding the lock, so no one else's is removed
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,265 @@
+use super::{config_dir, MountSource};
+use log::debug;
+use proc_mounts::MountInfo;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+// The kernel's mount table, with a kqueue that is told of every mount or unmount
+// anywhere. OpenBSD has no kqueue filter for that, so there changes are only found
+// by the periodic checks.
+pub struct Kqueue(OwnedFd);
+
+impl Kqueue {
+    pub fn open() -> io::Result<Self> {
+        let kqueue = kqueue()?;
+        #[cfg(not(target_os = "openbsd"))]
+        register(&kqueue, 0, libc::EVFILT_FS, 0)?;
+        Ok(Kqueue(kqueue))
+    }
+}
+
+impl MountSource for Kqueue {
+    fn read(&mut self) -> io::Result<BTreeMap<PathBuf, String>> {
+        Ok(mount_entries()?
+            .into_iter()
+            .map(|m| (m.dest, m.fstype))
+            .collect())
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        drain(&self.0).map(|_| ())
+    }
+}
+
+impl AsRawFd for Kqueue {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+// Every mount, as getmntinfo(3) gives them. That keeps what it found in a buffer of
+// its own that probes on several threads at once would trample, so getfsstat(2),
+// which it wraps, is asked instead. MNT_NOWAIT takes what the kernel has rather than
+// asking each filesystem, which would hang on an NFS server that is down.
+pub fn mount_entries() -> io::Result<Vec<MountInfo>> {
+    let count = unsafe { libc::getfsstat(ptr::null_mut(), 0, libc::MNT_NOWAIT) };
+    if count < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Room for a few more, should any be mounted in between
+    let mut mounts: Vec<libc::statfs> = Vec::with_capacity(count as usize + 8);
+    let size = mounts.capacity() * mem::size_of::<libc::statfs>();
+    let count = unsafe { libc::getfsstat(mounts.as_mut_ptr(), size as _, libc::MNT_NOWAIT) };
+    if count < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    unsafe { mounts.set_len(count as usize) };
+    Ok(mounts.iter().map(entry).collect())
+}
+
+fn entry(mount: &libc::statfs) -> MountInfo {
+    // The flags are 64 bits on FreeBSD, but 32 on OpenBSD
+    #[allow(clippy::unnecessary_cast)]
+    let flags = mount.f_flags as u64;
+    let set = |flag: libc::c_int| flags & flag as u64 != 0;
+    let mut options = vec![if set(libc::MNT_RDONLY) { "ro" } else { "rw" }.to_string()];
+    for (flag, option) in [
+        (libc::MNT_NOEXEC, "noexec"),
+        (libc::MNT_NOSUID, "nosuid"),
+        (libc::MNT_SYNCHRONOUS, "sync"),
+    ] {
+        if set(flag) {
+            options.push(option.to_string());
+        }
+    }
+    MountInfo {
+        source: PathBuf::from(text(&mount.f_mntfromname)),
+        dest: PathBuf::from(text(&mount.f_mntonname)),
+        fstype: text(&mount.f_fstypename).to_string_lossy().into_owned(),
+        options,
+        dump: 0,
+        pass: 0,
+    }
+}
+
+// A string statfs keeps in a field of fixed size, padded with NULs
+fn text(field: &[libc::c_char]) -> OsString {
+    let bytes = field
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as u8)
+        .collect();
+    OsString::from_vec(bytes)
+}
+
+// Mount namespaces are Linux's alone
+pub fn enter_namespace(_namespace: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "mount namespaces are only on Linux",
+    ))
+}
+
+// Watches for the config file being written or replaced: the file itself for
+// writes, and its directory for a new file renamed over it, as editors commonly
+// save. Either only counts once the file is found to differ from before.
+pub struct ConfigWatcher {
+    kqueue: OwnedFd,
+    path: PathBuf,
+    // Kept open for as long as they are watched
+    _dir: Option<File>,
+    file: Option<File>,
+    seen: Option<Stamp>,
+}
+
+// What tells one version of the file from another: device, inode, modification time
+// and size
+type Stamp = (u64, u64, i64, i64, u64);
+
+impl ConfigWatcher {
+    pub fn new(config_path: &Path) -> io::Result<Self> {
+        let kqueue = kqueue()?;
+        let dir = watch(&kqueue, config_dir(config_path), libc::NOTE_WRITE);
+        let mut watcher = ConfigWatcher {
+            kqueue,
+            path: config_path.to_path_buf(),
+            _dir: dir,
+            file: None,
+            seen: stamp(config_path),
+        };
+        watcher.watch_file();
+        Ok(watcher)
+    }
+
+    // Watch the file at the path, which after a rename is another one than before
+    fn watch_file(&mut self) {
+        // Closing the old one drops its watch
+        self.file = None;
+        self.file = watch(
+            &self.kqueue,
+            &self.path,
+            libc::NOTE_WRITE
+                | libc::NOTE_EXTEND
+                | libc::NOTE_ATTRIB
+                | libc::NOTE_DELETE
+                | libc::NOTE_RENAME,
+        );
+    }
+
+    // Process pending kqueue events, returning whether the config file may have
+    // changed
+    pub fn read_events(&mut self) -> io::Result<bool> {
+        if drain(&self.kqueue)? == 0 {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.watch_file();
+        let stamp = stamp(&self.path);
+        if stamp == self.seen {
+            return Ok(false);
+        }
+        debug!("Config file changed on disk");
+        self.seen = stamp;
+        Ok(true)
+    }
+}
+
+impl AsRawFd for ConfigWatcher {
+    fn as_raw_fd(&self) -> RawFd {
+        self.kqueue.as_raw_fd()
+    }
+}
+
+fn stamp(path: &Path) -> Option<Stamp> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((
+        metadata.dev(),
+        metadata.ino(),
+        metadata.mtime(),
+        metadata.mtime_nsec(),
+        metadata.size(),
+    ))
+}
+
+fn kqueue() -> io::Result<OwnedFd> {
+    let kqueue = unsafe { libc::kqueue() };
+    if kqueue < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(kqueue) })
+}
+
+// Open a file or directory to have the kqueue told of the changes to it
+fn watch(kqueue: &OwnedFd, path: &Path, changes: u32) -> Option<File> {
+    let file = File::open(path).ok()?;
+    match register(
+        kqueue,
+        file.as_raw_fd() as usize,
+        libc::EVFILT_VNODE,
+        changes,
+    ) {
+        Ok(()) => Some(file),
+        Err(e) => {
+            debug!("Unable to watch {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn register(kqueue: &OwnedFd, ident: usize, filter: i16, flags: u32) -> io::Result<()> {
+    let mut change: libc::kevent = unsafe { mem::zeroed() };
+    change.ident = ident as _;
+    change.filter = filter as _;
+    change.flags = libc::EV_ADD | libc::EV_CLEAR;
+    change.fflags = flags;
+    let result = unsafe {
+        libc::kevent(
+            kqueue.as_raw_fd(),
+            &change,
+            1,
+            ptr::null_mut(),
+            0,
+            ptr::null(),
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Take every event waiting on the kqueue, returning how many there were
+fn drain(kqueue: &OwnedFd) -> io::Result<usize> {
+    let mut events: [libc::kevent; 8] = unsafe { mem::zeroed() };
+    let now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    let mut taken = 0;
+    loop {
+        let count = unsafe {
+            libc::kevent(
+                kqueue.as_raw_fd(),
+                ptr::null(),
+                0,
+                events.as_mut_ptr(),
+                events.len() as _,
+                &now,
+            )
+        };
+        if count < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        taken += count as usize;
+        if (count as usize) < events.len() {
+            return Ok(taken);
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use super::{config_dir, MountSource};
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use log::{debug, warn};
+use proc_mounts::{MountInfo, MountIter};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, Read, Seek};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+
+// The kernel's mount table, which signals every mount or unmount anywhere by making
+// /proc/self/mountinfo pollable for priority events
+pub struct MountInfoFile(File);
+
+impl MountInfoFile {
+    pub fn open() -> io::Result<Self> {
+        Ok(MountInfoFile(File::open("/proc/self/mountinfo")?))
+    }
+}
+
+impl MountSource for MountInfoFile {
+    fn read(&mut self) -> io::Result<BTreeMap<PathBuf, String>> {
+        let mut content = String::new();
+        self.0.rewind()?;
+        self.0.read_to_string(&mut content)?;
+        // The mount point is the fifth field of each line, and the filesystem type
+        // follows the "-" that ends the optional fields
+        Ok(content
+            .lines()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split(' ').collect();
+                let separator = fields.iter().skip(6).position(|f| *f == "-")? + 6;
+                Some((
+                    unescape(fields.get(4)?),
+                    fields.get(separator + 1)?.to_string(),
+                ))
+            })
+            .collect())
+    }
+}
+
+impl AsRawFd for MountInfoFile {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+// Undo the octal escapes mountinfo uses for spaces and other special characters
+fn unescape(field: &str) -> PathBuf {
+    let bytes = field.as_bytes();
+    let mut path = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 4)
+            .and_then(|octal| u8::from_str_radix(std::str::from_utf8(octal).ok()?, 8).ok());
+        match escaped {
+            Some(byte) if bytes[i] == b'\\' => {
+                path.push(byte);
+                i += 4;
+            }
+            _ => {
+                path.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(OsString::from_vec(path))
+}
+
+// The mount table as this thread sees it, which after entering another namespace
+// isn't the one /proc/mounts gives, as that follows the process' first thread
+pub fn mount_entries() -> io::Result<Vec<MountInfo>> {
+    let mounts =
+        MountIter::new_from_file("/proc/thread-self/mounts").or_else(|_| MountIter::new())?;
+    Ok(mounts.filter_map(Result::ok).collect())
+}
+
+// Move the calling thread into a mount namespace, for good, which also takes it to
+// the namespace's root. Probes are made on a thread of their own for that.
+pub fn enter_namespace(namespace: &Path) -> io::Result<()> {
+    let file = File::open(namespace)?;
+    // A thread has to stop sharing its root and working directory with the others
+    // to be let into another mount namespace
+    if unsafe { libc::unshare(libc::CLONE_FS) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { libc::setns(file.as_raw_fd(), libc::CLONE_NEWNS) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Watches for the config file being written or replaced. The directory is watched
+// since editors commonly save by renaming a new file over the old one.
+pub struct ConfigWatcher {
+    inotify: Inotify,
+    watch: Option<(WatchDescriptor, OsString)>,
+    buffer: [u8; 4096],
+}
+
+impl ConfigWatcher {
+    pub fn new(config_path: &Path) -> io::Result<Self> {
+        let inotify = Inotify::init()?;
+        let mut watch = None;
+        if let Some(name) = config_path.file_name() {
+            let dir = config_dir(config_path);
+            match inotify
+                .watches()
+                .add(dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)
+            {
+                Ok(wd) => watch = Some((wd, name.to_os_string())),
+                Err(e) => warn!("Unable to watch {}: {}", dir.display(), e),
+            }
+        }
+        Ok(ConfigWatcher {
+            inotify,
+            watch,
+            buffer: [0; 4096],
+        })
+    }
+
+    // Process pending inotify events, returning whether the config file may have changed
+    pub fn read_events(&mut self) -> io::Result<bool> {
+        let mut config_changed = false;
+        for event in self.inotify.read_events(&mut self.buffer)? {
+            if event.mask.contains(EventMask::Q_OVERFLOW) {
+                // Events were lost, so assume the worst and re-read everything
+                warn!("inotify event queue overflowed, forcing a full re-check");
+                config_changed = true;
+                continue;
+            }
+            let Some((wd, name)) = &self.watch else {
+                continue;
+            };
+            if *wd != event.wd {
+                continue;
+            }
+            if event.mask.contains(EventMask::IGNORED) {
+                warn!("Config directory is gone, no longer watching for changes");
+                self.watch = None;
+            } else if event.name == Some(name.as_os_str()) {
+                debug!("Config file changed on disk");
+                config_changed = true;
+            }
+        }
+        Ok(config_changed)
+    }
+}
+
+impl AsRawFd for ConfigWatcher {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inotify.as_raw_fd()
+    }
+}
@@ -0,0 +1,53 @@
+// What differs between the kernels nofus runs on: where the mount table comes from
+// and how it tells of mounts coming and going, how the config file is watched, and
+// mount namespaces
+#[cfg(any(target_os = "freebsd", target_os = "macos", target_os = "openbsd"))]
+mod bsd;
+#[cfg(target_os = "linux")]
+mod linux;
+
+use std::collections::BTreeMap;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use tokio::io::Interest;
+
+#[cfg(any(target_os = "freebsd", target_os = "macos", target_os = "openbsd"))]
+pub use bsd::{enter_namespace, mount_entries, ConfigWatcher, Kqueue as NativeSource};
+#[cfg(target_os = "linux")]
+pub use linux::{enter_namespace, mount_entries, ConfigWatcher, MountInfoFile as NativeSource};
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "macos",
+    target_os = "openbsd"
+)))]
+compile_error!("nofus runs on Linux, FreeBSD, OpenBSD and macOS");
+
+// The mount table, and a descriptor that is ready once a mount comes or goes
+pub trait MountSource: AsRawFd + Send {
+    // Each mount point and its filesystem type
+    fn read(&mut self) -> io::Result<BTreeMap<PathBuf, String>>;
+
+    // Take whatever made the descriptor ready, before reading the table again
+    fn clear(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// What the descriptor of a mount source is ready for on a change: mountinfo has
+// priority data, a kqueue something to read
+#[cfg(target_os = "linux")]
+pub const SOURCE_INTEREST: Interest = Interest::PRIORITY;
+#[cfg(not(target_os = "linux"))]
+pub const SOURCE_INTEREST: Interest = Interest::READABLE;
+
+// The directory the config file is in, which for a bare file name such as -c
+// config.yml is the working directory
+fn config_dir(config_path: &Path) -> &Path {
+    match config_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
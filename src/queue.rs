@@ -0,0 +1,426 @@
+use crate::command::run_command_with_exit_code;
+use crate::config::{CommandLine, OnError, RunAs};
+use crate::history::History;
+use crate::monitor::CommandOutcome;
+use crate::otlp::{Attribute, Otlp, Span};
+use log::{debug, error, info, warn};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+// The scopes of the global and lifecycle commands, which can't clash with a mount
+// point's path
+pub const GLOBAL: &str = "global";
+pub const LIFECYCLE: &str = "lifecycle";
+
+// Commands waiting to be run, and what they are run for
+pub struct Job {
+    // The mount point, group or global commands the job acts for
+    pub scope: String,
+    // The state change it was queued for
+    pub from: String,
+    pub to: String,
+    // The mount points whose last command this becomes
+    pub paths: Vec<String>,
+    pub commands: Vec<CommandLine>,
+    pub on_error: OnError,
+    pub env: Vec<(&'static str, String)>,
+    pub run_as: Option<RunAs>,
+    pub timeout: Duration,
+    pub retries: u32,
+    pub backoff: Duration,
+    // How long after this command was last queued it is skipped, and what counts as
+    // the same command
+    pub cooldown: Duration,
+    pub hook: String,
+}
+
+#[derive(Default)]
+struct State {
+    queued: VecDeque<Job>,
+    // Whether a job is being run, and for which mount points
+    busy: bool,
+    running: Vec<String>,
+    last_commands: HashMap<String, CommandOutcome>,
+    // When each hook was last queued, for cooldowns
+    last_queued: HashMap<String, Instant>,
+    // Whether failures are kept to be taken, and the first since it last was
+    fail_fast: bool,
+    failure: Option<String>,
+    recorders: Recorders,
+}
+
+// Where each command run is recorded, if anywhere
+#[derive(Clone, Default)]
+struct Recorders {
+    history: Option<Arc<History>>,
+    otlp: Option<Arc<Otlp>>,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    wakeup: Condvar,
+}
+
+// Runs commands one at a time on a worker thread, in the order they were queued, so
+// hooks never overlap and a slow one doesn't hold up monitoring
+pub struct CommandQueue {
+    shared: Arc<Shared>,
+}
+
+impl CommandQueue {
+    pub fn new() -> Self {
+        let shared = Arc::<Shared>::default();
+        let worker = Arc::clone(&shared);
+        thread::spawn(move || work(&worker));
+        CommandQueue { shared }
+    }
+
+    // Queue a job behind any others. With `cancel_reverted`, jobs for the same scope
+    // that haven't started are dropped in its favour, along with the job itself if it
+    // only returns to where they started.
+    pub fn push(&self, job: Job, cancel_reverted: bool) {
+        let mut state = self.shared.state.lock().unwrap();
+        let now = Instant::now();
+        if let Some(last) = state.last_queued.get(&job.hook) {
+            if now < *last + job.cooldown {
+                info!(
+                    event = "command_cooldown";
+                    "Skipping command for {}, it last ran {}s ago", job.scope, (now - *last).as_secs()
+                );
+                return;
+            }
+        }
+        if cancel_reverted {
+            let first = state.queued.iter().find(|j| j.scope == job.scope);
+            if let Some(from) = first.map(|j| j.from.clone()) {
+                state.queued.retain(|j| j.scope != job.scope);
+                info!(
+                    event = "command_cancelled"; "Cancelled queued commands for {}, now {}",
+                    job.scope, job.to
+                );
+                // Nothing runs, so nothing starts a cooldown
+                if from == job.to {
+                    return;
+                }
+            }
+        }
+        state.last_queued.insert(job.hook.clone(), now);
+        state.queued.push_back(job);
+        self.shared.wakeup.notify_all();
+    }
+
+    // Wait up to the timeout for every queued command to have run, dropping those
+    // that haven't started if it runs out. Returns whether they all ran.
+    pub fn drain(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.state.lock().unwrap();
+        while state.busy || !state.queued.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                state.queued.clear();
+                return false;
+            }
+            state = self
+                .shared
+                .wakeup
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+        true
+    }
+
+    // How many commands affecting the mount point are waiting or running
+    pub fn pending(&self, path: &str) -> usize {
+        let state = self.shared.state.lock().unwrap();
+        let running = state.running.iter().any(|p| p == path) as usize;
+        let queued = state
+            .queued
+            .iter()
+            .filter(|j| j.paths.iter().any(|p| p == path));
+        running + queued.count()
+    }
+
+    // The outcome of the last command that finished for the mount point
+    pub fn last_command(&self, path: &str) -> Option<CommandOutcome> {
+        let state = self.shared.state.lock().unwrap();
+        state.last_commands.get(path).cloned()
+    }
+
+    // Remember a mount point's last command from an earlier run, unless one has run
+    // since
+    pub fn restore(&self, path: &str, outcome: CommandOutcome) {
+        let mut state = self.shared.state.lock().unwrap();
+        state
+            .last_commands
+            .entry(path.to_string())
+            .or_insert(outcome);
+    }
+
+    // Record the commands run from now on in the history, or stop recording them
+    pub fn set_history(&self, history: Option<Arc<History>>) {
+        self.shared.state.lock().unwrap().recorders.history = history;
+    }
+
+    // Send a span for each command run from now on, or stop sending them
+    pub fn set_otlp(&self, otlp: Option<Arc<Otlp>>) {
+        self.shared.state.lock().unwrap().recorders.otlp = otlp;
+    }
+
+    // Keep the first job to fail from now on, for the daemon to exit over
+    pub fn set_fail_fast(&self, fail_fast: bool) {
+        self.shared.state.lock().unwrap().fail_fast = fail_fast;
+    }
+
+    // Take what failed first since last asked, if anything did
+    pub fn take_failure(&self) -> Option<String> {
+        self.shared.state.lock().unwrap().failure.take()
+    }
+
+    // Forget the last commands of mount points that are no longer monitored
+    pub fn retain(&self, monitored: impl Fn(&str) -> bool) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.last_commands.retain(|path, _| monitored(path));
+    }
+}
+
+impl Default for CommandQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn work(shared: &Shared) {
+    loop {
+        let (job, recorders) = {
+            let mut state = shared.state.lock().unwrap();
+            loop {
+                if let Some(job) = state.queued.pop_front() {
+                    state.busy = true;
+                    state.running = job.paths.clone();
+                    break (job, state.recorders.clone());
+                }
+                state = shared.wakeup.wait(state).unwrap();
+            }
+        };
+        let outcome = run(&job, &recorders);
+        let mut state = shared.state.lock().unwrap();
+        state.busy = false;
+        state.running.clear();
+        if let (Err(e), true) = (&outcome.result, state.fail_fast) {
+            let failure = format!("Commands for {} failed: {}", job.scope, e);
+            state.failure.get_or_insert(failure);
+        }
+        for path in job.paths {
+            state.last_commands.insert(path, outcome.clone());
+        }
+        shared.wakeup.notify_all();
+    }
+}
+
+// Run a job's commands in order, going on past failures only if asked to
+fn run(job: &Job, recorders: &Recorders) -> CommandOutcome {
+    let mut failures = Vec::new();
+    for cmd in &job.commands {
+        if let Err(e) = run_one(job, cmd, recorders) {
+            // Say which command failed when there is more than one
+            failures.push(if job.commands.len() > 1 {
+                format!("{}: {}", cmd, e)
+            } else {
+                e
+            });
+            if job.on_error == OnError::Stop {
+                break;
+            }
+        }
+    }
+    // Shown the way a shell would run them, by whether a failure stops the rest
+    let separator = match job.on_error {
+        OnError::Stop => " && ",
+        OnError::Continue => "; ",
+    };
+    let commands: Vec<String> = job.commands.iter().map(ToString::to_string).collect();
+    CommandOutcome {
+        command: commands.join(separator),
+        at: SystemTime::now(),
+        result: if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures.join("\n"))
+        },
+    }
+}
+
+// Run a single command, retrying it as configured if it fails
+fn run_one(job: &Job, cmd: &CommandLine, recorders: &Recorders) -> Result<(), String> {
+    let mut delay = job.backoff;
+    let mut attempt = 0;
+    loop {
+        debug!(event = "command", command:% = cmd; "Running command: {}", cmd);
+        let started = Instant::now();
+        let (result, exit_code) =
+            run_command_with_exit_code(cmd, &job.env, job.timeout, job.run_as.as_ref());
+        recorders.record(job, cmd, exit_code, started.elapsed(), &result);
+        let Err(e) = &result else {
+            return result;
+        };
+        if attempt == job.retries {
+            error!(event = "command", command:% = cmd; "{}", e);
+            return result;
+        }
+        attempt += 1;
+        warn!(
+            event = "command", command:% = cmd;
+            "{}, retrying in {}s ({} of {})", e, delay.as_secs(), attempt, job.retries
+        );
+        thread::sleep(delay);
+        delay *= 2;
+    }
+}
+
+impl Recorders {
+    fn record(
+        &self,
+        job: &Job,
+        cmd: &CommandLine,
+        exit_code: Option<i32>,
+        took: Duration,
+        result: &Result<(), String>,
+    ) {
+        let command = cmd.to_string();
+        if let Some(history) = &self.history {
+            history.command(&job.scope, &command, exit_code, took, result);
+        }
+        if let Some(otlp) = &self.otlp {
+            let end = SystemTime::now();
+            let mut attributes = vec![
+                ("nofus.scope", Attribute::String(job.scope.clone())),
+                ("nofus.hook", Attribute::String(job.hook.clone())),
+                ("process.command_line", Attribute::String(command)),
+            ];
+            if let Some(code) = exit_code {
+                attributes.push(("process.exit_code", Attribute::Int(code.into())));
+            }
+            otlp.span(Span {
+                name: "command",
+                start: end.checked_sub(took).unwrap_or(end),
+                end,
+                attributes,
+                error: result.clone().err(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A queue without a worker, so whatever is pushed stays queued
+    fn idle() -> CommandQueue {
+        CommandQueue {
+            shared: Arc::default(),
+        }
+    }
+
+    fn job(scope: &str, from: &str, to: &str, command: &str) -> Job {
+        Job {
+            scope: scope.to_string(),
+            from: from.to_string(),
+            to: to.to_string(),
+            paths: vec![scope.to_string()],
+            commands: vec![CommandLine::Shell(command.to_string())],
+            on_error: OnError::Stop,
+            env: Vec::new(),
+            run_as: None,
+            timeout: Duration::from_secs(5),
+            retries: 0,
+            backoff: Duration::ZERO,
+            cooldown: Duration::ZERO,
+            hook: format!("{}:{}", scope, to),
+        }
+    }
+
+    fn queued(queue: &CommandQueue) -> Vec<(String, String)> {
+        let state = queue.shared.state.lock().unwrap();
+        let jobs = state.queued.iter();
+        jobs.map(|j| (j.scope.clone(), j.to.clone())).collect()
+    }
+
+    #[test]
+    fn reverting_cancels_both_jobs() {
+        let queue = idle();
+        queue.push(job("/mnt/a", "mounted", "unmounted", "down"), true);
+        queue.push(job("/mnt/b", "mounted", "unmounted", "down"), true);
+        queue.push(job("/mnt/a", "unmounted", "mounted", "up"), true);
+        assert_eq!(
+            queued(&queue),
+            [("/mnt/b".to_string(), "unmounted".to_string())]
+        );
+        assert_eq!(queue.pending("/mnt/a"), 0);
+    }
+
+    #[test]
+    fn moving_on_replaces_the_queued_job() {
+        let queue = idle();
+        queue.push(job(GLOBAL, "unknown", "unmounted", "down"), true);
+        queue.push(job(GLOBAL, "unmounted", "mounted", "up"), true);
+        assert_eq!(
+            queued(&queue),
+            [(GLOBAL.to_string(), "mounted".to_string())]
+        );
+    }
+
+    #[test]
+    fn without_cancelling_every_job_runs() {
+        let queue = idle();
+        queue.push(job("/mnt/a", "mounted", "unmounted", "down"), false);
+        queue.push(job("/mnt/a", "unmounted", "mounted", "up"), false);
+        assert_eq!(queue.pending("/mnt/a"), 2);
+    }
+
+    #[test]
+    fn worker_runs_jobs_and_records_the_outcome() {
+        let queue = CommandQueue::new();
+        queue.push(job("/mnt/a", "mounted", "unmounted", "true"), false);
+        queue.push(job("/mnt/b", "mounted", "unmounted", "false"), false);
+        assert!(queue.drain(Duration::from_secs(10)));
+        assert_eq!(queue.pending("/mnt/a"), 0);
+        assert!(queue.last_command("/mnt/a").unwrap().result.is_ok());
+        assert!(queue.last_command("/mnt/b").unwrap().result.is_err());
+    }
+
+    #[test]
+    fn cooldown_skips_the_same_hook() {
+        let queue = idle();
+        let cooled = |to| Job {
+            cooldown: Duration::from_secs(60),
+            ..job("/mnt/a", "mounted", to, "true")
+        };
+        queue.push(cooled("unmounted"), false);
+        queue.push(cooled("unmounted"), false);
+        queue.push(cooled("mounted"), false);
+        assert_eq!(queue.pending("/mnt/a"), 2);
+    }
+
+    #[test]
+    fn cancelled_jobs_start_no_cooldown() {
+        let queue = idle();
+        let cooled = |from, to| Job {
+            cooldown: Duration::from_secs(60),
+            ..job("/mnt/a", from, to, "true")
+        };
+        queue.push(cooled("mounted", "unmounted"), true);
+        queue.push(cooled("unmounted", "mounted"), true);
+        assert_eq!(queue.pending("/mnt/a"), 0);
+        // Coming back up later still runs the command for it
+        queue.push(cooled("unmounted", "mounted"), true);
+        assert_eq!(
+            queued(&queue),
+            [("/mnt/a".to_string(), "mounted".to_string())]
+        );
+    }
+}
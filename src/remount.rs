@@ -0,0 +1,125 @@
+use crate::command::run_command;
+use crate::config::RemountAction;
+use crate::monitor::MountState;
+use crate::mounts::{disconnected, probe, Target};
+use log::{info, warn};
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+// Try to bring a vanished mount back, backing off between attempts, and return how
+// many attempts it took if it ended up mounted
+pub fn try_remount(
+    target: &Target,
+    action: &RemountAction,
+    attempts: u32,
+    backoff: Duration,
+    probe_timeout: Duration,
+    command_timeout: Duration,
+) -> Option<u32> {
+    let path = target.path;
+    let mut delay = backoff;
+    for attempt in 1..=attempts {
+        if attempt > 1 {
+            thread::sleep(delay);
+            delay *= 2;
+        }
+        info!(
+            event = "remount", mount_point = path;
+            "Attempting to remount {} ({} of {})", path, attempt, attempts
+        );
+        let result = match action {
+            RemountAction::Mount => remount(path),
+            RemountAction::Command(command) => run_command(command, command_timeout),
+        };
+        if let Err(e) = result {
+            warn!(event = "remount", mount_point = path; "Remount of {} failed: {}", path, e);
+        }
+        if probe(target, probe_timeout).state == MountState::Mounted {
+            info!(event = "remount", mount_point = path; "Remounted {}", path);
+            return Some(attempt);
+        }
+    }
+    None
+}
+
+// Try to bring a vanished mount back on a thread of its own, so the other mounts are
+// still checked meanwhile, and hand `done` how many attempts it took if it worked
+pub fn spawn_remount(
+    target: &Target,
+    action: &RemountAction,
+    attempts: u32,
+    backoff: Duration,
+    probe_timeout: Duration,
+    command_timeout: Duration,
+    done: impl FnOnce(Option<u32>) + Send + 'static,
+) {
+    let path = target.path.to_string();
+    let fs_types = target.fs_types.to_vec();
+    let canary = target.canary.clone();
+    let namespace = target.namespace.map(Path::to_path_buf);
+    let (ping_server, server_timeout, tcp_precheck, rpcbind_check, trigger_automount) = (
+        target.ping_server,
+        target.server_timeout,
+        target.tcp_precheck,
+        target.rpcbind_check,
+        target.trigger_automount,
+    );
+    let action = action.clone();
+    thread::spawn(move || {
+        let target = Target {
+            path: &path,
+            fs_types: &fs_types,
+            canary,
+            ping_server,
+            server_timeout,
+            tcp_precheck,
+            rpcbind_check,
+            trigger_automount,
+            namespace: namespace.as_deref(),
+        };
+        done(try_remount(
+            &target,
+            &action,
+            attempts,
+            backoff,
+            probe_timeout,
+            command_timeout,
+        ));
+    });
+}
+
+// Mount the path using its fstab entry
+fn remount(path: &str) -> Result<(), String> {
+    // What a dead FUSE process leaves behind has to be unmounted before the path can be
+    // mounted again, which fusermount lets the user who mounted it do. The BSDs have
+    // no fusermount, but a forced unmount does.
+    if disconnected(path) {
+        let unmounted = [
+            ("fusermount3", ["-u", "-z"]),
+            ("fusermount", ["-u", "-z"]),
+            ("umount", ["-f", "--"]),
+        ]
+        .iter()
+        .any(|(program, args)| {
+            Command::new(program)
+                .args(args)
+                .arg(path)
+                .status()
+                .is_ok_and(|s| s.success())
+        });
+        if !unmounted {
+            warn!("Unable to unmount the disconnected FUSE mount {}", path);
+        }
+    }
+    let status = Command::new("mount")
+        .arg(path)
+        .status()
+        .map_err(|e| format!("Failed to execute mount: {}", e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("mount failed with status: {}", status))
+    }
+}
@@ -0,0 +1,387 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// The programs nofus talks to
+pub const NFS_PROGRAM: u32 = 100003;
+pub const MOUNT_PROGRAM: u32 = 100005;
+const RPCBIND_PROGRAM: u32 = 100000;
+
+// Where rpcbind listens, and its version 2 (portmapper) GETPORT procedure
+const RPCBIND_PORT: u16 = 111;
+const PMAP_VERSION: u32 = 2;
+const PMAP_GETPORT: u32 = 3;
+const IPPROTO_TCP: u32 = 6;
+
+// Message types, reply statuses and the accepted call statuses of ONC RPC (RFC 5531)
+const CALL: u32 = 0;
+const REPLY: u32 = 1;
+const RPC_VERSION: u32 = 2;
+const MSG_ACCEPTED: u32 = 0;
+const AUTH_NONE: u32 = 0;
+const SUCCESS: u32 = 0;
+const PROG_UNAVAIL: u32 = 1;
+const PROG_MISMATCH: u32 = 2;
+const PROC_UNAVAIL: u32 = 3;
+
+// The last fragment bit of the record marking used over TCP
+const LAST_FRAGMENT: u32 = 0x8000_0000;
+
+// The largest reply taken, which is far more than any nofus asks for
+const MAX_REPLY: usize = 64 * 1024;
+
+static XID: AtomicU32 = AtomicU32::new(0);
+
+// Call the NULL procedure of a program, which does nothing but answer, to see whether
+// the server is serving it. A server that only serves other versions of the program
+// still counts.
+pub fn ping(
+    host: &str,
+    port: u16,
+    program: u32,
+    version: u32,
+    timeout: Duration,
+) -> Result<(), String> {
+    match call(host, port, program, version, 0, &[], timeout) {
+        Ok(_) => Ok(()),
+        Err(CallError::Rejected(PROG_MISMATCH)) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// Ask the server's rpcbind which port a version of a program is registered on over
+// TCP, if it is
+pub fn port(
+    host: &str,
+    program: u32,
+    version: u32,
+    timeout: Duration,
+) -> Result<Option<u16>, String> {
+    port_from(host, RPCBIND_PORT, program, version, timeout)
+}
+
+// Like port(), asking an rpcbind listening on the given port
+fn port_from(
+    host: &str,
+    rpcbind_port: u16,
+    program: u32,
+    version: u32,
+    timeout: Duration,
+) -> Result<Option<u16>, String> {
+    let results = call(
+        host,
+        rpcbind_port,
+        RPCBIND_PROGRAM,
+        PMAP_VERSION,
+        PMAP_GETPORT,
+        &[program, version, IPPROTO_TCP, 0],
+        timeout,
+    )
+    .map_err(|e| e.to_string())?;
+    let port = Reader(&results).word().map_err(|e| e.to_string())?;
+    Ok(u16::try_from(port).ok().filter(|p| *p != 0))
+}
+
+enum CallError {
+    Io(io::Error),
+    // The server answered, but not with a result
+    Rejected(u32),
+    // The server refused the call, e.g. for its credentials
+    Denied,
+    Garbled(&'static str),
+}
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::Io(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                write!(f, "timed out")
+            }
+            CallError::Io(e) => write!(f, "{}", e),
+            CallError::Rejected(PROG_UNAVAIL) => write!(f, "program not available"),
+            CallError::Rejected(PROG_MISMATCH) => write!(f, "version not supported"),
+            CallError::Rejected(PROC_UNAVAIL) => write!(f, "procedure not available"),
+            CallError::Rejected(status) => write!(f, "call rejected ({})", status),
+            CallError::Denied => write!(f, "call denied"),
+            CallError::Garbled(reason) => write!(f, "unexpected reply: {}", reason),
+        }
+    }
+}
+
+impl From<io::Error> for CallError {
+    fn from(error: io::Error) -> Self {
+        CallError::Io(error)
+    }
+}
+
+// Make a call over TCP with no credentials, returning the results as they came
+fn call(
+    host: &str,
+    port: u16,
+    program: u32,
+    version: u32,
+    procedure: u32,
+    args: &[u32],
+    timeout: Duration,
+) -> Result<Vec<u8>, CallError> {
+    let mut stream = connect(host, port, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let xid = next_xid();
+    let mut words = vec![
+        xid,
+        CALL,
+        RPC_VERSION,
+        program,
+        version,
+        procedure,
+        AUTH_NONE,
+        0,
+        AUTH_NONE,
+        0,
+    ];
+    words.extend_from_slice(args);
+    let mut message = Vec::with_capacity(4 + words.len() * 4);
+    message.extend_from_slice(&(LAST_FRAGMENT | (words.len() as u32 * 4)).to_be_bytes());
+    for word in words {
+        message.extend_from_slice(&word.to_be_bytes());
+    }
+    stream.write_all(&message)?;
+
+    let reply = read_record(&mut stream)?;
+    let mut reader = Reader(&reply);
+    if reader.word()? != xid {
+        return Err(CallError::Garbled("reply to another call"));
+    }
+    if reader.word()? != REPLY {
+        return Err(CallError::Garbled("not a reply"));
+    }
+    if reader.word()? != MSG_ACCEPTED {
+        return Err(CallError::Denied);
+    }
+    // Skip the verifier
+    let _flavor = reader.word()?;
+    let length = reader.word()? as usize;
+    reader.skip(length.div_ceil(4) * 4)?;
+    match reader.word()? {
+        SUCCESS => Ok(reader.0.to_vec()),
+        status => Err(CallError::Rejected(status)),
+    }
+}
+
+// Connect to the first of the host's addresses that answers
+pub fn connect(host: &str, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+    let addresses: Vec<SocketAddr> = (host, port).to_socket_addrs()?.collect();
+    let mut last = io::Error::new(io::ErrorKind::NotFound, "no addresses found");
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last = e,
+        }
+    }
+    Err(last)
+}
+
+// Read a whole record, which may come in several fragments
+fn read_record(stream: &mut TcpStream) -> Result<Vec<u8>, CallError> {
+    let mut record = Vec::new();
+    loop {
+        let mut header = [0; 4];
+        stream.read_exact(&mut header)?;
+        let header = u32::from_be_bytes(header);
+        let length = (header & !LAST_FRAGMENT) as usize;
+        if record.len() + length > MAX_REPLY {
+            return Err(CallError::Garbled("reply too long"));
+        }
+        let start = record.len();
+        record.resize(start + length, 0);
+        stream.read_exact(&mut record[start..])?;
+        if header & LAST_FRAGMENT != 0 {
+            return Ok(record);
+        }
+    }
+}
+
+// Start from the clock so calls from a restarted nofus don't reuse ids
+fn next_xid() -> u32 {
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    let _ = XID.compare_exchange(0, seed | 1, Ordering::Relaxed, Ordering::Relaxed);
+    XID.fetch_add(1, Ordering::Relaxed)
+}
+
+// Reads XDR words off the front of a message
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn word(&mut self) -> Result<u32, CallError> {
+        let (word, rest) = self
+            .0
+            .split_first_chunk::<4>()
+            .ok_or(CallError::Garbled("reply cut short"))?;
+        self.0 = rest;
+        Ok(u32::from_be_bytes(*word))
+    }
+
+    fn skip(&mut self, length: usize) -> Result<(), CallError> {
+        self.0 = self
+            .0
+            .get(length..)
+            .ok_or(CallError::Garbled("reply cut short"))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    // A server on a port of its own answering one call with the words the reply
+    // gives for it, in as many fragments as asked. Gives the words of the call.
+    fn serve(
+        fragments: usize,
+        reply: impl FnOnce(&[u32]) -> Vec<u32> + Send + 'static,
+    ) -> (u16, JoinHandle<Vec<u32>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let record = read_record(&mut stream).ok().unwrap();
+            let call: Vec<u32> = record
+                .chunks(4)
+                .map(|w| u32::from_be_bytes(w.try_into().unwrap()))
+                .collect();
+            let words = reply(&call);
+            let chunks: Vec<&[u32]> = words.chunks(words.len().div_ceil(fragments)).collect();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let last = if i == chunks.len() - 1 {
+                    LAST_FRAGMENT
+                } else {
+                    0
+                };
+                let mut message = (last | (chunk.len() as u32 * 4)).to_be_bytes().to_vec();
+                for word in chunk.iter() {
+                    message.extend_from_slice(&word.to_be_bytes());
+                }
+                stream.write_all(&message).unwrap();
+            }
+            call
+        });
+        (port, server)
+    }
+
+    // An accepted reply to the call with an empty verifier
+    fn accepted(call: &[u32], status: u32, results: &[u32]) -> Vec<u32> {
+        let mut words = vec![call[0], REPLY, MSG_ACCEPTED, AUTH_NONE, 0, status];
+        words.extend_from_slice(results);
+        words
+    }
+
+    #[test]
+    fn null_call_asks_for_nothing() {
+        let (port, server) = serve(1, |call| accepted(call, SUCCESS, &[]));
+        assert_eq!(ping("127.0.0.1", port, NFS_PROGRAM, 3, TIMEOUT), Ok(()));
+        let call = server.join().unwrap();
+        assert_eq!(
+            call[1..],
+            [
+                CALL,
+                RPC_VERSION,
+                NFS_PROGRAM,
+                3,
+                0,
+                AUTH_NONE,
+                0,
+                AUTH_NONE,
+                0
+            ]
+        );
+    }
+
+    #[test]
+    fn other_versions_still_count_as_answering() {
+        let (port, _) = serve(1, |call| accepted(call, PROG_MISMATCH, &[3, 4]));
+        assert_eq!(ping("127.0.0.1", port, NFS_PROGRAM, 2, TIMEOUT), Ok(()));
+        let (port, _) = serve(1, |call| accepted(call, PROG_UNAVAIL, &[]));
+        let error = ping("127.0.0.1", port, NFS_PROGRAM, 3, TIMEOUT).unwrap_err();
+        assert_eq!(error, "program not available");
+    }
+
+    #[test]
+    fn bad_replies_are_errors() {
+        let (port, _) = serve(1, |call| vec![call[0], REPLY, 1, 0]);
+        let error = ping("127.0.0.1", port, NFS_PROGRAM, 3, TIMEOUT).unwrap_err();
+        assert_eq!(error, "call denied");
+        let (port, _) = serve(1, |call| accepted(&[call[0] + 1], SUCCESS, &[]));
+        let error = ping("127.0.0.1", port, NFS_PROGRAM, 3, TIMEOUT).unwrap_err();
+        assert_eq!(error, "unexpected reply: reply to another call");
+        let (port, _) = serve(1, |call| vec![call[0], REPLY, MSG_ACCEPTED, AUTH_NONE, 8]);
+        let error = ping("127.0.0.1", port, NFS_PROGRAM, 3, TIMEOUT).unwrap_err();
+        assert_eq!(error, "unexpected reply: reply cut short");
+    }
+
+    #[test]
+    fn silent_servers_time_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let timeout = Duration::from_millis(200);
+        let error = ping("127.0.0.1", port, NFS_PROGRAM, 3, timeout).unwrap_err();
+        assert_eq!(error, "timed out");
+    }
+
+    #[test]
+    fn precheck_connects_or_says_why_not() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(connect("127.0.0.1", port, TIMEOUT).is_ok());
+        drop(listener);
+        let error = connect("127.0.0.1", port, TIMEOUT).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+        assert!(connect("nofus-test.invalid", port, TIMEOUT).is_err());
+    }
+
+    #[test]
+    fn getport_asks_for_the_program_over_tcp() {
+        let (rpcbind, server) = serve(2, |call| accepted(call, SUCCESS, &[2049]));
+        let port = port_from("127.0.0.1", rpcbind, NFS_PROGRAM, 3, TIMEOUT);
+        assert_eq!(port, Ok(Some(2049)));
+        let call = server.join().unwrap();
+        assert_eq!(
+            call[1..],
+            [
+                CALL,
+                RPC_VERSION,
+                RPCBIND_PROGRAM,
+                PMAP_VERSION,
+                PMAP_GETPORT,
+                AUTH_NONE,
+                0,
+                AUTH_NONE,
+                0,
+                NFS_PROGRAM,
+                3,
+                IPPROTO_TCP,
+                0
+            ]
+        );
+    }
+
+    #[test]
+    fn getport_of_zero_is_not_registered() {
+        let (rpcbind, _) = serve(1, |call| accepted(call, SUCCESS, &[0]));
+        let port = port_from("127.0.0.1", rpcbind, MOUNT_PROGRAM, 3, TIMEOUT);
+        assert_eq!(port, Ok(None));
+        let (rpcbind, _) = serve(1, |call| accepted(call, SUCCESS, &[]));
+        let error = port_from("127.0.0.1", rpcbind, MOUNT_PROGRAM, 3, TIMEOUT).unwrap_err();
+        assert_eq!(error, "unexpected reply: reply cut short");
+    }
+}
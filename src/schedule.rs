@@ -0,0 +1,223 @@
+use serde::Deserialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const DAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+// When something recurs, written like a crontab entry: minute, hour, day of month,
+// month and day of week, in local time, e.g. "0 3 * * sun" for 03:00 every Sunday
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Schedule {
+    // A bit for each value that matches
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // As in cron, a restricted day of month or of week is enough on its own
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl TryFrom<String> for Schedule {
+    type Error = String;
+
+    fn try_from(spec: String) -> Result<Self, Self::Error> {
+        let fields: Vec<&str> = spec.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!(
+                "schedule {:?} needs five fields: minute, hour, day, month and weekday",
+                spec
+            ));
+        };
+        let field = |field, min, max, names: &[&str], offset| {
+            parse_field(field, min, max, names, offset)
+                .map_err(|e| format!("invalid schedule {:?}: {}", spec, e))
+        };
+        let mut weekdays_bits = field(weekdays, 0, 7, &DAYS, 0)?;
+        // Both 0 and 7 are Sunday
+        if weekdays_bits & (1 << 7) != 0 {
+            weekdays_bits |= 1;
+        }
+        Ok(Schedule {
+            minutes: field(minutes, 0, 59, &[], 0)?,
+            hours: field(hours, 0, 23, &[], 0)?,
+            days: field(days, 1, 31, &[], 0)?,
+            months: field(months, 1, 12, &MONTHS, 1)?,
+            weekdays: weekdays_bits,
+            any_day: days == "*",
+            any_weekday: weekdays == "*",
+        })
+    }
+}
+
+// Parse a comma-separated list of values, ranges like 1-5 and steps like */15 into a
+// bit for each matching value. Names stand for their index plus the offset.
+fn parse_field(
+    field: &str,
+    min: u32,
+    max: u32,
+    names: &[&str],
+    offset: u32,
+) -> Result<u64, String> {
+    let value = |s: &str| -> Result<u32, String> {
+        let lower = s.to_lowercase();
+        let value = match names.iter().position(|n| *n == lower) {
+            Some(i) => i as u32 + offset,
+            None => s.parse().map_err(|_| format!("{:?} is not a number", s))?,
+        };
+        if value < min || value > max {
+            return Err(format!("{} is not between {} and {}", value, min, max));
+        }
+        Ok(value)
+    };
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step
+                    .parse()
+                    .map_err(|_| format!("{:?} is not a number", step))?;
+                if step == 0 {
+                    return Err("a step must be at least 1".to_string());
+                }
+                (range, Some(step))
+            }
+            None => (part, None),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (value(start)?, value(end)?),
+            // A single value with a step runs to the end, e.g. 5/15
+            None if step.is_some() => (value(range)?, max),
+            None => (value(range)?, value(range)?),
+        };
+        if start > end {
+            return Err(format!("{} is backwards", range));
+        }
+        for v in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Ok(bits)
+}
+
+impl Schedule {
+    // Whether the schedule came up at some minute in the given time before now
+    pub fn within(&self, now: SystemTime, window: Duration) -> bool {
+        let Ok(since_epoch) = now.duration_since(UNIX_EPOCH) else {
+            return false;
+        };
+        let minute = since_epoch.as_secs() as i64 / 60;
+        let minutes = window.as_secs().div_ceil(60) as i64;
+        (0..minutes).any(|back| local_time((minute - back) * 60).is_some_and(|t| self.matches(&t)))
+    }
+
+    fn matches(&self, t: &libc::tm) -> bool {
+        let bit = |bits: u64, value: i32| bits & (1 << value) != 0;
+        let day = bit(self.days, t.tm_mday);
+        let weekday = bit(self.weekdays, t.tm_wday);
+        let day_matches = if self.any_day || self.any_weekday {
+            day && weekday
+        } else {
+            day || weekday
+        };
+        bit(self.minutes, t.tm_min)
+            && bit(self.hours, t.tm_hour)
+            && bit(self.months, t.tm_mon + 1)
+            && day_matches
+    }
+}
+
+// Break a time in seconds since the epoch down into the local time zone's fields
+fn local_time(seconds: i64) -> Option<libc::tm> {
+    let time = seconds as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::localtime_r(&time, &mut tm) };
+    (!result.is_null()).then_some(tm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(spec: &str) -> Result<Schedule, String> {
+        Schedule::try_from(spec.to_string())
+    }
+
+    fn at(minute: i32, hour: i32, day: i32, month: i32, weekday: i32) -> libc::tm {
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        tm.tm_min = minute;
+        tm.tm_hour = hour;
+        tm.tm_mday = day;
+        tm.tm_mon = month - 1;
+        tm.tm_wday = weekday;
+        tm
+    }
+
+    #[test]
+    fn fields_expand_to_bits() {
+        let s = schedule("*/15 1-3,5 * jan,Mar */2").unwrap();
+        assert_eq!(s.minutes, 1 | 1 << 15 | 1 << 30 | 1 << 45);
+        assert_eq!(s.hours, 0b101110);
+        assert_eq!(s.months, 0b1010);
+        assert_eq!(s.weekdays, 0b1010101);
+        assert!(s.any_day && !s.any_weekday);
+        assert_eq!(
+            schedule("5/20 * * * *").unwrap().minutes,
+            1 << 5 | 1 << 25 | 1 << 45
+        );
+    }
+
+    #[test]
+    fn seven_is_sunday() {
+        assert_eq!(schedule("0 0 * * 7").unwrap().weekdays, 1 | 1 << 7);
+        assert_eq!(schedule("0 0 * * sun").unwrap().weekdays, 1);
+    }
+
+    #[test]
+    fn bad_schedules_are_rejected() {
+        assert!(schedule("0 3 * *").unwrap_err().contains("five fields"));
+        assert!(schedule("60 * * * *")
+            .unwrap_err()
+            .contains("not between 0 and 59"));
+        assert!(schedule("0 0 0 * *")
+            .unwrap_err()
+            .contains("not between 1 and 31"));
+        assert!(schedule("*/0 * * * *").unwrap_err().contains("at least 1"));
+        assert!(schedule("0 5-3 * * *").unwrap_err().contains("backwards"));
+        assert!(schedule("0 0 * * someday")
+            .unwrap_err()
+            .contains("not a number"));
+    }
+
+    #[test]
+    fn matches_local_time_fields() {
+        let s = schedule("0 3 * * sun").unwrap();
+        assert!(s.matches(&at(0, 3, 12, 10, 0)));
+        assert!(!s.matches(&at(0, 3, 13, 10, 1)));
+        assert!(!s.matches(&at(1, 3, 12, 10, 0)));
+    }
+
+    #[test]
+    fn restricted_day_or_weekday_is_enough() {
+        // The 1st of the month or any Monday
+        let s = schedule("0 0 1 * mon").unwrap();
+        assert!(s.matches(&at(0, 0, 1, 6, 3)));
+        assert!(s.matches(&at(0, 0, 9, 6, 1)));
+        assert!(!s.matches(&at(0, 0, 9, 6, 2)));
+        // With a wildcard weekday only the day of month counts
+        let s = schedule("0 0 1 * *").unwrap();
+        assert!(!s.matches(&at(0, 0, 9, 6, 1)));
+    }
+
+    #[test]
+    fn within_looks_back_over_the_window() {
+        let every_minute = schedule("* * * * *").unwrap();
+        assert!(every_minute.within(SystemTime::now(), Duration::from_secs(60)));
+        assert!(!every_minute.within(SystemTime::now(), Duration::ZERO));
+    }
+}
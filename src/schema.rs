@@ -0,0 +1,193 @@
+use serde::de::{self, value, Deserialize, Deserializer, Visitor};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+
+// The JSON Schema of a type that can appear in the config file
+pub trait Schema {
+    // Whether the value may be left out, as an Option can
+    const OPTIONAL: bool = false;
+
+    fn schema() -> Value;
+}
+
+// A struct field as the with_schema macro sees it
+pub struct Field {
+    pub name: &'static str,
+    // The field's attributes as written, e.g. serde(default)
+    pub attrs: &'static [&'static str],
+    pub schema: Value,
+    pub optional: bool,
+}
+
+// Define a config struct along with its schema, read off the struct's own fields and
+// their serde attributes so the two can't drift apart
+macro_rules! with_schema {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $(
+                $(#[$field_attr:meta])*
+                $field_vis:vis $field:ident: $ty:ty
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        $vis struct $name {
+            $(
+                $(#[$field_attr])*
+                $field_vis $field: $ty,
+            )*
+        }
+
+        impl $crate::schema::Schema for $name {
+            fn schema() -> serde_json::Value {
+                $crate::schema::object(vec![
+                    $(
+                        $crate::schema::Field {
+                            name: stringify!($field),
+                            attrs: &[$(stringify!($field_attr)),*],
+                            schema: <$ty as $crate::schema::Schema>::schema(),
+                            optional: <$ty as $crate::schema::Schema>::OPTIONAL,
+                        },
+                    )*
+                ])
+            }
+        }
+    };
+}
+pub(crate) use with_schema;
+
+// The schema of an object with the given fields. Fields serde skips are left out, and
+// those with a default or that may be missing aren't required.
+pub fn object(fields: Vec<Field>) -> Value {
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+    for field in fields {
+        let serde: Vec<&str> = field
+            .attrs
+            .iter()
+            .filter(|a| a.starts_with("serde"))
+            .copied()
+            .collect();
+        if serde.iter().any(|a| a.contains("skip")) {
+            continue;
+        }
+        if !field.optional && !serde.iter().any(|a| a.contains("default")) {
+            required.push(field.name);
+        }
+        // Names serde takes as well, given as alias = "name"
+        for alias in serde.iter().flat_map(|a| a.split("alias").skip(1)) {
+            if let Some(name) = alias.split('"').nth(1) {
+                properties.insert(name.to_string(), field.schema.clone());
+            }
+        }
+        properties.insert(field.name.to_string(), field.schema);
+    }
+    // nofus ignores keys it doesn't know, which is how typos go unnoticed, so the
+    // schema points them out instead
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    })
+}
+
+// The schema of an enum of plain names, using the names serde accepts for it
+pub fn names<'de, T: Deserialize<'de>>() -> Value {
+    let mut variants: &[&str] = &[];
+    let _ = T::deserialize(VariantNames(&mut variants));
+    json!({ "type": "string", "enum": variants })
+}
+
+// Asks an enum for its variants, the way serde's derived code hands them over when
+// deserializing one, and then gives up
+struct VariantNames<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> Deserializer<'de> for VariantNames<'_> {
+    type Error = value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not an enum"))
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        variants: &'static [&'static str],
+        _: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = variants;
+        Err(de::Error::custom("only after the names"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes
+        byte_buf option unit unit_struct newtype_struct seq tuple tuple_struct map
+        struct identifier ignored_any
+    }
+}
+
+impl Schema for bool {
+    fn schema() -> Value {
+        json!({ "type": "boolean" })
+    }
+}
+
+impl Schema for String {
+    fn schema() -> Value {
+        json!({ "type": "string" })
+    }
+}
+
+impl Schema for u16 {
+    fn schema() -> Value {
+        json!({ "type": "integer", "minimum": 0, "maximum": u16::MAX })
+    }
+}
+
+impl Schema for u32 {
+    fn schema() -> Value {
+        json!({ "type": "integer", "minimum": 0, "maximum": u32::MAX })
+    }
+}
+
+// Anything larger than a JSON number can hold exactly is of no use here anyway
+impl Schema for u64 {
+    fn schema() -> Value {
+        json!({ "type": "integer", "minimum": 0 })
+    }
+}
+
+impl Schema for f64 {
+    fn schema() -> Value {
+        json!({ "type": "number" })
+    }
+}
+
+impl Schema for usize {
+    fn schema() -> Value {
+        u64::schema()
+    }
+}
+
+impl<T: Schema> Schema for Option<T> {
+    const OPTIONAL: bool = true;
+
+    // Left empty, as `key: ~` would leave it
+    fn schema() -> Value {
+        json!({ "anyOf": [T::schema(), { "type": "null" }] })
+    }
+}
+
+impl<T: Schema> Schema for Vec<T> {
+    fn schema() -> Value {
+        json!({ "type": "array", "items": T::schema() })
+    }
+}
+
+impl<T: Schema> Schema for BTreeMap<String, T> {
+    fn schema() -> Value {
+        json!({ "type": "object", "additionalProperties": T::schema() })
+    }
+}
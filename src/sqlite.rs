@@ -0,0 +1,237 @@
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::path::Path;
+use std::ptr;
+
+// Just enough of the SQLite C API for the event history, linked against the system's
+// libsqlite3
+#[allow(non_camel_case_types)]
+type sqlite3 = c_void;
+#[allow(non_camel_case_types)]
+type sqlite3_stmt = c_void;
+
+const SQLITE_OK: c_int = 0;
+const SQLITE_ROW: c_int = 100;
+const SQLITE_DONE: c_int = 101;
+const SQLITE_INTEGER: c_int = 1;
+const SQLITE_NULL: c_int = 5;
+const SQLITE_OPEN_READONLY: c_int = 0x01;
+const SQLITE_OPEN_READWRITE: c_int = 0x02;
+const SQLITE_OPEN_CREATE: c_int = 0x04;
+const SQLITE_OPEN_FULLMUTEX: c_int = 0x10000;
+// Have SQLite copy bound text, so it needn't outlive the call
+const SQLITE_TRANSIENT: isize = -1;
+
+#[link(name = "sqlite3")]
+extern "C" {
+    fn sqlite3_open_v2(
+        filename: *const c_char,
+        db: *mut *mut sqlite3,
+        flags: c_int,
+        vfs: *const c_char,
+    ) -> c_int;
+    fn sqlite3_close(db: *mut sqlite3) -> c_int;
+    fn sqlite3_errmsg(db: *mut sqlite3) -> *const c_char;
+    fn sqlite3_busy_timeout(db: *mut sqlite3, ms: c_int) -> c_int;
+    fn sqlite3_prepare_v2(
+        db: *mut sqlite3,
+        sql: *const c_char,
+        len: c_int,
+        statement: *mut *mut sqlite3_stmt,
+        tail: *mut *const c_char,
+    ) -> c_int;
+    fn sqlite3_bind_null(statement: *mut sqlite3_stmt, index: c_int) -> c_int;
+    fn sqlite3_bind_int64(statement: *mut sqlite3_stmt, index: c_int, value: i64) -> c_int;
+    fn sqlite3_bind_text(
+        statement: *mut sqlite3_stmt,
+        index: c_int,
+        text: *const c_char,
+        len: c_int,
+        destructor: isize,
+    ) -> c_int;
+    fn sqlite3_step(statement: *mut sqlite3_stmt) -> c_int;
+    fn sqlite3_column_count(statement: *mut sqlite3_stmt) -> c_int;
+    fn sqlite3_column_type(statement: *mut sqlite3_stmt, column: c_int) -> c_int;
+    fn sqlite3_column_int64(statement: *mut sqlite3_stmt, column: c_int) -> i64;
+    fn sqlite3_column_text(statement: *mut sqlite3_stmt, column: c_int) -> *const c_char;
+    fn sqlite3_finalize(statement: *mut sqlite3_stmt) -> c_int;
+}
+
+// A value bound to a statement or read from a row
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Text(String),
+}
+
+impl From<&str> for Value {
+    fn from(text: &str) -> Self {
+        Value::Text(text.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Self {
+        Value::Text(text)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Integer(value)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+impl Value {
+    pub fn integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            Value::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+pub struct Connection {
+    db: *mut sqlite3,
+}
+
+// Opened in serialized mode, so SQLite does its own locking
+unsafe impl Send for Connection {}
+unsafe impl Sync for Connection {}
+
+impl Connection {
+    // Open the database, creating it unless only reading
+    pub fn open(path: &Path, read_only: bool) -> Result<Self, String> {
+        let filename = CString::new(path.as_os_str().as_encoded_bytes())
+            .map_err(|_| "Path contains a NUL byte".to_string())?;
+        let flags = if read_only {
+            SQLITE_OPEN_READONLY
+        } else {
+            SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE
+        };
+        let mut db = ptr::null_mut();
+        let result = unsafe {
+            sqlite3_open_v2(
+                filename.as_ptr(),
+                &mut db,
+                flags | SQLITE_OPEN_FULLMUTEX,
+                ptr::null(),
+            )
+        };
+        // A handle comes back even on failure, to tell why and then be closed
+        let connection = Connection { db };
+        if result != SQLITE_OK {
+            return Err(connection.error());
+        }
+        // The daemon may be writing while history is read
+        unsafe { sqlite3_busy_timeout(db, 5000) };
+        Ok(connection)
+    }
+
+    fn error(&self) -> String {
+        if self.db.is_null() {
+            return "Out of memory".to_string();
+        }
+        unsafe { CStr::from_ptr(sqlite3_errmsg(self.db)) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    // Run a statement that returns no rows
+    pub fn execute(&self, sql: &str, params: &[Value]) -> Result<(), String> {
+        self.query(sql, params, |_| ()).map(|_| ())
+    }
+
+    // Run a statement, turning each row it returns into a value
+    pub fn query<T>(
+        &self,
+        sql: &str,
+        params: &[Value],
+        mut row: impl FnMut(&[Value]) -> T,
+    ) -> Result<Vec<T>, String> {
+        let sql = CString::new(sql).map_err(|_| "SQL contains a NUL byte".to_string())?;
+        let mut statement = ptr::null_mut();
+        let result = unsafe {
+            sqlite3_prepare_v2(self.db, sql.as_ptr(), -1, &mut statement, ptr::null_mut())
+        };
+        if result != SQLITE_OK {
+            return Err(self.error());
+        }
+        let statement = Statement(statement);
+        for (i, param) in params.iter().enumerate() {
+            let index = i as c_int + 1;
+            let result = match param {
+                Value::Null => unsafe { sqlite3_bind_null(statement.0, index) },
+                Value::Integer(value) => unsafe { sqlite3_bind_int64(statement.0, index, *value) },
+                Value::Text(text) => unsafe {
+                    sqlite3_bind_text(
+                        statement.0,
+                        index,
+                        text.as_ptr() as *const c_char,
+                        text.len() as c_int,
+                        SQLITE_TRANSIENT,
+                    )
+                },
+            };
+            if result != SQLITE_OK {
+                return Err(self.error());
+            }
+        }
+        let mut rows = Vec::new();
+        let mut values = Vec::new();
+        loop {
+            match unsafe { sqlite3_step(statement.0) } {
+                SQLITE_ROW => {}
+                SQLITE_DONE => return Ok(rows),
+                _ => return Err(self.error()),
+            }
+            values.clear();
+            for column in 0..unsafe { sqlite3_column_count(statement.0) } {
+                values.push(match unsafe { sqlite3_column_type(statement.0, column) } {
+                    SQLITE_NULL => Value::Null,
+                    SQLITE_INTEGER => {
+                        Value::Integer(unsafe { sqlite3_column_int64(statement.0, column) })
+                    }
+                    // Anything else is read as text, the only other kind stored
+                    _ => {
+                        let text = unsafe { sqlite3_column_text(statement.0, column) };
+                        if text.is_null() {
+                            Value::Null
+                        } else {
+                            let text = unsafe { CStr::from_ptr(text) };
+                            Value::Text(text.to_string_lossy().into_owned())
+                        }
+                    }
+                });
+            }
+            rows.push(row(&values));
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        unsafe { sqlite3_close(self.db) };
+    }
+}
+
+struct Statement(*mut sqlite3_stmt);
+
+impl Drop for Statement {
+    fn drop(&mut self) {
+        unsafe { sqlite3_finalize(self.0) };
+    }
+}
@@ -0,0 +1,116 @@
+use crate::control::CommandReport;
+use crate::monitor::MountState;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// What the daemon last knew about the mounts, kept across restarts so one that was
+// already down isn't acted on again
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SavedState {
+    #[serde(default)]
+    pub mounts: BTreeMap<String, SavedMount>,
+    // Whether every mount was last seen mounted, and each group up. Mounts with a
+    // command of their own for going down, but not for coming up, only count
+    // towards mounted.
+    pub all_mounted: Option<bool>,
+    #[serde(default)]
+    pub mounted: Option<bool>,
+    #[serde(default)]
+    pub groups: BTreeMap<String, bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedMount {
+    pub state: MountState,
+    // When it entered that state, in seconds since the epoch
+    pub since: Option<u64>,
+    pub last_command: Option<CommandReport>,
+}
+
+// Read the saved state, or None if nothing has been saved yet
+pub fn load(path: &Path) -> Result<Option<SavedState>, String> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.to_string()),
+    };
+    serde_json::from_str(&content)
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+// Write the state out as given by to_json, replacing the old file in one go so a
+// crash midway can't leave half of it behind
+pub fn save(path: &Path, json: &str) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    fs::write(&temporary, json)?;
+    fs::rename(&temporary, path)
+}
+
+pub fn to_json(state: &SavedState) -> String {
+    serde_json::to_string_pretty(state).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("nofus-test-{}", std::process::id()));
+        dir.join(name).join("state.json")
+    }
+
+    #[test]
+    fn saved_state_reads_back() {
+        let path = scratch("saved");
+        let mut state = SavedState {
+            all_mounted: Some(false),
+            mounted: Some(true),
+            ..Default::default()
+        };
+        let mount = SavedMount {
+            state: MountState::Stale,
+            since: Some(1_700_000_000),
+            last_command: None,
+        };
+        state.mounts.insert("/mnt/a".to_string(), mount);
+        state.groups.insert("media".to_string(), true);
+        save(&path, &to_json(&state)).unwrap();
+        assert!(!path.with_extension("json.tmp").exists());
+
+        let loaded = load(&path).unwrap().unwrap();
+        assert_eq!(to_json(&loaded), to_json(&state));
+        assert_eq!(loaded.mounts["/mnt/a"].state, MountState::Stale);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn nothing_saved_yet_is_none() {
+        assert!(load(&scratch("missing")).unwrap().is_none());
+    }
+
+    #[test]
+    fn unreadable_state_is_an_error() {
+        let path = scratch("corrupt");
+        save(&path, "{\"mounts\": [").unwrap();
+        assert!(load(&path).is_err());
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn older_state_files_still_load() {
+        let path = scratch("older");
+        save(&path, r#"{"mounts": {"/mnt/a": {"state": "mounted", "since": null, "last_command": null}}, "all_mounted": true}"#).unwrap();
+        let loaded = load(&path).unwrap().unwrap();
+        assert_eq!(loaded.mounted, None);
+        assert!(loaded.groups.is_empty());
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
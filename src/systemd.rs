@@ -0,0 +1,184 @@
+use crate::config::UnitAction;
+use log::{debug, info, warn};
+use std::env;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use zbus::blocking::Connection;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+
+// Where systemd answers on the system bus
+const SYSTEMD: &str = "org.freedesktop.systemd1";
+const MANAGER_PATH: &str = "/org/freedesktop/systemd1";
+const MANAGER: &str = "org.freedesktop.systemd1.Manager";
+const UNIT: &str = "org.freedesktop.systemd1.Unit";
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+
+// How often to look at a unit while its job runs
+const JOB_POLL: Duration = Duration::from_millis(100);
+
+// Send a state update to systemd, doing nothing when not started by systemd
+pub fn notify(state: &str) {
+    let Ok(path) = env::var("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(e) = send(&path, state) {
+        warn!("Unable to notify systemd via {}: {}", path, e);
+    }
+}
+
+fn send(path: &str, state: &str) -> std::io::Result<()> {
+    // A leading @ means the socket is in the abstract namespace
+    let addr = match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        _ => SocketAddr::from_pathname(path)?,
+    };
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+    debug!("Notified systemd: {}", state);
+    Ok(())
+}
+
+// The watchdog interval systemd expects pings within, if the watchdog is enabled
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    // WATCHDOG_PID is only set when the watchdog is meant for a specific process
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return None;
+        }
+    }
+    Some(Duration::from_micros(usec))
+}
+
+// The first file descriptor systemd passes in for socket activation
+const LISTEN_FDS_START: RawFd = 3;
+
+// The sockets systemd passed in, until they are taken
+static LISTENERS: Mutex<Option<Vec<OwnedFd>>> = Mutex::new(None);
+
+// Take a socket systemd passed in through socket activation whose address is of one
+// of the families, e.g. AF_UNIX for the control socket, if there is one
+pub fn take_listener(families: &[libc::c_int]) -> Option<OwnedFd> {
+    let mut listeners = LISTENERS.lock().unwrap();
+    let listeners = listeners.get_or_insert_with(inherited);
+    let i = listeners
+        .iter()
+        .position(|fd| family(fd).is_some_and(|f| families.contains(&f)))?;
+    Some(listeners.remove(i))
+}
+
+// Pick up the sockets passed in, if they were meant for this process, and keep them
+// from being passed on to the commands nofus runs
+fn inherited() -> Vec<OwnedFd> {
+    let pid = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|p| p.parse::<u32>().ok());
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse::<RawFd>().ok());
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(name);
+    }
+    let (Some(pid), Some(count)) = (pid, count) else {
+        return Vec::new();
+    };
+    if pid != std::process::id() {
+        return Vec::new();
+    }
+    debug!("Received {} sockets from systemd", count);
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+            unsafe { OwnedFd::from_raw_fd(fd) }
+        })
+        .collect()
+}
+
+// The address family of a socket
+fn family(fd: &OwnedFd) -> Option<libc::c_int> {
+    let mut address: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut length = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockname(
+            fd.as_raw_fd(),
+            &mut address as *mut _ as *mut libc::sockaddr,
+            &mut length,
+        )
+    };
+    (result == 0).then_some(address.ss_family.into())
+}
+
+// Stop, restart and start the units of an action through systemd, one after the
+// other, waiting for each job to finish and making sure the unit ended up inactive or
+// active as asked. The timeout covers the whole action.
+pub fn run_units(action: &UnitAction, timeout: Duration) -> Result<(), String> {
+    let deadline = Instant::now() + timeout;
+    let bus = Connection::system().map_err(|e| format!("Unable to reach systemd: {}", e))?;
+    for (verb, unit) in action.steps() {
+        let state = act(&bus, verb, unit, deadline)
+            .map_err(|e| format!("Unable to {} {}: {}", verb, unit, e))?;
+        let wanted = if verb == "stop" { "inactive" } else { "active" };
+        if state != wanted {
+            return Err(format!("{} is {} after {}", unit, state, verb));
+        }
+        info!(event = "command"; "{} is {} after {}", unit, state, verb);
+    }
+    Ok(())
+}
+
+// Have systemd stop, restart or start a unit, giving the state it settled in once its
+// job is done
+fn act(bus: &Connection, verb: &str, unit: &str, deadline: Instant) -> Result<String, String> {
+    let method = match verb {
+        "stop" => "StopUnit",
+        "restart" => "RestartUnit",
+        _ => "StartUnit",
+    };
+    bus.call_method(
+        Some(SYSTEMD),
+        MANAGER_PATH,
+        Some(MANAGER),
+        method,
+        &(unit, "replace"),
+    )
+    .map_err(|e| e.to_string())?;
+    let path: OwnedObjectPath = bus
+        .call_method(
+            Some(SYSTEMD),
+            MANAGER_PATH,
+            Some(MANAGER),
+            "LoadUnit",
+            &(unit,),
+        )
+        .and_then(|reply| reply.body().deserialize())
+        .map_err(|e| e.to_string())?;
+    loop {
+        // The unit has no job, with an id of 0, once it is done
+        let (job, _): (u32, OwnedObjectPath) = unit_property(bus, &path, "Job")?;
+        if job == 0 {
+            return unit_property(bus, &path, "ActiveState");
+        }
+        if Instant::now() >= deadline {
+            return Err("timed out waiting for systemd".to_string());
+        }
+        thread::sleep(JOB_POLL);
+    }
+}
+
+fn unit_property<T>(bus: &Connection, path: &OwnedObjectPath, name: &str) -> Result<T, String>
+where
+    T: TryFrom<OwnedValue>,
+    T::Error: std::fmt::Display,
+{
+    let value: OwnedValue = bus
+        .call_method(Some(SYSTEMD), path, Some(PROPERTIES), "Get", &(UNIT, name))
+        .and_then(|reply| reply.body().deserialize())
+        .map_err(|e| e.to_string())?;
+    T::try_from(value).map_err(|e| e.to_string())
+}
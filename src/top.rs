@@ -0,0 +1,223 @@
+use crate::control::{self, EventReport, MountReport, Request, Response};
+use crate::monitor::MountState;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Cell, List, ListItem, Paragraph, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+// Show the daemon's view of the mounts, refreshed from the control socket until q is
+// pressed
+pub fn run(socket: &Path, interval: Duration) -> io::Result<()> {
+    if unsafe { libc::isatty(libc::STDIN_FILENO) } == 0 {
+        return Err(io::Error::other("nofus top needs a terminal"));
+    }
+    let mut terminal = ratatui::try_init()?;
+    let result = show(&mut terminal, socket, interval);
+    ratatui::restore();
+    result
+}
+
+fn show(terminal: &mut DefaultTerminal, socket: &Path, interval: Duration) -> io::Result<()> {
+    loop {
+        let status = match control::request(socket, &Request::Status) {
+            Ok(Response::Status { mounts }) => Ok(mounts),
+            Ok(Response::Ok { message }) | Ok(Response::Error { message }) => Err(message),
+            Err(e) => Err(e.to_string()),
+        };
+        terminal.draw(|frame| draw(frame, socket, &status))?;
+        if wait_for_quit(interval)? {
+            return Ok(());
+        }
+    }
+}
+
+// Wait for a key until the interval is up, returning whether it asked to quit. Any
+// other key refreshes the screen right away, as does resizing the terminal.
+fn wait_for_quit(interval: Duration) -> io::Result<bool> {
+    if !event::poll(interval)? {
+        return Ok(false);
+    }
+    let Event::Key(key) = event::read()? else {
+        return Ok(false);
+    };
+    // Ctrl-C isn't turned into a signal while keys are read as they are pressed
+    let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+    Ok(key.kind == KeyEventKind::Press
+        && (ctrl_c || matches!(key.code, KeyCode::Char('q' | 'Q') | KeyCode::Esc)))
+}
+
+fn draw(frame: &mut Frame, socket: &Path, status: &Result<Vec<MountReport>, String>) {
+    let now = humantime::format_rfc3339_seconds(SystemTime::now());
+    let header = format!("nofus top: {}  {}  (q to quit)", socket.display(), now);
+    let mounts = match status {
+        Ok(mounts) => mounts,
+        Err(message) => {
+            let [top, rest] =
+                Layout::vertical([Constraint::Length(2), Constraint::Fill(1)]).areas(frame.area());
+            frame.render_widget(Paragraph::new(header), top);
+            let error = Paragraph::new(message.as_str()).style(Style::new().fg(Color::Red));
+            frame.render_widget(error, rest);
+            return;
+        }
+    };
+    let [top, table, changes] = Layout::vertical([
+        Constraint::Length(2),
+        // The heading and a row for each mount, and a line between them and the changes
+        Constraint::Length(mounts.len() as u16 + 2),
+        Constraint::Fill(1),
+    ])
+    .areas(frame.area());
+    frame.render_widget(Paragraph::new(header), top);
+    frame.render_widget(mount_table(mounts), table);
+    frame.render_widget(recent_changes(mounts, changes.height), changes);
+}
+
+// A row for each mount with its state, coloured by state
+fn mount_table(mounts: &[MountReport]) -> Table<'_> {
+    let now = SystemTime::now();
+    let rows = mounts.iter().map(|mount| {
+        let last_change = match mount.events.last() {
+            Some(event) if event.from != MountState::Unknown => {
+                format!("{} -> {}", event.from, event.to)
+            }
+            _ => "-".to_string(),
+        };
+        let mut state = mount.state.to_string();
+        if mount.silenced {
+            state.push_str(" (s)");
+        }
+        Row::new([
+            Cell::from(state).style(Style::new().fg(colour(mount.state))),
+            Cell::from(since(mount.since.as_deref(), now)),
+            Cell::from(last_change),
+            Cell::from(sparkline(&mount.latencies_ms)),
+            Cell::from(mount.path.as_str()),
+        ])
+    });
+    let heading = Row::new(["STATE", "FOR", "LAST CHANGE", "CHECK LATENCY", "PATH"])
+        .style(Style::new().add_modifier(Modifier::BOLD));
+    let widths = [
+        Constraint::Length(12),
+        Constraint::Length(10),
+        Constraint::Length(29),
+        Constraint::Length(29),
+        Constraint::Fill(1),
+    ];
+    Table::new(rows, widths).header(heading)
+}
+
+// As many of the latest state changes as fit, newest last
+fn recent_changes(mounts: &[MountReport], height: u16) -> List<'_> {
+    let mut events: Vec<(&str, &EventReport)> = mounts
+        .iter()
+        .flat_map(|m| m.events.iter().map(move |e| (m.path.as_str(), e)))
+        .collect();
+    events.sort_by(|a, b| a.1.at.cmp(&b.1.at));
+    // Below the heading
+    let room = usize::from(height.saturating_sub(1));
+    let items = events[events.len().saturating_sub(room)..]
+        .iter()
+        .map(|(path, event)| {
+            ListItem::new(format!(
+                "{}  {}  {} -> {} ({})",
+                event.at, path, event.from, event.to, event.cause
+            ))
+        });
+    let title = Line::from(Span::styled(
+        "RECENT CHANGES",
+        Style::new().add_modifier(Modifier::BOLD),
+    ));
+    List::new(items).block(Block::new().title(title).borders(Borders::TOP))
+}
+
+fn colour(state: MountState) -> Color {
+    match state {
+        MountState::Mounted => Color::Green,
+        MountState::Unmounted | MountState::Stale | MountState::ServerDown => Color::Red,
+        _ => Color::Yellow,
+    }
+}
+
+// How long a mount has been in its state, to the largest unit or two
+fn since(since: Option<&str>, now: SystemTime) -> String {
+    let Some(at) = since.and_then(|s| humantime::parse_rfc3339(s).ok()) else {
+        return "-".to_string();
+    };
+    let seconds = now.duration_since(at).unwrap_or_default().as_secs();
+    humantime::format_duration(Duration::from_secs(seconds))
+        .to_string()
+        .split(' ')
+        .take(2)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// The recent latencies as bars scaled to the slowest, followed by the latest
+fn sparkline(latencies: &[u64]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let Some(latest) = latencies.last() else {
+        return "-".to_string();
+    };
+    let slowest = latencies.iter().copied().max().unwrap_or(0).max(1);
+    let bars: String = latencies
+        .iter()
+        .rev()
+        .take(20)
+        .rev()
+        .map(|l| BARS[(*l * (BARS.len() as u64 - 1) / slowest) as usize])
+        .collect();
+    format!("{} {}ms", bars, latest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    // What the screen shows, line by line
+    fn screen(status: &Result<Vec<MountReport>, String>) -> (Vec<String>, ratatui::buffer::Buffer) {
+        let mut terminal = Terminal::new(TestBackend::new(120, 12)).unwrap();
+        terminal
+            .draw(|frame| draw(frame, Path::new("/run/nofus.sock"), status))
+            .unwrap();
+        let buffer = terminal.backend().buffer().clone();
+        let lines = (0..buffer.area.height)
+            .map(|y| {
+                (0..buffer.area.width)
+                    .map(|x| buffer[(x, y)].symbol())
+                    .collect::<String>()
+            })
+            .collect();
+        (lines, buffer)
+    }
+
+    #[test]
+    fn mounts_and_their_changes_are_shown() {
+        let mounts: Vec<MountReport> = serde_json::from_str(
+            r#"[{"path": "/mnt/a", "state": "stale", "since": null, "last_command": null,
+                "latencies_ms": [1, 8], "events": [{"at": "2026-01-01T00:00:00Z",
+                "from": "mounted", "to": "stale", "cause": "stale file handle"}]}]"#,
+        )
+        .unwrap();
+        let (lines, buffer) = screen(&Ok(mounts));
+        assert!(lines[0].starts_with("nofus top: /run/nofus.sock"));
+        assert!(lines[2].starts_with("STATE"));
+        assert!(lines[3].starts_with("stale") && lines[3].contains("/mnt/a"));
+        assert!(lines[3].contains("mounted -> stale") && lines[3].contains("▁█ 8ms"));
+        assert_eq!(buffer[(0, 3)].fg, Color::Red);
+        assert!(lines[5].contains("RECENT CHANGES"));
+        assert!(lines[6].contains("/mnt/a  mounted -> stale (stale file handle)"));
+    }
+
+    #[test]
+    fn errors_are_shown_instead() {
+        let (lines, _) = screen(&Err("Unable to connect".to_string()));
+        assert!(lines[2].starts_with("Unable to connect"));
+    }
+}
@@ -0,0 +1,60 @@
+use crate::config::UsageLimits;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+// How full a mount is against its thresholds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    #[default]
+    Ok,
+    Warning,
+    Critical,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Level::Ok => "ok",
+            Level::Warning => "warning",
+            Level::Critical => "critical",
+        })
+    }
+}
+
+impl Level {
+    pub fn of(used_percent: f64, limits: &UsageLimits) -> Self {
+        let over = |threshold: Option<f64>| threshold.is_some_and(|t| used_percent >= t);
+        if over(limits.critical_percent) {
+            Level::Critical
+        } else if over(limits.warn_percent) {
+            Level::Warning
+        } else {
+            Level::Ok
+        }
+    }
+}
+
+// The level each mount was last found at for each thing that can run out, taken to be
+// ok until found otherwise
+#[derive(Default)]
+pub struct Usage {
+    levels: HashMap<(String, &'static str), Level>,
+}
+
+impl Usage {
+    // Record a mount's level, returning the one it was at before if that changed
+    pub fn update(&mut self, path: &str, resource: &'static str, level: Level) -> Option<Level> {
+        let previous = self
+            .levels
+            .insert((path.to_string(), resource), level)
+            .unwrap_or_default();
+        (previous != level).then_some(previous)
+    }
+
+    // Forget mounts no longer monitored
+    pub fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        self.levels.retain(|(path, _), _| keep(path));
+    }
+}
@@ -0,0 +1,490 @@
+use crate::config::WebConfig;
+use crate::control::{MountReport, Request, Response};
+use crate::monitor::{MountEvent, MountState};
+use crate::systemd;
+use log::{debug, info, warn};
+use serde_json::json;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpStream;
+use tokio::sync::broadcast;
+use tokio::time::{self, Duration};
+
+// The status page, which fetches everything it shows from the API
+const DASHBOARD: &str = include_str!("web/dashboard.html");
+
+// What can be asked for besides /mounts/<path>, to tell a wrong method from a wrong
+// path
+const ROUTES: &[&str] = &[
+    "/events",
+    "/metrics",
+    "/api",
+    "/api/status",
+    "/mounts",
+    "/recheck",
+    "/silence",
+    "/unsilence",
+    "/trigger",
+];
+
+// How much of a request is read before giving up on it
+const MAX_HEADERS: usize = 16 * 1024;
+const MAX_BODY: usize = 64 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How often an idle event stream gets a comment, so a client that has gone away is
+// noticed
+const KEEPALIVE: Duration = Duration::from_secs(30);
+
+// Serve the dashboard in a background task, answering its API requests with the
+// handler the control socket uses and streaming what is sent on `changes` to
+// clients of /events. Gives the address it ended up listening on.
+pub fn serve<F>(
+    config: &WebConfig,
+    changes: broadcast::Sender<String>,
+    handler: F,
+) -> io::Result<SocketAddr>
+where
+    F: Fn(Request) -> Response + Send + Sync + 'static,
+{
+    // Anyone could use the API with an empty token, so it is left off
+    if config.token.is_empty() {
+        info!("web.token isn't set, so only /healthz and /readyz are served");
+    }
+    let listener = match systemd::take_listener(&[libc::AF_INET, libc::AF_INET6]) {
+        Some(fd) => {
+            debug!("Using the dashboard socket systemd passed in");
+            std::net::TcpListener::from(fd)
+        }
+        None => std::net::TcpListener::bind(&config.listen)?,
+    };
+    listener.set_nonblocking(true)?;
+    let address = listener.local_addr()?;
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+    let token = Arc::new(config.token.clone());
+    let listen = address;
+    let handler = Arc::new(handler);
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let handler = Arc::clone(&handler);
+                    let token = Arc::clone(&token);
+                    let changes = changes.subscribe();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_client(stream, &token, changes, &*handler).await {
+                            debug!("Web client error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Web listener {} error: {}", listen, e),
+            }
+        }
+    });
+    Ok(address)
+}
+
+// A request as far as the dashboard cares
+struct HttpRequest {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+async fn handle_client<F>(
+    stream: TcpStream,
+    token: &str,
+    changes: broadcast::Receiver<String>,
+    handler: &F,
+) -> io::Result<()>
+where
+    F: Fn(Request) -> Response,
+{
+    let (reader, mut writer) = stream.into_split();
+    let request = match time::timeout(READ_TIMEOUT, read_request(BufReader::new(reader))).await {
+        Ok(Ok(request)) => request,
+        Ok(Err(e)) => {
+            let body = json!({ "message": e.to_string() }).to_string();
+            return respond(&mut writer, 400, "application/json", &body).await;
+        }
+        Err(_) => return Ok(()),
+    };
+
+    let (path, query) = request
+        .path
+        .split_once('?')
+        .unwrap_or((request.path.as_str(), ""));
+    if path == "/" {
+        return match request.method.as_str() {
+            "GET" => respond(&mut writer, 200, "text/html; charset=utf-8", DASHBOARD).await,
+            _ => respond(&mut writer, 405, "text/plain", "Method not allowed").await,
+        };
+    }
+    // For Kubernetes probes and the like, which have no token
+    if let "/healthz" | "/readyz" = path {
+        return match request.method.as_str() {
+            "GET" => {
+                let (status, body) = health(handler(Request::Status), path == "/readyz");
+                respond(&mut writer, status, "text/plain; charset=utf-8", &body).await
+            }
+            _ => respond(&mut writer, 405, "text/plain", "Method not allowed").await,
+        };
+    }
+    // Everything else needs the token. Browsers can't set headers on an event
+    // stream, so for that it may come in the query instead.
+    let mut given = request.authorization.clone();
+    if path == "/events" && given.is_none() {
+        given = query_param(query, "token").map(|t| format!("Bearer {}", t));
+    }
+    if !authorized(given.as_deref(), token) {
+        let body = message("Missing or wrong token");
+        return respond(&mut writer, 401, "application/json", &body).await;
+    }
+
+    let method = request.method.as_str();
+    let (status, body) = match (method, path) {
+        ("GET", "/events") => return stream_changes(&mut writer, changes).await,
+        ("GET", "/metrics") => match handler(Request::Status) {
+            Response::Status { mounts } => {
+                let body = prometheus(&mounts);
+                let content_type = "text/plain; version=0.0.4; charset=utf-8";
+                return respond(&mut writer, 200, content_type, &body).await;
+            }
+            response => reply(response),
+        },
+        // The control requests as they are sent over the socket
+        ("GET", "/api/status") => (200, serde_json::to_string(&handler(Request::Status))?),
+        ("POST", "/api") => match serde_json::from_slice(&request.body) {
+            Ok(control) => {
+                let response = handler(control);
+                let status = match response {
+                    Response::Error { .. } => 400,
+                    _ => 200,
+                };
+                (status, serde_json::to_string(&response)?)
+            }
+            Err(e) => (400, message(&format!("Invalid request: {}", e))),
+        },
+        ("GET", "/mounts") => match handler(Request::Status) {
+            Response::Status { mounts } => (200, serde_json::to_string(&mounts)?),
+            response => reply(response),
+        },
+        // The mount point's path follows, escaped or not
+        ("GET", _) if path.starts_with("/mounts/") => {
+            let wanted = percent_decode(&path["/mounts".len()..]).unwrap_or_default();
+            let wanted = format!("/{}", wanted.trim_start_matches('/'));
+            match handler(Request::Status) {
+                Response::Status { mounts } => match mounts.iter().find(|m| m.path == wanted) {
+                    Some(mount) => (200, serde_json::to_string(mount)?),
+                    None => (404, message(&format!("Not monitoring {}", wanted))),
+                },
+                response => reply(response),
+            }
+        }
+        ("POST", "/recheck" | "/silence" | "/unsilence" | "/trigger") => {
+            match rest_request(&path[1..], &request.body) {
+                Ok(control) => reply(handler(control)),
+                Err(e) => (400, message(&format!("Invalid request: {}", e))),
+            }
+        }
+        _ if ROUTES.contains(&path) || path.starts_with("/mounts/") => {
+            (405, message("Method not allowed"))
+        }
+        _ => (404, message("Not found")),
+    };
+    respond(&mut writer, status, "application/json", &body).await
+}
+
+async fn read_request(
+    mut reader: BufReader<tokio::net::tcp::OwnedReadHalf>,
+) -> io::Result<HttpRequest> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut line = String::new();
+    let mut read = reader.read_line(&mut line).await?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(invalid("Malformed request line"));
+    };
+    let mut request = HttpRequest {
+        method: method.to_string(),
+        path: path.to_string(),
+        authorization: None,
+        body: Vec::new(),
+    };
+    let mut length = 0;
+    loop {
+        line.clear();
+        read += reader.read_line(&mut line).await?;
+        if read > MAX_HEADERS {
+            return Err(invalid("Headers too large"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(invalid("Malformed header"));
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            length = value
+                .parse()
+                .map_err(|_| invalid("Malformed Content-Length"))?;
+        } else if name.eq_ignore_ascii_case("authorization") {
+            request.authorization = Some(value.to_string());
+        }
+    }
+    if length > MAX_BODY {
+        return Err(invalid("Body too large"));
+    }
+    request.body.resize(length, 0);
+    reader.read_exact(&mut request.body).await?;
+    Ok(request)
+}
+
+// Send each state change as a server-sent event until the client goes away
+async fn stream_changes(
+    writer: &mut OwnedWriteHalf,
+    mut changes: broadcast::Receiver<String>,
+) -> io::Result<()> {
+    writer
+        .write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+              Cache-Control: no-store\r\nConnection: keep-alive\r\n\r\n",
+        )
+        .await?;
+    loop {
+        let message = match time::timeout(KEEPALIVE, changes.recv()).await {
+            Ok(Ok(change)) => format!("event: state_change\ndata: {}\n\n", change),
+            // A client too slow to keep up is told how much it missed
+            Ok(Err(broadcast::error::RecvError::Lagged(missed))) => {
+                format!(": missed {} events\n\n", missed)
+            }
+            Ok(Err(broadcast::error::RecvError::Closed)) => return Ok(()),
+            Err(_) => ": keepalive\n\n".to_string(),
+        };
+        writer.write_all(message.as_bytes()).await?;
+    }
+}
+
+// A state change as sent to clients of /events
+pub fn event_json(event: &MountEvent) -> String {
+    json!({
+        "path": event.path,
+        "kind": event.kind,
+        "from": event.from,
+        "to": event.to,
+        "at": humantime::format_rfc3339_seconds(event.at).to_string(),
+        "cause": event.cause,
+    })
+    .to_string()
+}
+
+// The mounts' states and check latencies in Prometheus' text format, each series with
+// the mount's labels from the config
+fn prometheus(mounts: &[MountReport]) -> String {
+    let mount_labels = |mount: &MountReport| {
+        let labels: String = mount
+            .labels
+            .iter()
+            .map(|(name, value)| format!(",{}=\"{}\"", name, label(value)))
+            .collect();
+        format!("mount=\"{}\"{}", label(&mount.path), labels)
+    };
+    let mut text = String::new();
+    text.push_str("# HELP nofus_mount_up Whether the mount is mounted, if only slowly\n");
+    text.push_str("# TYPE nofus_mount_up gauge\n");
+    for mount in mounts {
+        text.push_str(&format!(
+            "nofus_mount_up{{{},server=\"{}\",state=\"{}\"}} {}\n",
+            mount_labels(mount),
+            label(mount.server.as_deref().unwrap_or_default()),
+            mount.state,
+            mount.state.is_up() as u8
+        ));
+    }
+    text.push_str("# HELP nofus_probe_latency_seconds How long checking the mount took\n");
+    text.push_str("# TYPE nofus_probe_latency_seconds histogram\n");
+    for mount in mounts {
+        let labels = mount_labels(mount);
+        let histogram = &mount.latency_histogram;
+        let bounds = histogram
+            .buckets_ms
+            .iter()
+            .map(|ms| (*ms as f64 / 1000.0).to_string())
+            .chain(["+Inf".to_string()]);
+        for (bound, count) in bounds.zip(&histogram.counts) {
+            text.push_str(&format!(
+                "nofus_probe_latency_seconds_bucket{{{},le=\"{}\"}} {}\n",
+                labels, bound, count
+            ));
+        }
+        text.push_str(&format!(
+            "nofus_probe_latency_seconds_sum{{{}}} {}\n",
+            labels,
+            histogram.sum_ms / 1000.0
+        ));
+        text.push_str(&format!(
+            "nofus_probe_latency_seconds_count{{{}}} {}\n",
+            labels, histogram.count
+        ));
+    }
+    text
+}
+
+// Escape a Prometheus label value
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// A control request from the body of a POST to /<command>, which takes the request's
+// fields as a JSON object, e.g. {"mount": "/mnt/a", "seconds": 3600} for /silence
+fn rest_request(command: &str, body: &[u8]) -> Result<Request, serde_json::Error> {
+    let mut fields = if body.iter().all(u8::is_ascii_whitespace) {
+        serde_json::Map::new()
+    } else {
+        serde_json::from_slice(body)?
+    };
+    fields.insert("command".to_string(), command.into());
+    serde_json::from_value(fields.into())
+}
+
+// The answer to a control request, as the REST routes give it
+fn reply(response: Response) -> (u16, String) {
+    match response {
+        Response::Ok { message: m } => (200, message(&m)),
+        Response::Error { message: m } => (400, message(&m)),
+        Response::Status { .. } => (500, message("Unexpected response")),
+    }
+}
+
+fn message(message: &str) -> String {
+    json!({ "message": message }).to_string()
+}
+
+// A parameter from a query string, with any escapes decoded
+fn query_param(query: &str, name: &str) -> Option<String> {
+    let value = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))?;
+    percent_decode(&value.replace('+', " "))
+}
+
+// Decode %-escapes, as in a URL
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::new();
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = match (byte, tail) {
+            (b'%', [high, low, ..]) => std::str::from_utf8(&[*high, *low])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                bytes.push(byte);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+// Whether the request carries the token, compared in full so how long that takes
+// doesn't give away how much of it was right
+fn authorized(authorization: Option<&str>, token: &str) -> bool {
+    if token.is_empty() {
+        return false;
+    }
+    let Some(given) = authorization.and_then(|a| a.strip_prefix("Bearer ")) else {
+        return false;
+    };
+    let (given, token) = (given.trim().as_bytes(), token.as_bytes());
+    given.len() == token.len() && given.iter().zip(token).fold(0, |d, (a, b)| d | (a ^ b)) == 0
+}
+
+// Whether every mount is up, as a status and a line saying which aren't. Mounts not
+// checked yet count as healthy, but not yet as ready.
+fn health(response: Response, ready: bool) -> (u16, String) {
+    let mounts = match response {
+        Response::Status { mounts } => mounts,
+        Response::Ok { message } | Response::Error { message } => {
+            return (503, format!("unhealthy: {}\n", message))
+        }
+    };
+    let down: Vec<String> = mounts
+        .iter()
+        .filter(|m| !m.state.is_up() && (ready || m.state != MountState::Unknown))
+        .map(|m| format!("{} {}", m.path, m.state))
+        .collect();
+    if down.is_empty() {
+        (200, "ok\n".to_string())
+    } else if ready {
+        (503, format!("not ready: {}\n", down.join(", ")))
+    } else {
+        (503, format!("unhealthy: {}\n", down.join(", ")))
+    }
+}
+
+async fn respond(
+    writer: &mut OwnedWriteHalf,
+    status: u16,
+    content_type: &str,
+    body: &str,
+) -> io::Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "Error",
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+         Cache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        reason,
+        content_type,
+        body.len()
+    );
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(body.as_bytes()).await?;
+    writer.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bearer_token_must_match() {
+        assert!(authorized(Some("Bearer s3cret"), "s3cret"));
+        assert!(authorized(Some("Bearer s3cret "), "s3cret"));
+        assert!(!authorized(Some("Bearer s3cre"), "s3cret"));
+        assert!(!authorized(Some("Bearer s3cret!"), "s3cret"));
+        assert!(!authorized(Some("s3cret"), "s3cret"));
+        assert!(!authorized(Some("Basic s3cret"), "s3cret"));
+        assert!(!authorized(None, "s3cret"));
+    }
+
+    #[test]
+    fn empty_token_lets_nobody_in() {
+        assert!(!authorized(Some("Bearer "), ""));
+        assert!(!authorized(None, ""));
+    }
+}